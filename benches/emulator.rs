@@ -0,0 +1,45 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use rc8::{emulator::Emulator, opcode::Opcode, timing::FRAME_CYCLES};
+
+const ROMS: [(&str, &[u8]); 3] = [
+    ("test suite", include_bytes!("../roms/chip8-test-suite.ch8")),
+    ("pictures", include_bytes!("../roms/chip8-pictures.ch8")),
+    ("ibm logo", include_bytes!("../roms/ibm-logo.ch8")),
+];
+
+/// One frame worth of instructions, as the frontend runs them
+fn frame(emu: &mut Emulator) {
+    emu.decrease_timers();
+    emu.vblank();
+    for _ in 0..FRAME_CYCLES {
+        // roms that end in an error just stop doing work
+        if emu.execute().is_err() {
+            break;
+        }
+    }
+}
+
+fn bench_frames(c: &mut Criterion) {
+    let mut group = c.benchmark_group("frame");
+    for (name, rom) in ROMS {
+        group.bench_function(name, |b| {
+            let mut emu = Emulator::load_rom(rom).unwrap();
+            emu.seed_rng(0);
+            b.iter(|| frame(black_box(&mut emu)))
+        });
+    }
+    group.finish();
+}
+
+fn bench_decode(c: &mut Criterion) {
+    c.bench_function("decode", |b| {
+        b.iter(|| {
+            for word in 0..=u16::MAX {
+                black_box(Opcode::decode(black_box(word)));
+            }
+        })
+    });
+}
+
+criterion_group!(benches, bench_frames, bench_decode);
+criterion_main!(benches);
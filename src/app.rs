@@ -1,28 +1,108 @@
-use std::time::Instant;
+use std::{
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::Context;
+use clap::ValueEnum;
 use sdl2::{
-    audio::AudioSpecDesired,
-    event::Event,
+    audio::{AudioDevice, AudioDeviceLockGuard, AudioSpecDesired, AudioStatus},
+    event::{Event, WindowEvent},
+    keyboard::Keycode,
+    mouse::MouseButton,
     pixels::{Color, PixelFormatEnum},
     rect::Rect,
-    render::{BlendMode, Texture, TextureCreator, TextureValueError},
+    render::{
+        BlendMode, Texture, TextureCreator, TextureValueError, UpdateTextureError, WindowCanvas,
+    },
     surface::Surface,
     ttf::Font,
+    video::{FullscreenType, Window, WindowPos},
+    AudioSubsystem, IntegerOrSdlError,
 };
 use thiserror::Error;
+use tracing::{debug, error, info, trace_span, warn};
 
 use super::{
-    beep::Beep,
-    emulator::{Emulator, DISPLAY_HEIGHT, DISPLAY_WIDTH},
-    keymap::{Action, Keymap},
+    beep::Tone,
+    capture::{Capture, CaptureFormat},
+    chat::Chat,
+    chip8x::ColorBoard,
+    clock::{Clock, SystemClock},
+    crt,
+    debugger::{self, Debugger},
+    diagnose::{self, Diagnosis},
+    display::Display,
+    emulator::{
+        Emulator, EmulatorError, IndexMode, Quirks, StackMode, DISPLAY_HEIGHT, DISPLAY_WIDTH,
+    },
+    filter::{Filter, Persistence},
+    flags,
+    frontend::{AudioSink, InputSource},
+    gdb::{self, GdbServer},
+    keymap::{translate_bindings, translate_remap, Action, Keymap, KEYPAD},
+    metadata::Metadata,
+    mixer::Mixer,
+    mousekeys::{MouseInput, MouseKeys},
+    netplay::{Netplay, NetplayMode},
+    opcode::Variant,
+    osd::Osd,
+    palette::Palette,
+    perf::Stats,
+    players::Players,
+    profile::Profile,
+    recent::RecentRoms,
+    record::Recorder,
+    remap::Remap,
+    rng::RngKind,
+    romdb::RomDb,
+    rsp,
+    shape::PixelShape,
+    source::RomSource,
+    stream::StreamServer,
+    timing::{run_frame, Timing},
+    trace::{Tracer, MAIN_THREAD},
+    tui::{self, DebugTui},
+    turbo::Turbo,
+    wav::WavRecorder,
+    worker::{self, Machine, Screen, Worker},
 };
 
+#[cfg(feature = "net")]
+use super::update;
+
 pub const PIXEL_SIZE: usize = 10;
+pub const MIN_SCALE: u32 = PIXEL_SIZE as u32;
+// enough to fill an 8K screen
+pub const MAX_SCALE: u32 = 120;
+
+// how bright the pixels that are off are, for shapes that show them
+const UNLIT_LEVEL: f32 = 0.12;
+
+// how long to wait for input when there is nothing else to do, before
+// checking again; short while running, so new frames show up quickly,
+// and long when stopped with nothing on screen about to change
+const RUNNING_IDLE: u32 = 1;
+const PAUSED_IDLE: u32 = 50;
+const STOPPED_IDLE: u32 = 1000;
 
-const CYCLE_DELAY: u128 = 1_000_000 / 540;
-const TIMER_DELAY: u128 = 1_000_000 / 60;
-const VBLANK_DELAY: u128 = 1_000_000 / 60;
+// how long the rom runs while looking for the keys it uses
+const REMAP_FRAMES: usize = 600;
+
+// asked of the audio device, which may pick another
+const SAMPLE_RATE: i32 = 44100;
+
+// disassembly view geometry, in logical pixels; it sits on the left side
+const DEBUGGER_WIDTH: u32 = 300;
+const DEBUGGER_LINE: u32 = 24;
+const DEBUGGER_TOP: u32 = 4;
+
+// keypad overlay geometry, in logical pixels; it sits on the bottom-right corner
+const KEYPAD_CELL: u32 = 48;
+const KEYPAD_MARGIN: u32 = 8;
+const KEYPAD_LEFT: u32 = (DISPLAY_WIDTH * PIXEL_SIZE) as u32 - KEYPAD_CELL * 4 - KEYPAD_MARGIN;
+const KEYPAD_TOP: u32 = (DISPLAY_HEIGHT * PIXEL_SIZE) as u32 - KEYPAD_CELL * 4 - KEYPAD_MARGIN;
 
 #[derive(Error, Debug)]
 enum AppError {
@@ -37,6 +117,12 @@ enum AppError {
 
     #[error("SDL texture error: {0}")]
     Texture(#[from] TextureValueError),
+
+    #[error("SDL texture update error: {0}")]
+    TextureUpdate(#[from] UpdateTextureError),
+
+    #[error("SDL window error: {0}")]
+    Window(#[from] IntegerOrSdlError),
 }
 
 impl From<String> for AppError {
@@ -45,23 +131,110 @@ impl From<String> for AppError {
     }
 }
 
+/// How the screen is fit on the window
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Scaling {
+    /// As big as possible while keeping every pixel the same size
+    Integer,
+    /// As big as possible, keeping the aspect ratio
+    Fit,
+    /// Fill the whole window, even if the pixels are not square
+    Stretch,
+}
+
 pub struct Options {
     pub width: u32,
     pub height: u32,
     pub fullscreen: bool,
-    pub bgcolor: u32,
-    pub fgcolor: u32,
+    pub scaling: Scaling,
+    /// Color of the bars around the screen, as 0xRRGGBB00
+    pub letterbox: u32,
+    pub palette: Palette,
+    /// The colors were chosen on the command line, so the rom ones are ignored
+    pub custom_palette: bool,
+    pub tone: Tone,
+    pub volume: u8,
+    pub master_volume: u8,
+    /// Samples per audio callback; SDL picks one if not given
+    pub audio_buffer: Option<u16>,
+    /// Name of the audio output device; the default one if not given
+    pub audio_device: Option<String>,
+    pub filter: Filter,
+    pub crt: bool,
+    pub pixel_shape: PixelShape,
+    pub wrap: bool,
+    pub vip_machine_code: bool,
+    pub wait_key_press: bool,
+    pub dream6800: bool,
+    pub half_scroll: bool,
+    pub full_memory: bool,
+    /// Run roms too big for memory, instead of failing
+    pub force: bool,
+    pub short_beeps: bool,
+    pub no_db: bool,
+    /// Keep the RPL user flags of each rom on disk
+    pub persist_flags: bool,
+    pub gdb: Option<u16>,
+    pub debug_tui: bool,
+    pub profile: Option<PathBuf>,
+    pub netplay: Option<NetplayMode>,
+    /// Netplay guesses the keys of the other player, and goes back when wrong
+    pub rollback: bool,
+    pub stream: Option<u16>,
+    #[cfg(feature = "net")]
+    pub check_updates: bool,
+    pub vip_timing: bool,
+    pub draw_budget: Option<u32>,
+    pub keypad_click: bool,
+    pub record: Option<PathBuf>,
+    /// Save the sound output as a WAV file
+    pub record_audio: Option<PathBuf>,
+    pub capture_format: CaptureFormat,
+    /// Keys pressed over and over while held, and how many times per second
+    pub turbo: Vec<usize>,
+    pub turbo_rate: u32,
+    /// Mouse buttons and wheel bound to keys
+    pub mouse_keys: Vec<(MouseInput, usize)>,
+    /// The keys swapped by `Action::SwapPlayers`
+    pub players: Players,
+    pub rng: Option<RngKind>,
+    pub variant: Option<Variant>,
+    pub index_mode: Option<IndexMode>,
+    pub stack_depth: Option<StackMode>,
+    pub speed: Option<u32>,
 }
 
-#[derive(PartialEq)]
+#[derive(PartialEq, Clone, Copy)]
 enum AppState {
     Running,
     Paused,
+    Switching,
+    Crashed,
     Quit,
 }
 
 /// Main application loop
-pub fn run(mut emu: Emulator, options: Options) -> Result<(), anyhow::Error> {
+pub fn run(
+    mut emu: Emulator,
+    path: &Path,
+    metadata: Metadata,
+    options: Options,
+    mut recent: RecentRoms,
+    tracer: Tracer,
+) -> Result<(), anyhow::Error> {
+    // the other player must be there before the game starts
+    let netplay = options
+        .netplay
+        .as_ref()
+        .map(|mode| Netplay::connect(mode, &mut emu))
+        .transpose()
+        .context("error starting netplay")?
+        .map(|mut netplay| {
+            netplay.set_rollback(options.rollback);
+            netplay
+        });
+    let mut netplay_on = netplay.is_some();
+
     // initialize SDL context and subsystems
     let sdl_context = sdl2::init()
         .map_err(AppError::from)
@@ -75,6 +248,9 @@ pub fn run(mut emu: Emulator, options: Options) -> Result<(), anyhow::Error> {
         .map_err(AppError::from)
         .context("failed to initialize audio subsystem")?;
 
+    // text input is only on while typing a chat message
+    sdl_video.text_input().stop();
+
     // initialize SDL_ttf
     let ttf_context = sdl2::ttf::init()
         .map_err(AppError::from)
@@ -86,9 +262,15 @@ pub fn run(mut emu: Emulator, options: Options) -> Result<(), anyhow::Error> {
     let font = ttf_context
         .load_font_from_rwops(font_rwops, 64)
         .map_err(AppError::from)?;
+    let font_rwops = sdl2::rwops::RWops::from_bytes(font_bytes).map_err(AppError::from)?;
+    let small_font = ttf_context
+        .load_font_from_rwops(font_rwops, 20)
+        .map_err(AppError::from)?;
 
     // build the window
-    let mut window = sdl_video.window("RC8", options.width, options.height);
+    let title = metadata.window_title(path, emu.quirks.variant);
+    let mut window = sdl_video.window(&title, options.width, options.height);
+    window.resizable();
 
     if options.fullscreen {
         window.fullscreen_desktop();
@@ -96,7 +278,19 @@ pub fn run(mut emu: Emulator, options: Options) -> Result<(), anyhow::Error> {
         window.position_centered();
     }
 
-    let window = window.build().context("error creating window")?;
+    let mut window = window.build().context("error creating window")?;
+
+    // the overlays and their text do not fit on anything smaller
+    window
+        .set_minimum_size(
+            (DISPLAY_WIDTH * PIXEL_SIZE) as u32,
+            (DISPLAY_HEIGHT * PIXEL_SIZE) as u32,
+        )
+        .context("error setting the minimum window size")?;
+    info!(
+        "window created: {}x{}, fullscreen: {}",
+        options.width, options.height, options.fullscreen
+    );
 
     // get the drawing canvas
     let mut canvas = window
@@ -104,12 +298,8 @@ pub fn run(mut emu: Emulator, options: Options) -> Result<(), anyhow::Error> {
         .build()
         .context("error creating window canvas")?;
 
-    canvas
-        .set_logical_size(
-            (DISPLAY_WIDTH * PIXEL_SIZE) as u32,
-            (DISPLAY_HEIGHT * PIXEL_SIZE) as u32,
-        )
-        .context("failed to set logical resolution")?;
+    apply_scaling(&mut canvas, options.scaling).context("failed to set logical resolution")?;
+    let letterbox = rgb_color(options.letterbox);
 
     // build a texture creator
     let texture_creator = canvas.texture_creator();
@@ -120,113 +310,796 @@ pub fn run(mut emu: Emulator, options: Options) -> Result<(), anyhow::Error> {
         .map_err(AppError::from)
         .context("error obtaining the event pump")?;
 
-    // desired audio spec
-    let desired_spec = AudioSpecDesired {
-        freq: Some(44100),
-        channels: Some(1),
-        samples: None,
-    };
-
     // get sound device
-    let audio_device = sdl_audio
-        .open_playback(None, &desired_spec, Beep::from)
+    let mixer = Mixer::new(
+        SAMPLE_RATE as f32,
+        options.tone,
+        options.volume,
+        options.master_volume,
+        tracer.clone(),
+    );
+    let mut speaker = Speaker::new(sdl_audio, &options, mixer)
         .map_err(AppError::from)
         .context("error opening audio device")?;
 
-    // convert color values
-    let bgcolor = options.bgcolor.to_be_bytes();
-    let bgcolor = Color::RGBA(bgcolor[0], bgcolor[1], bgcolor[2], 0xff);
-    let fgcolor = options.fgcolor.to_be_bytes();
-    let fgcolor = Color::RGBA(fgcolor[0], fgcolor[1], fgcolor[2], 0xff);
+    // everything played from now on also goes to the file
+    let wav = match &options.record_audio {
+        Some(path) => {
+            let sample_rate = speaker.mixer().sample_rate() as u32;
+            let wav = WavRecorder::create(path, sample_rate).with_context(|| {
+                format!("error creating the audio recording: {}", path.display())
+            })?;
+            speaker.mixer().taps.push(wav.sender());
+            Some(wav)
+        }
+        None => None,
+    };
+
+    let mut turbo = Turbo::new(&options.turbo, options.turbo_rate);
+    let mut mouse_keys = MouseKeys::new(&options.mouse_keys);
+    let mut players = options.players.clone();
+
+    // the video being recorded, and the ones still being saved
+    let mut capture: Option<Capture> = None;
+    let mut capturing = Vec::new();
+
+    let mut colors = rom_colors(&options, &metadata);
+    let mut crt_effect = options.crt;
 
     let mut state = AppState::Running;
-    let keymap = Keymap::Chip8;
-    let mut previous = Instant::now();
-    let mut timer_delta = 0;
-    let mut cpu_delta = 0;
-    let mut vblank_delta = 0;
+    let clock = SystemClock::new();
+    let mut previous = clock.now();
     let mut emulator_texture = None;
+    let mut emulator_surface = Surface::new(
+        (DISPLAY_WIDTH * PIXEL_SIZE) as u32,
+        (DISPLAY_HEIGHT * PIXEL_SIZE) as u32,
+        PixelFormatEnum::RGBA8888,
+    )
+    .map_err(AppError::from)?;
     let mut pause_texture = None;
+    let mut step_texture = None;
+    let mut step_frame = false;
+    let mut pause_overlay = true;
+    let mut switcher_texture = None;
+    let mut switcher_selected = 0;
+    let mut switcher_resume = AppState::Running;
+    // playing with the mouse, the keypad is at hand from the start
+    let mut keypad_visible = !options.mouse_keys.is_empty();
+    let mut keypad_texture = None;
+    let mut keypad_pressed = 0u16;
+    let mut mouse_key = None;
+    let mut osd_texture = None;
+    let mut osd_drawn = 0;
+    let mut emu_error = None;
+    let mut debugger_lines: Vec<debugger::Line> = Vec::new();
+    let mut debugger_texture = None;
+    let mut error_texture = None;
+    let mut diagnosis: Option<Diagnosis> = None;
+    let mut suggested = false;
+    let mut perf = Stats::new(clock.now());
+    let mut perf_visible = false;
+    let mut perf_texture = None;
+    let mut quality_texture = None;
+    let mut quality_drawn = None;
+    let mut chat = Chat::default();
+    let mut chat_texture = None;
+    let mut chat_drawn = Vec::new();
+    let mut redrawn = true;
+    let mut remap: Option<Remap> = None;
+    let mut bindings = metadata.bindings();
+    let mut controls = metadata.controls();
+    let mut remap_offer = offer_remap(&emu, &bindings);
+    let mut osd = Osd::default();
+    if let Some(message) = rom_message(&metadata, remap_offer.as_ref()) {
+        osd.show(message, clock.now());
+    }
+    let mut saved_flags = emu.flags;
+
+    // a newer release is only mentioned, never in the way
+    #[cfg(feature = "net")]
+    let updates = options.check_updates.then(update::spawn_check);
+
+    // gdb can attach at any time, and stops the rom while attached
+    let gdb = options
+        .gdb
+        .map(GdbServer::spawn)
+        .transpose()
+        .context("error starting the gdb server")?;
+    let mut gdb_continue: Option<gdb::Request> = None;
+
+    let debug_tui = options
+        .debug_tui
+        .then(DebugTui::spawn)
+        .transpose()
+        .context("error starting the terminal debugger")?;
+
+    let stream = options
+        .stream
+        .map(StreamServer::spawn)
+        .transpose()
+        .context("error starting the stream server")?;
+
+    // the emulation runs on its own thread from now on
+    let profile = options.profile.as_ref().map(|_| Profile::new(&emu));
+    let worker = Worker::spawn(
+        emu,
+        new_timing(&options, &metadata),
+        SystemClock::new(),
+        options.filter,
+        options.record.as_ref().map(|_| Recorder::new()),
+        netplay,
+        tracer.clone(),
+    );
+    worker.lock().profile = profile;
+
+    // what the frame is drawn from, copied out of the machine
+    let mut display = Display::default();
+    let mut color_board: Option<ColorBoard> = None;
+    let mut persistence: Option<Persistence> = None;
 
     loop {
-        let now = Instant::now();
-        let elapsed = previous.elapsed().as_micros();
+        let _frame_span = tracer.span("frame", MAIN_THREAD);
+        let _frame = trace_span!("frame").entered();
+        let now = clock.now();
+        let elapsed = now - previous;
         previous = now;
 
-        // process input events
-        for event in event_pump.poll_iter() {
-            match keymap.translate_action(&event) {
-                Some(Action::EmulateKeyState(key, state)) => emu.set_key(key, state),
-                Some(Action::Quit) => state = AppState::Quit,
-                Some(Action::TogglePause) => {
-                    state = if state == AppState::Running {
-                        AppState::Paused
-                    } else {
-                        AppState::Running
+        // sleep until some input comes, instead of spinning, if nothing
+        // changed on the last frame or the emulator is stopped; messages
+        // and the performance numbers still need to be updated meanwhile,
+        // and gdb waits on the loop for every packet
+        let animating = osd.is_active()
+            || chat.is_active()
+            || perf_visible
+            || gdb.is_some()
+            || debug_tui.is_some();
+        let timeout = match state {
+            AppState::Running if redrawn => None,
+            AppState::Running => Some(RUNNING_IDLE),
+            _ if animating => Some(PAUSED_IDLE),
+            _ => Some(STOPPED_IDLE),
+        };
+        let waited = timeout.and_then(|timeout| event_pump.wait_event_timeout(timeout));
+
+        // the emulation waits while the machine is locked, so it is only
+        // held to handle the input and to copy what the frame needs
+        let mut machine = worker.lock();
+        let Machine {
+            emu,
+            timing,
+            debugger,
+            screen,
+            keypad,
+            recorder,
+            running,
+            fast_forward,
+            instructions,
+            profile,
+            netplay,
+            ..
+        } = &mut *machine;
+
+        #[cfg(feature = "net")]
+        if let Some(release) = updates.as_ref().and_then(|updates| updates.try_recv().ok()) {
+            osd.show(
+                format!("rc8 {} is out: {}", release.version, release.url),
+                clock.now(),
+            );
+        }
+
+        // high scores are written as soon as the rom saves them
+        if options.persist_flags && emu.flags != saved_flags {
+            saved_flags = emu.flags;
+            if let Err(err) = flags::save(emu.rom(), &saved_flags) {
+                warn!("could not save the rpl flags: {}", err);
+            }
+        }
+
+        // whatever the emulation had to say since the last frame
+        for event in worker.events() {
+            match event {
+                worker::Event::Buzzer(on) => {
+                    debug!("buzzer {}", if on { "on" } else { "off" });
+                    speaker.set_buzzer(on && state == AppState::Running)
+                }
+                worker::Event::Breakpoint => {
+                    debug!("breakpoint at 0x{:03X}", emu.PC);
+                    state = AppState::Paused;
+                    pause_overlay = false;
+                    osd.show(format!("Breakpoint at 0x{:03X}", emu.PC), clock.now());
+                    if debug_tui.is_some() {
+                        tui::print(&format!(
+                            "\nbreakpoint at 0x{:03X}\n{}",
+                            emu.PC,
+                            tui::list(emu, debugger, emu.PC)
+                        ));
                     }
                 }
-                None => {
-                    if let Event::Quit { .. } = event {
-                        state = AppState::Quit
+                worker::Event::PlayerLeft => {
+                    let message = match options.netplay {
+                        Some(NetplayMode::Watch(_)) => "The game is over",
+                        _ => "The other player left",
+                    };
+                    info!("{}", message.to_lowercase());
+                    netplay_on = false;
+                    osd.show(message.to_owned(), clock.now());
+                }
+                worker::Event::Crashed(err) => {
+                    error!("{}", err);
+                    diagnosis = diagnose::crash(emu, &err);
+                    emu_error = Some(err);
+                    error_texture = None;
+                    state = AppState::Crashed;
+                }
+            }
+        }
+
+        // the browsers play on the same keypad
+        for (key, pressed) in stream.iter().flat_map(StreamServer::keys) {
+            worker.set_key(key, pressed);
+        }
+
+        // gdb decides when the rom runs, as long as it is attached
+        for request in gdb.iter().flat_map(GdbServer::requests) {
+            match request.command {
+                rsp::Command::Attach | rsp::Command::Interrupt => {
+                    if state == AppState::Running {
+                        state = AppState::Paused;
+                        pause_overlay = false;
+                    }
+                }
+                rsp::Command::Continue => {
+                    if state == AppState::Paused {
+                        debugger.resume();
+                        state = AppState::Running;
+                    }
+                    gdb_continue = Some(request);
+                }
+                rsp::Command::Detach => {
+                    gdb_continue = None;
+                    if state == AppState::Paused {
+                        debugger.resume();
+                        state = AppState::Running;
+                    }
+                }
+                _ => {
+                    if let Some(reply) = rsp::access(&request.command, emu, debugger) {
+                        request.reply(&reply);
+                    }
+                }
+            }
+        }
+        // and so does the terminal debugger
+        for command in debug_tui.iter().flat_map(DebugTui::commands) {
+            match command {
+                tui::Command::Continue => {
+                    if state == AppState::Paused {
+                        debugger.resume();
+                        state = AppState::Running;
+                    }
+                    tui::print("running");
+                }
+                tui::Command::Pause => {
+                    if state == AppState::Running {
+                        state = AppState::Paused;
+                        pause_overlay = false;
+                    }
+                    tui::print(&tui::list(emu, debugger, emu.PC));
+                }
+                command => {
+                    // stepping only makes sense on a stopped rom
+                    if matches!(command, tui::Command::Step(_)) && state == AppState::Running {
+                        state = AppState::Paused;
+                        pause_overlay = false;
+                    }
+                    if let Some(answer) = tui::access(&command, emu, debugger) {
+                        tui::print(&answer);
                     }
                 }
             }
         }
 
-        match state {
-            // Only update the simulation when it is running
-            AppState::Running => {
-                timer_delta += elapsed;
-                cpu_delta += elapsed;
-                vblank_delta += elapsed;
+        if state != AppState::Running {
+            if let Some(request) = gdb_continue.take() {
+                request.reply(rsp::STOPPED);
+            }
+        }
 
-                // vblank signal - just one trigger is enough
-                if vblank_delta >= VBLANK_DELAY {
-                    emu.vblank();
-                    vblank_delta -= VBLANK_DELAY;
+        // process input events
+        for event in waited.into_iter().chain(event_pump.poll_iter()) {
+            let keymap = active_keymap(state, debugger, &chat);
+
+            // clicks on the keypad overlay or the debugger are not game keys
+            let clicked = match event {
+                Event::MouseButtonDown {
+                    mouse_btn: MouseButton::Left,
+                    x,
+                    y,
+                    ..
+                } => {
+                    (keypad_visible && keypad_key_at(x, y).is_some())
+                        || (debugger.visible && debugger_line_at(x, y).is_some())
+                }
+                Event::MouseButtonUp {
+                    mouse_btn: MouseButton::Left,
+                    ..
+                } => mouse_key.is_some(),
+                _ => false,
+            };
+
+            // the arrows are only remapped while playing
+            let action = remap
+                .as_ref()
+                .filter(|_| matches!(keymap, Keymap::Chip8))
+                .and_then(|remap| translate_remap(remap, &event))
+                .or_else(|| {
+                    // the keys the rom asks for; like the keypad, only
+                    // releases while stopped
+                    translate_bindings(&bindings, &event).filter(|action| {
+                        matches!(keymap, Keymap::Chip8)
+                            || matches!(action, Action::EmulateKeyState(_, false))
+                    })
+                })
+                .or_else(|| {
+                    (emu.quirks.variant == Variant::Chip8X)
+                        .then(|| keymap.translate_keypad2(&event))
+                        .flatten()
+                })
+                .or_else(|| keymap.translate_action(&event))
+                .or_else(|| {
+                    // like the keyboard, only releases while stopped
+                    mouse_keys
+                        .translate(&event, clock.now())
+                        .filter(|action| {
+                            matches!(keymap, Keymap::Chip8 | Keymap::Debugger { paused: false })
+                                || matches!(action, Action::EmulateKeyState(_, false))
+                        })
+                        .filter(|_| !clicked)
+                });
+
+            // the keys of one player press the ones of the other, if swapped
+            let action = match action {
+                Some(Action::EmulateKeyState(key, state)) => {
+                    Some(Action::EmulateKeyState(players.key(key), state))
+                }
+                action => action,
+            };
+
+            match action {
+                Some(Action::EmulateKeyState(key, state)) if turbo.is_turbo(key) => {
+                    turbo.set_held(key, state, clock.now());
+                }
+                Some(Action::EmulateKeyState(key, state)) => {
+                    // key repeats do not click again
+                    if state && options.keypad_click && !emu.is_key_pressed(key) {
+                        speaker.mixer().keypad.source.trigger();
+                    }
+                    worker.set_key(key, state);
+                }
+                Some(Action::EmulateKey2State(key, state)) => worker.set_key2(key, state),
+                Some(Action::FastForward(held)) => *fast_forward = held,
+                Some(Action::Quit) => state = AppState::Quit,
+                Some(Action::ScaleUp) => rescale_window(canvas.window_mut(), true)
+                    .context("error resizing the window")?,
+                Some(Action::ScaleDown) => rescale_window(canvas.window_mut(), false)
+                    .context("error resizing the window")?,
+                Some(Action::TogglePause) => {
+                    state = match state {
+                        AppState::Running => {
+                            pause_overlay = true;
+                            AppState::Paused
+                        }
+                        AppState::Paused => {
+                            debugger.resume();
+                            AppState::Running
+                        }
+                        // a crashed rom can only be reset
+                        other => other,
+                    }
+                }
+                // both games must stay the same
+                Some(Action::Reset | Action::RetryQuirks | Action::OpenSwitcher) if netplay_on => {
+                    osd.show("Not available during netplay".to_owned(), clock.now());
+                }
+                Some(Action::FrameAdvance) => step_frame = state == AppState::Paused,
+                Some(Action::Reset) => {
+                    emu.reset();
+                    if state == AppState::Crashed {
+                        state = AppState::Running;
+                    }
                 }
+                Some(Action::RetryQuirks) => {
+                    if let Some(quirks) = diagnosis.as_ref().and_then(|found| found.retry) {
+                        diagnosis = None;
+                        emu.quirks = quirks;
+                        emu.reset();
+                        if state == AppState::Crashed {
+                            state = AppState::Running;
+                        }
 
-                // run cpu
-                while cpu_delta >= CYCLE_DELAY {
-                    emu.execute()?;
-                    cpu_delta -= CYCLE_DELAY;
+                        osd.show("Retrying with the suggested quirks".to_owned(), clock.now());
+                    }
+                }
+                Some(Action::ToggleRemap) => {
+                    if let Some(active) = remap.take() {
+                        osd.show("Arrows and space back to normal", clock.now());
+                        remap_offer = Some(active);
+                    } else if let Some(offer) = remap_offer.take() {
+                        osd.show(format!("Playing with {} - P: pause", offer), clock.now());
+                        remap = Some(offer);
+                    }
+                }
+                Some(Action::ToggleKeypad) => keypad_visible = !keypad_visible,
+                Some(Action::SwapPlayers) => {
+                    // held keys would never be released otherwise
+                    for key in players.keys() {
+                        turbo.set_held(key, false, clock.now());
+                        worker.set_key(key, false);
+                    }
+                    let message = match players.swap() {
+                        true => "Players swapped",
+                        false => "Players back to normal",
+                    };
+                    osd.show(message.to_owned(), clock.now());
+                }
+                Some(action @ (Action::NetplayDelayUp | Action::NetplayDelayDown)) => {
+                    if let Some(netplay) = netplay.as_mut().filter(|netplay| !netplay.watching()) {
+                        let delay = match action {
+                            Action::NetplayDelayUp => netplay.delay() + 1,
+                            _ => netplay.delay() - 1,
+                        };
+                        let delay = netplay.set_delay(delay);
+                        osd.show(format!("Input delay: {} frames", delay), clock.now());
+                    }
+                }
+                // spectators only watch
+                Some(Action::OpenChat) => {
+                    if netplay.as_ref().is_some_and(|netplay| !netplay.watching()) {
+                        chat.open();
+                        sdl_video.text_input().start();
+                    }
+                }
+                Some(Action::ChatText(text)) => chat.type_text(&text),
+                Some(Action::ChatErase) => chat.erase(),
+                Some(Action::ChatSend) => {
+                    if let (Some(text), Some(netplay)) = (chat.send(), netplay.as_mut()) {
+                        netplay.say(&text);
+                        chat.push(format!("you: {}", text), clock.now());
+                    }
+                    sdl_video.text_input().stop();
+                }
+                Some(Action::ChatClose) => {
+                    chat.cancel();
+                    sdl_video.text_input().stop();
+                }
+                Some(Action::ToggleDebugger) => {
+                    debugger.visible = !debugger.visible;
+                }
+                Some(Action::DebugUp) => debugger.move_cursor(emu, true),
+                Some(Action::DebugDown) => debugger.move_cursor(emu, false),
+                Some(Action::ToggleBreakpoint) => {
+                    debugger.toggle_breakpoint(debugger.cursor(emu));
+                }
+                Some(
+                    action @ (Action::CycleWaveform
+                    | Action::PitchDown
+                    | Action::PitchUp
+                    | Action::VolumeDown
+                    | Action::VolumeUp
+                    | Action::ToggleMute),
+                ) => {
+                    if let Some(message) = adjust_sound(&mut speaker.mixer(), action) {
+                        osd.show(message, clock.now());
+                    }
                 }
+                Some(Action::ToggleCrt) => {
+                    crt_effect = !crt_effect;
+                    emulator_texture = None;
+                    osd.show(
+                        if crt_effect { "CRT on" } else { "CRT off" }.to_owned(),
+                        clock.now(),
+                    );
+                }
+                Some(Action::ToggleCapture) => {
+                    let message = match capture.take() {
+                        Some(video) => {
+                            let message = format!("Saving {}", video.path().display());
+                            capturing.push(video.stop());
+                            message
+                        }
+                        None => {
+                            let sample_rate = speaker.mixer().sample_rate() as u32;
+                            match Capture::start(
+                                options.capture_format,
+                                emulator_surface.width(),
+                                emulator_surface.height(),
+                                sample_rate,
+                                clock.now(),
+                            ) {
+                                Ok(video) => {
+                                    speaker.mixer().taps.push(video.audio());
+                                    capture = Some(video);
+                                    "Recording video".to_owned()
+                                }
+                                Err(err) => {
+                                    warn!("could not start ffmpeg: {}", err);
+                                    "Could not start ffmpeg".to_owned()
+                                }
+                            }
+                        }
+                    };
+                    osd.show(message, clock.now());
+                }
+                Some(Action::ExportTrace) => export_trace(&tracer),
+                Some(Action::TogglePerf) => {
+                    perf_visible = !perf_visible;
+                    perf_texture = None;
+                }
+                Some(Action::OpenSwitcher) => {
+                    if !recent.entries().is_empty() {
+                        // the current rom is usually the first one, so
+                        // start on the one played before it
+                        switcher_selected = 1.min(recent.entries().len() - 1);
+                        switcher_resume = state;
+                        switcher_texture = None;
+                        state = AppState::Switching;
+                    }
+                }
+                Some(Action::MenuUp) => {
+                    switcher_selected = switcher_selected.saturating_sub(1);
+                    switcher_texture = None;
+                }
+                Some(Action::MenuDown) => {
+                    switcher_selected = (switcher_selected + 1).min(recent.entries().len() - 1);
+                    switcher_texture = None;
+                }
+                Some(Action::MenuSelect) => {
+                    let path = recent.entries()[switcher_selected].clone();
+                    match load_rom(&RomSource::File(path.clone()), &options) {
+                        Ok((new_emu, metadata)) => {
+                            *emu = new_emu;
+                            saved_flags = emu.flags;
+                            if profile.is_some() {
+                                *profile = Some(Profile::new(emu));
+                            }
+                            debugger.clear();
+                            diagnosis = None;
+                            suggested = false;
+                            *timing = new_timing(&options, &metadata);
+                            colors = rom_colors(&options, &metadata);
+                            canvas
+                                .window_mut()
+                                .set_title(&metadata.window_title(&path, emu.quirks.variant))
+                                .context("error setting the window title")?;
+                            *screen = Screen::new(options.filter);
+                            emulator_texture = None;
+
+                            remap = None;
+                            bindings = metadata.bindings();
+                            controls = metadata.controls();
+                            keypad_texture = None;
+                            remap_offer = offer_remap(emu, &bindings);
+                            if let Some(message) = rom_message(&metadata, remap_offer.as_ref()) {
+                                osd.show(message, clock.now());
+                            }
 
-                // update timers
-                while timer_delta >= TIMER_DELAY {
-                    emu.decrease_timers();
-                    timer_delta -= TIMER_DELAY;
+                            recent.push(&path);
+                            if let Err(err) = recent.save() {
+                                warn!("could not save the recent roms list: {}", err);
+                            }
+
+                            state = AppState::Running;
+                        }
+                        Err(err) => {
+                            error!("{:#}", err);
+                            state = switcher_resume;
+                        }
+                    }
                 }
+                Some(Action::MenuClose) => {
+                    state = switcher_resume;
+                }
+                None => match event {
+                    Event::Quit { .. } => state = AppState::Quit,
+
+                    Event::AudioDeviceAdded { .. } | Event::AudioDeviceRemoved { .. } => {
+                        speaker.device_event(&event)
+                    }
+
+                    // the screen is fit again on the new size
+                    Event::Window {
+                        win_event: WindowEvent::Resized(..) | WindowEvent::SizeChanged(..),
+                        ..
+                    } => apply_scaling(&mut canvas, options.scaling)
+                        .context("error scaling the screen")?,
+
+                    // the keypad overlay can also be played with the mouse
+                    Event::MouseButtonDown {
+                        mouse_btn: MouseButton::Left,
+                        x,
+                        y,
+                        ..
+                    } if state != AppState::Switching => {
+                        if let Some(key) = keypad_key_at(x, y).filter(|_| keypad_visible) {
+                            if options.keypad_click {
+                                speaker.mixer().keypad.source.trigger();
+                            }
+                            worker.set_key(key, true);
+                            mouse_key = Some(key);
+                        } else if let Some(line) =
+                            debugger_line_at(x, y).filter(|_| debugger.visible)
+                        {
+                            // clicking a line toggles its breakpoint
+                            if let Some(line) = debugger_lines.get(line) {
+                                debugger.toggle_breakpoint(line.address);
+                            }
+                        }
+                    }
+                    Event::MouseButtonUp {
+                        mouse_btn: MouseButton::Left,
+                        ..
+                    } => {
+                        if let Some(key) = mouse_key.take() {
+                            worker.set_key(key, false);
+                        }
+                    }
+                    _ => {}
+                },
+            }
+        }
+
+        // held turbo keys go up and down on their own
+        for (key, pressed) in turbo.update(clock.now()) {
+            worker.set_key(key, pressed);
+        }
+        for key in mouse_keys.update(clock.now()) {
+            worker.set_key(players.key(key), false);
+        }
 
-                // on COSMAC VIP, the sound is not played if ST is less than 2
-                // this is a hardware quirk.
-                if emu.ST > 1 {
-                    audio_device.resume()
-                } else {
-                    audio_device.pause()
+        match state {
+            // Only update the simulation when it is running
+            AppState::Running => {
+                // point out odd behavior, but just once per rom
+                if !suggested {
+                    if let Some(found) = diagnose::running(emu) {
+                        osd.show(format!("{} - F2: retry", found.message), clock.now());
+                        diagnosis = Some(found);
+                        suggested = true;
+                    }
                 }
             }
 
             // do nothing if paused, except stopping the buzzer
             // it will be resumed in the running logic, if needed
             AppState::Paused => {
-                audio_device.pause();
+                speaker.set_buzzer(false);
+
+                // keep the keypad overlay up to date
+                keypad.poll(emu);
+
+                // frame advance: run exactly one frame worth of emulation
+                if step_frame {
+                    if let Err(err) = run_frame(emu) {
+                        diagnosis = diagnose::crash(emu, &err);
+                        emu_error = Some(err);
+                        error_texture = None;
+                        state = AppState::Crashed;
+                    }
+
+                    screen.update(emu);
+                    if let Some(recorder) = recorder {
+                        recorder.record(emu);
+                    }
+
+                    // hide the pause overlay, so the new frame can be seen
+                    step_frame = false;
+                    pause_overlay = false;
+                }
             }
 
+            // the emulator also stands still while picking another
+            // rom, or after crashing
+            AppState::Switching | AppState::Crashed => speaker.set_buzzer(false),
+
             // signal to get out of the routine
             AppState::Quit => break,
         }
 
+        // copy the screen, if any of its rows changed...
+        let mut rows = emu.dirty_rows();
+        if screen.changed || emulator_texture.is_none() {
+            rows = (0..emu.display().height()).collect();
+        }
+        screen.changed = false;
+        if !rows.is_empty() {
+            display.clone_from(emu.display());
+            color_board = emu.color_board().cloned();
+            persistence.clone_from(&screen.persistence);
+        }
+
+        // ...and everything the overlays show
+        let debugger_visible = debugger.visible;
+        let mut lines = Vec::new();
+        if debugger_visible {
+            lines = debugger.lines(emu);
+            if let Some(profile) = profile {
+                for line in &mut lines {
+                    line.heat = profile.heat(line.address);
+                }
+            }
+        }
+        let pressed = emu.pressed_keys();
+        let instructions = std::mem::take(instructions);
+        let quality = netplay.as_ref().map(Netplay::quality);
+        for line in netplay.iter_mut().flat_map(Netplay::heard) {
+            chat.push(line, clock.now());
+        }
+
+        // the emulation goes on while the frame is drawn
+        *running = state == AppState::Running;
+        drop(machine);
+
         // draw a frame - this will always happens, regardless of the simulation state
-        // first, we cache the screen state
-        if emu.screen_changed() || emulator_texture.is_none() {
-            let texture = draw_emulator_screen(&emu, bgcolor, fgcolor, &texture_creator)
-                .context("error computing emulator state")?;
-            emulator_texture = Some(texture);
+        let _render_span = tracer.span("render", MAIN_THREAD);
+        redrawn = false;
+
+        // first, we cache the screen state, drawing only the rows that changed
+        if let (Some(&first), Some(&last)) = (rows.first(), rows.last()) {
+            draw_emulator_rows(
+                &mut emulator_surface,
+                &display,
+                color_board.as_ref(),
+                &rows,
+                persistence.as_ref(),
+                &colors,
+                options.pixel_shape,
+            )
+            .context("error computing emulator state")?;
+
+            let texture = match &mut emulator_texture {
+                Some(texture) => texture,
+                None => {
+                    let mut texture = texture_creator
+                        .create_texture_streaming(
+                            PixelFormatEnum::RGBA8888,
+                            emulator_surface.width(),
+                            emulator_surface.height(),
+                        )
+                        .map_err(AppError::from)?;
+                    texture.set_blend_mode(BlendMode::Blend);
+                    emulator_texture.insert(texture)
+                }
+            };
+            let size = emulator_surface.height() as usize / display.height();
+            update_emulator_texture(
+                texture,
+                &emulator_surface,
+                first * size..(last + 1) * size,
+                crt_effect,
+            )
+            .context("error computing emulator state")?;
+            redrawn = true;
+
+            if let Some(stream) = &stream {
+                let rgb = |color: Color| [color.r, color.g, color.b];
+                stream.publish(&display, colors.map(rgb));
+            }
+        }
+
+        // the video keeps going while nothing changes on the screen
+        if let Some(video) = &mut capture {
+            if let Err(err) = video.frame(&emulator_surface, clock.now()) {
+                warn!("the video capture stopped: {}", err);
+                osd.show("Video capture failed".to_owned(), clock.now());
+                capturing.extend(capture.take().map(Capture::stop));
+            }
         }
 
         // then, we do the real drawing
         {
+            canvas.set_draw_color(letterbox);
+            canvas.clear();
+
             let texture = emulator_texture.as_ref().unwrap();
             canvas
                 .copy(texture, None, None)
@@ -235,12 +1108,12 @@ pub fn run(mut emu: Emulator, options: Options) -> Result<(), anyhow::Error> {
         }
 
         // when paused, we add an extra overlay
-        if state == AppState::Paused {
+        if state == AppState::Paused && pause_overlay && !debugger_visible {
             if pause_texture.is_none() {
-                let texture = draw_pause_screen(&font, &texture_creator)
-                    .map_err(AppError::from)
+                let texture = draw_pause_screen(&font, &small_font, &texture_creator)
                     .context("error creating pause screen")?;
                 pause_texture = Some(texture);
+                redrawn = true;
             }
 
             let texture = pause_texture.as_ref().unwrap();
@@ -251,84 +1124,1273 @@ pub fn run(mut emu: Emulator, options: Options) -> Result<(), anyhow::Error> {
                 .context("error drawing pause screen")?;
         }
 
-        // update the screen
-        canvas.present();
-    }
+        // a crashed rom shows what went wrong
+        if state == AppState::Crashed {
+            if error_texture.is_none() {
+                let texture = draw_error_screen(
+                    &font,
+                    &small_font,
+                    emu_error.as_ref(),
+                    diagnosis.as_ref(),
+                    &texture_creator,
+                )
+                .context("error creating error screen")?;
+                error_texture = Some(texture);
+                redrawn = true;
+            }
 
-    // pause_texture = None;
-    audio_device.pause();
-    Ok(())
-}
+            let texture = error_texture.as_ref().unwrap();
 
-fn draw_emulator_screen<'a, T>(
-    emu: &Emulator,
-    bgcolor: Color,
-    fgcolor: Color,
-    texture_creator: &'a TextureCreator<T>,
-) -> Result<Texture<'a>, AppError> {
-    // create the screen surface
-    let mut surface = Surface::new(
-        (DISPLAY_WIDTH * PIXEL_SIZE) as u32,
-        (DISPLAY_HEIGHT * PIXEL_SIZE) as u32,
-        PixelFormatEnum::RGBA8888,
-    )?;
+            canvas
+                .copy(texture, None, None)
+                .map_err(AppError::from)
+                .context("error drawing error screen")?;
+        }
+
+        // when stepping frames, just a small indicator
+        if state == AppState::Paused && !pause_overlay && !debugger_visible {
+            if step_texture.is_none() {
+                let texture = draw_step_screen(&small_font, &texture_creator)
+                    .context("error creating frame step screen")?;
+                step_texture = Some(texture);
+                redrawn = true;
+            }
 
-    // clear the background
-    surface.fill_rect(None, bgcolor)?;
+            let texture = step_texture.as_ref().unwrap();
 
-    // draw the squares
-    for x in 0..DISPLAY_WIDTH {
-        for y in 0..DISPLAY_HEIGHT {
-            if emu.get_pixel(x, y) {
-                let rect = Rect::new(
-                    (x * PIXEL_SIZE) as i32,
-                    (y * PIXEL_SIZE) as i32,
-                    PIXEL_SIZE as u32,
-                    PIXEL_SIZE as u32,
-                );
-                surface.fill_rect(rect, fgcolor)?;
+            canvas
+                .copy(texture, None, None)
+                .map_err(AppError::from)
+                .context("error drawing frame step screen")?;
+        }
+
+        // the disassembly view follows the execution
+        if debugger_visible {
+            if debugger_texture.is_none() || lines != debugger_lines {
+                let texture = draw_debugger_screen(&small_font, &lines, &texture_creator)
+                    .context("error creating disassembly screen")?;
+                debugger_texture = Some(texture);
+                redrawn = true;
+                debugger_lines = lines;
             }
+
+            let texture = debugger_texture.as_ref().unwrap();
+
+            canvas
+                .copy(texture, None, None)
+                .map_err(AppError::from)
+                .context("error drawing disassembly screen")?;
         }
-    }
 
-    Ok(texture_creator.create_texture_from_surface(surface)?)
-}
+        // short messages, that go away by themselves
+        if let Some((toast, alpha)) = osd.visible(clock.now()) {
+            if osd_texture.is_none() || osd_drawn != toast.id {
+                let texture = draw_osd_screen(&small_font, &toast.text, &texture_creator)
+                    .context("error creating osd screen")?;
+                osd_texture = Some(texture);
+                osd_drawn = toast.id;
+            }
 
-fn draw_pause_screen<'a, T>(
-    font: &Font,
-    texture_creator: &'a TextureCreator<T>,
-) -> Result<Texture<'a>, AppError> {
-    const TEXT: &str = "-- PAUSE --";
-    const BG_COLOR: Color = Color::RGBA(0x80, 0x80, 0x80, 240);
-    const FG_COLOR: Color = Color::BLACK;
+            let texture = osd_texture.as_mut().unwrap();
+            texture.set_blend_mode(BlendMode::Blend);
+            texture.set_alpha_mod((alpha * 255.0) as u8);
+            redrawn = true;
 
-    // create the text surface and compute the rect size for
-    // the center of the screen
-    let text = font.render(TEXT).solid(FG_COLOR)?;
-    let text_rect = {
-        let (w, h) = font.size_of(TEXT)?;
-        let center_w = DISPLAY_WIDTH * PIXEL_SIZE / 2;
-        let center_h = DISPLAY_HEIGHT * PIXEL_SIZE / 2;
-        let x = (center_w as u32) - (w / 2);
-        let y = (center_h as u32) - (h / 2);
-
-        Rect::new(x as i32, y as i32, w, h)
-    };
+            canvas
+                .copy(texture, None, None)
+                .map_err(AppError::from)
+                .context("error drawing osd screen")?;
+        }
 
-    // create a surface to paint the screen
-    let mut surface = Surface::new(
-        (DISPLAY_WIDTH * PIXEL_SIZE) as u32,
-        (DISPLAY_HEIGHT * PIXEL_SIZE) as u32,
-        PixelFormatEnum::RGBA8888,
-    )?;
-    surface.set_blend_mode(BlendMode::Blend)?;
+        // frame pacing numbers, refreshed a couple of times per second
+        if perf.frame(elapsed, instructions, now) {
+            perf_texture = None;
+        }
 
-    // semi-transparent background
-    surface.fill_rect(None, BG_COLOR)?;
+        if perf_visible {
+            if perf_texture.is_none() {
+                let texture = draw_perf_screen(&small_font, &perf.lines(), false, &texture_creator)
+                    .context("error creating performance screen")?;
+                perf_texture = Some(texture);
+                redrawn = true;
+            }
 
-    // text
-    text.blit(None, &mut surface, text_rect)?;
+            let texture = perf_texture.as_ref().unwrap();
 
-    // return the texture
-    Ok(texture_creator.create_texture_from_surface(&surface)?)
+            canvas
+                .copy(texture, None, None)
+                .map_err(AppError::from)
+                .context("error drawing performance screen")?;
+        }
+
+        // how the netplay connection is doing, while there is one
+        if quality != quality_drawn {
+            quality_texture = match &quality {
+                Some(quality) => Some(
+                    draw_perf_screen(&small_font, &quality.lines(), true, &texture_creator)
+                        .context("error creating netplay screen")?,
+                ),
+                None => None,
+            };
+            quality_drawn = quality;
+            redrawn = true;
+        }
+
+        if let Some(texture) = &quality_texture {
+            canvas
+                .copy(texture, None, None)
+                .map_err(AppError::from)
+                .context("error drawing netplay screen")?;
+        }
+
+        // the netplay chat, at the bottom
+        let chat_lines = chat.visible(clock.now());
+        if chat_lines != chat_drawn {
+            chat_texture = match chat_lines.is_empty() {
+                true => None,
+                false => Some(
+                    draw_chat_screen(&small_font, &chat_lines, &texture_creator)
+                        .context("error creating chat screen")?,
+                ),
+            };
+            chat_drawn = chat_lines;
+            redrawn = true;
+        }
+
+        if let Some(texture) = &chat_texture {
+            canvas
+                .copy(texture, None, None)
+                .map_err(AppError::from)
+                .context("error drawing chat screen")?;
+        }
+
+        // the keypad, with the pressed keys highlighted
+        if keypad_visible {
+            if keypad_texture.is_none() || pressed != keypad_pressed {
+                let texture = draw_keypad_screen(
+                    &small_font,
+                    pressed,
+                    &bindings,
+                    &controls,
+                    &texture_creator,
+                )
+                .context("error creating keypad screen")?;
+                keypad_texture = Some(texture);
+                redrawn = true;
+                keypad_pressed = pressed;
+            }
+
+            let texture = keypad_texture.as_ref().unwrap();
+
+            canvas
+                .copy(texture, None, None)
+                .map_err(AppError::from)
+                .context("error drawing keypad screen")?;
+        }
+
+        // the rom switcher goes on top of everything
+        if state == AppState::Switching {
+            if switcher_texture.is_none() {
+                let texture = draw_switcher_screen(
+                    &small_font,
+                    recent.entries(),
+                    switcher_selected,
+                    &texture_creator,
+                )
+                .context("error creating rom switcher screen")?;
+                switcher_texture = Some(texture);
+                redrawn = true;
+            }
+
+            let texture = switcher_texture.as_ref().unwrap();
+
+            canvas
+                .copy(texture, None, None)
+                .map_err(AppError::from)
+                .context("error drawing rom switcher screen")?;
+        }
+
+        // update the screen
+        canvas.present();
+    }
+
+    // pause_texture = None;
+    speaker.set_buzzer(false);
+
+    if let Some(path) = &options.profile {
+        let machine = worker.lock();
+        if let Some(profile) = &machine.profile {
+            std::fs::write(path, profile.report(&machine.emu))
+                .with_context(|| format!("error saving the profile: {}", path.display()))?;
+            eprintln!("profile saved to {}", path.display());
+        }
+    }
+
+    if let (Some(recorder), Some(path)) = (worker.stop(), &options.record) {
+        recorder
+            .save(path)
+            .with_context(|| format!("error saving recording: {}", path.display()))?;
+        eprintln!(
+            "{} frames recorded to {}",
+            recorder.frames(),
+            path.display()
+        );
+    }
+
+    // videos are only done once ffmpeg finishes them
+    capturing.extend(capture.take().map(Capture::stop));
+    for video in capturing {
+        match video.join() {
+            Ok(Ok(path)) => eprintln!("video saved to {}", path.display()),
+            Ok(Err(err)) => warn!("could not save the video: {}", err),
+            Err(_) => warn!("could not save the video"),
+        }
+    }
+
+    if let (Some(wav), Some(path)) = (wav, &options.record_audio) {
+        let seconds = wav
+            .finish()
+            .with_context(|| format!("error saving the audio recording: {}", path.display()))?;
+        eprintln!(
+            "{:.1} seconds of audio recorded to {}",
+            seconds,
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+/// Names of the audio output devices, for `--audio-device`
+pub fn list_audio_devices() -> Result<(), anyhow::Error> {
+    let sdl_audio = sdl2::init()
+        .and_then(|sdl_context| sdl_context.audio())
+        .map_err(AppError::from)
+        .context("failed to initialize audio subsystem")?;
+
+    let count = sdl_audio.num_audio_playback_devices().unwrap_or_default();
+    for index in 0..count {
+        match sdl_audio.audio_playback_device_name(index) {
+            Ok(name) => println!("{}", name),
+            Err(err) => warn!("could not get the name of audio device {}: {}", index, err),
+        }
+    }
+    Ok(())
+}
+
+/// Open the audio device called `name`, or the default one, playing `mixer`
+fn open_audio(
+    audio: &AudioSubsystem,
+    name: Option<&str>,
+    samples: Option<u16>,
+    mut mixer: Mixer,
+) -> Result<AudioDevice<Mixer>, String> {
+    let desired_spec = AudioSpecDesired {
+        freq: Some(SAMPLE_RATE),
+        channels: Some(1),
+        samples,
+    };
+    let device = audio.open_playback(name, &desired_spec, |spec| {
+        mixer.set_sample_rate(spec.freq as f32);
+        mixer
+    })?;
+
+    let spec = device.spec();
+    // what the buffer adds to the delay between a sound and the screen
+    info!(
+        "audio device opened: {}, {} Hz, {} channel(s), {} samples ({:.1} ms)",
+        name.unwrap_or("default"),
+        spec.freq,
+        spec.channels,
+        spec.samples,
+        spec.samples as f32 * 1000.0 / spec.freq as f32
+    );
+    if let Some(samples) = samples.filter(|&s| s != spec.samples) {
+        warn!(
+            "asked for a buffer of {} samples, but the device uses {}",
+            samples, spec.samples
+        );
+    }
+
+    // the device is always running, as the keypad clicks can play
+    // while the buzzer is off
+    device.resume();
+    Ok(device)
+}
+
+/// Plays the buzzer on the SDL audio device, opening it again when it is
+/// unplugged, or when the one asked for is plugged back in
+struct Speaker {
+    audio: AudioSubsystem,
+    device: AudioDevice<Mixer>,
+    // the device asked for, and if the default one plays while it is away
+    name: Option<String>,
+    fallback: bool,
+    samples: Option<u16>,
+}
+
+impl Speaker {
+    fn new(audio: AudioSubsystem, options: &Options, mixer: Mixer) -> Result<Self, String> {
+        let name = options.audio_device.clone();
+        let (device, fallback) =
+            open_speaker(&audio, name.as_deref(), options.audio_buffer, mixer)?;
+        Ok(Speaker {
+            audio,
+            device,
+            name,
+            fallback,
+            samples: options.audio_buffer,
+        })
+    }
+
+    fn mixer(&mut self) -> AudioDeviceLockGuard<'_, Mixer> {
+        self.device.lock()
+    }
+
+    /// Follow the devices being plugged and unplugged
+    fn device_event(&mut self, event: &Event) {
+        let reopen = match *event {
+            // a lost device stops, and stays silent until opened again
+            Event::AudioDeviceRemoved {
+                iscapture: false, ..
+            } => self.device.status() == AudioStatus::Stopped,
+            Event::AudioDeviceAdded {
+                iscapture: false,
+                which,
+                ..
+            } => self.fallback && self.audio.audio_playback_device_name(which).ok() == self.name,
+            _ => false,
+        };
+        if !reopen {
+            return;
+        }
+
+        let mixer = self.mixer().clone();
+        match open_speaker(&self.audio, self.name.as_deref(), self.samples, mixer) {
+            Ok((device, fallback)) => {
+                self.device = device;
+                self.fallback = fallback;
+            }
+            Err(err) => warn!("could not open the audio device again: {}", err),
+        }
+    }
+}
+
+/// Open the device called `name`, falling back to the default one if it
+/// cannot be opened (like unplugged headphones); tells if it fell back
+fn open_speaker(
+    audio: &AudioSubsystem,
+    name: Option<&str>,
+    samples: Option<u16>,
+    mixer: Mixer,
+) -> Result<(AudioDevice<Mixer>, bool), String> {
+    let Some(name) = name else {
+        return Ok((open_audio(audio, None, samples, mixer)?, false));
+    };
+
+    match open_audio(audio, Some(name), samples, mixer.clone()) {
+        Ok(device) => Ok((device, false)),
+        Err(err) => {
+            warn!(
+                "could not open the audio device {}: {}; using the default one",
+                name, err
+            );
+            Ok((open_audio(audio, None, samples, mixer)?, true))
+        }
+    }
+}
+
+impl AudioSink for Speaker {
+    fn set_buzzer(&mut self, on: bool) {
+        self.mixer().buzzer.source.playing = on;
+    }
+}
+
+/// Change the buzzer sound, returning a message describing the change
+fn adjust_sound(mixer: &mut Mixer, action: Action) -> Option<String> {
+    let beep = &mut mixer.buzzer.source;
+    let message = match action {
+        Action::CycleWaveform => {
+            beep.tone.waveform = beep.tone.waveform.next();
+            let waveform = beep.tone.waveform.to_possible_value();
+            format!(
+                "Waveform: {}",
+                waveform.map(|v| v.get_name()).unwrap_or("?")
+            )
+        }
+        Action::PitchDown | Action::PitchUp => {
+            beep.tone.shift_pitch(matches!(action, Action::PitchUp));
+            format!("Pitch: {:.0} Hz", beep.tone.pitch)
+        }
+        Action::VolumeDown | Action::VolumeUp => {
+            mixer
+                .buzzer
+                .shift_volume(matches!(action, Action::VolumeUp));
+            format!("Volume: {}", mixer.buzzer.volume)
+        }
+        Action::ToggleMute => {
+            mixer.muted = !mixer.muted;
+            if mixer.muted { "Sound off" } else { "Sound on" }.to_owned()
+        }
+        _ => return None,
+    };
+
+    Some(message)
+}
+
+/// Save the recent spans to a timestamped file on the current directory
+pub fn export_trace(tracer: &Tracer) {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let path = PathBuf::from(format!("rc8-trace-{}.json", timestamp));
+
+    match tracer.export(&path) {
+        Ok(()) => eprintln!("trace saved to {}", path.display()),
+        Err(err) => warn!("could not save the trace: {}", err),
+    }
+}
+
+/// The background color, and the color of each combination of planes
+fn rom_colors(options: &Options, metadata: &Metadata) -> [Color; 4] {
+    let mut palette = options.palette;
+    if !options.custom_palette {
+        metadata.apply_palette(&mut palette);
+    }
+
+    palette.colors.map(rgb_color)
+}
+
+/// An opaque color from 0xRRGGBB00
+fn rgb_color(rgb: u32) -> Color {
+    let [r, g, b, _] = rgb.to_be_bytes();
+    Color::RGBA(r, g, b, 0xff)
+}
+
+/// The cpu pacing, from the command line or the rom metadata
+fn new_timing(options: &Options, metadata: &Metadata) -> Timing {
+    if options.vip_timing {
+        let timing = Timing::vip();
+        match options.draw_budget {
+            Some(rows) => timing.with_draw_budget(rows),
+            None => timing,
+        }
+    } else {
+        Timing::new(options.speed.unwrap_or(metadata.speed()))
+    }
+}
+
+/// Build a new emulator for the rom from `source`, with the quirks
+/// from its metadata and the command line
+pub fn load_rom(
+    source: &RomSource,
+    options: &Options,
+) -> Result<(Emulator, Metadata), anyhow::Error> {
+    let rom = source.read()?;
+    // the size is checked below, after the quirks are set
+    let mut emu = Emulator::builder()
+        .truncate(true)
+        .load_rom(&rom[..])
+        .context("error loading rom")?;
+
+    // a broken sidecar should not stop the game from running
+    let path = source.name();
+    let mut metadata = source
+        .file()
+        .map_or_else(|| Ok(Metadata::default()), Metadata::load)
+        .unwrap_or_else(|err| {
+            warn!("{:#}", err);
+            Metadata::default()
+        });
+
+    // the sidecar wins over the database, as it was made for this very file
+    if !options.no_db {
+        match RomDb::load() {
+            Ok(db) => {
+                if let Some(known) = db.lookup(emu.rom()) {
+                    metadata = metadata.or(known);
+                }
+            }
+            Err(err) => warn!("{:#}", err),
+        }
+    }
+
+    // the rest of the settings go on top of the interpreter ones
+    if options.dream6800 {
+        emu.quirks = Quirks::dream6800();
+    }
+    metadata.apply(&mut emu.quirks);
+    if options.wrap {
+        emu.quirks.wrap_sprites = true;
+    }
+    if options.vip_machine_code {
+        emu.quirks.vip_machine_code = true;
+    }
+    if options.wait_key_press {
+        emu.quirks.wait_key_press = true;
+    }
+    if options.half_scroll {
+        emu.quirks.half_scroll = true;
+    }
+    if options.full_memory {
+        emu.quirks.full_memory = true;
+    }
+    if options.short_beeps {
+        emu.quirks.short_beeps = true;
+    }
+    if let Some(rng) = options.rng {
+        emu.quirks.rng = rng;
+    }
+    if let Some(variant) = options.variant {
+        emu.quirks.variant = variant;
+    }
+    if let Some(index) = options.index_mode {
+        emu.quirks.index = index;
+    }
+    if let Some(stack) = options.stack_depth {
+        emu.quirks.stack = stack;
+    }
+    if options.persist_flags {
+        if let Some(saved) = flags::load(emu.rom()) {
+            emu.flags = saved;
+        }
+    }
+
+    // some quirks only take effect on reset
+    emu.reset();
+    let left_out = rom.len() - (emu.rom().len() - emu.truncated());
+    if left_out > 0 && options.force {
+        warn!(
+            "the rom is {} bytes too big to fit in memory, running it without them",
+            left_out
+        );
+    } else if left_out > 0 {
+        anyhow::bail!(
+            "the rom is {} bytes too big to fit in memory; use --force to run it anyway (SCHIP and XO-CHIP roms can use all of it with --full-memory)",
+            left_out
+        );
+    }
+    info!(
+        "loaded {} ({} bytes): {}",
+        path.display(),
+        emu.rom().len(),
+        metadata.window_title(&path, emu.quirks.variant)
+    );
+    debug!("quirks: {:?}", emu.quirks);
+
+    Ok((emu, metadata))
+}
+
+/// Which CHIP-8 key (if any) of the keypad overlay is at the given position
+fn keypad_key_at(x: i32, y: i32) -> Option<usize> {
+    let col = (x - KEYPAD_LEFT as i32).div_euclid(KEYPAD_CELL as i32);
+    let row = (y - KEYPAD_TOP as i32).div_euclid(KEYPAD_CELL as i32);
+
+    if !(0..4).contains(&col) || !(0..4).contains(&row) {
+        return None;
+    }
+
+    Some(KEYPAD[row as usize][col as usize].0)
+}
+
+/// Which line (if any) of the disassembly view is at the given position
+fn debugger_line_at(x: i32, y: i32) -> Option<usize> {
+    if x < 0 || x >= DEBUGGER_WIDTH as i32 || y < DEBUGGER_TOP as i32 {
+        return None;
+    }
+
+    let line = (y as u32 - DEBUGGER_TOP) / DEBUGGER_LINE;
+    (line < debugger::LINES as u32).then_some(line as usize)
+}
+
+/// The arrows and space mapping to offer, unless the rom has keys of its own
+fn offer_remap(emu: &Emulator, bindings: &[(Keycode, usize)]) -> Option<Remap> {
+    match bindings.is_empty() {
+        true => Remap::suggest(emu.rom(), REMAP_FRAMES),
+        false => None,
+    }
+}
+
+/// Offer to play with the arrows and space
+fn remap_message(remap: &Remap) -> String {
+    format!("F4: play with {}", remap)
+}
+
+/// What is shown when a rom starts: who made it, and the remap offer
+fn rom_message(metadata: &Metadata, remap: Option<&Remap>) -> Option<String> {
+    match (metadata.credits(), remap.map(remap_message)) {
+        (Some(credits), Some(offer)) => Some(format!("{} - {}", credits, offer)),
+        (credits, offer) => credits.or(offer),
+    }
+}
+
+/// The key bindings for the app state, and the disassembly view being open
+fn active_keymap(state: AppState, debugger: &Debugger, chat: &Chat) -> Keymap {
+    let paused = state != AppState::Running;
+    match state {
+        AppState::Switching => Keymap::Menu,
+        _ if chat.is_typing() => Keymap::Chat,
+        _ if debugger.visible => Keymap::Debugger { paused },
+        AppState::Running => Keymap::Chip8,
+        _ => Keymap::Paused,
+    }
+}
+
+/// Map the logical screen on the window, as asked by `--scaling`
+fn apply_scaling(canvas: &mut WindowCanvas, scaling: Scaling) -> Result<(), AppError> {
+    let (width, height) = (
+        (DISPLAY_WIDTH * PIXEL_SIZE) as u32,
+        (DISPLAY_HEIGHT * PIXEL_SIZE) as u32,
+    );
+
+    match scaling {
+        Scaling::Integer | Scaling::Fit => {
+            canvas.set_logical_size(width, height)?;
+            canvas.set_integer_scale(scaling == Scaling::Integer)?;
+        }
+        // there is no logical size that stretches, so scale by hand
+        Scaling::Stretch => {
+            let (window_width, window_height) = canvas.output_size()?;
+            canvas.set_scale(
+                window_width as f32 / width as f32,
+                window_height as f32 / height as f32,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Resize the window to the next (or previous) integer multiple of the display size
+fn rescale_window(window: &mut Window, grow: bool) -> Result<(), AppError> {
+    // a fullscreen window already uses all the space it can
+    if window.fullscreen_state() != FullscreenType::Off {
+        return Ok(());
+    }
+
+    let (width, height) = window.size();
+    let current = (width / DISPLAY_WIDTH as u32).min(height / DISPLAY_HEIGHT as u32);
+    let exact =
+        width == current * DISPLAY_WIDTH as u32 && height == current * DISPLAY_HEIGHT as u32;
+
+    // when the window is not on an exact scale, shrinking
+    // just snaps it to the closest smaller one
+    let scale = match (grow, exact) {
+        (true, _) => current + 1,
+        (false, true) => current.saturating_sub(1),
+        (false, false) => current,
+    };
+
+    // do not go past the limits of the screen the window is on
+    let mode = window
+        .subsystem()
+        .desktop_display_mode(window.display_index()?)?;
+    let max_scale =
+        (mode.w as u32 / DISPLAY_WIDTH as u32).min(mode.h as u32 / DISPLAY_HEIGHT as u32);
+
+    if scale < MIN_SCALE || scale > max_scale.min(MAX_SCALE) {
+        return Ok(());
+    }
+
+    window.set_size(scale * DISPLAY_WIDTH as u32, scale * DISPLAY_HEIGHT as u32)?;
+    window.set_position(WindowPos::Centered, WindowPos::Centered);
+    Ok(())
+}
+
+/// Draw `rows` of the emulator screen on `surface`, leaving the other rows
+/// as they were
+fn draw_emulator_rows(
+    surface: &mut Surface,
+    display: &Display,
+    colors: Option<&ColorBoard>,
+    rows: &[usize],
+    persistence: Option<&Persistence>,
+    palette: &[Color; 4],
+    shape: PixelShape,
+) -> Result<(), AppError> {
+    // CHIP-8X roms pick their own colors
+    let bgcolor = colors.map_or(palette[0], |board| rgb_color(board.background_rgb()));
+
+    // high resolution pixels are half as big
+    let size = PIXEL_SIZE * DISPLAY_WIDTH / display.width();
+    let spans = shape.spans(size);
+
+    for &y in rows {
+        // clear the background
+        let row = Rect::new(0, (y * size) as i32, surface.width(), size as u32);
+        surface.fill_rect(row, bgcolor)?;
+
+        // the row on every plane, so pixels are a bit test away
+        let planes: Vec<_> = (0..display.planes())
+            .map(|plane| display.rows(plane)[y])
+            .collect();
+
+        // draw the pixels
+        for x in 0..display.width() {
+            let bit = 1 << (display.width() - 1 - x);
+            let fgcolor = match colors {
+                Some(board) => rgb_color(board.pixel_rgb(x, y)),
+                None => palette[plane_index(&planes, bit)],
+            };
+
+            // with a filter, pixels can be "partially" lit
+            let lit = match persistence {
+                Some(persistence) => match persistence.level(x, y) {
+                    level if level > 0.0 => Some(blend(bgcolor, fgcolor, level)),
+                    _ => None,
+                },
+                None => planes.iter().any(|row| row & bit != 0).then_some(fgcolor),
+            };
+            let color = match lit {
+                Some(color) => color,
+                None if shape.shows_unlit() => blend(bgcolor, fgcolor, UNLIT_LEVEL),
+                None => continue,
+            };
+
+            if shape == PixelShape::Square {
+                let rect = Rect::new(
+                    (x * size) as i32,
+                    (y * size) as i32,
+                    size as u32,
+                    size as u32,
+                );
+                surface.fill_rect(rect, color)?;
+                continue;
+            }
+
+            for (line, &(offset, len)) in spans.iter().enumerate().filter(|(_, span)| span.1 > 0) {
+                let rect = Rect::new(
+                    (x * size + offset) as i32,
+                    (y * size + line) as i32,
+                    len as u32,
+                    1,
+                );
+                surface.fill_rect(rect, color)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Copy the lines `lines` of `surface` to the screen texture; with the CRT
+/// effect, the whole screen goes, as the glow spreads the pixels around
+fn update_emulator_texture(
+    texture: &mut Texture,
+    surface: &Surface,
+    lines: Range<usize>,
+    crt_effect: bool,
+) -> Result<(), AppError> {
+    let pitch = surface.pitch() as usize;
+    let bytes = surface.without_lock().ok_or_else(|| {
+        AppError::Sdl("the screen surface cannot be read without a lock".to_owned())
+    })?;
+
+    if crt_effect {
+        let (width, height) = (surface.width() as usize, surface.height() as usize);
+        let mut pixels: Vec<_> = bytes
+            .chunks_exact(4)
+            .map(|pixel| u32::from_ne_bytes(pixel.try_into().unwrap()))
+            .collect();
+        crt::apply(&mut pixels, width, height);
+        let bytes: Vec<_> = pixels
+            .iter()
+            .flat_map(|pixel| pixel.to_ne_bytes())
+            .collect();
+        texture.update(None, &bytes, pitch)?;
+        return Ok(());
+    }
+
+    let rect = Rect::new(0, lines.start as i32, surface.width(), lines.len() as u32);
+    texture.update(rect, &bytes[lines.start * pitch..], pitch)?;
+    Ok(())
+}
+
+/// Which palette color a pixel uses: bit 0 is the first plane, bit 1 the
+/// second; pixels that are off (e.g. still fading out) use the foreground
+fn plane_index(planes: &[u128], bit: u128) -> usize {
+    match planes.iter().enumerate().fold(0, |index, (plane, row)| {
+        index | ((row & bit != 0) as usize) << plane
+    }) {
+        0 => 1,
+        index => index,
+    }
+}
+
+/// Mix two colors; level 0.0 is fully `from`, 1.0 is fully `to`
+fn blend(from: Color, to: Color, level: f32) -> Color {
+    let mix = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * level).round() as u8;
+    Color::RGBA(
+        mix(from.r, to.r),
+        mix(from.g, to.g),
+        mix(from.b, to.b),
+        0xff,
+    )
+}
+
+fn draw_pause_screen<'a, T>(
+    font: &Font,
+    small_font: &Font,
+    texture_creator: &'a TextureCreator<T>,
+) -> Result<Texture<'a>, AppError> {
+    const TEXT: &str = "-- PAUSE --";
+    const MENU: [&str; 5] = [
+        "Space: resume",
+        ".: advance one frame",
+        "Backspace: reset",
+        "Tab: show/hide the keypad",
+        "Esc: quit",
+    ];
+
+    draw_menu_screen(font, small_font, TEXT, &MENU, texture_creator)
+}
+
+fn draw_error_screen<'a, T>(
+    font: &Font,
+    small_font: &Font,
+    error: Option<&EmulatorError>,
+    diagnosis: Option<&Diagnosis>,
+    texture_creator: &'a TextureCreator<T>,
+) -> Result<Texture<'a>, AppError> {
+    const TEXT: &str = "-- ERROR --";
+
+    let message = error.map(|err| err.to_string()).unwrap_or_default();
+    let mut menu = vec![message.as_str()];
+    if let Some(diagnosis) = diagnosis {
+        menu.push(&diagnosis.message);
+    }
+    menu.push("");
+    if diagnosis.and_then(|diagnosis| diagnosis.retry).is_some() {
+        menu.push("F2: retry with the suggested quirks");
+    }
+    menu.extend(["Backspace: reset", "Esc: quit"]);
+
+    draw_menu_screen(font, small_font, TEXT, &menu, texture_creator)
+}
+
+/// A title, with a few lines of text below it, centered on the screen
+fn draw_menu_screen<'a, T>(
+    font: &Font,
+    small_font: &Font,
+    title: &str,
+    menu: &[&str],
+    texture_creator: &'a TextureCreator<T>,
+) -> Result<Texture<'a>, AppError> {
+    const BG_COLOR: Color = Color::RGBA(0x80, 0x80, 0x80, 240);
+    const FG_COLOR: Color = Color::BLACK;
+
+    // create a surface to paint the screen
+    let mut surface = Surface::new(
+        (DISPLAY_WIDTH * PIXEL_SIZE) as u32,
+        (DISPLAY_HEIGHT * PIXEL_SIZE) as u32,
+        PixelFormatEnum::RGBA8888,
+    )?;
+    surface.set_blend_mode(BlendMode::Blend)?;
+
+    // semi-transparent background
+    surface.fill_rect(None, BG_COLOR)?;
+
+    // the title goes a bit above the center of the screen,
+    // with the menu entries right below it
+    let (_, title_h) = font.size_of(title)?;
+    let line_h = small_font.height() as u32;
+    let total_h = title_h + line_h * menu.len() as u32;
+    let mut y = ((DISPLAY_HEIGHT * PIXEL_SIZE) as u32).saturating_sub(total_h) / 2;
+
+    for (text, font) in std::iter::once((title, font)).chain(menu.iter().map(|&m| (m, small_font)))
+    {
+        // empty lines are just spacing; SDL_ttf can not render them
+        if text.is_empty() {
+            y += line_h;
+            continue;
+        }
+
+        let rendered = font.render(text).solid(FG_COLOR)?;
+        let (w, h) = font.size_of(text)?;
+        let x = ((DISPLAY_WIDTH * PIXEL_SIZE) as u32).saturating_sub(w) / 2;
+
+        rendered.blit(None, &mut surface, Rect::new(x as i32, y as i32, w, h))?;
+        y += h;
+    }
+
+    // return the texture
+    Ok(texture_creator.create_texture_from_surface(&surface)?)
+}
+
+fn draw_step_screen<'a, T>(
+    font: &Font,
+    texture_creator: &'a TextureCreator<T>,
+) -> Result<Texture<'a>, AppError> {
+    const TEXT: &str = "PAUSED (frame step)";
+    const BG_COLOR: Color = Color::RGBA(0x80, 0x80, 0x80, 240);
+    const FG_COLOR: Color = Color::BLACK;
+    const MARGIN: u32 = 4;
+
+    // the text goes on the top-left corner of the screen
+    let text = font.render(TEXT).solid(FG_COLOR)?;
+    let (w, h) = font.size_of(TEXT)?;
+    let text_rect = Rect::new(MARGIN as i32, MARGIN as i32, w, h);
+    let box_rect = Rect::new(0, 0, w + MARGIN * 2, h + MARGIN * 2);
+
+    // create a transparent surface to paint the screen
+    let mut surface = Surface::new(
+        (DISPLAY_WIDTH * PIXEL_SIZE) as u32,
+        (DISPLAY_HEIGHT * PIXEL_SIZE) as u32,
+        PixelFormatEnum::RGBA8888,
+    )?;
+    surface.set_blend_mode(BlendMode::Blend)?;
+    surface.fill_rect(None, Color::RGBA(0, 0, 0, 0))?;
+
+    // background and text
+    surface.fill_rect(box_rect, BG_COLOR)?;
+    text.blit(None, &mut surface, text_rect)?;
+
+    // return the texture
+    Ok(texture_creator.create_texture_from_surface(&surface)?)
+}
+
+fn draw_switcher_screen<'a, T>(
+    font: &Font,
+    entries: &[PathBuf],
+    selected: usize,
+    texture_creator: &'a TextureCreator<T>,
+) -> Result<Texture<'a>, AppError> {
+    const TITLE: &str = "Recent ROMs";
+    const BG_COLOR: Color = Color::RGBA(0x80, 0x80, 0x80, 240);
+    const FG_COLOR: Color = Color::BLACK;
+    const SELECTED_COLOR: Color = Color::WHITE;
+    const MARGIN: u32 = 8;
+
+    // create a surface to paint the screen
+    let mut surface = Surface::new(
+        (DISPLAY_WIDTH * PIXEL_SIZE) as u32,
+        (DISPLAY_HEIGHT * PIXEL_SIZE) as u32,
+        PixelFormatEnum::RGBA8888,
+    )?;
+    surface.set_blend_mode(BlendMode::Blend)?;
+    surface.fill_rect(None, BG_COLOR)?;
+
+    // only the file names, the full paths do not fit on the screen
+    let names = entries.iter().enumerate().map(|(i, entry)| {
+        let name = entry
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| entry.to_string_lossy().into_owned());
+
+        if i == selected {
+            (format!("> {}", name), SELECTED_COLOR)
+        } else {
+            (format!("  {}", name), FG_COLOR)
+        }
+    });
+
+    // title on top, one rom per line below it
+    let mut y = MARGIN;
+    for (text, color) in std::iter::once((TITLE.to_owned(), FG_COLOR)).chain(names) {
+        let rendered = font.render(&text).solid(color)?;
+        let (w, h) = font.size_of(&text)?;
+
+        rendered.blit(None, &mut surface, Rect::new(MARGIN as i32, y as i32, w, h))?;
+        y += h;
+    }
+
+    // return the texture
+    Ok(texture_creator.create_texture_from_surface(&surface)?)
+}
+
+fn draw_keypad_screen<'a, T>(
+    font: &Font,
+    pressed: u16,
+    bindings: &[(Keycode, usize)],
+    controls: &[Option<String>; 16],
+    texture_creator: &'a TextureCreator<T>,
+) -> Result<Texture<'a>, AppError> {
+    const BG_COLOR: Color = Color::RGBA(0x80, 0x80, 0x80, 200);
+    const PRESSED_COLOR: Color = Color::RGBA(0xff, 0xff, 0xff, 240);
+    const FG_COLOR: Color = Color::BLACK;
+    const LABEL_COLOR: Color = Color::RGB(0x30, 0x30, 0x30);
+    const BORDER: u32 = 2;
+
+    // create a transparent surface to paint the screen
+    let mut surface = Surface::new(
+        (DISPLAY_WIDTH * PIXEL_SIZE) as u32,
+        (DISPLAY_HEIGHT * PIXEL_SIZE) as u32,
+        PixelFormatEnum::RGBA8888,
+    )?;
+    surface.set_blend_mode(BlendMode::Blend)?;
+    surface.fill_rect(None, Color::RGBA(0, 0, 0, 0))?;
+
+    for (row, keys) in KEYPAD.iter().enumerate() {
+        for (col, &(key, label)) in keys.iter().enumerate() {
+            let left = KEYPAD_LEFT + col as u32 * KEYPAD_CELL;
+            let top = KEYPAD_TOP + row as u32 * KEYPAD_CELL;
+            let color = if pressed & (1 << key) != 0 {
+                PRESSED_COLOR
+            } else {
+                BG_COLOR
+            };
+
+            // the gap between cells works as a border
+            let cell = Rect::new(
+                (left + BORDER) as i32,
+                (top + BORDER) as i32,
+                KEYPAD_CELL - BORDER * 2,
+                KEYPAD_CELL - BORDER * 2,
+            );
+            surface.fill_rect(cell, color)?;
+
+            // the CHIP-8 key, with the keyboard key right below it; when
+            // the rom tells what the key does, that goes in place of the
+            // CHIP-8 key
+            let label = bindings
+                .iter()
+                .find(|&&(_, bound)| bound == key)
+                .map_or_else(|| label.to_owned(), |(keycode, _)| keycode.name());
+            let name = match &controls[key] {
+                Some(control) => control.clone(),
+                None => format!("{:X}", key),
+            };
+            let mut y = top + BORDER;
+            for (text, color) in [(name.as_str(), FG_COLOR), (label.as_str(), LABEL_COLOR)] {
+                let rendered = font.render(text).solid(color)?;
+                let (w, h) = font.size_of(text)?;
+                let x = left + (KEYPAD_CELL.saturating_sub(w)) / 2;
+
+                rendered.blit(None, &mut surface, Rect::new(x as i32, y as i32, w, h))?;
+                y += h;
+            }
+        }
+    }
+
+    // return the texture
+    Ok(texture_creator.create_texture_from_surface(&surface)?)
+}
+
+fn draw_osd_screen<'a, T>(
+    font: &Font,
+    text: &str,
+    texture_creator: &'a TextureCreator<T>,
+) -> Result<Texture<'a>, AppError> {
+    const BG_COLOR: Color = Color::RGBA(0x80, 0x80, 0x80, 240);
+    const FG_COLOR: Color = Color::BLACK;
+    const MARGIN: u32 = 4;
+    const SCREEN_WIDTH: u32 = (DISPLAY_WIDTH * PIXEL_SIZE) as u32;
+
+    // the text goes on the top-right corner of the screen
+    let rendered = font.render(text).solid(FG_COLOR)?;
+    let (w, h) = font.size_of(text)?;
+    let text_rect = Rect::new((SCREEN_WIDTH - w - MARGIN) as i32, MARGIN as i32, w, h);
+    let box_rect = Rect::new(
+        (SCREEN_WIDTH - w - MARGIN * 2) as i32,
+        0,
+        w + MARGIN * 2,
+        h + MARGIN * 2,
+    );
+
+    // create a transparent surface to paint the screen
+    let mut surface = Surface::new(
+        (DISPLAY_WIDTH * PIXEL_SIZE) as u32,
+        (DISPLAY_HEIGHT * PIXEL_SIZE) as u32,
+        PixelFormatEnum::RGBA8888,
+    )?;
+    surface.set_blend_mode(BlendMode::Blend)?;
+    surface.fill_rect(None, Color::RGBA(0, 0, 0, 0))?;
+
+    // background and text
+    surface.fill_rect(box_rect, BG_COLOR)?;
+    rendered.blit(None, &mut surface, text_rect)?;
+
+    // return the texture
+    Ok(texture_creator.create_texture_from_surface(&surface)?)
+}
+
+/// Chat messages on the bottom-left corner, the last one at the bottom
+fn draw_chat_screen<'a, T>(
+    font: &Font,
+    lines: &[String],
+    texture_creator: &'a TextureCreator<T>,
+) -> Result<Texture<'a>, AppError> {
+    const BG_COLOR: Color = Color::RGBA(0x00, 0x00, 0x00, 160);
+    const FG_COLOR: Color = Color::WHITE;
+    const MARGIN: u32 = 4;
+    const SCREEN_HEIGHT: u32 = (DISPLAY_HEIGHT * PIXEL_SIZE) as u32;
+
+    let line_h = font.height() as u32;
+    let mut box_w = 0;
+    for line in lines {
+        box_w = box_w.max(font.size_of(line)?.0);
+    }
+    let box_h = line_h * lines.len() as u32 + MARGIN * 2;
+    let top = SCREEN_HEIGHT - box_h;
+    let box_rect = Rect::new(0, top as i32, box_w + MARGIN * 2, box_h);
+
+    // create a transparent surface to paint the screen
+    let mut surface = Surface::new(
+        (DISPLAY_WIDTH * PIXEL_SIZE) as u32,
+        SCREEN_HEIGHT,
+        PixelFormatEnum::RGBA8888,
+    )?;
+    surface.set_blend_mode(BlendMode::Blend)?;
+    surface.fill_rect(None, Color::RGBA(0, 0, 0, 0))?;
+    surface.fill_rect(box_rect, BG_COLOR)?;
+
+    for (i, line) in lines.iter().enumerate() {
+        let rendered = font.render(line).solid(FG_COLOR)?;
+        let (w, h) = font.size_of(line)?;
+        let y = top + MARGIN + line_h * i as u32;
+        rendered.blit(None, &mut surface, Rect::new(MARGIN as i32, y as i32, w, h))?;
+    }
+
+    // return the texture
+    Ok(texture_creator.create_texture_from_surface(&surface)?)
+}
+
+/// Lines of numbers on a top corner, away from the osd messages: the left
+/// one, or the right one with `right`
+fn draw_perf_screen<'a, T>(
+    font: &Font,
+    lines: &[String],
+    right: bool,
+    texture_creator: &'a TextureCreator<T>,
+) -> Result<Texture<'a>, AppError> {
+    const BG_COLOR: Color = Color::RGBA(0x00, 0x00, 0x00, 200);
+    const FG_COLOR: Color = Color::RGB(0x40, 0xFF, 0x40);
+    const MARGIN: u32 = 4;
+
+    let line_h = font.height() as u32;
+    let mut box_w = 0;
+    for line in lines {
+        box_w = box_w.max(font.size_of(line)?.0);
+    }
+    let box_w = box_w + MARGIN * 2;
+    let left = match right {
+        true => (DISPLAY_WIDTH * PIXEL_SIZE) as u32 - box_w,
+        false => 0,
+    };
+    let box_rect = Rect::new(
+        left as i32,
+        0,
+        box_w,
+        line_h * lines.len() as u32 + MARGIN * 2,
+    );
+
+    // create a transparent surface to paint the screen
+    let mut surface = Surface::new(
+        (DISPLAY_WIDTH * PIXEL_SIZE) as u32,
+        (DISPLAY_HEIGHT * PIXEL_SIZE) as u32,
+        PixelFormatEnum::RGBA8888,
+    )?;
+    surface.set_blend_mode(BlendMode::Blend)?;
+    surface.fill_rect(None, Color::RGBA(0, 0, 0, 0))?;
+    surface.fill_rect(box_rect, BG_COLOR)?;
+
+    for (i, line) in lines.iter().enumerate() {
+        let rendered = font.render(line).solid(FG_COLOR)?;
+        let (w, h) = font.size_of(line)?;
+        let (x, y) = (left + MARGIN, MARGIN + line_h * i as u32);
+        rendered.blit(None, &mut surface, Rect::new(x as i32, y as i32, w, h))?;
+    }
+
+    // return the texture
+    Ok(texture_creator.create_texture_from_surface(&surface)?)
+}
+
+fn draw_debugger_screen<'a, T>(
+    font: &Font,
+    lines: &[debugger::Line],
+    texture_creator: &'a TextureCreator<T>,
+) -> Result<Texture<'a>, AppError> {
+    const BG_COLOR: Color = Color::RGBA(0x80, 0x80, 0x80, 220);
+    const CURSOR_COLOR: Color = Color::RGBA(0xff, 0xff, 0xff, 240);
+    const FG_COLOR: Color = Color::BLACK;
+    const BREAKPOINT_COLOR: Color = Color::RGB(0xa0, 0x00, 0x00);
+    const HOT_COLOR: Color = Color::RGB(0xff, 0x60, 0x00);
+    const MARGIN: u32 = 4;
+
+    // create a transparent surface to paint the screen
+    let mut surface = Surface::new(
+        (DISPLAY_WIDTH * PIXEL_SIZE) as u32,
+        (DISPLAY_HEIGHT * PIXEL_SIZE) as u32,
+        PixelFormatEnum::RGBA8888,
+    )?;
+    surface.set_blend_mode(BlendMode::Blend)?;
+    surface.fill_rect(None, Color::RGBA(0, 0, 0, 0))?;
+
+    // the panel goes on the left side of the screen
+    surface.fill_rect(
+        Rect::new(0, 0, DEBUGGER_WIDTH, (DISPLAY_HEIGHT * PIXEL_SIZE) as u32),
+        BG_COLOR,
+    )?;
+
+    for (i, line) in lines.iter().enumerate() {
+        let y = DEBUGGER_TOP + i as u32 * DEBUGGER_LINE;
+        if line.is_cursor {
+            surface.fill_rect(
+                Rect::new(0, y as i32, DEBUGGER_WIDTH, DEBUGGER_LINE),
+                CURSOR_COLOR,
+            )?;
+        } else if line.heat > 0 {
+            // when profiling, the lines that run the most are the hottest
+            let heat = blend(BG_COLOR, HOT_COLOR, line.heat as f32 / 255.0);
+            surface.fill_rect(
+                Rect::new(0, y as i32, DEBUGGER_WIDTH, DEBUGGER_LINE),
+                Color::RGBA(heat.r, heat.g, heat.b, BG_COLOR.a),
+            )?;
+        }
+
+        // breakpoint and PC markers, then the instruction itself
+        let text = format!(
+            "{}{} {:03X}: {}",
+            if line.breakpoint { "*" } else { " " },
+            if line.is_pc { ">" } else { " " },
+            line.address,
+            line.text
+        );
+        let color = if line.breakpoint {
+            BREAKPOINT_COLOR
+        } else {
+            FG_COLOR
+        };
+
+        let rendered = font.render(&text).solid(color)?;
+        let (w, h) = font.size_of(&text)?;
+        rendered.blit(None, &mut surface, Rect::new(MARGIN as i32, y as i32, w, h))?;
+    }
+
+    // return the texture
+    Ok(texture_creator.create_texture_from_surface(&surface)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plane_index() {
+        let rom = [
+            0xF2, 0x01, // 0x200: Select the second plane
+            0xA2, 0x08, // 0x202: Set I = 0x208
+            0xD0, 0x01, // 0x204: Draw[V0, V0], 1 row
+            0x12, 0x06, // 0x206: Jump to 0x206
+            0x80, 0x00, // 0x208: the sprite, the leftmost pixel
+        ];
+        let mut emu = Emulator::builder()
+            .variant(Variant::XoChip)
+            .load_rom(&rom[..])
+            .unwrap();
+        for _ in 0..3 {
+            emu.vblank();
+            emu.execute().unwrap();
+        }
+
+        let display = emu.display();
+        let planes: Vec<_> = (0..display.planes())
+            .map(|plane| display.rows(plane)[0])
+            .collect();
+        let bit = 1 << (display.width() - 1);
+        assert_eq!(plane_index(&planes, bit), 2);
+
+        // pixels that are off use the foreground
+        assert_eq!(plane_index(&planes, bit >> 1), 1);
+        assert_eq!(plane_index(&[bit, bit], bit), 3);
+    }
 }
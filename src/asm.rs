@@ -0,0 +1,559 @@
+//! A small assembler for the Octo syntax.
+//!
+//! Supports labels (`: name`), constants (`:const`), register aliases
+//! (`:alias`), raw bytes, the standard statements and the structured
+//! `loop`/`again` and `if`/`begin`/`else`/`end` blocks. Calling a label is
+//! done by just writing its name, like in Octo.
+//!
+//! ```
+//! let rom = rc8::asm::assemble(
+//!     ": main
+//!        v0 := 5
+//!        loop
+//!          v0 -= 1
+//!          if v0 != 0 then
+//!        again",
+//! )
+//! .unwrap();
+//!
+//! assert_eq!(rom, [0x60, 0x05, 0x70, 0xFF, 0x30, 0x00, 0x12, 0x02]);
+//! ```
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use super::{disasm::ENTRY_POINT, opcode::Opcode};
+
+#[derive(Error, Debug, PartialEq, Eq)]
+#[error("line {line}: {message}")]
+pub struct AsmError {
+    pub line: usize,
+    pub message: String,
+}
+
+/// A 12-bit address to be filled once the label is defined
+struct Fixup<'a> {
+    offset: usize,
+    name: &'a str,
+    line: usize,
+}
+
+struct Assembler<'a> {
+    tokens: Vec<(usize, &'a str)>,
+    pos: usize,
+    out: Vec<u8>,
+
+    labels: HashMap<&'a str, u16>,
+    consts: HashMap<&'a str, i32>,
+    aliases: HashMap<&'a str, u8>,
+    fixups: Vec<Fixup<'a>>,
+
+    // open `loop` addresses and `begin`/`else` jumps to patch
+    loops: Vec<u16>,
+    blocks: Vec<(usize, usize)>,
+}
+
+/// Assemble Octo source code into a rom
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let tokens = source
+        .lines()
+        .enumerate()
+        .flat_map(|(i, line)| {
+            let code = line.split('#').next().unwrap_or_default();
+            code.split_whitespace().map(move |token| (i + 1, token))
+        })
+        .collect();
+
+    let mut asm = Assembler {
+        tokens,
+        pos: 0,
+        out: Vec::new(),
+        labels: HashMap::new(),
+        consts: HashMap::new(),
+        aliases: HashMap::new(),
+        fixups: Vec::new(),
+        loops: Vec::new(),
+        blocks: Vec::new(),
+    };
+
+    while asm.pos < asm.tokens.len() {
+        asm.statement()?;
+    }
+
+    asm.finish()
+}
+
+/// Parse a decimal, hex (0x) or binary (0b) number, possibly negative
+fn parse_number(token: &str) -> Option<i32> {
+    let (negative, digits) = match token.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, token),
+    };
+
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i32::from_str_radix(bin, 2).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+impl<'a> Assembler<'a> {
+    fn error<T>(&self, line: usize, message: impl Into<String>) -> Result<T, AsmError> {
+        Err(AsmError {
+            line,
+            message: message.into(),
+        })
+    }
+
+    fn next(&mut self) -> Result<(usize, &'a str), AsmError> {
+        match self.tokens.get(self.pos) {
+            Some(&token) => {
+                self.pos += 1;
+                Ok(token)
+            }
+            None => {
+                let line = self.tokens.last().map(|&(line, _)| line).unwrap_or(1);
+                self.error(line, "unexpected end of file")
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), AsmError> {
+        let (line, token) = self.next()?;
+        if token != expected {
+            return self.error(line, format!("expected '{}', got '{}'", expected, token));
+        }
+        Ok(())
+    }
+
+    fn address(&self) -> u16 {
+        ENTRY_POINT + self.out.len() as u16
+    }
+
+    fn emit(&mut self, op: Opcode) {
+        self.out.extend_from_slice(&op.encode().to_be_bytes());
+    }
+
+    /// Parse a register name (v0 - vf) or alias
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let (line, token) = self.next()?;
+        self.as_register(token).map_or_else(
+            || self.error(line, format!("expected a register, got '{}'", token)),
+            Ok,
+        )
+    }
+
+    fn as_register(&self, token: &str) -> Option<u8> {
+        if let Some(&register) = self.aliases.get(token) {
+            return Some(register);
+        }
+
+        let digit = token
+            .strip_prefix('v')
+            .or_else(|| token.strip_prefix('V'))?;
+        if digit.len() != 1 {
+            return None;
+        }
+        u8::from_str_radix(digit, 16).ok()
+    }
+
+    /// Parse a number or constant
+    fn value(&mut self, min: i32, max: i32) -> Result<i32, AsmError> {
+        let (line, token) = self.next()?;
+        let value = match parse_number(token).or_else(|| self.consts.get(token).copied()) {
+            Some(value) => value,
+            None => return self.error(line, format!("expected a number, got '{}'", token)),
+        };
+
+        if value < min || value > max {
+            return self.error(
+                line,
+                format!("{} is out of range ({} to {})", value, min, max),
+            );
+        }
+        Ok(value)
+    }
+
+    fn byte(&mut self) -> Result<u8, AsmError> {
+        // negative bytes wrap around, so `v0 += -1` works
+        self.value(-128, 255).map(|value| value as u8)
+    }
+
+    /// Emit an instruction with an address, which may be a label defined later
+    fn emit_address(&mut self, make: fn(u16) -> Opcode) -> Result<(), AsmError> {
+        let (line, token) = self.next()?;
+        let value = parse_number(token)
+            .or_else(|| self.consts.get(token).copied())
+            .or_else(|| self.labels.get(token).map(|&addr| addr as i32));
+
+        match value {
+            Some(value) if (0..=0xFFF).contains(&value) => self.emit(make(value as u16)),
+            Some(value) => return self.error(line, format!("address {} is out of range", value)),
+            None => {
+                self.fixups.push(Fixup {
+                    offset: self.out.len(),
+                    name: token,
+                    line,
+                });
+                self.emit(make(0));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Emit a jump whose target is patched later
+    fn emit_placeholder_jump(&mut self) -> usize {
+        let offset = self.out.len();
+        self.emit(Opcode::Jump(0));
+        offset
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        let op = Opcode::Jump(self.address()).encode().to_be_bytes();
+        self.out[offset..offset + 2].copy_from_slice(&op);
+    }
+
+    /// Parse an `if` condition, returning the skip that runs the next
+    /// instruction only if the condition holds, and its inverse
+    fn condition(&mut self) -> Result<(Opcode, Opcode), AsmError> {
+        let x = self.register()?;
+        let (line, op) = self.next()?;
+
+        let skips = match op {
+            "key" => (Opcode::SkipNotKey(x), Opcode::SkipKey(x)),
+            "-key" => (Opcode::SkipKey(x), Opcode::SkipNotKey(x)),
+            "==" | "!=" => {
+                let (_, rhs) = self.tokens.get(self.pos).copied().unwrap_or_default();
+                let (equal, different) = match self.as_register(rhs) {
+                    Some(y) => {
+                        self.pos += 1;
+                        (Opcode::SkipNeqReg(x, y), Opcode::SkipEqReg(x, y))
+                    }
+                    None => {
+                        let nn = self.byte()?;
+                        (Opcode::SkipNeqByte(x, nn), Opcode::SkipEqByte(x, nn))
+                    }
+                };
+
+                if op == "==" {
+                    (equal, different)
+                } else {
+                    (different, equal)
+                }
+            }
+            _ => return self.error(line, format!("unsupported condition '{}'", op)),
+        };
+
+        Ok(skips)
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError> {
+        let (line, token) = self.next()?;
+
+        match token {
+            ":" => {
+                let (line, name) = self.next()?;
+                if self.labels.insert(name, self.address()).is_some() {
+                    return self.error(line, format!("label '{}' defined twice", name));
+                }
+            }
+            ":const" => {
+                let (_, name) = self.next()?;
+                let value = self.value(-0xFFFF, 0xFFFF)?;
+                self.consts.insert(name, value);
+            }
+            ":alias" => {
+                let (_, name) = self.next()?;
+                let register = self.register()?;
+                self.aliases.insert(name, register);
+            }
+            ":call" => self.emit_address(Opcode::Call)?,
+            "jump" => self.emit_address(Opcode::Jump)?,
+            "jump0" => self.emit_address(Opcode::JumpV0)?,
+            "return" | ";" => self.emit(Opcode::Return),
+            "clear" => self.emit(Opcode::Clear),
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(Opcode::Bcd(x));
+            }
+            "save" => {
+                let x = self.register()?;
+                self.emit(Opcode::Save(x));
+            }
+            "load" => {
+                let x = self.register()?;
+                self.emit(Opcode::Load(x));
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.value(0, 15)? as u8;
+                self.emit(Opcode::Draw(x, y, n));
+            }
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                self.emit(if token == "delay" {
+                    Opcode::SetDelay(x)
+                } else {
+                    Opcode::SetSound(x)
+                });
+            }
+            "i" => self.index_statement()?,
+            "loop" => self.loops.push(self.address()),
+            "again" => match self.loops.pop() {
+                Some(start) => self.emit(Opcode::Jump(start)),
+                None => return self.error(line, "'again' without 'loop'"),
+            },
+            "if" => {
+                let (then_skip, begin_skip) = self.condition()?;
+                let (line, kind) = self.next()?;
+                match kind {
+                    "then" => self.emit(then_skip),
+                    "begin" => {
+                        // when the condition holds, skip the jump to the else/end
+                        self.emit(begin_skip);
+                        let jump = self.emit_placeholder_jump();
+                        self.blocks.push((jump, line));
+                    }
+                    _ => {
+                        return self
+                            .error(line, format!("expected 'then' or 'begin', got '{}'", kind))
+                    }
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some((jump, _)) => {
+                    let end_jump = self.emit_placeholder_jump();
+                    self.patch_jump(jump);
+                    self.blocks.push((end_jump, line));
+                }
+                None => return self.error(line, "'else' without 'begin'"),
+            },
+            "end" => match self.blocks.pop() {
+                Some((jump, _)) => self.patch_jump(jump),
+                None => return self.error(line, "'end' without 'begin'"),
+            },
+            _ => {
+                if let Some(x) = self.as_register(token) {
+                    self.register_statement(x)?;
+                } else if parse_number(token).is_some() || self.consts.contains_key(token) {
+                    // raw bytes
+                    self.pos -= 1;
+                    let byte = self.byte()?;
+                    self.out.push(byte);
+                } else if token.starts_with(':') {
+                    return self.error(line, format!("unsupported directive '{}'", token));
+                } else {
+                    // calling a subroutine by name
+                    self.pos -= 1;
+                    self.emit_address(Opcode::Call)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn index_statement(&mut self) -> Result<(), AsmError> {
+        let (line, op) = self.next()?;
+        match op {
+            ":=" => {
+                if self.tokens.get(self.pos).map(|&(_, token)| token) == Some("hex") {
+                    self.pos += 1;
+                    let x = self.register()?;
+                    self.emit(Opcode::LoadFont(x));
+                    Ok(())
+                } else {
+                    self.emit_address(Opcode::LoadI)
+                }
+            }
+            "+=" => {
+                let x = self.register()?;
+                self.emit(Opcode::AddI(x));
+                Ok(())
+            }
+            _ => self.error(line, format!("unsupported operator 'i {}'", op)),
+        }
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<(), AsmError> {
+        let (line, op) = self.next()?;
+        let (_, rhs) = self.tokens.get(self.pos).copied().unwrap_or_default();
+        let y = self.as_register(rhs);
+        if y.is_some() {
+            self.pos += 1;
+        }
+
+        let instruction = match (op, y) {
+            (":=", Some(y)) => Opcode::LoadReg(x, y),
+            (":=", None) => match rhs {
+                "random" => {
+                    self.pos += 1;
+                    Opcode::Random(x, self.byte()?)
+                }
+                "delay" => {
+                    self.pos += 1;
+                    Opcode::GetDelay(x)
+                }
+                "key" => {
+                    self.pos += 1;
+                    Opcode::WaitKey(x)
+                }
+                _ => Opcode::LoadByte(x, self.byte()?),
+            },
+            ("+=", Some(y)) => Opcode::AddReg(x, y),
+            ("+=", None) => Opcode::AddByte(x, self.byte()?),
+            ("-=", Some(y)) => Opcode::Sub(x, y),
+            ("-=", None) => Opcode::AddByte(x, self.byte()?.wrapping_neg()),
+            ("=-", Some(y)) => Opcode::SubN(x, y),
+            ("|=", Some(y)) => Opcode::Or(x, y),
+            ("&=", Some(y)) => Opcode::And(x, y),
+            ("^=", Some(y)) => Opcode::Xor(x, y),
+            (">>=", Some(y)) => Opcode::ShiftRight(x, y),
+            ("<<=", Some(y)) => Opcode::ShiftLeft(x, y),
+            _ => return self.error(line, format!("unsupported operator 'v{:x} {}'", x, op)),
+        };
+
+        self.emit(instruction);
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, AsmError> {
+        if let Some(&(_, line)) = self.blocks.last() {
+            return self.error(line, "'begin' without 'end'");
+        }
+
+        for fixup in std::mem::take(&mut self.fixups) {
+            let addr = match self.labels.get(fixup.name) {
+                Some(&addr) => addr,
+                None => return self.error(fixup.line, format!("undefined name '{}'", fixup.name)),
+            };
+
+            let word = u16::from_be_bytes([self.out[fixup.offset], self.out[fixup.offset + 1]]);
+            let word = (word & 0xF000) | (addr & 0xFFF);
+            self.out[fixup.offset..fixup.offset + 2].copy_from_slice(&word.to_be_bytes());
+        }
+
+        Ok(self.out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::disassemble;
+
+    #[test]
+    fn test_statements() {
+        let source = "
+            clear
+            v0 := 0x0A       # load
+            v1 := v0
+            va += 3
+            v2 -= v1
+            v3 =- v2
+            v4 := random 0b1111
+            i := hex v0
+            i += v1
+            sprite v0 v1 5
+            delay := v2
+            buzzer := v2
+            v5 := delay
+            v6 := key
+            bcd v6
+            save v6
+            load v6
+            return
+        ";
+
+        let expected = [
+            0x00E0, 0x600A, 0x8100, 0x7A03, 0x8215, 0x8327, 0xC40F, 0xF029, 0xF11E, 0xD015, 0xF215,
+            0xF218, 0xF507, 0xF60A, 0xF633, 0xF655, 0xF665, 0x00EE,
+        ];
+        let expected: Vec<u8> = expected
+            .iter()
+            .flat_map(|w: &u16| w.to_be_bytes())
+            .collect();
+
+        assert_eq!(assemble(source).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_labels() {
+        let source = "
+            :const SPEED 3
+            :alias speed v4
+            : main
+              speed := SPEED
+              draw
+              jump main
+            : draw
+              i := sprite
+              ;
+            : sprite
+              0xFF 0x81
+        ";
+
+        let rom = assemble(source).unwrap();
+        assert_eq!(
+            rom,
+            [0x64, 0x03, 0x22, 0x06, 0x12, 0x00, 0xA2, 0x0A, 0x00, 0xEE, 0xFF, 0x81]
+        );
+    }
+
+    #[test]
+    fn test_conditions() {
+        let source = "
+            if v0 == 1 then v1 := 1
+            if v0 != v1 then v1 := 2
+            if v2 key begin
+              v3 := 1
+            else
+              v3 := 2
+            end
+        ";
+
+        let rom = assemble(source).unwrap();
+        let words: Vec<u16> = rom
+            .chunks(2)
+            .map(|w| u16::from_be_bytes([w[0], w[1]]))
+            .collect();
+
+        assert_eq!(
+            words,
+            [0x4001, 0x6101, 0x5010, 0x6102, 0xE29E, 0x1210, 0x6301, 0x1212, 0x6302]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source| assemble(source).unwrap_err();
+
+        assert_eq!(error("v0 := 256").line, 1);
+        assert_eq!(error("\njump nowhere").line, 2);
+        assert_eq!(error("if v0 == 1 begin\nclear").line, 1);
+        assert_eq!(error("\n\nagain").line, 3);
+        assert_eq!(error("v0 <<=").line, 1);
+        assert_eq!(error(": a\n: a").line, 2);
+    }
+
+    #[test]
+    fn test_disassembly_roundtrip() {
+        let roms: [&[u8]; 3] = [
+            include_bytes!("../roms/ibm-logo.ch8"),
+            include_bytes!("../roms/chip8-emulator-logo.ch8"),
+            include_bytes!("../roms/chip8-test-suite.ch8"),
+        ];
+
+        for rom in roms {
+            let source = disassemble(rom).to_text();
+            assert_eq!(assemble(&source).unwrap(), rom);
+        }
+    }
+}
@@ -0,0 +1,321 @@
+use anyhow::bail;
+use nanorand::{Rng, WyRand};
+
+use super::{emulator::Emulator, timing::FRAME_CYCLES};
+
+// how often (in frames) the scripted input changes
+const INPUT_PERIOD: usize = 8;
+
+/// Per-frame state hashes of a single run
+struct Trace {
+    hashes: Vec<u64>,
+    error: Option<String>,
+}
+
+/// How a single run is set up
+#[derive(Clone, Copy)]
+struct Setup {
+    // seed for the emulator rng; unseeded runs use system entropy
+    rng_seed: Option<u64>,
+
+    // seed used to move the vblank interrupt around inside each frame
+    jitter_seed: Option<u64>,
+}
+
+/// Generate the scripted key presses (one bitmask per frame)
+fn script(seed: u64, frames: usize) -> Vec<u16> {
+    let mut rng = WyRand::new_seed(seed);
+    let mut keys = 0u16;
+
+    (0..frames)
+        .map(|frame| {
+            if frame % INPUT_PERIOD == 0 {
+                keys = 1 << rng.generate_range(0u16..16);
+                if rng.generate_range(0u8..4) == 0 {
+                    keys = 0;
+                }
+            }
+            keys
+        })
+        .collect()
+}
+
+/// Run the rom headlessly with the scripted inputs, calling `inspect` before
+/// every instruction; stops at the first error
+pub fn scripted_run(rom: &[u8], frames: usize, mut inspect: impl FnMut(&Emulator)) {
+    let mut emu = match Emulator::load_rom(rom) {
+        Ok(emu) => emu,
+        Err(_) => return,
+    };
+    emu.seed_rng(0);
+
+    let mut previous = 0u16;
+    for keys in script(0, frames) {
+        for key in 0..16 {
+            let mask = 1 << key;
+            if (keys ^ previous) & mask != 0 {
+                emu.set_key(key, keys & mask != 0);
+            }
+        }
+        previous = keys;
+
+        emu.vblank();
+        for _ in 0..FRAME_CYCLES {
+            inspect(&emu);
+            if emu.execute().is_err() {
+                return;
+            }
+        }
+        emu.decrease_timers();
+    }
+}
+
+fn run(rom: &[u8], inputs: &[u16], setup: Setup) -> Trace {
+    let mut emu = match Emulator::load_rom(rom) {
+        Ok(emu) => emu,
+        Err(err) => {
+            return Trace {
+                hashes: Vec::new(),
+                error: Some(err.to_string()),
+            }
+        }
+    };
+
+    if let Some(seed) = setup.rng_seed {
+        emu.seed_rng(seed);
+    }
+    let mut jitter = setup.jitter_seed.map(WyRand::new_seed);
+
+    let mut hashes = Vec::with_capacity(inputs.len());
+    let mut previous = 0u16;
+    for &keys in inputs {
+        // apply only the keys that changed
+        for key in 0..16 {
+            let mask = 1 << key;
+            if (keys ^ previous) & mask != 0 {
+                emu.set_key(key, keys & mask != 0);
+            }
+        }
+        previous = keys;
+
+        // the vblank normally arrives at the start of the frame, but a
+        // slow or busy host can deliver it a few cycles late
+        let vblank_at = jitter
+            .as_mut()
+            .map(|rng| rng.generate_range(0..FRAME_CYCLES as u64) as u128)
+            .unwrap_or(0);
+
+        for cycle in 0..FRAME_CYCLES {
+            if cycle == vblank_at {
+                emu.vblank();
+            }
+
+            if let Err(err) = emu.execute() {
+                return Trace {
+                    hashes,
+                    error: Some(err.to_string()),
+                };
+            }
+        }
+        emu.decrease_timers();
+
+        hashes.push(emu.state_hash());
+    }
+
+    Trace {
+        hashes,
+        error: None,
+    }
+}
+
+/// Compare two traces, returning the first frame where they differ
+fn divergence(a: &Trace, b: &Trace) -> Option<usize> {
+    let first = a
+        .hashes
+        .iter()
+        .zip(b.hashes.iter())
+        .position(|(a, b)| a != b);
+
+    match first {
+        Some(frame) => Some(frame),
+        None if a.hashes.len() != b.hashes.len() || a.error != b.error => {
+            Some(a.hashes.len().min(b.hashes.len()))
+        }
+        None => None,
+    }
+}
+
+/// If two diverging traces end up in the same state, returns the
+/// frame where they started matching again
+fn reconvergence(a: &Trace, b: &Trace) -> Option<usize> {
+    if a.hashes.len() != b.hashes.len() || a.error != b.error || a.hashes.last() != b.hashes.last()
+    {
+        return None;
+    }
+
+    let last_mismatch = a
+        .hashes
+        .iter()
+        .zip(b.hashes.iter())
+        .rposition(|(a, b)| a != b);
+
+    last_mismatch.map(|frame| frame + 1)
+}
+
+fn describe(a: &Trace, b: &Trace) -> String {
+    match (divergence(a, b), reconvergence(a, b)) {
+        (None, _) => "identical".to_owned(),
+        (Some(start), Some(end)) => {
+            format!("diverged at frame {}, matched again at {}", start, end)
+        }
+        (Some(start), None) => format!("diverged at frame {}", start),
+    }
+}
+
+/// Run the same rom and inputs several times, comparing the machine state
+/// after every frame to find sources of nondeterminism.
+pub fn audit(rom: &[u8], frames: usize, seed: u64) -> Result<(), anyhow::Error> {
+    let inputs = script(seed, frames);
+    let seeded = Setup {
+        rng_seed: Some(seed),
+        jitter_seed: None,
+    };
+
+    // the reference run, and an identical one on two separate threads
+    let (reference, threaded) = std::thread::scope(|s| {
+        let a = s.spawn(|| run(rom, &inputs, seeded));
+        let b = s.spawn(|| run(rom, &inputs, seeded));
+        (a.join().unwrap(), b.join().unwrap())
+    });
+
+    let unseeded = run(
+        rom,
+        &inputs,
+        Setup {
+            rng_seed: None,
+            ..seeded
+        },
+    );
+
+    let jittered = run(
+        rom,
+        &inputs,
+        Setup {
+            jitter_seed: Some(seed),
+            ..seeded
+        },
+    );
+
+    println!("determinism audit ({} frames, seed {}):", frames, seed);
+    if let Some(err) = &reference.error {
+        println!(
+            "  note: rom stopped at frame {}: {}",
+            reference.hashes.len(),
+            err
+        );
+    }
+
+    println!(
+        "  same setup, two threads:  {}",
+        describe(&reference, &threaded)
+    );
+    println!(
+        "  unseeded rng:             {}",
+        describe(&reference, &unseeded)
+    );
+    println!(
+        "  display wait timing:      {}",
+        describe(&reference, &jittered)
+    );
+
+    // a late vblank always shifts execution a bit; it only matters
+    // if the runs never get back to the same state
+    if divergence(&reference, &unseeded).is_some() {
+        println!("  warning: the rom depends on random numbers; seed the rng to replay it");
+    }
+    if reconvergence(&reference, &jittered).is_none() && divergence(&reference, &jittered).is_some()
+    {
+        println!("  warning: the rom depends on when the vblank interrupt arrives");
+    }
+
+    if divergence(&reference, &threaded).is_some() {
+        bail!("identical runs diverged; the emulator itself is not deterministic");
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a new random number on every instruction pair
+    const RANDOM: [u8; 4] = [
+        0xC0, 0xFF, // 0x200: Set V0 = random
+        0x12, 0x00, // 0x202: Jump to 0x200
+    ];
+
+    fn trace(hashes: &[u64]) -> Trace {
+        Trace {
+            hashes: hashes.to_vec(),
+            error: None,
+        }
+    }
+
+    #[test]
+    fn test_script() {
+        let keys = script(7, 3 * INPUT_PERIOD);
+        assert_eq!(keys, script(7, 3 * INPUT_PERIOD));
+
+        // the keys only change every few frames, one at a time
+        assert!(keys.iter().all(|keys| keys.count_ones() <= 1));
+        for chunk in keys.chunks(INPUT_PERIOD) {
+            assert!(chunk.iter().all(|&keys| keys == chunk[0]));
+        }
+    }
+
+    #[test]
+    fn test_same_seed() {
+        let inputs = script(1, 30);
+        let setup = Setup {
+            rng_seed: Some(1),
+            jitter_seed: None,
+        };
+
+        let a = run(&RANDOM, &inputs, setup);
+        let b = run(&RANDOM, &inputs, setup);
+        assert_eq!(a.hashes.len(), 30);
+        assert_eq!(divergence(&a, &b), None);
+        assert_eq!(describe(&a, &b), "identical");
+
+        // another seed draws other numbers from the first frame
+        let c = run(
+            &RANDOM,
+            &inputs,
+            Setup {
+                rng_seed: Some(2),
+                ..setup
+            },
+        );
+        assert_eq!(divergence(&a, &c), Some(0));
+        assert_eq!(describe(&a, &c), "diverged at frame 0");
+    }
+
+    #[test]
+    fn test_mismatch() {
+        let a = trace(&[1, 2, 3, 4]);
+        assert_eq!(
+            describe(&a, &trace(&[1, 5, 6, 4])),
+            "diverged at frame 1, matched again at 3"
+        );
+        assert_eq!(describe(&a, &trace(&[1, 2, 3, 5])), "diverged at frame 3");
+
+        // a run that stopped early differs where it stopped
+        let stopped = Trace {
+            hashes: vec![1, 2],
+            error: Some("crashed".to_owned()),
+        };
+        assert_eq!(divergence(&a, &stopped), Some(2));
+        assert_eq!(reconvergence(&a, &stopped), None);
+    }
+}
@@ -0,0 +1,118 @@
+//! Runs many emulator instances in parallel: a corpus of roms, or many
+//! seeds of the same one. Each run is headless and independent, so they
+//! are spread over a pool of worker threads.
+use rayon::{prelude::*, ThreadPoolBuildError, ThreadPoolBuilder};
+use thiserror::Error;
+
+use super::{
+    emulator::{Emulator, EmulatorError, Quirks},
+    timing::run_frame,
+};
+
+#[derive(Error, Debug)]
+pub enum BatchError {
+    #[error("failed to start the worker threads: {0}")]
+    Pool(#[from] ThreadPoolBuildError),
+}
+
+/// A rom to run, and how
+#[derive(Clone, Copy)]
+pub struct Run<'a> {
+    pub rom: &'a [u8],
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub frames: usize,
+}
+
+/// How a run ended
+#[derive(Debug)]
+pub struct Outcome {
+    /// Frames that ran to the end
+    pub frames: usize,
+    pub screen_hash: u64,
+    pub state_hash: u64,
+
+    /// The error that stopped the rom, if it crashed
+    pub error: Option<EmulatorError>,
+}
+
+impl Run<'_> {
+    /// Run the rom headless, with no keys pressed
+    pub fn run(&self) -> Outcome {
+        let builder = Emulator::builder().quirks(self.quirks).seed(self.seed);
+        let mut emu = match builder.load_rom(self.rom) {
+            Ok(emu) => emu,
+            Err(err) => {
+                return Outcome {
+                    frames: 0,
+                    screen_hash: 0,
+                    state_hash: 0,
+                    error: Some(err),
+                }
+            }
+        };
+
+        let mut frames = 0;
+        let mut error = None;
+        while frames < self.frames {
+            if let Err(err) = run_frame(&mut emu) {
+                error = Some(err);
+                break;
+            }
+            frames += 1;
+        }
+
+        Outcome {
+            frames,
+            screen_hash: emu.screen_hash(),
+            state_hash: emu.state_hash(),
+            error,
+        }
+    }
+}
+
+/// Run everything on `jobs` threads (0 uses one per cpu); the outcomes
+/// come in the same order as the runs
+pub fn run_many(runs: &[Run], jobs: usize) -> Result<Vec<Outcome>, BatchError> {
+    let pool = ThreadPoolBuilder::new().num_threads(jobs).build()?;
+    Ok(pool.install(|| runs.par_iter().map(Run::run).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_many() {
+        let random = [
+            0xC0, 0xFF, // 0x200: V0 = random
+            0x12, 0x00, // 0x202: jump to 0x200
+        ];
+        let crash = [0x00, 0xEE]; // 0x200: return, with an empty stack
+
+        let run = Run {
+            rom: &random,
+            quirks: Quirks::default(),
+            seed: 1,
+            frames: 10,
+        };
+        let runs = [
+            run,
+            Run { seed: 2, ..run },
+            Run { seed: 1, ..run },
+            Run { rom: &crash, ..run },
+        ];
+
+        let outcomes = run_many(&runs, 2).unwrap();
+        assert_eq!(outcomes.len(), 4);
+        assert_eq!(outcomes[0].frames, 10);
+        assert_eq!(outcomes[0].state_hash, outcomes[2].state_hash);
+        assert_ne!(outcomes[0].state_hash, outcomes[1].state_hash);
+
+        assert_eq!(outcomes[3].frames, 0);
+        assert!(matches!(
+            outcomes[3].error,
+            Some(EmulatorError::InvalidReturn(_))
+        ));
+    }
+}
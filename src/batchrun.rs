@@ -0,0 +1,65 @@
+//! Runs a corpus of roms, or many seeds of each one, in parallel and
+//! prints how each run ended.
+use std::time::Instant;
+
+use anyhow::Context;
+
+use super::{
+    batch::{run_many, Run},
+    emulator::Quirks,
+};
+
+pub fn batch_run(
+    filenames: &[String],
+    seeds: u64,
+    frames: usize,
+    jobs: usize,
+) -> Result<(), anyhow::Error> {
+    let roms = filenames
+        .iter()
+        .map(|filename| {
+            std::fs::read(filename).with_context(|| format!("error opening rom file: {}", filename))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let mut names = Vec::new();
+    let mut runs = Vec::new();
+    for (filename, rom) in filenames.iter().zip(&roms) {
+        for seed in 0..seeds {
+            names.push(filename);
+            runs.push(Run {
+                rom,
+                quirks: Quirks::default(),
+                seed,
+                frames,
+            });
+        }
+    }
+
+    let start = Instant::now();
+    let outcomes = run_many(&runs, jobs)?;
+    let elapsed = start.elapsed();
+
+    let mut crashed = 0;
+    for ((name, run), outcome) in names.iter().zip(&runs).zip(&outcomes) {
+        let result = match &outcome.error {
+            Some(err) => {
+                crashed += 1;
+                format!("crashed on frame {}: {}", outcome.frames, err)
+            }
+            None => "ok".to_owned(),
+        };
+        println!(
+            "  {} (seed {}): screen {:016x}, state {:016x}, {}",
+            name, run.seed, outcome.screen_hash, outcome.state_hash, result
+        );
+    }
+
+    println!(
+        "{} run(s), {} crashed, in {:.2} s",
+        runs.len(),
+        crashed,
+        elapsed.as_secs_f64()
+    );
+    Ok(())
+}
@@ -1,37 +1,201 @@
-use sdl2::audio::{AudioCallback, AudioSpec};
+//! The sound generators played by the [`Mixer`](super::mixer::Mixer).
+use clap::ValueEnum;
+use nanorand::{Rng, WyRand};
 
-/// A simple square wave.
+use super::mixer::Source;
+
+// how many times per period the noise picks a new value
+const NOISE_STEPS: f32 = 8.0;
+
+// how long a keypad click lasts, in seconds
+const CLICK_LENGTH: f32 = 0.008;
+
+/// How long the buzzer takes to fade in and out, in seconds; cutting the
+/// wave at full volume makes the speaker pop
+pub const RAMP_LENGTH: f32 = 0.005;
+
+/// Accepted range for the buzzer frequency, in Hz
+pub const MIN_PITCH: f32 = 20.0;
+pub const MAX_PITCH: f32 = 2000.0;
+
+/// Shape of the buzzer sound
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    Noise,
+}
+
+impl Waveform {
+    /// The next waveform, going back to the first after the last one
+    pub fn next(self) -> Self {
+        match self {
+            Waveform::Square => Waveform::Sine,
+            Waveform::Sine => Waveform::Triangle,
+            Waveform::Triangle => Waveform::Noise,
+            Waveform::Noise => Waveform::Square,
+        }
+    }
+}
+
+/// How the buzzer sounds
+#[derive(Clone, Copy)]
+pub struct Tone {
+    pub waveform: Waveform,
+
+    /// Frequency, in Hz
+    pub pitch: f32,
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            waveform: Waveform::Square,
+            pitch: 120.0,
+        }
+    }
+}
+
+impl Tone {
+    /// Move the pitch up or down by a semitone
+    pub fn shift_pitch(&mut self, up: bool) {
+        let factor = 2f32.powf(1.0 / 12.0);
+        let pitch = if up {
+            self.pitch * factor
+        } else {
+            self.pitch / factor
+        };
+
+        self.pitch = pitch.clamp(MIN_PITCH, MAX_PITCH);
+    }
+}
+
+/// The buzzer sound generator.
 /// Adapted from sdl2::audio sample code.
 ///
 /// A good tool for testing tone changes is https://onlinetonegenerator.com/?waveform=square
+#[derive(Clone)]
 pub struct Beep {
-    phase_inc: f32,
+    pub tone: Tone,
+    /// If the buzzer is sounding
+    pub playing: bool,
+    sample_rate: f32,
     phase: f32,
-    volume: f32,
+    rng: WyRand,
+    noise: f32,
+    // volume of the envelope, from 0.0 (silent) to 1.0, and how much it
+    // moves per sample
+    gain: f32,
+    ramp_step: f32,
 }
 
-impl From<AudioSpec> for Beep {
-    fn from(spec: AudioSpec) -> Self {
+impl Beep {
+    pub fn new(sample_rate: f32, tone: Tone) -> Self {
         Beep {
-            phase_inc: 120.0 / spec.freq as f32,
+            tone,
+            playing: false,
+            sample_rate,
             phase: 0.0,
-            volume: 0.10,
+            rng: WyRand::new(),
+            noise: 0.0,
+            gain: 0.0,
+            ramp_step: ramp_step(sample_rate),
         }
     }
+
+    /// Play on a device with another sample rate
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.sample_rate = sample_rate;
+        self.ramp_step = ramp_step(sample_rate);
+    }
+
+    /// Value of the wave at the current phase, from -1.0 to 1.0
+    fn sample(&mut self, phase_inc: f32) -> f32 {
+        match self.tone.waveform {
+            Waveform::Square => {
+                if self.phase <= 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (self.phase * std::f32::consts::TAU).sin(),
+            Waveform::Triangle => 4.0 * (self.phase - 0.5).abs() - 1.0,
+            Waveform::Noise => {
+                // sample and hold, so the noise still follows the pitch
+                let step = (self.phase * NOISE_STEPS).floor();
+                let next_step = ((self.phase + phase_inc) * NOISE_STEPS).floor();
+                if step != next_step || self.noise == 0.0 {
+                    self.noise = self.rng.generate::<f32>() * 2.0 - 1.0;
+                }
+                self.noise
+            }
+        }
+    }
+}
+
+fn ramp_step(sample_rate: f32) -> f32 {
+    1.0 / (RAMP_LENGTH * sample_rate).max(1.0)
+}
+
+impl Source for Beep {
+    fn next_sample(&mut self) -> f32 {
+        // the wave goes on while fading out, instead of stopping right away
+        self.gain = match self.playing {
+            true => (self.gain + self.ramp_step).min(1.0),
+            false => (self.gain - self.ramp_step).max(0.0),
+        };
+        if self.gain == 0.0 {
+            return 0.0;
+        }
+
+        let phase_inc = self.tone.pitch / self.sample_rate;
+        let value = self.sample(phase_inc);
+        self.phase = (self.phase + phase_inc) % 1.0;
+        value * self.gain
+    }
 }
 
-impl AudioCallback for Beep {
-    type Channel = f32;
+/// The soft click of a COSMAC VIP keypad key: a short burst of noise,
+/// fading out
+#[derive(Clone)]
+pub struct Click {
+    length: u32,
+    // samples left of the current click
+    left: u32,
+    rng: WyRand,
+}
 
-    fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
+impl Click {
+    pub fn new(sample_rate: f32) -> Self {
+        Click {
+            length: (CLICK_LENGTH * sample_rate) as u32,
+            left: 0,
+            rng: WyRand::new(),
         }
     }
+
+    /// Play on a device with another sample rate
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        self.length = (CLICK_LENGTH * sample_rate) as u32;
+        self.left = self.left.min(self.length);
+    }
+
+    /// Start a new click, cutting the current one short
+    pub fn trigger(&mut self) {
+        self.left = self.length;
+    }
+}
+
+impl Source for Click {
+    fn next_sample(&mut self) -> f32 {
+        if self.left == 0 {
+            return 0.0;
+        }
+
+        let fade = self.left as f32 / self.length as f32;
+        self.left -= 1;
+        (self.rng.generate::<f32>() * 2.0 - 1.0) * fade
+    }
 }
@@ -0,0 +1,393 @@
+//! Headless benchmark of the interpreter core, to measure the effect of
+//! optimizations on `execute` and friends.
+//!
+//! Each rom runs as fast as possible under each quirks profile, for the
+//! overall speed and the time each frame takes. A single rom also runs once
+//! more timing every instruction, for the breakdown by opcode (which is
+//! slower, as reading the clock costs about as much as an instruction).
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use anyhow::Context;
+use clap::ValueEnum;
+use serde::Serialize;
+
+use super::{
+    emulator::{Emulator, EmulatorError},
+    opcode::Variant,
+    timing::FRAME_CYCLES,
+};
+
+// what a rom in a directory is named like
+const ROM_EXTENSIONS: [&str; 4] = ["ch8", "c8x", "sc8", "xo8"];
+
+/// How the results are printed
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Format {
+    /// A table, with the opcode breakdown for a single rom
+    Text,
+    /// An array with an object per rom and profile
+    Json,
+    /// A header, then a line per rom and profile
+    Csv,
+}
+
+/// How a rom did under a quirks profile; frame times are in microseconds
+#[derive(Serialize, Debug)]
+struct Row {
+    rom: String,
+    variant: String,
+    instructions: u64,
+    seconds: f64,
+    mips: f64,
+    frame_p50: f64,
+    frame_p90: f64,
+    frame_p99: f64,
+    error: Option<String>,
+}
+
+/// What a benchmark run went through
+struct Ran {
+    executed: u64,
+    error: Option<EmulatorError>,
+    frames: Vec<Duration>,
+}
+
+/// Parse an instruction count, accepting `_` as a separator (`10_000_000`)
+pub fn parse_count(s: &str) -> Result<u64, String> {
+    s.replace('_', "")
+        .parse()
+        .map_err(|_| format!("invalid instruction count: {}", s))
+}
+
+/// Run up to `cycles` instructions of the `variant` rom, with a vblank and
+/// a timer tick every frame; returns how many ran, the error that stopped
+/// the rom, if any, and how long each whole frame took
+fn run(
+    rom: &[u8],
+    variant: Variant,
+    cycles: u64,
+    mut execute: impl FnMut(&mut Emulator) -> Result<(), EmulatorError>,
+) -> Result<Ran, EmulatorError> {
+    let mut emu = Emulator::builder().variant(variant).load_rom(rom)?;
+    emu.seed_rng(0);
+
+    let mut frames = Vec::with_capacity((cycles / FRAME_CYCLES as u64) as usize);
+    let mut frame_start = Instant::now();
+    for executed in 0..cycles {
+        if executed % FRAME_CYCLES as u64 == 0 {
+            if executed > 0 {
+                let now = Instant::now();
+                frames.push(now - frame_start);
+                frame_start = now;
+            }
+            emu.decrease_timers();
+            emu.vblank();
+        }
+
+        if let Err(err) = execute(&mut emu) {
+            return Ok(Ran {
+                executed,
+                error: Some(err),
+                frames,
+            });
+        }
+    }
+
+    Ok(Ran {
+        executed: cycles,
+        error: None,
+        frames,
+    })
+}
+
+/// The `p` percentile of the sorted `times`, in microseconds
+fn percentile(times: &[Duration], p: usize) -> f64 {
+    match times.len() {
+        0 => 0.0,
+        len => times[(len * p).div_ceil(100).max(1) - 1].as_secs_f64() * 1_000_000.0,
+    }
+}
+
+/// Run the rom at full speed, for the overall numbers
+fn measure(name: &str, rom: &[u8], variant: Variant, cycles: u64) -> Row {
+    let start = Instant::now();
+    let ran = run(rom, variant, cycles, Emulator::execute);
+    let elapsed = start.elapsed().as_secs_f64();
+
+    let (executed, error, mut frames) = match ran {
+        Ok(ran) => (
+            ran.executed,
+            ran.error.map(|err| err.to_string()),
+            ran.frames,
+        ),
+        Err(err) => (0, Some(err.to_string()), Vec::new()),
+    };
+    frames.sort();
+
+    Row {
+        rom: name.to_owned(),
+        variant: variant.to_string(),
+        instructions: executed,
+        seconds: elapsed,
+        mips: executed as f64 / elapsed.max(f64::EPSILON) / 1_000_000.0,
+        frame_p50: percentile(&frames, 50),
+        frame_p90: percentile(&frames, 90),
+        frame_p99: percentile(&frames, 99),
+        error,
+    }
+}
+
+/// The rom files to run: `path` itself, or the roms inside it, by name
+fn rom_files(path: &Path) -> Result<Vec<PathBuf>, anyhow::Error> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_owned()]);
+    }
+
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(path)
+        .with_context(|| format!("error reading the directory: {}", path.display()))?
+    {
+        let file = entry?.path();
+        let is_rom = file
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ROM_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()));
+        if is_rom && file.is_file() {
+            files.push(file);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Time spent on each instruction pattern
+#[derive(Default)]
+struct Breakdown {
+    count: u64,
+    time: Duration,
+}
+
+fn breakdown(
+    rom: &[u8],
+    variant: Variant,
+    cycles: u64,
+) -> Result<HashMap<&'static str, Breakdown>, EmulatorError> {
+    let mut patterns: HashMap<&'static str, Breakdown> = HashMap::new();
+
+    run(rom, variant, cycles, |emu| {
+        let pattern = emu.next_opcode().map_or("????", |op| op.pattern());
+
+        let start = Instant::now();
+        let result = emu.execute();
+        let entry = patterns.entry(pattern).or_default();
+        entry.time += start.elapsed();
+        entry.count += 1;
+        result
+    })?;
+
+    Ok(patterns)
+}
+
+/// Print how fast each rom runs under each of the `variants`; for a single
+/// rom and profile, on text, also where the time goes
+pub fn bench(
+    path: &Path,
+    variants: &[Variant],
+    cycles: u64,
+    format: Format,
+) -> Result<(), anyhow::Error> {
+    let files = rom_files(path)?;
+    if files.is_empty() {
+        anyhow::bail!("no roms found in {}", path.display());
+    }
+
+    let mut rows = Vec::new();
+    let mut roms = Vec::new();
+    for file in &files {
+        let rom = std::fs::read(file)
+            .with_context(|| format!("error opening rom file: {}", file.display()))?;
+        let name = file.display().to_string();
+        for &variant in variants {
+            rows.push(measure(&name, &rom, variant, cycles));
+        }
+        roms.push(rom);
+    }
+
+    match format {
+        Format::Text => print!("{}", to_text(&rows)),
+        Format::Json => println!("{}", serde_json::to_string_pretty(&rows)?),
+        Format::Csv => print!("{}", to_csv(&rows)),
+    }
+
+    if let ([rom], [variant], Format::Text) = (&roms[..], variants, format) {
+        print_breakdown(rom, *variant, cycles, rows[0].instructions)?;
+    }
+    Ok(())
+}
+
+fn to_text(rows: &[Row]) -> String {
+    let width = rows
+        .iter()
+        .map(|row| row.rom.len())
+        .max()
+        .unwrap_or(0)
+        .max(3);
+    let mut text = format!(
+        "{:<width$} {:<7} {:>12} {:>8} {:>9} {:>9} {:>9}\n",
+        "rom", "variant", "instructions", "mips", "p50 us", "p90 us", "p99 us"
+    );
+    for row in rows {
+        text += &format!(
+            "{:<width$} {:<7} {:>12} {:>8.2} {:>9.1} {:>9.1} {:>9.1}\n",
+            row.rom,
+            row.variant,
+            row.instructions,
+            row.mips,
+            row.frame_p50,
+            row.frame_p90,
+            row.frame_p99
+        );
+        if let Some(err) = &row.error {
+            text += &format!(
+                "  stopped after {} instructions: {}\n",
+                row.instructions, err
+            );
+        }
+    }
+    text
+}
+
+fn to_csv(rows: &[Row]) -> String {
+    // names with commas or quotes are quoted, with the quotes doubled
+    let field = |text: &str| match text.contains([',', '"', '\n']) {
+        true => format!("\"{}\"", text.replace('"', "\"\"")),
+        false => text.to_owned(),
+    };
+
+    let mut csv =
+        String::from("rom,variant,instructions,seconds,mips,frame_p50,frame_p90,frame_p99,error\n");
+    for row in rows {
+        csv += &format!(
+            "{},{},{},{:.6},{:.3},{:.1},{:.1},{:.1},{}\n",
+            field(&row.rom),
+            field(&row.variant),
+            row.instructions,
+            row.seconds,
+            row.mips,
+            row.frame_p50,
+            row.frame_p90,
+            row.frame_p99,
+            field(row.error.as_deref().unwrap_or(""))
+        );
+    }
+    csv
+}
+
+/// Where the time goes, by opcode
+fn print_breakdown(
+    rom: &[u8],
+    variant: Variant,
+    cycles: u64,
+    executed: u64,
+) -> Result<(), anyhow::Error> {
+    let mut patterns: Vec<_> = breakdown(rom, variant, cycles)?.into_iter().collect();
+    patterns.sort_by_key(|(_, entry)| std::cmp::Reverse(entry.time));
+    let total: Duration = patterns.iter().map(|(_, entry)| entry.time).sum();
+
+    println!();
+    println!(
+        "  {:<7} {:>12} {:>7} {:>8} {:>7}",
+        "opcode", "count", "count%", "avg ns", "time%"
+    );
+    for (pattern, entry) in patterns {
+        println!(
+            "  {:<7} {:>12} {:>6.1}% {:>8.1} {:>6.1}%",
+            pattern,
+            entry.count,
+            entry.count as f64 * 100.0 / executed.max(1) as f64,
+            entry.time.as_nanos() as f64 / entry.count as f64,
+            entry.time.as_secs_f64() * 100.0 / total.as_secs_f64().max(f64::EPSILON)
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breakdown() {
+        let rom = [
+            0x60, 0x01, // 0x200: V0 = 1
+            0x70, 0x01, // 0x202: V0 += 1
+            0x12, 0x02, // 0x204: jump to 0x202
+        ];
+
+        let patterns = breakdown(&rom, Variant::Chip8, 101).unwrap();
+        assert_eq!(patterns["6XNN"].count, 1);
+        assert_eq!(patterns["7XNN"].count, 50);
+        assert_eq!(patterns["1NNN"].count, 50);
+
+        assert_eq!(parse_count("10_000"), Ok(10_000));
+        assert!(parse_count("lots").is_err());
+    }
+
+    #[test]
+    fn test_stops_on_error() {
+        let rom = [0x00, 0xEE]; // 0x200: return, with an empty stack
+        let ran = run(&rom, Variant::Chip8, 10, Emulator::execute).unwrap();
+        assert_eq!(ran.executed, 0);
+        assert!(matches!(ran.error, Some(EmulatorError::InvalidReturn(_))));
+    }
+
+    #[test]
+    fn test_report() {
+        let rom = [0x12, 0x00]; // 0x200: jump to 0x200
+        let row = measure(
+            "a, \"b\".ch8",
+            &rom,
+            Variant::SChip,
+            FRAME_CYCLES as u64 * 5,
+        );
+        assert_eq!(row.instructions, FRAME_CYCLES as u64 * 5);
+        assert_eq!(row.variant, "SCHIP");
+        assert!(row.frame_p50 <= row.frame_p90 && row.frame_p90 <= row.frame_p99);
+        assert!(row.error.is_none());
+
+        let csv = to_csv(&[row]);
+        let mut lines = csv.lines();
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("rom,variant,instructions,"));
+        assert!(lines
+            .next()
+            .unwrap()
+            .starts_with("\"a, \"\"b\"\".ch8\",SCHIP,"));
+        assert!(lines.next().is_none());
+    }
+
+    #[test]
+    fn test_percentile() {
+        let times: Vec<_> = (1..=100).map(Duration::from_micros).collect();
+        assert_eq!(percentile(&times, 50), 50.0);
+        assert_eq!(percentile(&times, 99), 99.0);
+        assert_eq!(percentile(&times[..1], 90), 1.0);
+        assert_eq!(percentile(&[], 50), 0.0);
+    }
+
+    #[test]
+    fn test_rom_files() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("roms");
+        let files = rom_files(&dir).unwrap();
+        assert!(files.iter().any(|file| file.ends_with("ibm-logo.ch8")));
+        assert!(!files.iter().any(|file| file.ends_with("README.TXT")));
+        assert!(files.windows(2).all(|pair| pair[0] < pair[1]));
+    }
+}
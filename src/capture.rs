@@ -0,0 +1,211 @@
+//! Records the screen and the sound into a video file, through ffmpeg.
+//!
+//! Frames are piped raw to an `ffmpeg` process found on the PATH, at a steady
+//! 60 frames per second, and the sound is saved next to them as a WAV file
+//! (see [`crate::wav`]); once stopped, both are muxed into a single file, on
+//! a thread of its own so the game does not stop meanwhile.
+use std::{
+    io::{self, Write},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::Sender,
+    thread::JoinHandle,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+use sdl2::surface::Surface;
+
+use super::wav::WavRecorder;
+
+const FPS: u128 = 60;
+
+// how SDL keeps RGBA8888 pixels on memory, as an ffmpeg pixel format
+const PIXEL_FORMAT: &str = if cfg!(target_endian = "little") {
+    "abgr"
+} else {
+    "rgba"
+};
+
+/// Container and codecs of the videos
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum CaptureFormat {
+    /// H.264 and AAC
+    Mp4,
+    /// VP9 and Opus
+    Webm,
+}
+
+impl CaptureFormat {
+    fn extension(self) -> &'static str {
+        match self {
+            CaptureFormat::Mp4 => "mp4",
+            CaptureFormat::Webm => "webm",
+        }
+    }
+
+    fn video_codec(self) -> &'static [&'static str] {
+        match self {
+            CaptureFormat::Mp4 => &[
+                "-c:v", "libx264", "-preset", "veryfast", "-crf", "18", "-pix_fmt", "yuv420p",
+            ],
+            CaptureFormat::Webm => &["-c:v", "libvpx-vp9", "-crf", "30", "-b:v", "0"],
+        }
+    }
+
+    fn audio_codec(self) -> &'static [&'static str] {
+        match self {
+            CaptureFormat::Mp4 => &["-c:a", "aac", "-b:a", "128k"],
+            CaptureFormat::Webm => &["-c:a", "libopus", "-b:a", "96k"],
+        }
+    }
+}
+
+/// A video being recorded
+pub struct Capture {
+    format: CaptureFormat,
+    ffmpeg: Child,
+    stdin: ChildStdin,
+    audio: WavRecorder,
+    // where the video goes, and the files it is made of until then
+    path: PathBuf,
+    video_path: PathBuf,
+    audio_path: PathBuf,
+    // when it started, in microseconds, and how many frames went so far
+    start: u128,
+    frames: u128,
+}
+
+impl Capture {
+    /// Start recording `width`x`height` frames, and the sound played at
+    /// `sample_rate`, into a timestamped file on the current directory
+    pub fn start(
+        format: CaptureFormat,
+        width: u32,
+        height: u32,
+        sample_rate: u32,
+        now: u128,
+    ) -> io::Result<Self> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let name = format!("rc8-capture-{}", timestamp);
+        let path = PathBuf::from(format!("{}.{}", name, format.extension()));
+        let video_path = PathBuf::from(format!("{}-video.{}", name, format.extension()));
+        let audio_path = PathBuf::from(format!("{}-audio.wav", name));
+
+        let mut ffmpeg = Command::new("ffmpeg")
+            .args(["-y", "-loglevel", "error", "-f", "rawvideo"])
+            .args(["-pixel_format", PIXEL_FORMAT])
+            .args(["-video_size", &format!("{}x{}", width, height)])
+            .args(["-framerate", &FPS.to_string(), "-i", "pipe:0"])
+            .args(format.video_codec())
+            .arg(&video_path)
+            .stdin(Stdio::piped())
+            .spawn()?;
+        let stdin = ffmpeg.stdin.take().expect("ffmpeg stdin is piped");
+        let audio = WavRecorder::create(&audio_path, sample_rate)?;
+
+        Ok(Capture {
+            format,
+            ffmpeg,
+            stdin,
+            audio,
+            path,
+            video_path,
+            audio_path,
+            start: now,
+            frames: 0,
+        })
+    }
+
+    /// Where the mixer sends what it plays
+    pub fn audio(&self) -> Sender<Vec<f32>> {
+        self.audio.sender()
+    }
+
+    /// The file the video is saved to
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Send `surface` as many times as needed to keep the video on time:
+    /// more than once if the app is running slow, or not at all if a frame
+    /// was already sent for now
+    pub fn frame(&mut self, surface: &Surface, now: u128) -> io::Result<()> {
+        let due = (now - self.start) * FPS / 1_000_000 + 1;
+        if self.frames >= due {
+            return Ok(());
+        }
+
+        let row = surface.width() as usize * 4;
+        let pitch = surface.pitch() as usize;
+        let pixels = surface
+            .without_lock()
+            .ok_or_else(|| io::Error::other("the screen surface is locked"))?;
+        let mut frame = Vec::with_capacity(row * surface.height() as usize);
+        for line in pixels.chunks(pitch) {
+            frame.extend_from_slice(&line[..row]);
+        }
+
+        while self.frames < due {
+            self.stdin.write_all(&frame)?;
+            self.frames += 1;
+        }
+        Ok(())
+    }
+
+    /// Stop recording; the video is finished on the returned thread, which
+    /// tells where it was saved
+    pub fn stop(self) -> JoinHandle<Result<PathBuf, String>> {
+        std::thread::spawn(move || {
+            let Capture {
+                format,
+                mut ffmpeg,
+                stdin,
+                audio,
+                path,
+                video_path,
+                audio_path,
+                ..
+            } = self;
+
+            // closing the pipe lets ffmpeg finish the video
+            drop(stdin);
+            let video = ffmpeg.wait();
+            let audio = audio.finish();
+            let result = match (video, audio) {
+                (Ok(status), Ok(_)) if status.success() => {
+                    mux(format, &video_path, &audio_path, &path)
+                }
+                (Ok(status), Ok(_)) => Err(format!("ffmpeg failed: {}", status)),
+                (Err(err), _) | (_, Err(err)) => Err(err.to_string()),
+            };
+
+            let _ = std::fs::remove_file(&video_path);
+            let _ = std::fs::remove_file(&audio_path);
+            result.map(|_| path)
+        })
+    }
+}
+
+/// Put the video and the sound together, without encoding the video again
+fn mux(format: CaptureFormat, video: &Path, audio: &Path, path: &Path) -> Result<(), String> {
+    let status = Command::new("ffmpeg")
+        .args(["-y", "-loglevel", "error", "-i"])
+        .arg(video)
+        .arg("-i")
+        .arg(audio)
+        .args(["-c:v", "copy"])
+        .args(format.audio_codec())
+        .arg("-shortest")
+        .arg(path)
+        .status()
+        .map_err(|err| err.to_string())?;
+
+    match status.success() {
+        true => Ok(()),
+        false => Err(format!("ffmpeg failed: {}", status)),
+    }
+}
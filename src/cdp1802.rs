@@ -0,0 +1,396 @@
+//! A small RCA CDP1802 interpreter, for the machine code subroutines (0NNN)
+//! used by hybrid COSMAC VIP roms.
+//!
+//! Only the cpu is emulated: there are no interrupts or DMA, input ports
+//! read as zero and the EF flags are never set, which is all the usual
+//! routines (display tricks, faster math, custom timers) need.
+//!
+//! ```
+//! use rc8::cdp1802::Cdp1802;
+//!
+//! let mut memory = [0u8; 0x1000];
+//! memory[0x300..0x304].copy_from_slice(&[
+//!     0xF8, 0x2A, // 0x300: LDI 0x2A
+//!     0xA7, //       0x302: PLO R7
+//!     0xD4, //       0x303: SEP R4
+//! ]);
+//!
+//! let mut cpu = Cdp1802::default();
+//! cpu.P = 3;
+//! cpu.R[3] = 0x300;
+//! cpu.run_until_sep(&mut memory, 4, 100).unwrap();
+//! assert_eq!(cpu.R[7], 0x2A);
+//! ```
+use thiserror::Error;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum Cdp1802Error {
+    #[error("invalid 1802 instruction at address {1:#05X}: {0:02X}")]
+    InvalidInstruction(u8, u16),
+
+    #[error("1802 machine code waiting for an interrupt at address {0:#05X}")]
+    Idle(u16),
+
+    #[error("1802 machine code at address {0:#05X} did not return")]
+    Timeout(u16),
+}
+
+/// The 1802 registers
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[allow(non_snake_case)]
+pub struct Cdp1802 {
+    // scratchpad registers; any of them can be the program counter
+    pub R: [u16; 16],
+
+    // accumulator
+    pub D: u8,
+
+    // carry / no borrow
+    pub DF: bool,
+
+    // which R is the data pointer
+    pub X: u8,
+
+    // which R is the program counter
+    pub P: u8,
+
+    // X and P saved by MARK, for interrupts and subroutines
+    pub T: u8,
+
+    // output flip-flop (the speaker, on the VIP)
+    pub Q: bool,
+
+    // interrupts enabled
+    pub IE: bool,
+}
+
+impl Cdp1802 {
+    /// Run until the program counter changes to `R[p]` (usually, a `SEP`
+    /// back to the caller), or fail after `limit` instructions.
+    pub fn run_until_sep(
+        &mut self,
+        memory: &mut [u8],
+        p: u8,
+        limit: usize,
+    ) -> Result<(), Cdp1802Error> {
+        let start = self.R[self.P as usize];
+        for _ in 0..limit {
+            self.step(memory)?;
+            if self.P == p {
+                return Ok(());
+            }
+        }
+
+        Err(Cdp1802Error::Timeout(start))
+    }
+
+    /// Execute a single instruction.
+    pub fn step(&mut self, memory: &mut [u8]) -> Result<(), Cdp1802Error> {
+        let addr = self.R[self.P as usize];
+        let opcode = self.fetch(memory);
+        let n = (opcode & 0xF) as usize;
+        let x = self.X as usize;
+
+        match opcode >> 4 {
+            // 00 - IDL, wait for an interrupt that never comes
+            0x0 if n == 0 => return Err(Cdp1802Error::Idle(addr)),
+            // 0N - LDN, D = M(RN)
+            0x0 => self.D = read(memory, self.R[n]),
+            // 1N - INC
+            0x1 => self.R[n] = self.R[n].wrapping_add(1),
+            // 2N - DEC
+            0x2 => self.R[n] = self.R[n].wrapping_sub(1),
+            // 3N - short branches, on the same page
+            0x3 => {
+                let target = self.fetch(memory);
+                if self.condition(n) {
+                    let pc = &mut self.R[self.P as usize];
+                    *pc = (*pc & 0xFF00) | target as u16;
+                }
+            }
+            // 4N - LDA, D = M(RN) and increment RN
+            0x4 => {
+                self.D = read(memory, self.R[n]);
+                self.R[n] = self.R[n].wrapping_add(1);
+            }
+            // 5N - STR, M(RN) = D
+            0x5 => write(memory, self.R[n], self.D),
+            // 60 - IRX
+            0x6 if n == 0 => self.R[x] = self.R[x].wrapping_add(1),
+            // 61-67 - OUT, nothing is listening
+            0x6 if n < 8 => self.R[x] = self.R[x].wrapping_add(1),
+            // 69-6F - INP, no device sends anything
+            0x6 if n > 8 => {
+                self.D = 0;
+                write(memory, self.R[x], 0);
+            }
+            0x7 => self.misc(memory, n, addr)?,
+            // 8N - GLO
+            0x8 => self.D = self.R[n] as u8,
+            // 9N - GHI
+            0x9 => self.D = (self.R[n] >> 8) as u8,
+            // AN - PLO
+            0xA => self.R[n] = (self.R[n] & 0xFF00) | self.D as u16,
+            // BN - PHI
+            0xB => self.R[n] = (self.R[n] & 0x00FF) | ((self.D as u16) << 8),
+            0xC => self.long_branch(memory, n),
+            // DN - SEP
+            0xD => self.P = n as u8,
+            // EN - SEX
+            0xE => self.X = n as u8,
+            // F6 / FE - SHR / SHL, with no immediate byte
+            0xF if n == 0x6 => self.shift_right(false),
+            0xF if n == 0xE => self.shift_left(false),
+            // FN - arithmetic and logic; F8-FF use an immediate byte
+            0xF => {
+                let operand = if n < 8 {
+                    read(memory, self.R[x])
+                } else {
+                    self.fetch(memory)
+                };
+                match n & 0x7 {
+                    // LDX / LDI
+                    0x0 => self.D = operand,
+                    0x1 => self.D |= operand,
+                    0x2 => self.D &= operand,
+                    0x3 => self.D ^= operand,
+                    0x4 => self.add(operand, self.D, false),
+                    // SD, M - D
+                    0x5 => self.add(operand, !self.D, true),
+                    // SM, D - M
+                    _ => self.add(self.D, !operand, true),
+                }
+            }
+            _ => return Err(Cdp1802Error::InvalidInstruction(opcode, addr)),
+        }
+
+        Ok(())
+    }
+
+    /// 7N - interrupt control, stack and arithmetic with carry
+    fn misc(&mut self, memory: &mut [u8], n: usize, addr: u16) -> Result<(), Cdp1802Error> {
+        let x = self.X as usize;
+        match n {
+            // RET / DIS
+            0x0 | 0x1 => {
+                let value = read(memory, self.R[x]);
+                self.R[x] = self.R[x].wrapping_add(1);
+                self.X = value >> 4;
+                self.P = value & 0xF;
+                self.IE = n == 0x0;
+            }
+            // LDXA
+            0x2 => {
+                self.D = read(memory, self.R[x]);
+                self.R[x] = self.R[x].wrapping_add(1);
+            }
+            // STXD
+            0x3 => {
+                write(memory, self.R[x], self.D);
+                self.R[x] = self.R[x].wrapping_sub(1);
+            }
+            // SAV
+            0x8 => write(memory, self.R[x], self.T),
+            // MARK
+            0x9 => {
+                self.T = (self.X << 4) | self.P;
+                write(memory, self.R[2], self.T);
+                self.X = self.P;
+                self.R[2] = self.R[2].wrapping_sub(1);
+            }
+            // REQ / SEQ
+            0xA => self.Q = false,
+            0xB => self.Q = true,
+            // SHRC / SHLC
+            0x6 => self.shift_right(true),
+            0xE => self.shift_left(true),
+            // ADC, SDB, SMB and their immediate versions
+            0x4 | 0x5 | 0x7 | 0xC | 0xD | 0xF => {
+                let operand = if n < 8 {
+                    read(memory, self.R[x])
+                } else {
+                    self.fetch(memory)
+                };
+                match n & 0x7 {
+                    0x4 => self.add(operand, self.D, self.DF),
+                    0x5 => self.add(operand, !self.D, self.DF),
+                    _ => self.add(self.D, !operand, self.DF),
+                }
+            }
+            _ => return Err(Cdp1802Error::InvalidInstruction(0x70 | n as u8, addr)),
+        }
+
+        Ok(())
+    }
+
+    /// CN - long branches and skips
+    fn long_branch(&mut self, memory: &[u8], n: usize) {
+        let pc = self.P as usize;
+        let taken = match n {
+            // NOP
+            0x4 => return,
+            // LSIE
+            0xC => self.IE,
+            // LSNQ, LSNZ, LSNF, LSQ, LSZ, LSDF
+            0x5..=0x7 | 0xD..=0xF => self.condition(n & 0x3) == (n > 8),
+            // LBR, LBQ, LBZ, LBDF, LSKP, LBNQ, LBNZ, LBNF
+            _ => {
+                if self.condition(n) {
+                    let high = read(memory, self.R[pc]);
+                    let low = read(memory, self.R[pc].wrapping_add(1));
+                    self.R[pc] = u16::from_be_bytes([high, low]);
+                } else {
+                    self.R[pc] = self.R[pc].wrapping_add(2);
+                }
+                return;
+            }
+        };
+
+        if taken {
+            self.R[pc] = self.R[pc].wrapping_add(2);
+        }
+    }
+
+    /// Branch conditions: 0 always, 1 Q, 2 D == 0, 3 DF, 4-7 EF1-EF4;
+    /// 8-F are the same ones, negated.
+    fn condition(&self, n: usize) -> bool {
+        let result = match n & 0x7 {
+            0x0 => true,
+            0x1 => self.Q,
+            0x2 => self.D == 0,
+            0x3 => self.DF,
+            _ => false,
+        };
+
+        result != (n >= 8)
+    }
+
+    fn fetch(&mut self, memory: &[u8]) -> u8 {
+        let pc = &mut self.R[self.P as usize];
+        let value = read(memory, *pc);
+        *pc = pc.wrapping_add(1);
+        value
+    }
+
+    /// D = a + b + carry; subtractions add the complement, so DF ends up
+    /// set when there is no borrow
+    fn add(&mut self, a: u8, b: u8, carry: bool) {
+        let sum = a as u16 + b as u16 + carry as u16;
+        self.D = sum as u8;
+        self.DF = sum > 0xFF;
+    }
+
+    fn shift_right(&mut self, with_carry: bool) {
+        let carry = (with_carry && self.DF) as u8;
+        self.DF = self.D & 0x1 == 1;
+        self.D = (self.D >> 1) | (carry << 7);
+    }
+
+    fn shift_left(&mut self, with_carry: bool) {
+        let carry = (with_carry && self.DF) as u8;
+        self.DF = self.D & 0x80 != 0;
+        self.D = (self.D << 1) | carry;
+    }
+}
+
+// addresses wrap around the available memory, like the partially decoded
+// address lines on the VIP
+fn read(memory: &[u8], addr: u16) -> u8 {
+    memory[addr as usize % memory.len()]
+}
+
+fn write(memory: &mut [u8], addr: u16, value: u8) {
+    let len = memory.len();
+    memory[addr as usize % len] = value;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn run(code: &[u8]) -> Cdp1802 {
+        let mut memory = [0u8; 0x1000];
+        memory[0x300..0x300 + code.len()].copy_from_slice(code);
+
+        let mut cpu = Cdp1802 {
+            P: 3,
+            X: 2,
+            ..Cdp1802::default()
+        };
+        cpu.R[2] = 0xECF;
+        cpu.R[3] = 0x300;
+        cpu.run_until_sep(&mut memory, 4, 1000).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        let cpu = run(&[
+            0xF8, 0xF0, // 0x300: LDI 0xF0
+            0xFC, 0x20, // 0x302: ADI 0x20, sets DF
+            0xA5, //       0x304: PLO R5
+            0xF8, 0x10, // 0x305: LDI 0x10
+            0xFF, 0x20, // 0x307: SMI 0x20, borrows
+            0xB5, //       0x309: PHI R5
+            0xF6, //       0x30A: SHR
+            0xD4, //       0x30B: SEP R4
+        ]);
+
+        assert_eq!(cpu.R[5], 0xF010);
+        assert_eq!(cpu.D, 0x78);
+        assert!(!cpu.DF);
+    }
+
+    #[test]
+    fn test_branches() {
+        let cpu = run(&[
+            0xF8, 0x03, // 0x300: LDI 3
+            0xFF, 0x01, // 0x302: SMI 1
+            0x3A, 0x02, // 0x304: BNZ 0x302
+            0x1A, //       0x306: INC RA
+            0xC2, 0x03, 0x0C, // 0x307: LBZ 0x30C
+            0x1A, //       0x30A: INC RA, skipped
+            0x00, //       0x30B: IDL, skipped
+            0xD4, //       0x30C: SEP R4
+        ]);
+
+        assert_eq!(cpu.D, 0);
+        assert_eq!(cpu.R[0xA], 1);
+    }
+
+    #[test]
+    fn test_stack() {
+        let cpu = run(&[
+            0xF8, 0x55, // 0x300: LDI 0x55
+            0x73, //       0x302: STXD
+            0xF8, 0x00, // 0x303: LDI 0
+            0x60, //       0x305: IRX
+            0xF0, //       0x306: LDX
+            0xD4, //       0x307: SEP R4
+        ]);
+
+        assert_eq!(cpu.D, 0x55);
+        assert_eq!(cpu.R[2], 0xECF);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut memory = [0u8; 0x1000];
+        let mut cpu = Cdp1802::default();
+        assert_eq!(cpu.step(&mut memory), Err(Cdp1802Error::Idle(0)));
+
+        memory[0x300..0x302].copy_from_slice(&[0x30, 0x00]); // BR 0x300
+        cpu.R[0] = 0x300;
+        assert_eq!(
+            cpu.run_until_sep(&mut memory, 4, 100),
+            Err(Cdp1802Error::Timeout(0x300))
+        );
+
+        memory[0x300] = 0x68;
+        cpu.R[0] = 0x300;
+        assert_eq!(
+            cpu.step(&mut memory),
+            Err(Cdp1802Error::InvalidInstruction(0x68, 0x300))
+        );
+    }
+}
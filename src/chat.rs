@@ -0,0 +1,132 @@
+//! A chat line for netplay: the last few messages at the bottom of the
+//! screen, and the one being typed.
+use std::{collections::VecDeque, time::Duration};
+
+// how long a message stays up, how many show at once, and how long one
+// can be, in characters
+const SHOW_DURATION: Duration = Duration::from_secs(10);
+const LINES: usize = 4;
+const MAX_LENGTH: usize = 100;
+
+/// The messages of both players, and the one being typed
+#[derive(Default)]
+pub struct Chat {
+    lines: VecDeque<(String, u128)>,
+    typing: Option<String>,
+}
+
+impl Chat {
+    /// Show `line` from `now` (in microseconds) on
+    pub fn push(&mut self, line: String, now: u128) {
+        self.lines.push_back((line, now));
+        if self.lines.len() > LINES {
+            self.lines.pop_front();
+        }
+    }
+
+    /// If a message is being typed
+    pub fn is_typing(&self) -> bool {
+        self.typing.is_some()
+    }
+
+    /// If anything is up, which will need redrawing as it goes away
+    pub fn is_active(&self) -> bool {
+        self.is_typing() || !self.lines.is_empty()
+    }
+
+    /// Start typing a message
+    pub fn open(&mut self) {
+        self.typing = Some(String::new());
+    }
+
+    /// Add `text` to the message being typed, as long as it fits
+    pub fn type_text(&mut self, text: &str) {
+        if let Some(typing) = &mut self.typing {
+            let room = MAX_LENGTH - typing.chars().count();
+            typing.extend(text.chars().take(room));
+        }
+    }
+
+    /// Take back the last character typed
+    pub fn erase(&mut self) {
+        if let Some(typing) = &mut self.typing {
+            typing.pop();
+        }
+    }
+
+    /// Stop typing, returning the message to send, if there is one
+    pub fn send(&mut self) -> Option<String> {
+        let typed = self.typing.take()?;
+        let typed = typed.trim();
+        (!typed.is_empty()).then(|| typed.to_owned())
+    }
+
+    /// Stop typing, throwing the message away
+    pub fn cancel(&mut self) {
+        self.typing = None;
+    }
+
+    /// What to show at `now`: the recent messages, and the one being typed;
+    /// while typing, the older messages stay up too
+    pub fn visible(&mut self, now: u128) -> Vec<String> {
+        if self.typing.is_none() {
+            self.lines
+                .retain(|&(_, shown)| now.saturating_sub(shown) < SHOW_DURATION.as_micros());
+        }
+
+        let mut lines: Vec<_> = self.lines.iter().map(|(line, _)| line.clone()).collect();
+        if let Some(typing) = &self.typing {
+            lines.push(format!("say: {}_", typing));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines() {
+        let mut chat = Chat::default();
+        assert!(!chat.is_active());
+
+        for i in 0..6 {
+            chat.push(format!("host: {}", i), 1_000_000);
+        }
+        assert_eq!(
+            chat.visible(2_000_000),
+            ["host: 2", "host: 3", "host: 4", "host: 5"]
+        );
+
+        // old messages go away, unless someone is typing
+        chat.open();
+        assert_eq!(chat.visible(20_000_000).len(), 5);
+        chat.cancel();
+        assert!(chat.visible(20_000_000).is_empty());
+        assert!(!chat.is_active());
+    }
+
+    #[test]
+    fn test_typing() {
+        let mut chat = Chat::default();
+        chat.type_text("ignored");
+        assert!(!chat.is_typing());
+
+        chat.open();
+        chat.type_text("gg ");
+        chat.type_text("wp!");
+        chat.erase();
+        assert_eq!(chat.visible(0), ["say: gg wp_"]);
+        assert_eq!(chat.send(), Some("gg wp".to_owned()));
+        assert!(!chat.is_typing());
+
+        // long and empty messages
+        chat.open();
+        chat.type_text(&"a".repeat(150));
+        assert_eq!(chat.send().map(|sent| sent.len()), Some(MAX_LENGTH));
+        chat.open();
+        chat.type_text("  ");
+        assert_eq!(chat.send(), None);
+    }
+}
@@ -0,0 +1,232 @@
+use anyhow::bail;
+
+use super::{
+    disasm::{disassemble, ENTRY_POINT},
+    emulator::{MAX_FULL_ROM_SIZE, MAX_ROM_SIZE},
+    opcode::Opcode,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Severity {
+    Warning,
+    Error,
+}
+
+/// Something wrong (or suspicious) about the rom
+#[derive(Debug)]
+struct Finding {
+    severity: Severity,
+    address: Option<u16>,
+    message: String,
+}
+
+/// Instructions from the CHIP-8 extensions, as (variant, description)
+pub fn variant_hint(word: u16) -> Option<(&'static str, &'static str)> {
+    let x_low = word & 0xF0FF;
+
+    let hint = match word {
+        0x00FB => ("SCHIP", "scroll right"),
+        0x00FC => ("SCHIP", "scroll left"),
+        0x00FD => ("SCHIP", "exit"),
+        0x00FE => ("SCHIP", "low resolution"),
+        0x00FF => ("SCHIP", "high resolution"),
+        0xF000 => ("XO-CHIP", "long i := NNNN"),
+        0xF002 => ("XO-CHIP", "audio pattern"),
+        _ if word & 0xFFF0 == 0x00C0 => ("SCHIP", "scroll down"),
+        _ if word & 0xFFF0 == 0x00D0 => ("XO-CHIP", "scroll up"),
+        _ if x_low == 0xF030 => ("SCHIP", "big font sprite"),
+        _ if x_low == 0xF075 => ("SCHIP", "save flags"),
+        _ if x_low == 0xF085 => ("SCHIP", "load flags"),
+        _ if x_low == 0xF001 => ("XO-CHIP", "select drawing plane"),
+        _ if x_low == 0xF03A => ("XO-CHIP", "audio pitch"),
+        _ if word & 0xF00F == 0x5002 => ("XO-CHIP", "save register range"),
+        _ if word & 0xF00F == 0x5003 => ("XO-CHIP", "load register range"),
+        _ => return None,
+    };
+
+    Some(hint)
+}
+
+fn findings(rom: &[u8]) -> (Vec<Finding>, Vec<&'static str>) {
+    let mut findings = Vec::new();
+    let mut variants = Vec::new();
+    let mut report = |severity, address, message| {
+        findings.push(Finding {
+            severity,
+            address,
+            message,
+        })
+    };
+
+    // size problems
+    if rom.is_empty() {
+        report(Severity::Error, None, "the rom is empty".to_owned());
+    } else if rom.len() > MAX_ROM_SIZE {
+        report(
+            Severity::Error,
+            None,
+            format!(
+                "the rom has {} bytes, but only {} fit in memory ({} with --full-memory, for SCHIP and XO-CHIP)",
+                rom.len(),
+                MAX_ROM_SIZE,
+                MAX_FULL_ROM_SIZE
+            ),
+        );
+        variants.push("XO-CHIP");
+    }
+
+    // instructions that can be reached from the entry point
+    let rom_end = ENTRY_POINT as usize + rom.len();
+    let outside = |addr: u16| (addr as usize) < ENTRY_POINT as usize || addr as usize >= rom_end;
+
+    let disassembly = disassemble(rom);
+    let instructions = disassembly
+        .blocks
+        .iter()
+        .flat_map(|block| block.instructions.iter());
+
+    for &(addr, word, op) in instructions {
+        if let Some((variant, description)) = variant_hint(word) {
+            report(
+                Severity::Error,
+                Some(addr),
+                format!(
+                    "{:04X} is a {} instruction ({})",
+                    word, variant, description
+                ),
+            );
+            if !variants.contains(&variant) {
+                variants.push(variant);
+            }
+            continue;
+        }
+
+        match op {
+            None => report(
+                Severity::Error,
+                Some(addr),
+                format!("unknown opcode {:04X}", word),
+            ),
+            // zeroed space reached as code is usually patched by the rom itself
+            Some(Opcode::Sys(0)) => report(
+                Severity::Warning,
+                Some(addr),
+                "0000 is not valid code, unless the rom writes over it first".to_owned(),
+            ),
+            Some(Opcode::Sys(_)) => report(
+                Severity::Error,
+                Some(addr),
+                format!("machine code call {:04X} is not supported", word),
+            ),
+            Some(op @ (Opcode::Jump(nnn) | Opcode::Call(nnn) | Opcode::JumpV0(nnn)))
+                if outside(nnn) =>
+            {
+                report(
+                    Severity::Warning,
+                    Some(addr),
+                    format!("{} goes outside the rom", op),
+                )
+            }
+            Some(Opcode::Draw(_, _, 0)) => {
+                report(
+                    Severity::Warning,
+                    Some(addr),
+                    format!(
+                        "{:04X} draws nothing on CHIP-8, but a 16x16 sprite on SCHIP",
+                        word
+                    ),
+                );
+                if !variants.contains(&"SCHIP") {
+                    variants.push("SCHIP");
+                }
+            }
+            _ => {}
+        }
+    }
+
+    (findings, variants)
+}
+
+/// Statically check a rom, printing a report of the problems found
+pub fn check(rom: &[u8]) -> Result<(), anyhow::Error> {
+    let (findings, variants) = findings(rom);
+
+    println!("rom check ({} bytes):", rom.len());
+    for finding in &findings {
+        let severity = match finding.severity {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        };
+
+        match finding.address {
+            Some(addr) => println!("  {:<8} {:03X}: {}", severity, addr, finding.message),
+            None => println!("  {:<8} {}", severity, finding.message),
+        }
+    }
+
+    if !variants.is_empty() {
+        println!(
+            "  note: this rom seems to be written for {}, which rc8 does not support",
+            variants.join("/")
+        );
+    }
+
+    let errors = findings
+        .iter()
+        .filter(|finding| finding.severity == Severity::Error)
+        .count();
+
+    if errors > 0 {
+        bail!("{} error(s) found", errors);
+    }
+
+    println!("  no errors found");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clean_rom() {
+        let rom = [0x60, 0x01, 0x12, 0x02];
+        let (findings, variants) = findings(&rom);
+
+        assert!(findings.is_empty());
+        assert!(variants.is_empty());
+    }
+
+    #[test]
+    fn test_schip_rom() {
+        let rom = [
+            0x00, 0xFF, // 0x200: hires
+            0x12, 0x00, // 0x202: jump 0x200
+        ];
+        let (findings, variants) = findings(&rom);
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(findings[0].severity, Severity::Error);
+        assert_eq!(findings[0].address, Some(0x200));
+        assert_eq!(variants, vec!["SCHIP"]);
+    }
+
+    #[test]
+    fn test_problems() {
+        let rom = [
+            0x30, 0x00, // 0x200: if v0 != 0 then
+            0x13, 0x00, // 0x202: jump 0x300, outside the rom
+            0x01, 0x23, // 0x204: machine code call
+        ];
+        let (findings, _) = findings(&rom);
+
+        let addresses: Vec<_> = findings.iter().map(|f| (f.address, f.severity)).collect();
+        assert_eq!(
+            addresses,
+            vec![
+                (Some(0x202), Severity::Warning),
+                (Some(0x204), Severity::Error)
+            ]
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! CHIP-8X, the extension RCA made for the VP-590 color board and the
+//! VP-580 second keypad: a background color, foreground colors for zones of
+//! the screen and a few more instructions. Programs start at 0x300, as the
+//! interpreter itself is bigger.
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use super::emulator::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+/// Background colors, in the order 02A0 cycles through them, as 0xRRGGBB00
+pub const BACKGROUNDS: [u32; 4] = [0x00008000, 0x00000000, 0x00800000, 0x80000000];
+
+/// Foreground colors, as 0xRRGGBB00
+pub const COLORS: [u32; 8] = [
+    0x00000000, // black
+    0xff000000, // red
+    0x0000ff00, // blue
+    0xff00ff00, // violet
+    0x00ff0000, // green
+    0xffff0000, // yellow
+    0x00ffff00, // aqua
+    0xffffff00, // white
+];
+
+/// Where CHIP-8X programs start
+pub const ADDR_START: usize = 0x300;
+
+// the foreground until a rom sets one
+const DEFAULT_COLOR: u8 = 1;
+
+// colors are set for 8 pixels wide cells, one row tall; zones group them
+// in 8x4 pixel blocks
+const CELL_WIDTH: usize = 8;
+const COLUMNS: usize = DISPLAY_WIDTH / CELL_WIDTH;
+const ZONE_HEIGHT: usize = 4;
+const ZONE_ROWS: usize = DISPLAY_HEIGHT / ZONE_HEIGHT;
+
+/// The colors on the VP-590 board
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct ColorBoard {
+    /// Index on [`BACKGROUNDS`]
+    pub background: u8,
+
+    /// Index on [`COLORS`] of each cell, row by row
+    pub cells: Vec<u8>,
+}
+
+impl Default for ColorBoard {
+    fn default() -> Self {
+        ColorBoard {
+            background: 0,
+            cells: vec![DEFAULT_COLOR; COLUMNS * DISPLAY_HEIGHT],
+        }
+    }
+}
+
+impl ColorBoard {
+    /// 02A0
+    pub fn cycle_background(&mut self) {
+        self.background = (self.background + 1) % BACKGROUNDS.len() as u8;
+    }
+
+    /// BXY0: `x` has the first zone column on the low nibble and how many
+    /// more to the right on the high one; `y` is the same, for zone rows
+    pub fn color_zones(&mut self, x: u8, y: u8, color: u8) {
+        for column in zone_range(x, COLUMNS) {
+            for zone in zone_range(y, ZONE_ROWS) {
+                for row in zone * ZONE_HEIGHT..(zone + 1) * ZONE_HEIGHT {
+                    self.cells[row * COLUMNS + column] = color & 7;
+                }
+            }
+        }
+    }
+
+    /// BXYN: `rows` rows starting on `y`, on the cell with pixel `x`
+    pub fn color_rows(&mut self, x: u8, y: u8, rows: u8, color: u8) {
+        let column = x as usize / CELL_WIDTH % COLUMNS;
+        for row in y as usize..y as usize + rows as usize {
+            self.cells[row % DISPLAY_HEIGHT * COLUMNS + column] = color & 7;
+        }
+    }
+
+    pub fn background_rgb(&self) -> u32 {
+        BACKGROUNDS[self.background as usize % BACKGROUNDS.len()]
+    }
+
+    /// The color of a lit pixel
+    pub fn pixel_rgb(&self, x: usize, y: usize) -> u32 {
+        let cell = (y % DISPLAY_HEIGHT) * COLUMNS + (x % DISPLAY_WIDTH) / CELL_WIDTH;
+        COLORS[self.cells[cell] as usize & 7]
+    }
+}
+
+/// The columns (or rows) of zones picked by a BXY0 register
+fn zone_range(value: u8, count: usize) -> impl Iterator<Item = usize> {
+    let first = (value & 0xF) as usize;
+    let more = (value >> 4) as usize;
+    (first..=first + more).map(move |zone| zone % count)
+}
+
+/// 5XY1: each nibble is added on its own, modulo 8
+pub fn add_nibbles(x: u8, y: u8) -> u8 {
+    let high = ((x >> 4) + (y >> 4)) & 7;
+    let low = ((x & 0xF) + (y & 0xF)) & 7;
+    high << 4 | low
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_colors() {
+        let mut board = ColorBoard::default();
+        assert_eq!(board.background_rgb(), BACKGROUNDS[0]);
+        for _ in 0..5 {
+            board.cycle_background();
+        }
+        assert_eq!(board.background_rgb(), BACKGROUNDS[1]);
+
+        // two zones wide, one tall: pixels 8-23, rows 4-7
+        board.color_zones(0x11, 0x01, 4);
+        assert_eq!(board.pixel_rgb(8, 4), COLORS[4]);
+        assert_eq!(board.pixel_rgb(23, 7), COLORS[4]);
+        assert_eq!(board.pixel_rgb(24, 7), COLORS[1]);
+        assert_eq!(board.pixel_rgb(8, 8), COLORS[1]);
+
+        // single rows wrap at the bottom
+        board.color_rows(60, 31, 2, 7);
+        assert_eq!(board.pixel_rgb(56, 31), COLORS[7]);
+        assert_eq!(board.pixel_rgb(63, 0), COLORS[7]);
+        assert_eq!(board.pixel_rgb(63, 1), COLORS[1]);
+    }
+
+    #[test]
+    fn test_add_nibbles() {
+        assert_eq!(add_nibbles(0x12, 0x34), 0x46);
+        assert_eq!(add_nibbles(0x57, 0x45), 0x14);
+    }
+}
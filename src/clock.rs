@@ -0,0 +1,101 @@
+//! Where the host time comes from. The frontends ask a [`Clock`] instead of
+//! calling `Instant::now` directly, so tests can drive them with a
+//! [`VirtualClock`] and check the pacing exactly.
+use std::{
+    thread,
+    time::{Duration, Instant},
+};
+
+pub trait Clock {
+    /// Microseconds since the clock started
+    fn now(&self) -> u128;
+
+    /// Wait for about `duration` before the next step
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// The wall clock
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        SystemClock::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> u128 {
+        self.start.elapsed().as_micros()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        thread::sleep(duration);
+    }
+}
+
+/// A simulated clock that moves exactly one tick (`1/rate` of a second) on
+/// every sleep, whatever was asked for, and never really waits
+pub struct VirtualClock {
+    rate: u128,
+    ticks: u128,
+}
+
+impl VirtualClock {
+    /// A clock with `rate` ticks per second, e.g. 60 for one frame per sleep
+    pub fn new(rate: u32) -> Self {
+        VirtualClock {
+            rate: rate.max(1) as u128,
+            ticks: 0,
+        }
+    }
+
+    /// Move `ticks` forward without sleeping
+    pub fn advance(&mut self, ticks: u128) {
+        self.ticks += ticks;
+    }
+
+    /// Ticks since the clock started
+    pub fn ticks(&self) -> u128 {
+        self.ticks
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> u128 {
+        // rounded up, so a tick is never short of its time
+        (self.ticks * 1_000_000).div_ceil(self.rate)
+    }
+
+    fn sleep(&mut self, _duration: Duration) {
+        self.ticks += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_clock() {
+        let mut clock = VirtualClock::new(60);
+        assert_eq!(clock.now(), 0);
+
+        clock.sleep(Duration::from_millis(1));
+        assert_eq!(clock.now(), 16_667);
+        clock.sleep(Duration::from_secs(5));
+        assert_eq!(clock.now(), 33_334);
+
+        clock.advance(58);
+        assert_eq!((clock.ticks(), clock.now()), (60, 1_000_000));
+    }
+}
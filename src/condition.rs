@@ -0,0 +1,274 @@
+//! Conditions for the breakpoints, like `V3 == 0x1F && I > 0x300`.
+//!
+//! Values are the registers (`V0`-`VF`, `I`, `PC`, `SP`, `DT` and `ST`),
+//! numbers (decimal, or hex with `0x`) and memory bytes, as `[ADDR]`. They
+//! are compared with `==`, `!=`, `<`, `<=`, `>` and `>=`, and the comparisons
+//! joined with `&&` and `||`, with parentheses when needed.
+use std::{fmt, str::FromStr};
+
+use super::emulator::Emulator;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Register {
+    V(usize),
+    I,
+    PC,
+    SP,
+    DT,
+    ST,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+enum Expr {
+    Number(u32),
+    Register(Register),
+    Memory(Box<Expr>),
+    Binary(Op, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, emu: &Emulator) -> u32 {
+        match self {
+            Expr::Number(value) => *value,
+            Expr::Register(register) => match register {
+                Register::V(x) => emu.V[*x] as u32,
+                Register::I => emu.I as u32,
+                Register::PC => emu.PC as u32,
+                Register::SP => emu.sub_stack.len() as u32,
+                Register::DT => emu.DT as u32,
+                Register::ST => emu.ST as u32,
+            },
+            Expr::Memory(address) => {
+                let address = address.eval(emu) as usize;
+                emu.memory.get(address).copied().unwrap_or_default() as u32
+            }
+            Expr::Binary(op, left, right) => {
+                let (left, right) = (left.eval(emu), right.eval(emu));
+                let result = match op {
+                    Op::Eq => left == right,
+                    Op::Ne => left != right,
+                    Op::Lt => left < right,
+                    Op::Le => left <= right,
+                    Op::Gt => left > right,
+                    Op::Ge => left >= right,
+                    Op::And => left != 0 && right != 0,
+                    Op::Or => left != 0 || right != 0,
+                };
+                result as u32
+            }
+        }
+    }
+}
+
+/// When a breakpoint stops the rom
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Condition {
+    text: String,
+    expr: Expr,
+}
+
+impl Condition {
+    /// If the condition is true for the machine as it is now
+    pub fn holds(&self, emu: &Emulator) -> bool {
+        self.expr.eval(emu) != 0
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl FromStr for Condition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = tokenize(s)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.or()?;
+        match parser.tokens.get(parser.pos) {
+            Some(token) => Err(format!("unexpected '{}'", token)),
+            None => Ok(Condition {
+                text: s.trim().to_owned(),
+                expr,
+            }),
+        }
+    }
+}
+
+/// Split the text on numbers, names and operators
+fn tokenize(s: &str) -> Result<Vec<String>, String> {
+    const OPERATORS: [&str; 12] = [
+        "==", "!=", "<=", ">=", "&&", "||", "<", ">", "(", ")", "[", "]",
+    ];
+
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while !rest.is_empty() {
+        let len = match OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            Some(op) => op.len(),
+            None => rest
+                .find(|c: char| !c.is_ascii_alphanumeric())
+                .unwrap_or(rest.len()),
+        };
+        if len == 0 {
+            return Err(format!(
+                "unexpected '{}'",
+                &rest[..rest.chars().next().unwrap().len_utf8()]
+            ));
+        }
+        tokens.push(rest[..len].to_owned());
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    pos: usize,
+}
+
+impl Parser {
+    fn next(&mut self) -> Option<&str> {
+        let token = self.tokens.get(self.pos)?;
+        self.pos += 1;
+        Some(token)
+    }
+
+    fn accept(&mut self, expected: &str) -> bool {
+        let found = self
+            .tokens
+            .get(self.pos)
+            .is_some_and(|token| token == expected);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.accept(expected) {
+            true => Ok(()),
+            false => Err(format!("expected '{}'", expected)),
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.accept("||") {
+            expr = Expr::Binary(Op::Or, Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.comparison()?;
+        while self.accept("&&") {
+            expr = Expr::Binary(Op::And, Box::new(expr), Box::new(self.comparison()?));
+        }
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, String> {
+        let left = self.value()?;
+        let op = match self.tokens.get(self.pos).map(String::as_str) {
+            Some("==") => Op::Eq,
+            Some("!=") => Op::Ne,
+            Some("<") => Op::Lt,
+            Some("<=") => Op::Le,
+            Some(">") => Op::Gt,
+            Some(">=") => Op::Ge,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        Ok(Expr::Binary(op, Box::new(left), Box::new(self.value()?)))
+    }
+
+    fn value(&mut self) -> Result<Expr, String> {
+        let token = self
+            .next()
+            .ok_or_else(|| "unexpected end of the condition".to_owned())?
+            .to_ascii_uppercase();
+
+        let expr = match token.as_str() {
+            "(" => {
+                let expr = self.or()?;
+                self.expect(")")?;
+                expr
+            }
+            "[" => {
+                let address = self.or()?;
+                self.expect("]")?;
+                Expr::Memory(Box::new(address))
+            }
+            "I" => Expr::Register(Register::I),
+            "PC" => Expr::Register(Register::PC),
+            "SP" => Expr::Register(Register::SP),
+            "DT" => Expr::Register(Register::DT),
+            "ST" => Expr::Register(Register::ST),
+            _ => match token.strip_prefix('V') {
+                Some(x) if x.len() == 1 => {
+                    let x = usize::from_str_radix(x, 16)
+                        .map_err(|_| format!("unknown register '{}'", token))?;
+                    Expr::Register(Register::V(x))
+                }
+                _ => {
+                    let value = match token.strip_prefix("0X") {
+                        Some(hex) => u32::from_str_radix(hex, 16),
+                        None => token.parse(),
+                    };
+                    Expr::Number(value.map_err(|_| format!("unexpected '{}'", token))?)
+                }
+            },
+        };
+        Ok(expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_holds() {
+        let mut emu = Emulator::load_rom(&[0x00, 0xE0][..]).unwrap();
+        emu.V[3] = 0x1F;
+        emu.I = 0x310;
+        emu.memory[0x310] = 7;
+
+        let condition: Condition = "V3 == 0x1F && I > 0x300".parse().unwrap();
+        assert!(condition.holds(&emu));
+        assert_eq!(condition.to_string(), "V3 == 0x1F && I > 0x300");
+
+        emu.V[3] = 0;
+        assert!(!condition.holds(&emu));
+
+        let condition: Condition = "(v3 != 0 || pc == 512) && [i] >= 7".parse().unwrap();
+        assert!(condition.holds(&emu));
+        let condition: Condition = "[I] < 7 || DT".parse().unwrap();
+        assert!(!condition.holds(&emu));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!("".parse::<Condition>().is_err());
+        assert!("V3 ==".parse::<Condition>().is_err());
+        assert!("VG == 1".parse::<Condition>().is_err());
+        assert!("(V0 == 1".parse::<Condition>().is_err());
+        assert!("V0 = 1".parse::<Condition>().is_err());
+        assert!("V0 == 1 V1".parse::<Condition>().is_err());
+    }
+}
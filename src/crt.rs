@@ -0,0 +1,117 @@
+//! A CRT look for the emulator screen: a soft glow around the lit pixels,
+//! dark lines between the scanlines and a slightly curved picture.
+//!
+//! It works on the final RGBA8888 pixels (0xRRGGBBAA), after the emulator
+//! screen is drawn, so it goes along with any palette and filter.
+
+// how far the glow reaches, in screen pixels, and how bright it is
+const GLOW_RADIUS: usize = 4;
+const GLOW: f32 = 0.4;
+
+// every third row is a darker gap between scanlines
+const SCANLINE_HEIGHT: usize = 3;
+const SCANLINE_GAP: f32 = 0.6;
+
+// how much the picture bulges; the corners end up black
+const CURVATURE: f32 = 0.05;
+
+/// Apply the effect on `pixels`, an image of `width` by `height`
+pub fn apply(pixels: &mut [u32], width: usize, height: usize) {
+    let mut channels: Vec<Vec<f32>> = (0..3)
+        .map(|channel| {
+            let shift = 24 - channel * 8;
+            pixels
+                .iter()
+                .map(|pixel| ((pixel >> shift) & 0xFF) as f32)
+                .collect()
+        })
+        .collect();
+
+    // light leaks on the darker pixels around, but never darkens anything
+    for channel in &mut channels {
+        let blurred = blur(channel, width, height, GLOW_RADIUS);
+        for (value, glow) in channel.iter_mut().zip(blurred) {
+            *value += GLOW * (glow - *value).max(0.0);
+        }
+    }
+
+    for y in (SCANLINE_HEIGHT - 1..height).step_by(SCANLINE_HEIGHT) {
+        for channel in &mut channels {
+            for value in &mut channel[y * width..(y + 1) * width] {
+                *value *= SCANLINE_GAP;
+            }
+        }
+    }
+
+    // each pixel takes its color from a bit further from the center
+    for y in 0..height {
+        for x in 0..width {
+            let u = 2.0 * x as f32 / width as f32 - 1.0;
+            let v = 2.0 * y as f32 / height as f32 - 1.0;
+            let bulge = 1.0 + CURVATURE * (u * u + v * v);
+            let (u, v) = (u * bulge, v * bulge);
+
+            pixels[y * width + x] = if u.abs() > 1.0 || v.abs() > 1.0 {
+                0x000000FF
+            } else {
+                let sx = (((u + 1.0) / 2.0 * width as f32).round() as usize).min(width - 1);
+                let sy = (((v + 1.0) / 2.0 * height as f32).round() as usize).min(height - 1);
+                let [r, g, b] = [0, 1, 2].map(|c| channels[c][sy * width + sx].min(255.0) as u32);
+                r << 24 | g << 16 | b << 8 | 0xFF
+            };
+        }
+    }
+}
+
+/// Box blur, first on the rows and then on the columns
+fn blur(values: &[f32], width: usize, height: usize, radius: usize) -> Vec<f32> {
+    let mut rows = vec![0.0; values.len()];
+    for y in 0..height {
+        let row = &values[y * width..(y + 1) * width];
+        for x in 0..width {
+            let range = x.saturating_sub(radius)..(x + radius + 1).min(width);
+            let len = range.len() as f32;
+            rows[y * width + x] = row[range].iter().sum::<f32>() / len;
+        }
+    }
+
+    let mut blurred = vec![0.0; values.len()];
+    for x in 0..width {
+        for y in 0..height {
+            let range = y.saturating_sub(radius)..(y + radius + 1).min(height);
+            let len = range.len() as f32;
+            blurred[y * width + x] = range.map(|y| rows[y * width + x]).sum::<f32>() / len;
+        }
+    }
+    blurred
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crt() {
+        // a white square in the middle of a black screen
+        let (width, height) = (60, 30);
+        let mut pixels = vec![0x000000FF; width * height];
+        for y in 12..18 {
+            for x in 27..33 {
+                pixels[y * width + x] = 0xFFFFFFFF;
+            }
+        }
+        apply(&mut pixels, width, height);
+
+        // still white in the middle, glowing around it
+        assert_eq!(pixels[15 * width + 30], 0xFFFFFFFF);
+        assert_ne!(pixels[15 * width + 25], 0x000000FF);
+        assert_eq!(pixels[15 * width + 5], 0x000000FF);
+
+        // the gap between scanlines is darker
+        assert!(pixels[14 * width + 30] < 0xFFFFFFFF);
+
+        // nothing on the corners
+        assert_eq!(pixels[0], 0x000000FF);
+        assert!(pixels.iter().all(|pixel| pixel & 0xFF == 0xFF));
+    }
+}
@@ -0,0 +1,207 @@
+use std::collections::BTreeMap;
+
+use super::{condition::Condition, emulator::Emulator, opcode::Opcode, rsp::Breakpoints};
+
+/// How many lines of disassembly are shown
+pub const LINES: usize = 13;
+
+/// A single line of the disassembly view
+#[derive(Clone, PartialEq, Eq)]
+pub struct Line {
+    pub address: usize,
+    pub text: String,
+    pub is_pc: bool,
+    pub is_cursor: bool,
+    pub breakpoint: bool,
+    /// How often the line ran, from 0 to 255, when profiling
+    pub heat: u8,
+}
+
+/// State of the in-emulator disassembly view and its breakpoints
+#[derive(Default)]
+pub struct Debugger {
+    pub visible: bool,
+
+    // address under the cursor; follows the PC when not set
+    cursor: Option<usize>,
+
+    // the ones with a condition only stop when it holds
+    breakpoints: BTreeMap<usize, Option<Condition>>,
+
+    // set when resuming, so the breakpoint we stopped at does not trigger again
+    resuming: bool,
+}
+
+impl Debugger {
+    pub fn cursor(&self, emu: &Emulator) -> usize {
+        self.cursor.unwrap_or(emu.PC)
+    }
+
+    /// Move the cursor one instruction up or down
+    pub fn move_cursor(&mut self, emu: &Emulator, up: bool) {
+        let cursor = self.cursor(emu);
+        self.cursor = Some(if up {
+            cursor.saturating_sub(2)
+        } else {
+            (cursor + 2).min(emu.memory.len() - 2)
+        });
+    }
+
+    /// Go back to following the PC, when the emulator resumes
+    pub fn resume(&mut self) {
+        self.cursor = None;
+        self.resuming = true;
+    }
+
+    /// Forget the breakpoints and the cursor, when another rom is loaded
+    pub fn clear(&mut self) {
+        self.cursor = None;
+        self.breakpoints.clear();
+        self.resuming = false;
+    }
+
+    pub fn toggle_breakpoint(&mut self, address: usize) {
+        if self.breakpoints.remove(&address).is_none() {
+            self.breakpoints.insert(address, None);
+        }
+    }
+
+    pub fn set_breakpoint(&mut self, address: usize, set: bool) {
+        if set {
+            self.breakpoints.insert(address, None);
+        } else {
+            self.breakpoints.remove(&address);
+        }
+    }
+
+    /// Stop on `address` only when `condition` holds
+    pub fn set_conditional_breakpoint(&mut self, address: usize, condition: Condition) {
+        self.breakpoints.insert(address, Some(condition));
+    }
+
+    /// The addresses with a breakpoint, in order, and their conditions
+    pub fn breakpoints(&self) -> impl Iterator<Item = (usize, Option<&Condition>)> + '_ {
+        self.breakpoints
+            .iter()
+            .map(|(&address, condition)| (address, condition.as_ref()))
+    }
+
+    /// The disassembly around the cursor, decoded from the current memory
+    pub fn lines(&self, emu: &Emulator) -> Vec<Line> {
+        let cursor = self.cursor(emu);
+        let first = cursor
+            .saturating_sub(LINES / 2 * 2)
+            .min(emu.memory.len() - LINES * 2);
+
+        (0..LINES)
+            .map(|i| {
+                let address = first + i * 2;
+                let word = emu.word_at(address);
+                let text = match Opcode::decode_variant(word, emu.quirks.variant) {
+                    Some(op) => op.to_string(),
+                    None => format!("0x{:02X} 0x{:02X}", word >> 8, word & 0xFF),
+                };
+
+                Line {
+                    address,
+                    text,
+                    is_pc: address == emu.PC,
+                    is_cursor: address == cursor,
+                    breakpoint: self.breakpoints.contains_key(&address),
+                    heat: 0,
+                }
+            })
+            .collect()
+    }
+
+    /// If the emulator must stop before running the next instruction
+    pub fn should_stop(&mut self, emu: &Emulator) -> bool {
+        let resuming = std::mem::take(&mut self.resuming);
+        // conditions are only checked on their own address, so they cost
+        // nothing anywhere else
+        !resuming
+            && self
+                .breakpoints
+                .get(&emu.PC)
+                .is_some_and(|condition| condition.as_ref().is_none_or(|c| c.holds(emu)))
+    }
+}
+
+/// The breakpoints gdb sets
+impl Breakpoints for Debugger {
+    fn set_breakpoint(&mut self, addr: usize, set: bool) {
+        Debugger::set_breakpoint(self, addr, set);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run up to `cycles` instructions, like the app does; true if a breakpoint was hit
+    fn run_cycles(debugger: &mut Debugger, emu: &mut Emulator, cycles: u32) -> bool {
+        for _ in 0..cycles {
+            if debugger.should_stop(emu) {
+                return true;
+            }
+            emu.execute().unwrap();
+        }
+        false
+    }
+
+    #[test]
+    fn test_breakpoint() {
+        let rom = [
+            0x60, 0x01, // 0x200: v0 := 1
+            0x61, 0x02, // 0x202: v1 := 2
+            0x12, 0x00, // 0x204: jump 0x200
+        ];
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        let mut debugger = Debugger::default();
+        debugger.toggle_breakpoint(0x202);
+
+        assert!(run_cycles(&mut debugger, &mut emu, 10));
+        assert_eq!(emu.PC, 0x202);
+
+        // resuming runs past the breakpoint, until it comes around again
+        debugger.resume();
+        assert!(run_cycles(&mut debugger, &mut emu, 10));
+        assert_eq!(emu.PC, 0x202);
+        assert_eq!(emu.V[1], 2);
+
+        debugger.toggle_breakpoint(0x202);
+        assert!(!run_cycles(&mut debugger, &mut emu, 10));
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let rom = [
+            0x70, 0x01, // 0x200: v0 += 1
+            0x12, 0x00, // 0x202: jump 0x200
+        ];
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        let mut debugger = Debugger::default();
+        debugger.set_conditional_breakpoint(0x202, "V0 == 5".parse().unwrap());
+
+        assert!(run_cycles(&mut debugger, &mut emu, 100));
+        assert_eq!((emu.PC, emu.V[0]), (0x202, 5));
+
+        debugger.resume();
+        assert!(!run_cycles(&mut debugger, &mut emu, 100));
+    }
+
+    #[test]
+    fn test_lines() {
+        let rom = [0x60, 0x01, 0x61, 0x02];
+        let emu = Emulator::load_rom(&rom[..]).unwrap();
+        let lines = Debugger::default().lines(&emu);
+
+        assert_eq!(lines.len(), LINES);
+        let pc = lines.iter().find(|line| line.is_pc).unwrap();
+        assert_eq!(pc.address, 0x200);
+        assert!(pc.is_cursor);
+        assert_eq!(pc.text, "v0 := 0x01");
+    }
+}
@@ -0,0 +1,145 @@
+//! Guesses about why a rom is not working, usually because it was
+//! written for another CHIP-8 variant or expects different quirks.
+use super::{
+    check::variant_hint,
+    emulator::{Emulator, EmulatorError, Quirks, StackMode},
+};
+
+/// How many clipped sprites are needed to suspect the rom expects wrapping;
+/// a few are normal, when objects leave the screen.
+const CLIP_THRESHOLD: u32 = 60;
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Diagnosis {
+    pub message: String,
+
+    /// Quirks that might fix the problem, if any
+    pub retry: Option<Quirks>,
+}
+
+/// Suggest sprite wrapping, if a lot of sprites were clipped
+fn wrapping(emu: &Emulator, threshold: u32) -> Option<Diagnosis> {
+    if emu.quirks.wrap_sprites || emu.clipped_sprites() < threshold {
+        return None;
+    }
+
+    let mut quirks = emu.quirks;
+    quirks.wrap_sprites = true;
+
+    Some(Diagnosis {
+        message: "Sprites are being clipped, maybe they should wrap".to_owned(),
+        retry: Some(quirks),
+    })
+}
+
+/// Why the emulator might have stopped with `err`
+pub fn crash(emu: &Emulator, err: &EmulatorError) -> Option<Diagnosis> {
+    let word = match *err {
+        EmulatorError::InvalidOpcode(a, b, _) => Some(u16::from_be_bytes([a, b])),
+        // the address is the one after the offending instruction
+        EmulatorError::MachineSubroutine(addr) => Some(emu.word_at(addr as usize - 2)),
+        _ => None,
+    };
+
+    if let Some(word) = word {
+        if let Some((variant, what)) = variant_hint(word) {
+            return Some(Diagnosis {
+                message: format!("This looks like a {} rom ({})", variant, what),
+                retry: None,
+            });
+        }
+    }
+
+    // not an extension, so it is probably real VIP machine code
+    if let EmulatorError::MachineSubroutine(_) = err {
+        let mut quirks = emu.quirks;
+        quirks.vip_machine_code = true;
+
+        return Some(Diagnosis {
+            message: "This rom calls COSMAC VIP machine code".to_owned(),
+            retry: Some(quirks),
+        });
+    }
+
+    // SCHIP roms can nest a few more calls than the VIP allows
+    if let (EmulatorError::StackOverflow(_), StackMode::Vip) = (err, emu.quirks.stack) {
+        let mut quirks = emu.quirks;
+        quirks.stack = StackMode::Schip;
+
+        return Some(Diagnosis {
+            message: "The stack is full, maybe the rom expects 16 levels".to_owned(),
+            retry: Some(quirks),
+        });
+    }
+
+    // any clipped sprite is suspicious when the rom crashes
+    wrapping(emu, 1)
+}
+
+/// Something odd about a rom that is still running
+pub fn running(emu: &Emulator) -> Option<Diagnosis> {
+    wrapping(emu, CLIP_THRESHOLD)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variant() {
+        let rom = [0x00, 0xFF]; // 0x200: SCHIP high resolution
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        let err = emu.execute().unwrap_err();
+
+        let diagnosis = crash(&emu, &err).unwrap();
+        assert!(diagnosis.message.contains("SCHIP"));
+        assert_eq!(diagnosis.retry, None);
+    }
+
+    #[test]
+    fn test_machine_code() {
+        let rom = [0x03, 0x00]; // 0x200: call machine code at 0x300
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        let err = emu.execute().unwrap_err();
+
+        let diagnosis = crash(&emu, &err).unwrap();
+        assert!(diagnosis.retry.unwrap().vip_machine_code);
+    }
+
+    #[test]
+    fn test_stack() {
+        let rom = [0x22, 0x00]; // 0x200: call 0x200
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        emu.quirks.stack = StackMode::Vip;
+        let err = (0..13).find_map(|_| emu.execute().err()).unwrap();
+
+        let diagnosis = crash(&emu, &err).unwrap();
+        assert_eq!(diagnosis.retry.unwrap().stack, StackMode::Schip);
+    }
+
+    #[test]
+    fn test_clipping() {
+        let rom = [
+            0x60, 0x3E, // 0x200: V0 = 62
+            0xD0, 0x05, // 0x202: draw past the right edge
+            0x12, 0x02, // 0x204: jump 0x202
+        ];
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        while emu.clipped_sprites() < CLIP_THRESHOLD {
+            assert_eq!(running(&emu), None);
+            emu.vblank();
+            emu.execute().unwrap();
+        }
+
+        let diagnosis = running(&emu).unwrap();
+        let wrap = Quirks {
+            wrap_sprites: true,
+            ..Quirks::default()
+        };
+        assert_eq!(diagnosis.retry, Some(wrap));
+
+        emu.quirks.wrap_sprites = true;
+        assert_eq!(running(&emu), None);
+    }
+}
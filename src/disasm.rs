@@ -0,0 +1,363 @@
+//! Static disassembly of a ROM into basic blocks, following the control flow
+//! from the entry point.
+//!
+//! Indirect jumps (`jump0`) can not be followed, so the code they reach may
+//! show up as data.
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write as _,
+};
+
+use super::opcode::Opcode;
+
+/// Where roms are loaded, and start running
+pub const ENTRY_POINT: u16 = 0x200;
+
+/// How control gets from one block to another
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Falls into the next instruction
+    Next,
+    Jump,
+    Call,
+    /// The next instruction is skipped
+    Skip,
+}
+
+impl EdgeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeKind::Next => "next",
+            EdgeKind::Jump => "jump",
+            EdgeKind::Call => "call",
+            EdgeKind::Skip => "skip",
+        }
+    }
+}
+
+/// A run of instructions that always execute together
+#[derive(Debug)]
+pub struct Block {
+    pub start: u16,
+    /// Decoded instructions, with their addresses; `None` for unknown opcodes
+    pub instructions: Vec<(u16, u16, Option<Opcode>)>,
+}
+
+impl Block {
+    /// Address right after the last instruction
+    pub fn end(&self) -> u16 {
+        self.instructions
+            .last()
+            .map(|&(addr, _, _)| addr + 2)
+            .unwrap_or(self.start)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Edge {
+    pub from: u16,
+    pub to: u16,
+    pub kind: EdgeKind,
+}
+
+/// The result of the analysis
+#[derive(Debug)]
+pub struct Disassembly {
+    pub blocks: Vec<Block>,
+    pub edges: Vec<Edge>,
+    rom: Vec<u8>,
+}
+
+/// Read the instruction word at `addr`, if it is inside the rom
+fn word_at(rom: &[u8], addr: u16) -> Option<u16> {
+    let offset = addr.checked_sub(ENTRY_POINT)? as usize;
+    let bytes = rom.get(offset..offset + 2)?;
+    Some(u16::from_be_bytes([bytes[0], bytes[1]]))
+}
+
+/// Where control can go after the instruction at `addr`
+fn successors(addr: u16, op: Option<Opcode>) -> Vec<(u16, EdgeKind)> {
+    match op {
+        Some(Opcode::Jump(nnn)) => vec![(nnn, EdgeKind::Jump)],
+        Some(Opcode::Call(nnn)) => vec![(nnn, EdgeKind::Call), (addr + 2, EdgeKind::Next)],
+        Some(op) if op.is_skip() => vec![(addr + 2, EdgeKind::Next), (addr + 4, EdgeKind::Skip)],
+        // these stop (or leave) the known control flow
+        Some(Opcode::Return | Opcode::JumpV0(_) | Opcode::Sys(_)) | None => vec![],
+        Some(_) => vec![(addr + 2, EdgeKind::Next)],
+    }
+}
+
+/// Follow every reachable path from the entry point
+pub fn disassemble(rom: &[u8]) -> Disassembly {
+    // first, find all the reachable instructions, and where blocks start
+    let mut reached = BTreeMap::new();
+    let mut leaders = BTreeSet::from([ENTRY_POINT]);
+    let mut pending = vec![ENTRY_POINT];
+
+    while let Some(addr) = pending.pop() {
+        if reached.contains_key(&addr) {
+            continue;
+        }
+        let word = match word_at(rom, addr) {
+            Some(word) => word,
+            None => continue,
+        };
+
+        let op = Opcode::decode(word);
+        reached.insert(addr, (word, op));
+
+        let next = successors(addr, op);
+        let ends_block = next.len() != 1 || next[0].1 != EdgeKind::Next;
+        for (target, _) in next {
+            if ends_block {
+                leaders.insert(target);
+            }
+            pending.push(target);
+        }
+    }
+
+    // then, split the instructions into blocks
+    let mut blocks = Vec::new();
+    let mut edges = Vec::new();
+    for &start in leaders.iter().filter(|addr| reached.contains_key(addr)) {
+        let mut block = Block {
+            start,
+            instructions: Vec::new(),
+        };
+
+        let mut addr = start;
+        while let Some(&(word, op)) = reached.get(&addr) {
+            block.instructions.push((addr, word, op));
+
+            let next = successors(addr, op);
+            let falls_through = next.len() == 1 && next[0].1 == EdgeKind::Next;
+            if !falls_through || leaders.contains(&next[0].0) {
+                edges.extend(
+                    next.into_iter()
+                        .filter(|(to, _)| reached.contains_key(to))
+                        .map(|(to, kind)| Edge {
+                            from: start,
+                            to,
+                            kind,
+                        }),
+                );
+                break;
+            }
+
+            addr = next[0].0;
+        }
+
+        blocks.push(block);
+    }
+
+    Disassembly {
+        blocks,
+        edges,
+        rom: rom.to_vec(),
+    }
+}
+
+impl Disassembly {
+    /// Address ranges of the rom that are never reached as code
+    pub fn data_ranges(&self) -> Vec<(u16, u16)> {
+        let mut code = vec![false; self.rom.len()];
+        for block in &self.blocks {
+            for &(addr, _, _) in &block.instructions {
+                let offset = (addr - ENTRY_POINT) as usize;
+                code[offset] = true;
+                code[offset + 1] = true;
+            }
+        }
+
+        let mut ranges = Vec::new();
+        let mut start = None;
+        for (offset, &is_code) in code.iter().chain(std::iter::once(&true)).enumerate() {
+            match (start, is_code) {
+                (None, false) => start = Some(offset),
+                (Some(from), true) => {
+                    ranges.push((ENTRY_POINT + from as u16, ENTRY_POINT + offset as u16));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+
+        ranges
+    }
+
+    /// Octo-like listing, with the unreached bytes dumped as data
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        let mut data = self.data_ranges().into_iter().peekable();
+
+        for block in &self.blocks {
+            while let Some(&(from, to)) = data.peek().filter(|&&(from, _)| from < block.start) {
+                self.write_data(&mut out, from, to);
+                data.next();
+            }
+
+            let _ = writeln!(out, ": block_{:03X}", block.start);
+            for &(addr, word, op) in &block.instructions {
+                // unknown opcodes are written as raw bytes, so the listing reassembles
+                let text = op
+                    .map(|op| op.to_string())
+                    .unwrap_or_else(|| format!("0x{:02X} 0x{:02X}", word >> 8, word & 0xFF));
+                let _ = writeln!(out, "  {:<28} # {:03X}: {:04X}", text, addr, word);
+            }
+        }
+
+        for (from, to) in data {
+            self.write_data(&mut out, from, to);
+        }
+
+        out
+    }
+
+    fn write_data(&self, out: &mut String, from: u16, to: u16) {
+        let _ = writeln!(out, ": data_{:03X}", from);
+        let bytes = &self.rom[(from - ENTRY_POINT) as usize..(to - ENTRY_POINT) as usize];
+        for (i, row) in bytes.chunks(8).enumerate() {
+            let row: Vec<_> = row.iter().map(|b| format!("0x{:02X}", b)).collect();
+            let _ = writeln!(
+                out,
+                "  {:<28} # {:03X}",
+                row.join(" "),
+                from as usize + i * 8
+            );
+        }
+    }
+
+    /// Blocks and edges, as JSON
+    pub fn to_json(&self) -> String {
+        let blocks: Vec<_> = self
+            .blocks
+            .iter()
+            .map(|block| {
+                let instructions: Vec<_> = block
+                    .instructions
+                    .iter()
+                    .map(|&(addr, word, op)| {
+                        let text = op.map(|op| format!("\"{}\"", op)).unwrap_or("null".into());
+                        format!(
+                            "{{\"address\":{},\"opcode\":\"{:04X}\",\"text\":{}}}",
+                            addr, word, text
+                        )
+                    })
+                    .collect();
+
+                format!(
+                    "{{\"start\":{},\"end\":{},\"instructions\":[{}]}}",
+                    block.start,
+                    block.end(),
+                    instructions.join(",")
+                )
+            })
+            .collect();
+
+        let edges: Vec<_> = self
+            .edges
+            .iter()
+            .map(|edge| {
+                format!(
+                    "{{\"from\":{},\"to\":{},\"kind\":\"{}\"}}",
+                    edge.from,
+                    edge.to,
+                    edge.kind.name()
+                )
+            })
+            .collect();
+
+        let data: Vec<_> = self
+            .data_ranges()
+            .iter()
+            .map(|(from, to)| format!("{{\"start\":{},\"end\":{}}}", from, to))
+            .collect();
+
+        format!(
+            "{{\"blocks\":[{}],\"edges\":[{}],\"data\":[{}]}}\n",
+            blocks.join(","),
+            edges.join(","),
+            data.join(",")
+        )
+    }
+
+    /// Control-flow graph, for Graphviz
+    pub fn to_dot(&self) -> String {
+        let mut out = String::from("digraph rom {\n  node [shape=box, fontname=monospace];\n");
+
+        for block in &self.blocks {
+            let mut label = String::new();
+            for &(addr, _, op) in &block.instructions {
+                let text = op.map(|op| op.to_string()).unwrap_or_else(|| "???".into());
+                let _ = write!(label, "{:03X}: {}\\l", addr, text);
+            }
+            let _ = writeln!(out, "  b{:03X} [label=\"{}\"];", block.start, label);
+        }
+
+        for edge in &self.edges {
+            let _ = writeln!(
+                out,
+                "  b{:03X} -> b{:03X} [label=\"{}\"];",
+                edge.from,
+                edge.to,
+                edge.kind.name()
+            );
+        }
+
+        out.push_str("}\n");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_blocks() {
+        let rom = [
+            0x60, 0x00, // 0x200: v0 := 0
+            0x30, 0x05, // 0x202: if v0 != 5 then
+            0x12, 0x0A, // 0x204: jump 0x20A
+            0x22, 0x0C, // 0x206: call 0x20C
+            0x12, 0x08, // 0x208: jump 0x208
+            0x70, 0x01, // 0x20A: v0 += 1
+            0x00, 0xEE, // 0x20C: return
+            0xAA, 0xBB, // 0x20E: data
+        ];
+
+        let disasm = disassemble(&rom);
+        let starts: Vec<_> = disasm.blocks.iter().map(|b| b.start).collect();
+        assert_eq!(starts, vec![0x200, 0x204, 0x206, 0x208, 0x20A, 0x20C]);
+
+        assert!(disasm.edges.contains(&Edge {
+            from: 0x200,
+            to: 0x206,
+            kind: EdgeKind::Skip
+        }));
+        assert!(disasm.edges.contains(&Edge {
+            from: 0x206,
+            to: 0x20C,
+            kind: EdgeKind::Call
+        }));
+        assert!(disasm.edges.contains(&Edge {
+            from: 0x20A,
+            to: 0x20C,
+            kind: EdgeKind::Next
+        }));
+
+        assert_eq!(disasm.data_ranges(), vec![(0x20E, 0x210)]);
+    }
+
+    #[test]
+    fn test_text() {
+        let rom = [0x60, 0x01, 0x12, 0x02, 0xFF];
+        let text = disassemble(&rom).to_text();
+
+        assert!(text.contains(": block_200"));
+        assert!(text.contains("v0 := 0x01"));
+        assert!(text.contains(": block_202"));
+        assert!(text.contains(": data_204"));
+        assert!(text.contains("0xFF"));
+    }
+}
@@ -0,0 +1,278 @@
+//! The screen, as one or more bit planes of 64x32 pixels, or 128x64 in
+//! high resolution (SCHIP). XO-CHIP roms draw on more than one plane, and
+//! a pixel is lit if it is set on any of them.
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+pub const LORES_WIDTH: usize = 64;
+pub const LORES_HEIGHT: usize = 32;
+pub const HIRES_WIDTH: usize = 128;
+pub const HIRES_HEIGHT: usize = 64;
+
+/// Most planes a display can have
+pub const MAX_PLANES: usize = 2;
+
+// sprites are drawn one byte per row
+const SPRITE_WIDTH: usize = 8;
+
+/// What happened when drawing a sprite
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Drawn {
+    /// A lit pixel was turned off
+    pub collided: bool,
+    /// Part of the sprite was past the edges and was not drawn
+    pub clipped: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Display {
+    hires: bool,
+
+    // a row per entry, with the leftmost pixel on bit `width - 1`
+    planes: Vec<Vec<u128>>,
+}
+
+impl Default for Display {
+    fn default() -> Self {
+        Display::new(1)
+    }
+}
+
+impl Display {
+    /// A blank, low resolution display with `planes` planes
+    pub fn new(planes: usize) -> Self {
+        Display {
+            hires: false,
+            planes: vec![vec![0; LORES_HEIGHT]; planes.clamp(1, MAX_PLANES)],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        if self.hires {
+            HIRES_WIDTH
+        } else {
+            LORES_WIDTH
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        if self.hires {
+            HIRES_HEIGHT
+        } else {
+            LORES_HEIGHT
+        }
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    /// Switch the resolution, clearing the screen
+    pub fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        let height = self.height();
+        for plane in &mut self.planes {
+            *plane = vec![0; height];
+        }
+    }
+
+    pub fn planes(&self) -> usize {
+        self.planes.len()
+    }
+
+    pub fn clear(&mut self) {
+        for plane in &mut self.planes {
+            plane.fill(0);
+        }
+    }
+
+    /// If the pixel is lit on any plane; coordinates wrap around
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        (0..self.planes()).any(|plane| self.plane_pixel(plane, x, y))
+    }
+
+    /// If the pixel is set on `plane`; coordinates wrap around
+    pub fn plane_pixel(&self, plane: usize, x: usize, y: usize) -> bool {
+        let x = x % self.width();
+        let y = y % self.height();
+        self.planes[plane][y] & self.mask(x) != 0
+    }
+
+    /// The rows of `plane`, one bit per pixel with the leftmost pixel on
+    /// bit `width - 1`
+    pub fn rows(&self, plane: usize) -> &[u128] {
+        &self.planes[plane]
+    }
+
+    pub fn set_row(&mut self, plane: usize, y: usize, bits: u128) {
+        let width = self.width();
+        self.planes[plane][y] = bits & full_mask(width);
+    }
+
+    /// Every row of every plane, as `width / 8` big-endian bytes; on a
+    /// plain CHIP-8 screen, the same bytes as a `u64` per row
+    pub fn bytes(&self) -> impl Iterator<Item = u8> + '_ {
+        let skip = 16 - self.width() / 8;
+        self.planes
+            .iter()
+            .flatten()
+            .flat_map(move |row| row.to_be_bytes().into_iter().skip(skip))
+    }
+
+    /// XOR `sprite` (a byte per row) on `plane`, with its top left corner
+    /// at (x, y); with `wrap`, the parts past the edges show up on the
+    /// other side instead of being clipped
+    pub fn draw(&mut self, plane: usize, x: usize, y: usize, sprite: &[u8], wrap: bool) -> Drawn {
+        let (width, height) = (self.width(), self.height());
+        let limit = width - SPRITE_WIDTH;
+        let x = x % width;
+        let y = y % height;
+
+        let mut drawn = Drawn {
+            clipped: !wrap && (x > limit || y + sprite.len() > height),
+            ..Drawn::default()
+        };
+
+        for (offset, &byte) in sprite.iter().enumerate() {
+            let row = y + offset;
+            let row = if wrap {
+                row % height
+            } else if row >= height {
+                break;
+            } else {
+                row
+            };
+
+            let bits = (byte as u128) << limit;
+            let bits = if wrap {
+                // rotate within the width of the screen, not of the u128
+                (bits >> x | bits.checked_shl((width - x) as u32).unwrap_or(0)) & full_mask(width)
+            } else {
+                bits >> x
+            };
+
+            let current = &mut self.planes[plane][row];
+            drawn.collided |= *current & bits != 0;
+            *current ^= bits;
+        }
+
+        drawn
+    }
+
+    /// Move everything `n` pixels down; what goes past the edge is lost
+    pub fn scroll_down(&mut self, n: usize) {
+        for plane in &mut self.planes {
+            let n = n.min(plane.len());
+            plane.rotate_right(n);
+            plane[..n].fill(0);
+        }
+    }
+
+    pub fn scroll_up(&mut self, n: usize) {
+        for plane in &mut self.planes {
+            let n = n.min(plane.len());
+            plane.rotate_left(n);
+            let height = plane.len();
+            plane[height - n..].fill(0);
+        }
+    }
+
+    pub fn scroll_right(&mut self, n: usize) {
+        for row in self.planes.iter_mut().flatten() {
+            *row = row.checked_shr(n as u32).unwrap_or(0);
+        }
+    }
+
+    pub fn scroll_left(&mut self, n: usize) {
+        let mask = full_mask(self.width());
+        for row in self.planes.iter_mut().flatten() {
+            *row = row.checked_shl(n as u32).unwrap_or(0) & mask;
+        }
+    }
+
+    /// If the sizes make sense, for displays that were not built here
+    pub fn is_valid(&self) -> bool {
+        let height = self.height();
+        (1..=MAX_PLANES).contains(&self.planes())
+            && self.planes.iter().all(|plane| plane.len() == height)
+    }
+
+    fn mask(&self, x: usize) -> u128 {
+        1 << (self.width() - x - 1)
+    }
+}
+
+/// All the bits of a row `width` pixels wide
+fn full_mask(width: usize) -> u128 {
+    u128::MAX >> (128 - width)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_draw() {
+        let mut display = Display::default();
+        let drawn = display.draw(0, 60, 30, &[0xFF, 0x81, 0xFF], false);
+        assert!(drawn.clipped);
+        assert!(!drawn.collided);
+        assert_eq!(display.rows(0)[30], 0xF);
+        assert_eq!(display.rows(0)[31], 0x8);
+
+        // wrapping draws the rest on the other side
+        display.clear();
+        let drawn = display.draw(0, 60, 31, &[0xFF, 0x81], true);
+        assert!(!drawn.clipped);
+        assert_eq!(display.rows(0)[31], 0xF000_0000_0000_000F);
+        assert_eq!(display.rows(0)[0], 0x1000_0000_0000_0008);
+
+        let drawn = display.draw(0, 0, 0, &[0x10], true);
+        assert!(drawn.collided);
+        assert_eq!(display.rows(0)[0], 0x0000_0000_0000_0008);
+    }
+
+    #[test]
+    fn test_hires() {
+        let mut display = Display::new(2);
+        display.draw(0, 0, 0, &[0x80], false);
+        display.set_hires(true);
+        assert_eq!((display.width(), display.height()), (128, 64));
+        assert!(!display.get_pixel(0, 0));
+
+        display.draw(1, 127, 63, &[0xC0], true);
+        assert!(display.get_pixel(127, 63));
+        assert!(display.get_pixel(0, 63));
+        assert!(!display.plane_pixel(0, 0, 63));
+        assert_eq!(display.bytes().count(), 2 * 64 * 16);
+        assert!(display.is_valid());
+    }
+
+    #[test]
+    fn test_scroll() {
+        let mut display = Display::default();
+        display.set_row(0, 0, 0x8000_0000_0000_0001);
+        display.scroll_down(3);
+        assert_eq!(display.rows(0)[0], 0);
+        assert_eq!(display.rows(0)[3], 0x8000_0000_0000_0001);
+
+        display.scroll_left(4);
+        assert_eq!(display.rows(0)[3], 0x10);
+        display.scroll_right(4);
+        assert_eq!(display.rows(0)[3], 0x1);
+
+        display.scroll_up(40);
+        assert!(display.rows(0).iter().all(|&row| row == 0));
+    }
+
+    #[test]
+    fn test_bytes() {
+        let mut display = Display::default();
+        display.set_row(0, 0, 0x0102_0304_0506_0708);
+        assert_eq!(
+            display.bytes().take(8).collect::<Vec<_>>(),
+            0x0102_0304_0506_0708u64.to_be_bytes()
+        );
+    }
+}
@@ -1,13 +1,22 @@
-use std::{cmp::Ordering, io::Read};
+use std::{io::Read, str::FromStr};
 
-use nanorand::{BufferedRng, Rng, WyRand};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use thiserror::Error;
+use tracing::{debug, trace};
 
-pub const DISPLAY_WIDTH: usize = 64;
-pub const DISPLAY_HEIGHT: usize = 32;
+use super::{
+    cdp1802::{Cdp1802, Cdp1802Error},
+    chip8x::{self, ColorBoard},
+    display::{self, Display},
+    memory::{Memory, MEM_SIZE},
+    opcode::{Opcode, Variant},
+    rng::{Chip8Rng, RngKind},
+    snapshot::{Snapshot, SnapshotError, SNAPSHOT_VERSION},
+};
 
-// memory size
-const MEM_SIZE: usize = 4096;
+pub const DISPLAY_WIDTH: usize = display::LORES_WIDTH;
+pub const DISPLAY_HEIGHT: usize = display::LORES_HEIGHT;
 
 // start of the sprite data
 const SPRITE_DATA_START: usize = 0;
@@ -32,6 +41,47 @@ const SPRITE_DATA: [u8; 80] = [
     0xF0, 0x80, 0xF0, 0x80, 0x80, // F
 ];
 
+// the narrower digits of the Dream 6800 CHIPOS
+const DREAM6800_SPRITE_DATA: [u8; 80] = [
+    0xE0, 0xA0, 0xA0, 0xA0, 0xE0, // 0
+    0x40, 0x40, 0x40, 0x40, 0x40, // 1
+    0xE0, 0x20, 0xE0, 0x80, 0xE0, // 2
+    0xE0, 0x20, 0xE0, 0x20, 0xE0, // 3
+    0x80, 0xA0, 0xA0, 0xE0, 0x20, // 4
+    0xE0, 0x80, 0xE0, 0x20, 0xE0, // 5
+    0xE0, 0x80, 0xE0, 0xA0, 0xE0, // 6
+    0xE0, 0x20, 0x20, 0x20, 0x20, // 7
+    0xE0, 0xA0, 0xE0, 0xA0, 0xE0, // 8
+    0xE0, 0xA0, 0xE0, 0x20, 0xE0, // 9
+    0xE0, 0xA0, 0xE0, 0xA0, 0xA0, // A
+    0xC0, 0xA0, 0xE0, 0xA0, 0xC0, // B
+    0xE0, 0x80, 0x80, 0x80, 0xE0, // C
+    0xC0, 0xA0, 0xA0, 0xA0, 0xC0, // D
+    0xE0, 0x80, 0xE0, 0x80, 0xE0, // E
+    0xE0, 0x80, 0xC0, 0x80, 0x80, // F
+];
+
+// the SCHIP big digits, 8x10, right after the small ones
+const BIG_SPRITE_DATA_START: usize = SPRITE_DATA_START + SPRITE_DATA.len();
+const BIG_SPRITE_DATA: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
 // minimum subroutine stack size (to preallocate)
 const MIN_SUB_STACK_SIZE: usize = 12;
 
@@ -40,49 +90,284 @@ const MIN_SUB_STACK_SIZE: usize = 12;
 const ADDR_START: usize = 0x200;
 const ADDR_END: usize = 0xE8F;
 
-// rom size
-const MAX_ROM_SIZE: usize = ADDR_END - ADDR_START + 1;
+/// Largest rom that fits in memory; bigger ones are truncated
+pub const MAX_ROM_SIZE: usize = ADDR_END - ADDR_START + 1;
+
+/// Largest rom that fits in memory with [`Quirks::full_memory`]
+pub const MAX_FULL_ROM_SIZE: usize = MEM_SIZE - ADDR_START;
+
+// where the VIP interpreter keeps its stack, the V registers and the
+// display buffer, which machine code subroutines use directly
+const VIP_STACK: u16 = 0xECF;
+const VIP_REGISTERS: usize = 0xEF0;
+const VIP_DISPLAY: usize = 0xF00;
+
+// 1802 instructions a machine code subroutine can run before giving up
+const MACHINE_CODE_LIMIT: usize = 100_000;
 
 #[derive(Error, Debug)]
 pub enum EmulatorError {
     #[error("invalid return at address {0:#05X}")]
     InvalidReturn(u16),
 
+    #[error("stack overflow at address {0:#05X}")]
+    StackOverflow(u16),
+
     #[error("machine subroutine call at address {0:#05X}")]
     MachineSubroutine(u16),
 
+    #[error(transparent)]
+    MachineCode(#[from] Cdp1802Error),
+
     #[error("invalid jump at address {2:#05X}: {0:02X}{1:02X}")]
     InvalidJump(u8, u8, u16),
 
     #[error("invalid opcode at address {2:#05X}: {0:02X}{1:02X}")]
     InvalidOpcode(u8, u8, u16),
 
+    #[error("memory access past the end at address {1:#05X}: I = {0:#06X}")]
+    InvalidIndex(u16, u16),
+
+    #[error("memory access out of bounds: {0:#05X}")]
+    OutOfBounds(usize),
+
+    #[error("the rom is too big: only {0} bytes fit in memory")]
+    RomTooBig(usize),
+
     #[error("could not load rom")]
     Io(#[from] std::io::Error),
 }
 
-#[inline(always)]
-fn nibble_h(b: u8) -> u8 {
-    (b >> 4) & 0xF
+/// What [`Emulator::step`] ran, so tools don't need to decode it again
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct StepInfo {
+    pub op: Opcode,
+    /// PC before the instruction
+    pub old_pc: usize,
+    /// PC after the instruction; the same as before while DXYN waits for
+    /// the vblank or FX0A waits for a key
+    pub new_pc: usize,
+    /// If the instruction drew on, cleared or scrolled the screen, or
+    /// changed its resolution or colors
+    pub screen_changed: bool,
+    /// If the sound timer or the tone changed
+    pub sound_changed: bool,
+}
+
+/// What happened on a frame run by [`Emulator::run_frame`]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct FrameInfo {
+    /// Instructions run; fewer than asked when a draw waits for the next
+    /// frame
+    pub instructions: u32,
+    /// If any instruction changed the screen
+    pub screen_changed: bool,
+    /// If the buzzer is on at the end of the frame
+    pub buzzer: bool,
+}
+
+/// The built-in hex digits FX29 points to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Font {
+    #[default]
+    Vip,
+    Dream6800,
+}
+
+impl Font {
+    fn sprite_data(self) -> &'static [u8; 80] {
+        match self {
+            Font::Vip => &SPRITE_DATA,
+            Font::Dream6800 => &DREAM6800_SPRITE_DATA,
+        }
+    }
+}
+
+impl FromStr for Font {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vip" => Ok(Font::Vip),
+            "dream6800" => Ok(Font::Dream6800),
+            _ => Err(format!("unknown font: {}", s)),
+        }
+    }
+}
+
+/// How I behaves when it goes past the end of memory
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum IndexMode {
+    /// I is a 16-bit register; memory accesses wrap around at 4K
+    #[default]
+    Wrap16,
+    /// I only has 12 bits, so it wraps at 0xFFF
+    Wrap12,
+    /// I is 16-bit, but using it past the end of memory is an error
+    Checked,
+}
+
+impl FromStr for IndexMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wrap16" => Ok(IndexMode::Wrap16),
+            "wrap12" => Ok(IndexMode::Wrap12),
+            "checked" => Ok(IndexMode::Checked),
+            _ => Err(format!("unknown index mode: {}", s)),
+        }
+    }
+}
+
+/// How many subroutine calls can be nested
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum StackMode {
+    /// As many as the host memory allows
+    #[default]
+    Unbounded,
+    /// 12 levels, like the COSMAC VIP interpreter
+    Vip,
+    /// 16 levels, like SCHIP and most modern interpreters
+    Schip,
+}
+
+impl StackMode {
+    /// The deepest the stack goes, if there is a limit
+    pub fn depth(self) -> Option<usize> {
+        match self {
+            StackMode::Unbounded => None,
+            StackMode::Vip => Some(12),
+            StackMode::Schip => Some(16),
+        }
+    }
+}
+
+impl FromStr for StackMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "unbounded" => Ok(StackMode::Unbounded),
+            "12" => Ok(StackMode::Vip),
+            "16" => Ok(StackMode::Schip),
+            _ => Err(format!("unknown stack depth: {}", s)),
+        }
+    }
+}
+
+/// Behaviors that differ between CHIP-8 implementations.
+///
+/// ```
+/// use rc8::emulator::{Emulator, Quirks};
+///
+/// let rom = [0x00, 0xE0]; // 0x200: clear the screen
+/// let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+/// emu.quirks = Quirks {
+///     wrap_sprites: true,
+///     ..Quirks::default()
+/// };
+///
+/// // quirks survive a reset
+/// emu.reset();
+/// assert!(emu.quirks.wrap_sprites);
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Quirks {
+    /// Sprites that go past the edges of the screen wrap around to
+    /// the other side, instead of being clipped.
+    pub wrap_sprites: bool,
+
+    /// Generator used by CXNN; changes take effect on the next reset.
+    pub rng: RngKind,
+
+    /// 0NNN runs COSMAC VIP machine code on a small CDP1802 interpreter,
+    /// instead of stopping with an error.
+    pub vip_machine_code: bool,
+
+    /// FX0A finishes as soon as a key goes down, instead of waiting for it
+    /// to be released like the COSMAC VIP does.
+    pub wait_key_press: bool,
+
+    /// Instruction set of the rom; changes take effect on the next reset,
+    /// as CHIP-8X roms are loaded on another address.
+    pub variant: Variant,
+
+    /// Hex digits loaded on memory; changes take effect on the next reset.
+    pub font: Font,
+
+    /// How I wraps, for FX1E, FX55 and FX65, and for the memory it points to.
+    pub index: IndexMode,
+
+    /// In low resolution, scrolls move half as far, like SCHIP 1.1 does by
+    /// scrolling high resolution pixels; odd distances round down.
+    pub half_scroll: bool,
+
+    /// How deep subroutine calls go; calling past it is an error.
+    pub stack: StackMode,
+
+    /// SCHIP and XO-CHIP roms can be loaded up to 0xFFF, on the memory the
+    /// VIP interpreter keeps for itself; changes take effect on the next
+    /// reset.
+    pub full_memory: bool,
+
+    /// The buzzer sounds while ST is above 0, instead of above 1 like on
+    /// the COSMAC VIP, where setting ST to 1 is too short to be heard.
+    pub short_beeps: bool,
 }
 
-#[inline(always)]
-fn nibble_l(b: u8) -> u8 {
-    b & 0xF
+impl Quirks {
+    /// The CHIPOS interpreter of the Dream 6800: its own font and nothing
+    /// past the VIP instructions, with 0NNN being 6800 code rc8 cannot run
+    pub fn dream6800() -> Self {
+        Quirks {
+            font: Font::Dream6800,
+            ..Quirks::default()
+        }
+    }
 }
 
-#[inline(always)]
-fn nnn(a: u8, b: u8) -> u16 {
-    (((a as u16) << 8) | (b as u16)) & 0xFFF
+/// FNV-1a hash, used for fingerprints that must be stable across runs and platforms
+fn fnv1a<I: IntoIterator<Item = u8>>(bytes: I) -> u64 {
+    bytes.into_iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
 }
 
+/// A CHIP-8 machine: memory, registers, timers, keypad and screen.
+///
+/// The emulator does not keep track of time; the frontend calls
+/// [`Emulator::execute`], [`Emulator::decrease_timers`] and
+/// [`Emulator::vblank`] at the right pace (see [`crate::timing`]).
+///
+/// ```
+/// use rc8::emulator::Emulator;
+///
+/// let rom = [
+///     0x60, 0x2A, // 0x200: V0 = 42
+///     0x61, 0x08, // 0x202: V1 = 8
+///     0x80, 0x14, // 0x204: V0 += V1
+/// ];
+///
+/// let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+/// for _ in 0..3 {
+///     emu.execute().unwrap();
+/// }
+///
+/// assert_eq!(emu.V[0], 50);
+/// assert_eq!(emu.PC, 0x206);
+/// ```
 #[allow(non_snake_case)]
 pub struct Emulator {
     // program counter
     pub PC: usize,
 
     // full memory
-    pub memory: [u8; MEM_SIZE],
+    pub memory: Memory,
 
     // data registers: V0 - VF
     pub V: [u8; 16],
@@ -103,69 +388,406 @@ pub struct Emulator {
     keys: [bool; 16],
 
     // random number generator
-    rng: BufferedRng<WyRand, 8>,
+    rng: Box<dyn Chip8Rng>,
+
+    // which kind of generator `rng` is, to rebuild it when the quirks change
+    rng_kind: RngKind,
 
-    // screen - 64x32
-    screen: [u64; 32],
-    prev_screen: [u64; 32],
+    // the screen, and how it was when last checked for changes
+    screen: Display,
+    prev_screen: Display,
+
+    // XO-CHIP: the planes DXYN draws on, one bit per plane
+    planes: u8,
 
     // if a vblank interrupt happened
     // the draw command waits for this, to avoid
     // tearing on the sprites
     vblank_interrupt: bool,
 
-    // last pressed key
-    last_pressed_key: Option<u8>,
+    // how many times the keys changed so far, and on which of these
+    // changes each key last went down and up; FX0A uses them to tell which
+    // key came last, however many changed between two instructions
+    key_clock: u64,
+    key_down_at: [u64; 16],
+    key_up_at: [u64; 16],
+
+    // the key clock when the last instruction ended, and if FX0A is
+    // waiting; while it waits, it counts changes from before it started
+    key_mark: u64,
+    waiting_key: bool,
+
+    // implementation-specific behaviors
+    pub quirks: Quirks,
+
+    // how many sprites went past the screen edges and were clipped
+    clipped_sprites: u32,
+
+    // the loaded rom, kept for resetting
+    rom: Vec<u8>,
+
+    // CHIP-8X: the second keypad, the colors and the tone generator
+    keys2: [bool; 16],
+    color_board: ColorBoard,
+    tone: u8,
+
+    /// SCHIP: the RPL user flags, which are kept across resets (and can
+    /// be kept on disk), so roms use them for high scores
+    pub flags: [u8; 16],
+
+    // something besides the pixels changed, like the colors or a restore
+    redraw: bool,
+
+    // the buzzer state last reported, and who to report changes to
+    sound_on: bool,
+    on_sound_change: Option<Box<dyn FnMut(bool) + Send>>,
+}
+
+#[cfg(feature = "serde")]
+impl Serialize for Emulator {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.snapshot().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> Deserialize<'de> for Emulator {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let snapshot = Snapshot::deserialize(deserializer)?;
+        Emulator::from_snapshot(&snapshot).map_err(serde::de::Error::custom)
+    }
 }
 
 impl Emulator {
-    /// Load a chip-8 rom, up to the maximum allowed rom size.
+    /// Set the quirks, the random numbers or where the rom starts before
+    /// loading it; see [`EmulatorBuilder`]
+    pub fn builder() -> EmulatorBuilder {
+        EmulatorBuilder::default()
+    }
+
+    /// Load a chip-8 rom; fails if it is bigger than the maximum allowed
+    /// rom size. Use [`EmulatorBuilder::truncate`] to load it anyway.
     pub fn load_rom<T>(rom: T) -> Result<Self, EmulatorError>
     where
         T: Read,
     {
+        let (emu, oversized) = Self::read_rom(rom, RngKind::default(), None)?;
+        emu.check_size(oversized)?;
+        Ok(emu)
+    }
+
+    /// Read the rom and build the machine for it, with the default quirks
+    /// and a `rng` generator seeded with `seed` (or system entropy); also
+    /// tells if the rom had more bytes than any quirk allows, which are
+    /// left out
+    fn read_rom<T>(rom: T, rng: RngKind, seed: Option<u64>) -> Result<(Self, bool), EmulatorError>
+    where
+        T: Read,
+    {
+        // read the rom itself, and one byte more to know if it is too big
+        let mut bytes = Vec::with_capacity(MAX_ROM_SIZE);
+        rom.take(MAX_FULL_ROM_SIZE as u64 + 1)
+            .read_to_end(&mut bytes)?;
+        let oversized = bytes.len() > MAX_FULL_ROM_SIZE;
+        bytes.truncate(MAX_FULL_ROM_SIZE);
+
         let mut emu = Emulator {
             PC: ADDR_START,
-            memory: [0u8; MEM_SIZE],
+            memory: Memory::default(),
             V: [0u8; 16],
             I: 0,
             sub_stack: Vec::with_capacity(MIN_SUB_STACK_SIZE),
             DT: 0,
             ST: 0,
             keys: [false; 16],
-            rng: BufferedRng::new(WyRand::new()),
-            screen: [0u64; 32],
-            prev_screen: [0u64; 32],
+            rng: rng.build(seed),
+            rng_kind: rng,
+            screen: Display::default(),
+            prev_screen: Display::default(),
+            planes: 1,
             vblank_interrupt: false,
-            last_pressed_key: None,
+            key_clock: 0,
+            key_down_at: [0; 16],
+            key_up_at: [0; 16],
+            key_mark: 0,
+            waiting_key: false,
+            quirks: Quirks::default(),
+            clipped_sprites: 0,
+            rom: bytes,
+            keys2: [false; 16],
+            color_board: ColorBoard::default(),
+            redraw: false,
+            tone: 0,
+            flags: [0; 16],
+            sound_on: false,
+            on_sound_change: None,
         };
 
-        // load the sprite data
-        let sprite_area = &mut emu.memory[SPRITE_DATA_START..SPRITE_DATA_START + SPRITE_DATA.len()];
-        sprite_area.copy_from_slice(&SPRITE_DATA[..]);
+        emu.reset();
+        Ok((emu, oversized))
+    }
 
-        // load the rom itself
-        let mut rom = rom.take((MAX_ROM_SIZE) as u64);
-        let mut total_read = ADDR_START;
+    /// Load a chip-8 rom already in memory; fails if it is bigger than the
+    /// maximum allowed rom size.
+    pub fn load_rom_bytes(rom: &[u8]) -> Result<Self, EmulatorError> {
+        Self::load_rom(rom)
+    }
 
-        loop {
-            let bytes_read = rom.read(&mut emu.memory[total_read..ADDR_END + 1])?;
-            if bytes_read == 0 {
-                break;
-            } else {
-                total_read += bytes_read
-            }
+    /// Restore the machine to its initial state, as if the
+    /// rom was just loaded. Quirks are kept as they are.
+    pub fn reset(&mut self) {
+        let start = self.start_address();
+        debug!(
+            "reset, {} rom starting at 0x{:03X}",
+            self.quirks.variant, start
+        );
+        self.PC = start;
+        self.V.fill(0);
+        self.I = 0;
+        self.sub_stack.clear();
+        self.DT = 0;
+        self.ST = 0;
+        self.keys.fill(false);
+        self.screen = match self.quirks.variant {
+            Variant::XoChip => Display::new(2),
+            _ => Display::default(),
+        };
+        self.planes = 1;
+        self.vblank_interrupt = false;
+        self.key_clock = 0;
+        self.key_down_at.fill(0);
+        self.key_up_at.fill(0);
+        self.key_mark = 0;
+        self.waiting_key = false;
+        self.clipped_sprites = 0;
+        self.keys2.fill(false);
+        self.color_board = ColorBoard::default();
+        self.redraw = true;
+        self.tone = 0;
+        self.check_sound();
+
+        if self.rng_kind != self.quirks.rng {
+            self.rng = self.quirks.rng.build(None);
+            self.rng_kind = self.quirks.rng;
+        }
+
+        // load the sprite data and the rom itself
+        self.memory.fill(0);
+        let sprite_data = self.quirks.font.sprite_data();
+        let sprite_area =
+            &mut self.memory[SPRITE_DATA_START..SPRITE_DATA_START + sprite_data.len()];
+        sprite_area.copy_from_slice(&sprite_data[..]);
+
+        // only the variants with FX30 have the big ones
+        if matches!(self.quirks.variant, Variant::SChip | Variant::XoChip) {
+            let big_area = &mut self.memory
+                [BIG_SPRITE_DATA_START..BIG_SPRITE_DATA_START + BIG_SPRITE_DATA.len()];
+            big_area.copy_from_slice(&BIG_SPRITE_DATA);
         }
 
+        // a CHIP-8X rom has less room, as it starts later
+        let size = self.rom.len().min(self.rom_end() + 1 - start);
+        self.memory[start..start + size].copy_from_slice(&self.rom[..size]);
+    }
+
+    /// The last address a rom can be loaded on
+    fn rom_end(&self) -> usize {
+        match self.quirks.variant {
+            Variant::SChip | Variant::XoChip if self.quirks.full_memory => MEM_SIZE - 1,
+            _ => ADDR_END,
+        }
+    }
+
+    /// How many bytes at the end of the rom did not fit in memory, and were
+    /// left out
+    pub fn truncated(&self) -> usize {
+        self.rom.len().saturating_sub(self.rom_room())
+    }
+
+    // how many bytes of rom fit in memory, with the current quirks
+    fn rom_room(&self) -> usize {
+        self.rom_end() + 1 - self.start_address()
+    }
+
+    fn check_size(&self, oversized: bool) -> Result<(), EmulatorError> {
+        match oversized || self.truncated() > 0 {
+            true => Err(EmulatorError::RomTooBig(self.rom_room())),
+            false => Ok(()),
+        }
+    }
+
+    /// Where the rom is loaded, and the program starts
+    pub fn start_address(&self) -> usize {
+        match self.quirks.variant {
+            Variant::Chip8X => chip8x::ADDR_START,
+            Variant::Chip8 | Variant::SChip | Variant::XoChip => ADDR_START,
+        }
+    }
+
+    /// Replace the random number generator with one using a fixed seed,
+    /// making CXNN reproducible.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng = self.quirks.rng.build(Some(seed));
+        self.rng_kind = self.quirks.rng;
+    }
+
+    /// Use a custom random number generator, until the rng quirk changes
+    pub fn set_rng(&mut self, rng: Box<dyn Chip8Rng>) {
+        self.rng = rng;
+    }
+
+    /// A copy of the random number generator, as it is now, to put back
+    /// with [`Emulator::set_rng`] along with a snapshot; `None` if the
+    /// generator cannot be copied
+    pub fn copy_rng(&self) -> Option<Box<dyn Chip8Rng>> {
+        self.rng.try_clone()
+    }
+
+    /// Copy the whole machine state, except for the random number generator.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            version: SNAPSHOT_VERSION,
+            PC: self.PC,
+            memory: self.memory.to_vec(),
+            V: self.V,
+            I: self.I,
+            sub_stack: self.sub_stack.clone(),
+            DT: self.DT,
+            ST: self.ST,
+            keys: self.keys,
+            screen: self.screen.clone(),
+            planes: self.planes,
+            vblank_interrupt: self.vblank_interrupt,
+            key_clock: self.key_clock,
+            key_down_at: self.key_down_at,
+            key_up_at: self.key_up_at,
+            key_mark: self.key_mark,
+            waiting_key: self.waiting_key,
+            quirks: self.quirks,
+            clipped_sprites: self.clipped_sprites,
+            rom: self.rom.clone(),
+            keys2: self.keys2,
+            color_board: self.color_board.clone(),
+            tone: self.tone,
+            flags: self.flags,
+        }
+    }
+
+    /// Bring the machine back to a previous snapshot. Nothing is changed
+    /// if the snapshot is invalid.
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::Version(snapshot.version));
+        }
+
+        let memory = Memory::try_from(&snapshot.memory[..])
+            .map_err(|_| SnapshotError::Invalid("wrong memory size"))?;
+        if !snapshot.screen.is_valid() {
+            return Err(SnapshotError::Invalid("wrong screen size"));
+        }
+        if snapshot.PC >= MEM_SIZE - 1 {
+            return Err(SnapshotError::Invalid("PC outside of memory"));
+        }
+        if snapshot.rom.len() > MAX_FULL_ROM_SIZE {
+            return Err(SnapshotError::Invalid("rom too big"));
+        }
+        if snapshot.color_board.cells.len() != ColorBoard::default().cells.len() {
+            return Err(SnapshotError::Invalid("wrong color board size"));
+        }
+
+        self.PC = snapshot.PC;
+        self.memory = memory;
+        self.V = snapshot.V;
+        self.I = snapshot.I;
+        self.sub_stack = snapshot.sub_stack.clone();
+        self.DT = snapshot.DT;
+        self.ST = snapshot.ST;
+        self.keys = snapshot.keys;
+        self.screen = snapshot.screen.clone();
+        self.planes = snapshot.planes;
+        self.vblank_interrupt = snapshot.vblank_interrupt;
+        self.key_clock = snapshot.key_clock;
+        self.key_down_at = snapshot.key_down_at;
+        self.key_up_at = snapshot.key_up_at;
+        self.key_mark = snapshot.key_mark;
+        self.waiting_key = snapshot.waiting_key;
+        self.quirks = snapshot.quirks;
+        self.clipped_sprites = snapshot.clipped_sprites;
+        self.rom = snapshot.rom.clone();
+        self.keys2 = snapshot.keys2;
+        self.color_board = snapshot.color_board.clone();
+        self.redraw = true;
+        self.tone = snapshot.tone;
+        self.flags = snapshot.flags;
+        self.check_sound();
+
+        if self.rng_kind != self.quirks.rng {
+            self.rng = self.quirks.rng.build(None);
+            self.rng_kind = self.quirks.rng;
+        }
+        Ok(())
+    }
+
+    /// Create a new machine from a snapshot.
+    pub fn from_snapshot(snapshot: &Snapshot) -> Result<Self, SnapshotError> {
+        let mut emu = Emulator::load_rom(&[][..]).expect("an empty rom always loads");
+        emu.restore(snapshot)?;
         Ok(emu)
     }
 
     /// Set the state of a key (pressed/released).
     pub fn set_key(&mut self, key: usize, pressed: bool) {
-        if self.keys[key & 0xF] && !pressed {
-            self.last_pressed_key = Some(key as u8)
+        let key = key & 0xF;
+        if self.keys[key] == pressed {
+            return;
         }
-        self.keys[key & 0xF] = pressed;
+
+        self.keys[key] = pressed;
+        self.key_clock += 1;
+        match pressed {
+            true => self.key_down_at[key] = self.key_clock,
+            false => self.key_up_at[key] = self.key_clock,
+        }
+    }
+
+    /// Returns wether the key is currently pressed
+    pub fn is_key_pressed(&self, key: usize) -> bool {
+        self.keys[key & 0xF]
+    }
+
+    /// The keys currently pressed, one bit per key (bit 0 for key 0)
+    pub fn pressed_keys(&self) -> u16 {
+        (0..16).fold(0, |acc, key| acc | (self.keys[key] as u16) << key)
+    }
+
+    /// If FX0A is waiting for a key
+    pub fn waiting_for_key(&self) -> bool {
+        self.waiting_key
+    }
+
+    /// Set the state of a key on the second keypad (CHIP-8X)
+    pub fn set_key2(&mut self, key: usize, pressed: bool) {
+        self.keys2[key & 0xF] = pressed;
+    }
+
+    /// The colors of a CHIP-8X rom; `None` for plain CHIP-8
+    pub fn color_board(&self) -> Option<&ColorBoard> {
+        (self.quirks.variant == Variant::Chip8X).then_some(&self.color_board)
+    }
+
+    /// The last value sent to the tone generator by a CHIP-8X rom
+    pub fn tone(&self) -> u8 {
+        self.tone
+    }
+
+    /// The loaded rom, as it was before running
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    /// How many sprites were clipped at the screen edges since the last reset
+    pub fn clipped_sprites(&self) -> u32 {
+        self.clipped_sprites
     }
 
     // registers that a vblank interrupt happened
@@ -177,40 +799,291 @@ impl Emulator {
     pub fn decrease_timers(&mut self) {
         self.DT = self.DT.checked_sub(1).unwrap_or(self.DT);
         self.ST = self.ST.checked_sub(1).unwrap_or(self.ST);
+        self.check_sound();
+    }
+
+    /// If the buzzer should be sounding now
+    pub fn buzzer(&self) -> bool {
+        match self.quirks.short_beeps {
+            true => self.ST > 0,
+            // on COSMAC VIP, the sound is not played if ST is less than 2
+            // this is a hardware quirk.
+            false => self.ST > 1,
+        }
+    }
+
+    /// Call `callback` whenever the buzzer turns on or off, as the timers
+    /// run down, a rom sets ST, or the machine is reset or restored
+    pub fn on_sound_change(&mut self, callback: impl FnMut(bool) + Send + 'static) {
+        self.on_sound_change = Some(Box::new(callback));
+    }
+
+    fn check_sound(&mut self) {
+        let on = self.buzzer();
+        if on != self.sound_on {
+            self.sound_on = on;
+            if let Some(callback) = &mut self.on_sound_change {
+                callback(on);
+            }
+        }
     }
 
     /// Returns wether the pixel at location (x, y) is set
+    ///
+    /// ```
+    /// use rc8::emulator::Emulator;
+    ///
+    /// let rom = [
+    ///     0x60, 0x0A, // 0x200: V0 = 10
+    ///     0xF0, 0x29, // 0x202: I = sprite for the digit in V0 ("A")
+    ///     0xD1, 0x15, // 0x204: draw 5 rows at (V1, V1) = (0, 0)
+    /// ];
+    ///
+    /// let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+    /// emu.vblank();
+    /// for _ in 0..3 {
+    ///     emu.execute().unwrap();
+    /// }
+    ///
+    /// // the top row of "A" is 0xF0: four pixels set, then four clear
+    /// assert!((0..4).all(|x| emu.get_pixel(x, 0)));
+    /// assert!((4..8).all(|x| !emu.get_pixel(x, 0)));
+    /// ```
     pub fn get_pixel(&self, x: usize, y: usize) -> bool {
-        let x = x % DISPLAY_WIDTH;
-        let y = y % DISPLAY_HEIGHT;
+        self.screen.get_pixel(x, y)
+    }
+
+    /// The screen, with its size and planes
+    pub fn display(&self) -> &Display {
+        &self.screen
+    }
+
+    /// The screen, packed: a row per entry, with the leftmost pixel on bit
+    /// `width - 1`, so 32 rows of 64 bits on the low resolution screen.
+    /// Only the first plane; XO-CHIP roms can draw on the second one too,
+    /// on [`Display::rows`]
+    pub fn framebuffer_bits(&self) -> &[u128] {
+        self.screen.rows(0)
+    }
+
+    /// Draw the screen on `rgba`, 4 bytes per pixel, row by row: lit pixels
+    /// (on any plane) in `fg` and the others in `bg`, both as `0xRRGGBB`.
+    /// Returns the width and height of the screen; the pixels that don't
+    /// fit on `rgba` are left out.
+    pub fn render_rgba(&self, rgba: &mut [u8], fg: u32, bg: u32) -> (usize, usize) {
+        let (width, height) = (self.screen.width(), self.screen.height());
+        let color = |rgb: u32| {
+            let [_, r, g, b] = rgb.to_be_bytes();
+            [r, g, b, 0xFF]
+        };
+        let (fg, bg) = (color(fg), color(bg));
+
+        let mut pixels = rgba.chunks_exact_mut(4);
+        for y in 0..height {
+            let lit =
+                (0..self.screen.planes()).fold(0, |bits, plane| bits | self.screen.rows(plane)[y]);
+            for x in (0..width).rev() {
+                let Some(pixel) = pixels.next() else {
+                    return (width, height);
+                };
+                pixel.copy_from_slice(if lit >> x & 1 != 0 { &fg } else { &bg });
+            }
+        }
+        (width, height)
+    }
 
-        let mask = 1 << (DISPLAY_WIDTH - x - 1);
-        (self.screen[y] & mask) > 0
+    /// A fingerprint of the current screen contents
+    pub fn screen_hash(&self) -> u64 {
+        fnv1a(self.screen.bytes())
+    }
+
+    /// A fingerprint of the whole machine state (except the rng)
+    pub fn state_hash(&self) -> u64 {
+        let registers = [self.DT, self.ST, self.vblank_interrupt as u8]
+            .into_iter()
+            .chain(self.V)
+            .chain((self.PC as u16).to_be_bytes())
+            .chain(self.I.to_be_bytes())
+            .chain([if self.waiting_for_key() { 0xFE } else { 0xFF }]);
+        let stack = self
+            .sub_stack
+            .iter()
+            .flat_map(|&addr| (addr as u16).to_be_bytes());
+        let keys = self.keys.iter().map(|&pressed| pressed as u8);
+        let screen = self.screen.bytes();
+        // only XO-CHIP and CHIP-8X use these, so plain roms keep their hashes
+        let planes = (self.quirks.variant == Variant::XoChip).then_some(self.planes);
+        let colors = self.color_board().into_iter().flat_map(|board| {
+            [board.background, self.tone]
+                .into_iter()
+                .chain(board.cells.iter().copied())
+        });
+
+        fnv1a(
+            registers
+                .chain(stack)
+                .chain(keys)
+                .chain(self.memory.iter().copied())
+                .chain(screen)
+                .chain(planes)
+                .chain(colors),
+        )
     }
 
     /// Returns true if the pixels on the screen were changed since the
-    /// last call of this  method
+    /// last call of this method (or of [`dirty_rows`](Emulator::dirty_rows))
     pub fn screen_changed(&mut self) -> bool {
-        let changed = self.screen != self.prev_screen;
-        self.prev_screen = self.screen;
-        changed
+        !self.dirty_rows().is_empty()
+    }
+
+    /// The rows of the screen that changed since the last call of this
+    /// method, in order; all of them after a reset, a change of resolution
+    /// or a change of the CHIP-8X colors
+    pub fn dirty_rows(&mut self) -> Vec<usize> {
+        let (screen, prev) = (&self.screen, &self.prev_screen);
+        let all =
+            self.redraw || screen.is_hires() != prev.is_hires() || screen.planes() != prev.planes();
+
+        let rows = (0..screen.height())
+            .filter(|&y| all || (0..screen.planes()).any(|p| screen.rows(p)[y] != prev.rows(p)[y]))
+            .collect();
+        self.prev_screen.clone_from(&self.screen);
+        self.redraw = false;
+        rows
+    }
+
+    /// Run a machine code subroutine the way the VIP interpreter does: R3 is
+    /// the program counter and `SEP R4` returns to the interpreter. The
+    /// subroutine can change the registers and the screen through memory.
+    fn call_machine_code(&mut self, addr: u16) -> Result<(), Cdp1802Error> {
+        self.memory[VIP_REGISTERS..VIP_REGISTERS + 16].copy_from_slice(&self.V);
+        // the VIP only has the low resolution screen
+        let rows = self.screen.rows(0).iter();
+        for (bytes, &row) in self.memory[VIP_DISPLAY..].chunks_mut(8).zip(rows) {
+            bytes.copy_from_slice(&(row as u64).to_be_bytes());
+        }
+
+        let mut cpu = Cdp1802 {
+            X: 2,
+            P: 3,
+            ..Cdp1802::default()
+        };
+        cpu.R[0x2] = VIP_STACK;
+        cpu.R[0x3] = addr;
+        cpu.R[0x5] = self.PC as u16;
+        // X and Y, as if 0NNN was any other instruction
+        cpu.R[0x6] = VIP_REGISTERS as u16 + ((addr >> 8) & 0xF);
+        cpu.R[0x7] = VIP_REGISTERS as u16 + ((addr >> 4) & 0xF);
+        cpu.R[0x8] = u16::from_be_bytes([self.DT, self.ST]);
+        cpu.R[0xA] = self.I;
+        cpu.R[0xB] = VIP_DISPLAY as u16;
+        cpu.run_until_sep(&mut self.memory, 4, MACHINE_CODE_LIMIT)?;
+
+        self.V
+            .copy_from_slice(&self.memory[VIP_REGISTERS..VIP_REGISTERS + 16]);
+        for (row, bytes) in self.memory[VIP_DISPLAY..].chunks(8).enumerate() {
+            let bits = u64::from_be_bytes(bytes.try_into().unwrap());
+            self.screen.set_row(0, row, bits as u128);
+        }
+        [self.DT, self.ST] = cpu.R[0x8].to_be_bytes();
+        self.I = cpu.R[0xA] & 0xFFF;
+        self.PC = (cpu.R[0x5] & 0xFFF) as usize;
+        Ok(())
+    }
+
+    /// The big-endian instruction word at `addr`; panics past the end of
+    /// memory, use [`Memory::word`] for a checked read
+    pub fn word_at(&self, addr: usize) -> u16 {
+        u16::from_be_bytes([self.memory[addr], self.memory[addr + 1]])
+    }
+
+    /// The instruction on PC, the one [`execute`](Emulator::execute) runs
+    /// next; `None` if it is not a valid instruction
+    pub fn next_opcode(&self) -> Option<Opcode> {
+        let word = self.memory.word(self.PC).ok()?;
+        Opcode::decode_variant(word, self.quirks.variant)
     }
 
     /// Execute a single chip-8 CPU instruction.
     pub fn execute(&mut self) -> Result<(), EmulatorError> {
+        self.run_next().map(|_| ())
+    }
+
+    /// Execute a single chip-8 CPU instruction, telling what it did
+    pub fn step(&mut self) -> Result<StepInfo, EmulatorError> {
+        let old_pc = self.PC;
+        let sound = (self.ST, self.tone);
+        let op = self.run_next()?;
+
+        let screen_changed = match op {
+            // a waiting draw runs again, and only then draws
+            Opcode::Draw(..) => self.PC != old_pc,
+            // machine code can write to the display buffer
+            Opcode::Sys(_)
+            | Opcode::Clear
+            | Opcode::ScrollDown(_)
+            | Opcode::ScrollUp(_)
+            | Opcode::ScrollRight
+            | Opcode::ScrollLeft
+            | Opcode::Lores
+            | Opcode::Hires
+            | Opcode::CycleBackground
+            | Opcode::ColorZones(..)
+            | Opcode::ColorRows(..) => true,
+            _ => false,
+        };
+
+        Ok(StepInfo {
+            op,
+            old_pc,
+            new_pc: self.PC,
+            screen_changed,
+            sound_changed: (self.ST, self.tone) != sound,
+        })
+    }
+
+    /// Run a whole frame: the vblank, up to `ipf` instructions and a timer
+    /// tick. A draw waiting for the vblank ends the frame early, as nothing
+    /// else runs until the next one.
+    pub fn run_frame(&mut self, ipf: u32) -> Result<FrameInfo, EmulatorError> {
+        let mut frame = FrameInfo::default();
+        self.vblank();
+
+        for _ in 0..ipf {
+            let info = self.step()?;
+            if matches!(info.op, Opcode::Draw(..)) && info.new_pc == info.old_pc {
+                break;
+            }
+            frame.instructions += 1;
+            frame.screen_changed |= info.screen_changed;
+        }
+
+        self.decrease_timers();
+        frame.buzzer = self.buzzer();
+        Ok(frame)
+    }
+
+    /// Fetch, decode and run the instruction on PC
+    fn run_next(&mut self) -> Result<Opcode, EmulatorError> {
         // read a command
-        let a = self.memory[self.PC];
-        let b = self.memory[self.PC + 1];
+        let word = self.memory.word(self.PC)?;
+        let [a, b] = word.to_be_bytes();
         self.PC += 2;
 
-        // choose the instruction to run
-        match nibble_h(a) {
+        // decode it once, with the same decoder used by the tools
+        let op = match Opcode::decode_variant(word, self.quirks.variant) {
+            Some(op) => op,
+            None => return Err(EmulatorError::InvalidOpcode(a, b, (self.PC - 2) as u16)),
+        };
+        trace!("{:03X}: {}", self.PC - 2, op);
+
+        match op {
             // 00E0	- Clear the screen
-            0x0 if a == 0x00 && b == 0xE0 => {
-                self.screen.fill(0);
+            Opcode::Clear => {
+                self.screen.clear();
             }
             // 00EE	- Return from a subroutine
-            0x0 if a == 0x00 && b == 0xEE => {
+            Opcode::Return => {
                 if self.sub_stack.is_empty() {
                     return Err(EmulatorError::InvalidReturn((self.PC - 2) as u16));
                 }
@@ -218,135 +1091,123 @@ impl Emulator {
                 self.PC = self.sub_stack.pop().unwrap();
             }
             // 0NNN - Execute machine instruction
-            // it is ignored on emulators, here we return an error
-            // just to track it
-            0x0 => {
-                return Err(EmulatorError::MachineSubroutine(self.PC as u16));
+            // most emulators ignore it, here we return an error
+            // just to track it, unless the VIP is being emulated
+            Opcode::Sys(nnn) => {
+                if !self.quirks.vip_machine_code {
+                    return Err(EmulatorError::MachineSubroutine(self.PC as u16));
+                }
+                self.call_machine_code(nnn)?;
+                self.check_sound();
             }
             // 1NNN - jump to address NNN
-            0x1 => {
-                self.PC = nnn(a, b) as usize;
+            Opcode::Jump(nnn) => {
+                self.PC = nnn as usize;
             }
             // 2NNN	- Execute subroutine starting at address NNN
-            0x2 => {
+            Opcode::Call(nnn) => {
+                let depth = self.quirks.stack.depth();
+                if depth.is_some_and(|depth| self.sub_stack.len() >= depth) {
+                    self.PC -= 2;
+                    return Err(EmulatorError::StackOverflow(self.PC as u16));
+                }
                 self.sub_stack.push(self.PC);
-                self.PC = nnn(a, b) as usize;
+                self.PC = nnn as usize;
             }
             // 3XNN - skip next if VX == NN
-            0x3 => {
-                let index = nibble_l(a) as usize;
-                if self.V[index] == b {
+            Opcode::SkipEqByte(x, nn) => {
+                if self.V[x as usize] == nn {
                     self.PC += 2;
                 }
             }
             // 4XNN - skip next if VX != NN
-            0x4 => {
-                let index = nibble_l(a) as usize;
-                if self.V[index] != b {
+            Opcode::SkipNeqByte(x, nn) => {
+                if self.V[x as usize] != nn {
                     self.PC += 2;
                 }
             }
             // 5XY0 - skip next if VX == VY
-            0x5 if nibble_l(b) == 0x0 => {
-                let x = nibble_l(a) as usize;
-                let y = nibble_h(b) as usize;
-                if self.V[x] == self.V[y] {
+            Opcode::SkipEqReg(x, y) => {
+                if self.V[x as usize] == self.V[y as usize] {
                     self.PC += 2;
                 }
             }
             // 6XNN - Set VX to NN
-            0x6 => {
-                let index = nibble_l(a) as usize;
-                self.V[index] = b;
+            Opcode::LoadByte(x, nn) => {
+                self.V[x as usize] = nn;
             }
             // 7XNN - Set VX to VX + NN (ignore VF)
-            0x7 => {
-                let index = nibble_l(a) as usize;
-                let (result, _) = self.V[index].overflowing_add(b);
-                self.V[index] = result;
+            Opcode::AddByte(x, nn) => {
+                let x = x as usize;
+                self.V[x] = self.V[x].wrapping_add(nn);
             }
             // 8XY0 - Set VX = VY
-            0x8 if nibble_l(b) == 0x0 => {
-                let dst = nibble_l(a) as usize;
-                let src = nibble_h(b) as usize;
-                self.V[dst] = self.V[src];
+            Opcode::LoadReg(x, y) => {
+                self.V[x as usize] = self.V[y as usize];
             }
             // 8XY1 - Set VX = VX | VY
-            0x8 if nibble_l(b) == 0x1 => {
-                let x = nibble_l(a) as usize;
-                let y = nibble_h(b) as usize;
-                self.V[x] |= self.V[y];
+            Opcode::Or(x, y) => {
+                self.V[x as usize] |= self.V[y as usize];
                 self.V[0xF] = 0;
             }
             // 8XY2 - Set VX = VX & VY
-            0x8 if nibble_l(b) == 0x2 => {
-                let x = nibble_l(a) as usize;
-                let y = nibble_h(b) as usize;
-                self.V[x] &= self.V[y];
+            Opcode::And(x, y) => {
+                self.V[x as usize] &= self.V[y as usize];
                 self.V[0xF] = 0;
             }
             // 8XY3 - Set VX = VX ^ VY
-            0x8 if nibble_l(b) == 0x3 => {
-                let x = nibble_l(a) as usize;
-                let y = nibble_h(b) as usize;
-                self.V[x] ^= self.V[y];
+            Opcode::Xor(x, y) => {
+                self.V[x as usize] ^= self.V[y as usize];
                 self.V[0xF] = 0;
             }
             // 8XY4 - Set VX = VX + VY, set VF to 1 if carry
-            0x8 if nibble_l(b) == 0x4 => {
-                let x = nibble_l(a) as usize;
-                let y = nibble_h(b) as usize;
+            Opcode::AddReg(x, y) => {
+                let (x, y) = (x as usize, y as usize);
                 let (result, carry) = self.V[x].overflowing_add(self.V[y]);
                 self.V[x] = result;
                 self.V[0xF] = carry as u8;
             }
             // 8XY5 - Set VX = VX - VY, set VF to 0 if borrow
-            0x8 if nibble_l(b) == 0x5 => {
-                let x = nibble_l(a) as usize;
-                let y = nibble_h(b) as usize;
+            Opcode::Sub(x, y) => {
+                let (x, y) = (x as usize, y as usize);
                 let (result, carry) = self.V[x].overflowing_sub(self.V[y]);
                 self.V[x] = result;
                 self.V[0xF] = (!carry) as u8;
             }
             // 8XY6 - Set VX = VY >> 1; set VF to shifted bit
-            0x8 if nibble_l(b) == 0x6 => {
-                let x = nibble_l(a) as usize;
-                let y = nibble_h(b) as usize;
+            Opcode::ShiftRight(x, y) => {
+                let (x, y) = (x as usize, y as usize);
                 let flag = self.V[y] & 1;
                 self.V[x] = self.V[y] >> 1;
                 self.V[0xF] = flag;
             }
             // 8XY7 - Set VX = VY - VX, set VF to 0 if borrow
-            0x8 if nibble_l(b) == 0x7 => {
-                let x = nibble_l(a) as usize;
-                let y = nibble_h(b) as usize;
+            Opcode::SubN(x, y) => {
+                let (x, y) = (x as usize, y as usize);
                 let (result, carry) = self.V[y].overflowing_sub(self.V[x]);
                 self.V[x] = result;
                 self.V[0xF] = (!carry) as u8;
             }
             // 8XYE - Set VX = VY << 1; set VF to shitfted bit
-            0x8 if nibble_l(b) == 0xE => {
-                let x = nibble_l(a) as usize;
-                let y = nibble_h(b) as usize;
+            Opcode::ShiftLeft(x, y) => {
+                let (x, y) = (x as usize, y as usize);
                 let flag = self.V[y] >> 7;
                 self.V[x] = self.V[y] << 1;
                 self.V[0xF] = flag;
             }
             // 9XY0 - skip next if VX != VY
-            0x9 if nibble_l(b) == 0x0 => {
-                let x = nibble_l(a) as usize;
-                let y = nibble_h(b) as usize;
-                if self.V[x] != self.V[y] {
+            Opcode::SkipNeqReg(x, y) => {
+                if self.V[x as usize] != self.V[y as usize] {
                     self.PC += 2;
                 }
             }
             // ANNN - Set I = NNN
-            0xA => {
-                self.I = nnn(a, b);
+            Opcode::LoadI(nnn) => {
+                self.I = nnn;
             }
             // 0xBNNN - Jump to address NNN + V0
-            0xB => {
-                let addr = ((self.V[0x0] as u16) + nnn(a, b)) as usize;
+            Opcode::JumpV0(nnn) => {
+                let addr = ((self.V[0x0] as u16) + nnn) as usize;
                 if addr >= MEM_SIZE {
                     self.PC -= 2;
                     return Err(EmulatorError::InvalidJump(a, b, self.PC as u16));
@@ -354,140 +1215,362 @@ impl Emulator {
                 self.PC = addr;
             }
             // CXNN - Set VX to a random number with mask NN
-            0xC => {
-                let x = nibble_l(a) as usize;
-                let mut n = [0u8; 1];
-                self.rng.fill(&mut n);
-                self.V[x] = n[0] & b;
+            Opcode::Random(x, nn) => {
+                self.V[x as usize] = self.rng.next_byte() & nn;
             }
             // DXYN - Draw sprite at address I, on VX,VY and size N
             // set VF to 1 if any pixel is cleared
-            0xD => {
+            Opcode::Draw(x, y, n) => {
                 if !self.vblank_interrupt {
                     self.PC -= 2;
-                    return Ok(());
+                    return Ok(op);
                 }
                 self.vblank_interrupt = false;
-
-                const LIMIT: usize = 64 - 8; // 64 bits minus 1 byte from the sprite
-
-                let x = nibble_l(a) as usize;
-                let y = nibble_h(b) as usize;
-                let n = nibble_l(b) as usize;
-
-                let x = (self.V[x] % 0x40) as usize;
-                let y = (self.V[y] % 0x20) as usize;
-
-                for offset in 0..n {
-                    let row = y + offset;
-                    if row >= self.screen.len() {
-                        break;
-                    }
-
-                    let location = (self.I as usize) + offset;
-                    let to_draw = self.memory[location] as u64;
-
-                    let to_draw = match x.cmp(&LIMIT) {
-                        Ordering::Greater => to_draw >> (x - LIMIT),
-                        Ordering::Less => to_draw << (LIMIT - x),
-                        Ordering::Equal => to_draw,
-                    };
-
-                    let result = self.screen[row] ^ to_draw;
-                    if self.screen[row] != (self.screen[row] & result) {
-                        self.V[0xF] = 0x01;
-                    }
-                    self.screen[row] = result
-                }
+                self.draw(x as usize, y as usize, n as usize)?;
             }
             // EX9E - Skip next if the key on VX value is pressed
-            0xE if b == 0x9E => {
-                let x = nibble_l(a) as usize;
-                let key = (self.V[x] & 0xF) as usize;
+            Opcode::SkipKey(x) => {
+                let key = (self.V[x as usize] & 0xF) as usize;
                 if self.keys[key] {
                     self.PC += 2;
                 }
             }
             // EXA1 - Skip next if the key on VX value is NOT pressed
-            0xE if b == 0xA1 => {
-                let x = nibble_l(a) as usize;
-                let key = (self.V[x] & 0xF) as usize;
+            Opcode::SkipNotKey(x) => {
+                let key = (self.V[x as usize] & 0xF) as usize;
                 if !self.keys[key] {
                     self.PC += 2;
                 }
             }
+            // 02A0 - Cycle the background color (CHIP-8X)
+            Opcode::CycleBackground => {
+                self.color_board.cycle_background();
+                self.redraw = true;
+            }
+            // 5XY1 - Add VY to VX, one nibble at a time (CHIP-8X)
+            Opcode::AddNibbles(x, y) => {
+                self.V[x as usize] = chip8x::add_nibbles(self.V[x as usize], self.V[y as usize]);
+            }
+            // BXY0 - Color the zones on VX and VX+1 with VY (CHIP-8X)
+            Opcode::ColorZones(x, y) => {
+                let (columns, rows) = (self.V[x as usize], self.V[(x as usize + 1) & 0xF]);
+                self.color_board
+                    .color_zones(columns, rows, self.V[y as usize]);
+                self.redraw = true;
+            }
+            // BXYN - Color N rows on VX, VX+1 with VY (CHIP-8X)
+            Opcode::ColorRows(x, y, n) => {
+                let (column, row) = (self.V[x as usize], self.V[(x as usize + 1) & 0xF]);
+                self.color_board
+                    .color_rows(column, row, n, self.V[y as usize]);
+                self.redraw = true;
+            }
+            // EXF2 - Skip next if the key on VX is pressed on the second keypad (CHIP-8X)
+            Opcode::SkipKey2(x) => {
+                if self.keys2[(self.V[x as usize] & 0xF) as usize] {
+                    self.PC += 2;
+                }
+            }
+            // EXF5 - Skip next if the key on VX is NOT pressed on the second keypad (CHIP-8X)
+            Opcode::SkipNotKey2(x) => {
+                if !self.keys2[(self.V[x as usize] & 0xF) as usize] {
+                    self.PC += 2;
+                }
+            }
+            // FXF8 - Send VX to the tone generator (CHIP-8X)
+            Opcode::SetTone(x) => {
+                self.tone = self.V[x as usize];
+            }
+            // 00CN - Scroll down N pixels (SCHIP)
+            Opcode::ScrollDown(n) => {
+                self.screen.scroll_down(self.scroll_distance(n as usize));
+            }
+            // 00DN - Scroll up N pixels (XO-CHIP)
+            Opcode::ScrollUp(n) => {
+                self.screen.scroll_up(self.scroll_distance(n as usize));
+            }
+            // 00FB - Scroll right 4 pixels (SCHIP)
+            Opcode::ScrollRight => {
+                self.screen.scroll_right(self.scroll_distance(4));
+            }
+            // 00FC - Scroll left 4 pixels (SCHIP)
+            Opcode::ScrollLeft => {
+                self.screen.scroll_left(self.scroll_distance(4));
+            }
+            // 00FE - Low resolution (SCHIP)
+            Opcode::Lores => {
+                self.screen.set_hires(false);
+            }
+            // 00FF - High resolution (SCHIP)
+            Opcode::Hires => {
+                self.screen.set_hires(true);
+            }
+            // FX30 - Set I to the big sprite of the digit on VX (SCHIP)
+            Opcode::LoadBigFont(x) => {
+                let digit = (self.V[x as usize] & 0xF) as usize;
+                self.I = (BIG_SPRITE_DATA_START + digit * 10) as u16;
+            }
+            // FN01 - Select the planes to draw on (XO-CHIP)
+            Opcode::SelectPlanes(n) => {
+                self.planes = n;
+            }
+            // FX75 - Store from V0 to VX on the RPL flags (SCHIP)
+            Opcode::SaveFlags(x) => {
+                let end = x as usize + 1;
+                self.flags[..end].copy_from_slice(&self.V[..end]);
+            }
+            // FX85 - Load from the RPL flags into V0 -> VX (SCHIP)
+            Opcode::LoadFlags(x) => {
+                let end = x as usize + 1;
+                self.V[..end].copy_from_slice(&self.flags[..end]);
+            }
             // FX07 - Store the DT value into VX
-            0xF if b == 0x07 => {
-                let x = nibble_l(a) as usize;
-                self.V[x] = self.DT;
+            Opcode::GetDelay(x) => {
+                self.V[x as usize] = self.DT;
             }
             // FX0A - Wait for a key press and store the digit on VX
-            0xF if b == 0x0A => {
-                let x = nibble_l(a) as usize;
-                if let Some(key) = self.last_pressed_key {
-                    self.V[x] = key
-                } else {
-                    self.PC -= 2
+            Opcode::WaitKey(x) => {
+                // a key going down, or coming back up, since it started
+                // waiting; with several keys, the last one to change wins
+                let changes = match self.quirks.wait_key_press {
+                    true => &self.key_down_at,
+                    false => &self.key_up_at,
+                };
+                let key = (0..16)
+                    .filter(|&key| changes[key] > self.key_mark)
+                    .max_by_key(|&key| changes[key]);
+
+                match key {
+                    Some(key) => {
+                        self.V[x as usize] = key as u8;
+                        self.waiting_key = false;
+                    }
+                    None => {
+                        self.PC -= 2;
+                        self.waiting_key = true;
+                    }
                 }
             }
             // FX15 - Store the VX value into DT
-            0xF if b == 0x15 => {
-                let x = nibble_l(a) as usize;
-                self.DT = self.V[x];
+            Opcode::SetDelay(x) => {
+                self.DT = self.V[x as usize];
             }
             // FX18 - Store the VX value into ST
-            0xF if b == 0x18 => {
-                let x = nibble_l(a) as usize;
-                self.ST = self.V[x];
+            Opcode::SetSound(x) => {
+                self.ST = self.V[x as usize];
+                self.check_sound();
             }
             // FX1E - Set I = I + VX
-            0xF if b == 0x1E => {
-                let x = nibble_l(a) as usize;
-                self.I = self.I.wrapping_add(self.V[x] as u16);
+            Opcode::AddI(x) => {
+                self.set_index(self.I as usize + self.V[x as usize] as usize);
             }
             // FX29 - Set the address of the sprite of digit on VX to I
-            0xF if b == 0x29 => {
-                let x = nibble_l(a) as usize;
-                let digit = self.V[x] & 0xF;
+            Opcode::LoadFont(x) => {
+                let digit = self.V[x as usize] & 0xF;
                 self.I = (digit * 5) as u16;
             }
             // FX33 - Store BCD of VX into I, I+I and I+2
-            0xF if b == 0x33 => {
-                let x = nibble_l(a) as usize;
-                let i = self.I as usize;
-                self.memory[i] = self.V[x] / 100;
-                self.memory[i + 1] = self.V[x] / 10 % 10;
-                self.memory[i + 2] = self.V[x] % 100 % 10;
+            Opcode::Bcd(x) => {
+                let value = self.V[x as usize];
+                let digits = [value / 100, value / 10 % 10, value % 10];
+                for (offset, digit) in digits.into_iter().enumerate() {
+                    let addr = self.index_addr(offset)?;
+                    self.memory.write(addr, digit)?;
+                }
             }
             // FX55 - Store from V0 to VX, starting on I
             // at the end, I will point to the next byte
-            0xF if b == 0x55 => {
-                let start_addr = self.I as usize;
-                let end = (nibble_l(a) + 1) as usize;
-                let slice = &mut self.memory[start_addr..start_addr + end];
-                slice.copy_from_slice(&self.V[0..end]);
-                self.I += end as u16;
+            Opcode::Save(x) => {
+                let end = x as usize + 1;
+                for reg in 0..end {
+                    let addr = self.index_addr(reg)?;
+                    self.memory.write(addr, self.V[reg])?;
+                }
+                self.set_index(self.I as usize + end);
             }
             // FX65 - Load from I into V0 -> VX
             // at the end, I will point to the next byte
-            0xF if b == 0x65 => {
-                let start_addr = self.I as usize;
-                let end = (nibble_l(a) + 1) as usize;
-                let slice = &mut self.V[0..end];
-                slice.copy_from_slice(&self.memory[start_addr..start_addr + end]);
-                self.I += end as u16;
+            Opcode::Load(x) => {
+                let end = x as usize + 1;
+                for reg in 0..end {
+                    self.V[reg] = self.memory.read(self.index_addr(reg)?)?;
+                }
+                self.set_index(self.I as usize + end);
+            }
+        }
+
+        match op {
+            Opcode::WaitKey(_) if self.waiting_key => {}
+            _ => {
+                self.waiting_key = false;
+                self.key_mark = self.key_clock;
+            }
+        }
+        Ok(op)
+    }
+
+    /// Draw the N rows sprite at I on (VX, VY), on each selected plane; the
+    /// sprite of each plane follows the one of the previous plane. VF is
+    /// set to 1 if any pixel is cleared
+    fn draw(&mut self, x: usize, y: usize, n: usize) -> Result<(), EmulatorError> {
+        let x = self.V[x] as usize;
+        let y = self.V[y] as usize;
+
+        let (mut collided, mut clipped) = (false, false);
+        let mut offset = 0;
+        for plane in 0..self.screen.planes() {
+            if self.planes & (1 << plane) == 0 {
+                continue;
+            }
+
+            let mut sprite = [0u8; 15];
+            for byte in sprite[..n].iter_mut() {
+                *byte = self.memory.read(self.index_addr(offset)?)?;
+                offset += 1;
             }
-            _ => return Err(EmulatorError::InvalidOpcode(a, b, (self.PC - 2) as u16)),
+
+            let drawn = self
+                .screen
+                .draw(plane, x, y, &sprite[..n], self.quirks.wrap_sprites);
+            collided |= drawn.collided;
+            clipped |= drawn.clipped;
         }
 
-        self.last_pressed_key = None;
+        self.V[0xF] = collided as u8;
+        if clipped {
+            self.clipped_sprites += 1;
+        }
         Ok(())
     }
+
+    /// The address `offset` bytes past I, wrapped (or checked) as the
+    /// index quirk says; only valid while executing an instruction
+    fn index_addr(&self, offset: usize) -> Result<usize, EmulatorError> {
+        let addr = self.I as usize + offset;
+        match self.quirks.index {
+            IndexMode::Checked if addr >= MEM_SIZE => {
+                Err(EmulatorError::InvalidIndex(self.I, (self.PC - 2) as u16))
+            }
+            _ => Ok(addr % MEM_SIZE),
+        }
+    }
+
+    /// How many pixels a scroll of `n` moves, on the current resolution
+    fn scroll_distance(&self, n: usize) -> usize {
+        if self.quirks.half_scroll && !self.screen.is_hires() {
+            n / 2
+        } else {
+            n
+        }
+    }
+
+    /// Point I to `value`, wrapped as the index quirk says
+    fn set_index(&mut self, value: usize) {
+        self.I = match self.quirks.index {
+            IndexMode::Wrap12 => (value & 0xFFF) as u16,
+            IndexMode::Wrap16 | IndexMode::Checked => value as u16,
+        };
+    }
 }
 
+/// Builds an [`Emulator`] with everything set before the rom is loaded, so
+/// the quirks that only apply on reset (like the variant and the font)
+/// take effect from the start.
+///
+/// ```
+/// use rc8::{emulator::Emulator, opcode::Variant};
+///
+/// let rom = [0xC0, 0xFF]; // 0x200: V0 = <random>
+/// let run = || {
+///     let mut emu = Emulator::builder()
+///         .variant(Variant::SChip)
+///         .seed(42)
+///         .load_rom(&rom[..])
+///         .unwrap();
+///     emu.execute().unwrap();
+///     emu.V[0]
+/// };
+///
+/// // the same seed gives the same numbers
+/// assert_eq!(run(), run());
+/// ```
+#[derive(Default)]
+pub struct EmulatorBuilder {
+    quirks: Quirks,
+    seed: Option<u64>,
+    rng: Option<Box<dyn Chip8Rng>>,
+    start: Option<usize>,
+    truncate: bool,
+}
+
+impl EmulatorBuilder {
+    /// All the quirks, replacing any variant set before
+    pub fn quirks(mut self, quirks: Quirks) -> Self {
+        self.quirks = quirks;
+        self
+    }
+
+    /// The variant, keeping the other quirks
+    pub fn variant(mut self, variant: Variant) -> Self {
+        self.quirks.variant = variant;
+        self
+    }
+
+    /// Seed the random number generator of the rng quirk
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    /// Use a custom random number generator instead, until the rng quirk
+    /// changes
+    pub fn rng(mut self, rng: Box<dyn Chip8Rng>) -> Self {
+        self.rng = Some(rng);
+        self
+    }
+
+    /// Start running from `addr`, instead of the start of the rom; the rom
+    /// is still loaded on its usual place, and a reset goes back there
+    pub fn start(mut self, addr: usize) -> Self {
+        self.start = Some(addr);
+        self
+    }
+
+    /// Load roms too big for memory anyway, leaving their end out, instead
+    /// of failing; [`Emulator::truncated`] tells how much was left out
+    pub fn truncate(mut self, truncate: bool) -> Self {
+        self.truncate = truncate;
+        self
+    }
+
+    /// Load the rom, as [`Emulator::load_rom`] does; its size is checked
+    /// with the quirks set here
+    pub fn load_rom<T: Read>(self, rom: T) -> Result<Emulator, EmulatorError> {
+        // seeded from the start, so system entropy is not needed
+        let (mut emu, oversized) = Emulator::read_rom(rom, self.quirks.rng, self.seed)?;
+        emu.quirks = self.quirks;
+        emu.reset();
+        if !self.truncate {
+            emu.check_size(oversized)?;
+        }
+
+        if let Some(rng) = self.rng {
+            emu.set_rng(rng);
+        }
+        if let Some(addr) = self.start {
+            // the whole instruction must be in memory
+            if addr + 1 >= MEM_SIZE {
+                return Err(EmulatorError::OutOfBounds(addr + 1));
+            }
+            emu.PC = addr;
+        }
+        Ok(emu)
+    }
+}
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::rng::Sequence;
 
     fn exec_cycles(emu: &mut Emulator, mut cycles: i32) {
         while cycles > 0 {
@@ -498,15 +1581,69 @@ mod tests {
     }
 
     #[test]
-    fn test_nibble() {
-        let a = 0x12;
-        let b = 0x34;
+    fn test_screen_hash() {
+        let mut emu = Emulator::load_rom(&[][..]).unwrap();
+        let blank = emu.screen_hash();
+        assert_eq!(blank, fnv1a([0u8; 256]));
+
+        emu.screen.set_row(0, 31, 1);
+        assert_ne!(emu.screen_hash(), blank);
+    }
+
+    #[test]
+    fn test_next_opcode() {
+        let rom = [
+            0x6A, 0x02, // 0x200: VA = 2
+            0x5A, 0xB1, // 0x202: invalid
+        ];
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+
+        assert_eq!(emu.word_at(0x200), 0x6A02);
+        assert_eq!(emu.next_opcode(), Some(Opcode::LoadByte(0xA, 0x02)));
+        emu.execute().unwrap();
+        assert_eq!(emu.next_opcode(), None);
+    }
+
+    #[test]
+    fn test_decode_errors() {
+        // execute and the tools must agree on which words are instructions
+        let mut emu = Emulator::load_rom(&[][..]).unwrap();
+        for word in 0..=u16::MAX {
+            if Opcode::decode(word).is_some() {
+                continue;
+            }
 
-        assert_eq!(nibble_h(a), 0x1);
-        assert_eq!(nibble_l(a), 0x2);
-        assert_eq!(nibble_h(b), 0x3);
-        assert_eq!(nibble_l(b), 0x4);
-        assert_eq!(nnn(a, b), 0x234);
+            let [a, b] = word.to_be_bytes();
+            emu.PC = ADDR_START;
+            emu.memory[ADDR_START..ADDR_START + 2].copy_from_slice(&[a, b]);
+            assert!(matches!(
+                emu.execute(),
+                Err(EmulatorError::InvalidOpcode(x, y, 0x200)) if (x, y) == (a, b)
+            ));
+        }
+    }
+
+    #[test]
+    fn test_state_hash() {
+        let rom: [u8; 4] = [
+            0xC0, 0xFF, // 0x200: Set V0 = random
+            0x12, 0x00, // 0x202: Jump to 0x200
+        ];
+
+        let mut a = Emulator::load_rom(&rom[..]).unwrap();
+        let mut b = Emulator::load_rom(&rom[..]).unwrap();
+        assert_eq!(a.state_hash(), b.state_hash());
+
+        a.seed_rng(1234);
+        b.seed_rng(1234);
+        for _ in 0..100 {
+            a.execute().unwrap();
+            b.execute().unwrap();
+            assert_eq!(a.state_hash(), b.state_hash());
+        }
+
+        b.set_key(0x3, true);
+        assert_ne!(a.state_hash(), b.state_hash());
     }
 
     #[test]
@@ -530,7 +1667,20 @@ mod tests {
     #[test]
     fn test_load_big_rom_limit() {
         let rom = [0xEE; MAX_ROM_SIZE * 2];
-        let emu = Emulator::load_rom(&rom[..]).unwrap();
+        assert!(matches!(
+            Emulator::load_rom(&rom[..]),
+            Err(EmulatorError::RomTooBig(MAX_ROM_SIZE))
+        ));
+        assert!(matches!(
+            Emulator::load_rom(&rom[..MAX_ROM_SIZE + 1]),
+            Err(EmulatorError::RomTooBig(MAX_ROM_SIZE))
+        ));
+
+        let emu = Emulator::builder()
+            .truncate(true)
+            .load_rom(&rom[..])
+            .unwrap();
+        assert_eq!(emu.truncated(), MAX_FULL_ROM_SIZE - MAX_ROM_SIZE);
 
         assert_eq!(emu.memory[ADDR_START], 0xEE);
         assert_eq!(emu.memory[ADDR_START + 1], 0xEE);
@@ -552,16 +1702,77 @@ mod tests {
         rom[MAX_ROM_SIZE - 2] = 0xBB;
         rom[MAX_ROM_SIZE - 3] = 0xCC;
 
-        let emu = Emulator::load_rom(&rom[..]).unwrap();
-        assert_eq!(emu.memory[ADDR_START], 0xAA);
-        assert_eq!(emu.memory[ADDR_START + 1], 0xBB);
-        assert_eq!(emu.memory[ADDR_START + 2], 0xCC);
-        assert_eq!(emu.memory[ADDR_START + 3], 0xFF);
-        assert_eq!(emu.memory[ADDR_END], 0xAA);
-        assert_eq!(emu.memory[ADDR_END - 1], 0xBB);
-        assert_eq!(emu.memory[ADDR_END - 2], 0xCC);
-        assert_eq!(emu.memory[ADDR_END - 3], 0xFF);
-        assert_eq!(emu.memory[ADDR_END + 1], 0x00);
+        let emu = Emulator::load_rom(&rom[..]).unwrap();
+        assert_eq!(emu.memory[ADDR_START], 0xAA);
+        assert_eq!(emu.memory[ADDR_START + 1], 0xBB);
+        assert_eq!(emu.memory[ADDR_START + 2], 0xCC);
+        assert_eq!(emu.memory[ADDR_START + 3], 0xFF);
+        assert_eq!(emu.memory[ADDR_END], 0xAA);
+        assert_eq!(emu.memory[ADDR_END - 1], 0xBB);
+        assert_eq!(emu.memory[ADDR_END - 2], 0xCC);
+        assert_eq!(emu.memory[ADDR_END - 3], 0xFF);
+        assert_eq!(emu.memory[ADDR_END + 1], 0x00);
+    }
+
+    #[test]
+    fn test_full_memory() {
+        let mut rom = vec![0xAA; MAX_FULL_ROM_SIZE];
+        rom[MAX_ROM_SIZE - 1] = 0xBB;
+
+        let schip = Quirks {
+            variant: Variant::SChip,
+            full_memory: true,
+            ..Quirks::default()
+        };
+        assert!(Emulator::builder().quirks(schip).load_rom(&rom[..]).is_ok());
+
+        let mut emu = Emulator::builder()
+            .truncate(true)
+            .load_rom(&rom[..])
+            .unwrap();
+        assert_eq!(emu.truncated(), MAX_FULL_ROM_SIZE - MAX_ROM_SIZE);
+        assert_eq!(emu.memory[ADDR_END], 0xBB);
+        assert_eq!(emu.memory[ADDR_END + 1], 0x00);
+
+        // CHIP-8 roms never get the interpreter memory
+        emu.quirks.full_memory = true;
+        emu.reset();
+        assert_ne!(emu.truncated(), 0);
+
+        emu.quirks.variant = Variant::SChip;
+        emu.reset();
+        assert_eq!(emu.truncated(), 0);
+        assert_eq!(emu.memory[ADDR_END + 1..], [0xAA; MEM_SIZE - ADDR_END - 1]);
+    }
+
+    #[test]
+    fn test_reset() {
+        let rom: [u8; 8] = [
+            0x60, 0x0C, // 0x200: Set V0 = 12
+            0xA2, 0x00, // 0x202: Set I = 0x200
+            0xF0, 0x55, // 0x204: Save V0 on I (overwrite the rom)
+            0xD0, 0x05, // 0x206: Draw[V0, V0]
+        ];
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        emu.quirks.wrap_sprites = true;
+
+        exec_cycles(&mut emu, 4);
+        assert_eq!(emu.memory[ADDR_START], 0x0C);
+        assert!(emu.screen.rows(0).iter().any(|&row| row != 0));
+
+        emu.reset();
+        assert_eq!(emu.PC, ADDR_START);
+        assert_eq!(emu.V, [0u8; 16]);
+        assert_eq!(emu.I, 0);
+        assert_eq!(emu.memory[ADDR_START], 0x60);
+        assert_eq!(emu.memory[..SPRITE_DATA.len()], SPRITE_DATA);
+        assert!(emu.screen.rows(0).iter().all(|&row| row == 0));
+        assert!(emu.quirks.wrap_sprites);
+
+        emu.quirks = Quirks::dream6800();
+        emu.reset();
+        assert_eq!(emu.memory[..SPRITE_DATA.len()], DREAM6800_SPRITE_DATA);
     }
 
     #[test]
@@ -893,6 +2104,137 @@ mod tests {
         assert_eq!(emu.PC, 0x20A);
     }
 
+    #[test]
+    fn test_step() {
+        let rom: [u8; 8] = [
+            0x60, 0x05, // 0x200: SET V0 = 0x05
+            0xF0, 0x18, // 0x202: SET ST = V0
+            0xD0, 0x05, // 0x204: DRAW 5 rows on V0, V0
+            0x12, 0x06, // 0x206: JP 0x206
+        ];
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        let info = emu.step().unwrap();
+        assert_eq!(info.op, Opcode::LoadByte(0, 5));
+        assert_eq!((info.old_pc, info.new_pc), (0x200, 0x202));
+        assert!(!info.screen_changed && !info.sound_changed);
+
+        assert!(emu.step().unwrap().sound_changed);
+
+        // the draw waits for the vblank
+        let info = emu.step().unwrap();
+        assert_eq!((info.old_pc, info.new_pc), (0x204, 0x204));
+        assert!(!info.screen_changed);
+        emu.vblank();
+        assert!(emu.step().unwrap().screen_changed);
+
+        let info = emu.step().unwrap();
+        assert_eq!((info.op, info.new_pc), (Opcode::Jump(0x206), 0x206));
+    }
+
+    #[test]
+    fn test_run_frame() {
+        let rom: [u8; 10] = [
+            0x60, 0x05, // 0x200: SET V0 = 0x05
+            0xF0, 0x18, // 0x202: SET ST = V0
+            0xD0, 0x05, // 0x204: DRAW 5 rows on V0, V0
+            0xD0, 0x05, // 0x206: DRAW 5 rows on V0, V0
+            0x12, 0x08, // 0x208: JP 0x208
+        ];
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        let frame = emu.run_frame(9).unwrap();
+        assert_eq!(frame.instructions, 3);
+        assert!(frame.screen_changed && frame.buzzer);
+        assert_eq!((emu.PC, emu.ST), (0x206, 4));
+
+        // the second draw undoes the first
+        let frame = emu.run_frame(9).unwrap();
+        assert_eq!(frame.instructions, 9);
+        assert!(frame.screen_changed);
+        assert!(!emu.get_pixel(5, 5));
+
+        for _ in 0..3 {
+            emu.run_frame(9).unwrap();
+        }
+        assert!(!emu.run_frame(9).unwrap().buzzer);
+    }
+
+    #[test]
+    fn test_dirty_rows() {
+        let rom: [u8; 12] = [
+            0x60, 0x05, // 0x200: SET V0 = 0x05
+            0xD0, 0x03, // 0x202: DRAW 3 rows on V0, V0
+            0xD0, 0x03, // 0x204: DRAW 3 rows on V0, V0
+            0x00, 0xFF, // 0x206: HIRES
+            0x00, 0xE0, // 0x208: CLEAR
+            0x12, 0x0A, // 0x20A: JP 0x20A
+        ];
+
+        let mut emu = Emulator::builder()
+            .variant(Variant::SChip)
+            .load_rom(&rom[..])
+            .unwrap();
+        assert_eq!(emu.dirty_rows().len(), DISPLAY_HEIGHT);
+        assert!(emu.dirty_rows().is_empty());
+
+        exec_cycles(&mut emu, 2);
+        assert_eq!(emu.dirty_rows(), vec![5, 6, 7]);
+
+        // drawing the same sprite again changes the rows back
+        exec_cycles(&mut emu, 1);
+        assert_eq!(emu.dirty_rows(), vec![5, 6, 7]);
+
+        exec_cycles(&mut emu, 1);
+        assert_eq!(emu.dirty_rows().len(), display::HIRES_HEIGHT);
+
+        // clearing a blank screen changes nothing
+        exec_cycles(&mut emu, 1);
+        assert!(emu.dirty_rows().is_empty());
+    }
+
+    #[test]
+    fn test_render_rgba() {
+        let rom: [u8; 4] = [
+            0xA2, 0x02, // 0x200: SET I = 0x202 (the sprite is 0xD0)
+            0xD0, 0x01, // 0x202: DRAW 1 row on V0, V0
+        ];
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        exec_cycles(&mut emu, 2);
+        assert_eq!(emu.framebuffer_bits()[0], 0xD0 << 56);
+
+        let mut rgba = vec![0; DISPLAY_WIDTH * DISPLAY_HEIGHT * 4];
+        let size = emu.render_rgba(&mut rgba, 0x00DEAD, 0x102030);
+        assert_eq!(size, (DISPLAY_WIDTH, DISPLAY_HEIGHT));
+        assert_eq!(rgba[..8], [0x00, 0xDE, 0xAD, 0xFF, 0x00, 0xDE, 0xAD, 0xFF]);
+        assert_eq!(rgba[8..12], [0x10, 0x20, 0x30, 0xFF]);
+        assert_eq!(rgba[DISPLAY_WIDTH * 4..][..4], [0x10, 0x20, 0x30, 0xFF]);
+
+        // a small buffer only gets the first pixels
+        let mut rgba = [0; 8];
+        emu.render_rgba(&mut rgba, 0xFFFFFF, 0);
+        assert_eq!(rgba, [0xFF; 8]);
+    }
+
+    #[test]
+    fn test_fetch_out_of_bounds() {
+        let rom: [u8; 2] = [
+            0x1F, 0xFF, // 0x200: jump to the last byte of memory
+        ];
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        emu.execute().unwrap();
+        assert_eq!(emu.next_opcode(), None);
+
+        // the second byte of the instruction is past the end
+        assert!(matches!(
+            emu.execute(),
+            Err(EmulatorError::OutOfBounds(0x1000))
+        ));
+        assert_eq!(emu.PC, 0xFFF);
+    }
+
     #[test]
     fn test_store_register_into_dt() {
         let rom: [u8; 4] = [
@@ -1199,6 +2541,193 @@ mod tests {
         assert_eq!(emu.PC, 0x204);
     }
 
+    #[test]
+    fn test_wait_for_key_press_quirk() {
+        let rom: [u8; 4] = [
+            0xF0, 0x0A, // 0x200: Set V0 = <pressed key> (wait)
+            0xF1, 0x0A, // 0x202: Set V1 = <pressed key> (wait)
+        ];
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        emu.quirks.wait_key_press = true;
+
+        // the press is enough
+        emu.set_key(0xA, true);
+        exec_cycles(&mut emu, 2);
+        assert_eq!(emu.V[0x0], 0xA);
+        assert_eq!(emu.PC, 0x202);
+
+        // a key still held (or repeating) does not count again
+        emu.set_key(0xA, true);
+        exec_cycles(&mut emu, 2);
+        assert_eq!(emu.PC, 0x202);
+
+        // releasing it neither; pressing another one does
+        emu.set_key(0xA, false);
+        emu.set_key(0x3, true);
+        exec_cycles(&mut emu, 1);
+        assert_eq!(emu.V[0x1], 0x3);
+        assert_eq!(emu.PC, 0x204);
+    }
+
+    #[test]
+    fn test_wait_for_key_most_recent() {
+        let rom: [u8; 2] = [
+            0xF0, 0x0A, // 0x200: Set V0 = <pressed key> (wait)
+        ];
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        emu.set_key(0x5, true);
+        emu.set_key(0xA, true);
+        exec_cycles(&mut emu, 2);
+        assert!(emu.waiting_for_key());
+        assert_eq!(emu.pressed_keys(), 0b0000_0100_0010_0000);
+
+        // both released between two instructions: the last one counts,
+        // even if the other went down again meanwhile
+        emu.set_key(0xA, false);
+        emu.set_key(0x5, false);
+        emu.set_key(0xA, true);
+        exec_cycles(&mut emu, 1);
+        assert!(!emu.waiting_for_key());
+        assert_eq!(emu.V[0x0], 0x5);
+        assert_eq!(emu.pressed_keys(), 0b0000_0100_0000_0000);
+    }
+
+    #[test]
+    fn test_index_modes() {
+        let rom: [u8; 8] = [
+            0xAF, 0xFE, // 0x200: Set I = 0xFFE
+            0x60, 0x03, // 0x202: Set V0 = 3
+            0xF2, 0x55, // 0x204: Save V0 to V2 on I
+            0xF0, 0x1E, // 0x206: Set I = I + V0
+        ];
+
+        // the third byte goes to the start of memory
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        exec_cycles(&mut emu, 4);
+        assert_eq!(emu.memory[0xFFE..], [3, 0]);
+        assert_eq!(emu.memory[0x000], 0x00);
+        assert_eq!(emu.I, 0x1004);
+
+        emu.reset();
+        emu.quirks.index = IndexMode::Wrap12;
+        exec_cycles(&mut emu, 4);
+        assert_eq!(emu.I, 0x004);
+
+        emu.reset();
+        emu.quirks.index = IndexMode::Checked;
+        exec_cycles(&mut emu, 2);
+        assert!(matches!(
+            emu.execute(),
+            Err(EmulatorError::InvalidIndex(0xFFE, 0x204))
+        ));
+    }
+
+    #[test]
+    fn test_scroll() {
+        let rom: [u8; 8] = [
+            0xD0, 0x01, // 0x200: Draw the first row of "0" on (0, 0)
+            0x00, 0xC2, // 0x202: Scroll down 2
+            0x00, 0xFB, // 0x204: Scroll right 4
+            0x00, 0xFF, // 0x206: High resolution
+        ];
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        emu.quirks.variant = Variant::SChip;
+        exec_cycles(&mut emu, 3);
+        assert_eq!(emu.screen.rows(0)[2], 0x0F00_0000_0000_0000);
+
+        // SCHIP 1.1 moves half as far in low resolution
+        emu.reset();
+        emu.quirks.half_scroll = true;
+        exec_cycles(&mut emu, 3);
+        assert_eq!(emu.screen.rows(0)[1], 0x3C00_0000_0000_0000);
+
+        exec_cycles(&mut emu, 1);
+        assert!(emu.display().is_hires());
+        assert!(emu.screen.rows(0).iter().all(|&row| row == 0));
+    }
+
+    #[test]
+    fn test_big_font() {
+        let rom: [u8; 6] = [
+            0x60, 0x08, // 0x200: Set V0 = 8
+            0xF0, 0x30, // 0x202: Set I = big sprite of V0
+            0xD1, 0x1A, // 0x204: Draw 10 rows on (0, 0)
+        ];
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        emu.quirks.variant = Variant::SChip;
+        emu.reset();
+        exec_cycles(&mut emu, 3);
+        assert_eq!(emu.I, 0x50 + 80);
+        assert_eq!(emu.screen.rows(0)[2], 0xC300_0000_0000_0000);
+        assert_eq!(emu.screen.rows(0)[9], 0xFF00_0000_0000_0000);
+
+        // plain CHIP-8 has no big digits
+        emu.quirks.variant = Variant::Chip8;
+        emu.reset();
+        assert!(emu.execute().is_ok());
+        assert!(matches!(
+            emu.execute(),
+            Err(EmulatorError::InvalidOpcode(0xF0, 0x30, 0x202))
+        ));
+    }
+
+    #[test]
+    fn test_rpl_flags() {
+        let rom: [u8; 10] = [
+            0x60, 0x12, // 0x200: Set V0 = 0x12
+            0x61, 0x34, // 0x202: Set V1 = 0x34
+            0xF1, 0x75, // 0x204: Save V0-V1 on the flags
+            0x00, 0xE0, // 0x206: Clear the screen
+            0xF1, 0x85, // 0x208: Load V0-V1 from the flags
+        ];
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        emu.quirks.variant = Variant::SChip;
+        emu.reset();
+        exec_cycles(&mut emu, 3);
+        assert_eq!(emu.flags[..3], [0x12, 0x34, 0]);
+
+        // the flags survive a reset
+        emu.reset();
+        emu.PC = 0x208;
+        exec_cycles(&mut emu, 1);
+        assert_eq!(emu.V[..3], [0x12, 0x34, 0]);
+    }
+
+    #[test]
+    fn test_chip8x() {
+        let rom: [u8; 12] = [
+            0x02, 0xA0, // 0x300: Cycle the background
+            0x60, 0x00, // 0x302: Set V0 = 0
+            0x61, 0x10, // 0x304: Set V1 = 0x10
+            0x62, 0x04, // 0x306: Set V2 = 4 (green)
+            0xB0, 0x20, // 0x308: Color zones 0, rows 0-1 with V2
+            0xE0, 0xF2, // 0x30A: Skip if V0 is pressed on keypad 2
+        ];
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        assert!(emu.color_board().is_none());
+        emu.quirks.variant = Variant::Chip8X;
+        emu.reset();
+        assert_eq!(emu.PC, 0x300);
+        assert_eq!(emu.memory[0x200], 0);
+
+        exec_cycles(&mut emu, 5);
+        let board = emu.color_board().unwrap();
+        assert_eq!(board.background_rgb(), chip8x::BACKGROUNDS[1]);
+        assert_eq!(board.pixel_rgb(7, 7), chip8x::COLORS[4]);
+        assert_eq!(board.pixel_rgb(8, 7), chip8x::COLORS[1]);
+        assert!(emu.screen_changed());
+
+        emu.set_key2(0, true);
+        exec_cycles(&mut emu, 1);
+        assert_eq!(emu.PC, 0x30E);
+    }
+
     #[test]
     fn test_bulk_save() {
         let rom: [u8; 16] = [
@@ -1272,14 +2801,47 @@ mod tests {
             0xC2, 0x3C, // 0x204: Set V2 = <random> & 0x3C = 59 & 3C = 18
         ];
 
-        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
-        emu.rng = BufferedRng::new(WyRand::new_seed(0));
+        let mut emu = Emulator::builder().seed(0).load_rom(&rom[..]).unwrap();
 
         exec_cycles(&mut emu, 3);
         assert_eq!(emu.V[0x0], 0x0E);
         assert_eq!(emu.V[0x1], 0xA0);
         assert_eq!(emu.V[0x2], 0x18);
         assert_eq!(emu.PC, 0x206);
+
+        // the generator can be replaced
+        let mut emu = Emulator::builder()
+            .rng(Box::new(Sequence::new(vec![0xFF, 0x00])))
+            .load_rom(&rom[..])
+            .unwrap();
+
+        exec_cycles(&mut emu, 3);
+        assert_eq!(emu.V[0x0], 0x0F);
+        assert_eq!(emu.V[0x1], 0x00);
+        assert_eq!(emu.V[0x2], 0x3C);
+    }
+
+    #[test]
+    fn test_builder() {
+        let rom = [0x00, 0xE0, 0x00, 0xE0];
+
+        // the variant moves the rom before it is loaded
+        let emu = Emulator::builder()
+            .variant(Variant::Chip8X)
+            .load_rom(&rom[..])
+            .unwrap();
+        assert_eq!(emu.PC, chip8x::ADDR_START);
+        assert_eq!(emu.word_at(chip8x::ADDR_START), 0x00E0);
+
+        let mut emu = Emulator::builder().start(0x202).load_rom(&rom[..]).unwrap();
+        assert_eq!(emu.PC, 0x202);
+        emu.reset();
+        assert_eq!(emu.PC, ADDR_START);
+
+        assert!(matches!(
+            Emulator::builder().start(0xFFF).load_rom(&rom[..]),
+            Err(EmulatorError::OutOfBounds(0x1000))
+        ));
     }
 
     #[test]
@@ -1319,38 +2881,147 @@ mod tests {
         assert_eq!(emu.V[0xF], 0x00);
         assert_eq!(emu.PC, 0x228);
 
-        assert_eq!(emu.screen[0], 0xF00000000000000);
-        assert_eq!(emu.screen[1], 0x970000000000000);
-        assert_eq!(emu.screen[2], 0xF48000000000000);
-        assert_eq!(emu.screen[3], 0x970000000000000);
-        assert_eq!(emu.screen[4], 0x948000000000000);
-        assert_eq!(emu.screen[5], 0x070000000000000);
-        assert_eq!(emu.screen[6], 0x000000000000000);
-        assert_eq!(emu.screen[7], 0x000000000000000);
-        assert_eq!(emu.screen[8], 0x000000000000000);
-        assert_eq!(emu.screen[9], 0x000000000000000);
-        assert_eq!(emu.screen[10], 0x00000000000000F);
-        assert_eq!(emu.screen[11], 0x000000000000009);
-        assert_eq!(emu.screen[12], 0x00000000000000F);
-        assert_eq!(emu.screen[13], 0x000000000000001);
-        assert_eq!(emu.screen[14], 0x00000000000000F);
-        assert_eq!(emu.screen[15], 0x000000000000000);
-        assert_eq!(emu.screen[16], 0x000000000000000);
-        assert_eq!(emu.screen[17], 0x000000000000000);
-        assert_eq!(emu.screen[18], 0x000000000000000);
-        assert_eq!(emu.screen[19], 0x000000000000000);
-        assert_eq!(emu.screen[20], 0x000000000000000);
-        assert_eq!(emu.screen[21], 0x000000000000000);
-        assert_eq!(emu.screen[22], 0x000000000000000);
-        assert_eq!(emu.screen[23], 0x000000000000000);
-        assert_eq!(emu.screen[24], 0x000000000000000);
-        assert_eq!(emu.screen[25], 0x000000000000000);
-        assert_eq!(emu.screen[26], 0x000000000000000);
-        assert_eq!(emu.screen[27], 0x000000000000000);
-        assert_eq!(emu.screen[28], 0x000000000000000);
-        assert_eq!(emu.screen[29], 0x000000000000003);
-        assert_eq!(emu.screen[30], 0x000000000000002);
-        assert_eq!(emu.screen[31], 0x000000000000003);
+        assert_eq!(emu.screen.rows(0)[0], 0xF00000000000000);
+        assert_eq!(emu.screen.rows(0)[1], 0x970000000000000);
+        assert_eq!(emu.screen.rows(0)[2], 0xF48000000000000);
+        assert_eq!(emu.screen.rows(0)[3], 0x970000000000000);
+        assert_eq!(emu.screen.rows(0)[4], 0x948000000000000);
+        assert_eq!(emu.screen.rows(0)[5], 0x070000000000000);
+        assert_eq!(emu.screen.rows(0)[6], 0x000000000000000);
+        assert_eq!(emu.screen.rows(0)[7], 0x000000000000000);
+        assert_eq!(emu.screen.rows(0)[8], 0x000000000000000);
+        assert_eq!(emu.screen.rows(0)[9], 0x000000000000000);
+        assert_eq!(emu.screen.rows(0)[10], 0x00000000000000F);
+        assert_eq!(emu.screen.rows(0)[11], 0x000000000000009);
+        assert_eq!(emu.screen.rows(0)[12], 0x00000000000000F);
+        assert_eq!(emu.screen.rows(0)[13], 0x000000000000001);
+        assert_eq!(emu.screen.rows(0)[14], 0x00000000000000F);
+        assert_eq!(emu.screen.rows(0)[15], 0x000000000000000);
+        assert_eq!(emu.screen.rows(0)[16], 0x000000000000000);
+        assert_eq!(emu.screen.rows(0)[17], 0x000000000000000);
+        assert_eq!(emu.screen.rows(0)[18], 0x000000000000000);
+        assert_eq!(emu.screen.rows(0)[19], 0x000000000000000);
+        assert_eq!(emu.screen.rows(0)[20], 0x000000000000000);
+        assert_eq!(emu.screen.rows(0)[21], 0x000000000000000);
+        assert_eq!(emu.screen.rows(0)[22], 0x000000000000000);
+        assert_eq!(emu.screen.rows(0)[23], 0x000000000000000);
+        assert_eq!(emu.screen.rows(0)[24], 0x000000000000000);
+        assert_eq!(emu.screen.rows(0)[25], 0x000000000000000);
+        assert_eq!(emu.screen.rows(0)[26], 0x000000000000000);
+        assert_eq!(emu.screen.rows(0)[27], 0x000000000000000);
+        assert_eq!(emu.screen.rows(0)[28], 0x000000000000000);
+        assert_eq!(emu.screen.rows(0)[29], 0x000000000000003);
+        assert_eq!(emu.screen.rows(0)[30], 0x000000000000002);
+        assert_eq!(emu.screen.rows(0)[31], 0x000000000000003);
+    }
+
+    #[test]
+    fn test_draw_clears_flag() {
+        let rom: [u8; 10] = [
+            0x62, 0x08, // 0x200: Set V2 = 0x8
+            0xF2, 0x29, // 0x202: Set I to V2 ("8")
+            0xD0, 0x05, // 0x204: Draw[V0, V0] = "8"
+            0xD0, 0x05, // 0x206: Draw[V0, V0] = "8" (collision)
+            0xD0, 0x05, // 0x208: Draw[V0, V0] = "8" (no collision)
+        ];
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+
+        exec_cycles(&mut emu, 4);
+        assert_eq!(emu.V[0xF], 0x01);
+
+        exec_cycles(&mut emu, 1);
+        assert_eq!(emu.V[0xF], 0x00);
+    }
+
+    #[test]
+    fn test_draw_wrap() {
+        let rom: [u8; 10] = [
+            0x60, 0x3E, // 0x200: Set V0 = 62
+            0x61, 0x1D, // 0x202: Set V1 = 29
+            0x62, 0x0E, // 0x204: Set V2 = 0xE
+            0xF2, 0x29, // 0x206: Set I to V2 ("E")
+            0xD0, 0x15, // 0x208: Draw[VX, VY] = "E"
+        ];
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        emu.quirks.wrap_sprites = true;
+
+        exec_cycles(&mut emu, 5);
+        assert_eq!(emu.V[0xF], 0x00);
+        assert_eq!(emu.PC, 0x20A);
+
+        for (row, value) in emu.screen.rows(0).iter().enumerate() {
+            match row {
+                29 | 31 | 1 => assert_eq!(*value, 0xC000000000000003),
+                30 | 0 => assert_eq!(*value, 0x2),
+                _ => assert_eq!(*value, 0x0),
+            }
+        }
+
+        // wrapped sprites are not counted as clipped
+        assert_eq!(emu.clipped_sprites(), 0);
+    }
+
+    #[test]
+    fn test_draw_clipped() {
+        let rom: [u8; 12] = [
+            0x60, 0x3E, // 0x200: Set V0 = 62
+            0x61, 0x1D, // 0x202: Set V1 = 29
+            0xD0, 0x15, // 0x204: Draw[VX, VY], past the corner
+            0xD1, 0x05, // 0x206: Draw[VY, VX], past the bottom
+            0xD1, 0x11, // 0x208: Draw[VY, VY], inside the screen
+            0x00, 0xE0, // 0x20A: clear
+        ];
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        exec_cycles(&mut emu, 5);
+        assert_eq!(emu.clipped_sprites(), 2);
+
+        emu.reset();
+        assert_eq!(emu.clipped_sprites(), 0);
+    }
+
+    #[test]
+    fn test_draw_planes() {
+        let load = |planes: u8| {
+            let rom: [u8; 12] = [
+                0xF0 | planes,
+                0x01, // 0x200: Select the planes
+                0xA2,
+                0x08, // 0x202: Set I = 0x208
+                0xD0,
+                0x02, // 0x204: Draw[V0, V0], 2 rows
+                0x12,
+                0x06, // 0x206: Jump to 0x206
+                0xF0,
+                0x0F, // 0x208: plane 1 sprite
+                0xAA,
+                0x55, // 0x20A: plane 2 sprite
+            ];
+            let mut emu = Emulator::builder()
+                .variant(Variant::XoChip)
+                .load_rom(&rom[..])
+                .unwrap();
+            exec_cycles(&mut emu, 3);
+            emu
+        };
+
+        // both planes, each with its own sprite
+        let emu = load(0b11);
+        assert_eq!(emu.display().planes(), 2);
+        assert_eq!(emu.screen.rows(0)[..2], [0xF0 << 56, 0x0F << 56]);
+        assert_eq!(emu.screen.rows(1)[..2], [0xAA << 56, 0x55 << 56]);
+
+        // only the second plane, with the first sprite
+        let emu = load(0b10);
+        assert_eq!(emu.screen.rows(0)[..2], [0, 0]);
+        assert_eq!(emu.screen.rows(1)[..2], [0xF0 << 56, 0x0F << 56]);
+
+        // no planes, nothing drawn
+        let emu = load(0);
+        assert!(emu.screen.bytes().all(|byte| byte == 0));
+        assert_eq!(emu.V[0xF], 0);
     }
 
     #[test]
@@ -1376,7 +3047,7 @@ mod tests {
         assert_eq!(emu.V[0xF], 0x01);
         assert_eq!(emu.PC, 0x210);
 
-        for (row, value) in emu.screen.iter().enumerate() {
+        for (row, value) in emu.screen.rows(0).iter().enumerate() {
             if row == 3 {
                 assert_eq!(*value, 0x8000000000000)
             } else {
@@ -1405,7 +3076,7 @@ mod tests {
         assert_eq!(emu.V[0xF], 0x00);
         assert_eq!(emu.PC, 0x20C);
 
-        for value in emu.screen.iter() {
+        for value in emu.screen.rows(0).iter() {
             assert_eq!(*value, 0x0)
         }
     }
@@ -1434,6 +3105,56 @@ mod tests {
         assert_eq!(emu.PC, 0x212);
     }
 
+    #[test]
+    fn test_stack_overflow() {
+        let rom = [
+            0x22, 0x00, // 0x200: CALL 0x200
+        ];
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        exec_cycles(&mut emu, 100);
+        assert_eq!(emu.sub_stack.len(), 100);
+
+        for (mode, depth) in [(StackMode::Vip, 12), (StackMode::Schip, 16)] {
+            emu.quirks.stack = mode;
+            emu.reset();
+            exec_cycles(&mut emu, depth);
+            assert!(matches!(
+                emu.execute(),
+                Err(EmulatorError::StackOverflow(0x200))
+            ));
+            assert_eq!(emu.sub_stack.len(), depth as usize);
+        }
+    }
+
+    #[test]
+    fn test_sound_change() {
+        let rom = [
+            0x60, 0x02, // 0x200: V0 = 2
+            0x61, 0x01, // 0x202: V1 = 1
+            0xF0, 0x18, // 0x204: ST = V0
+            0xF1, 0x18, // 0x206: ST = V1
+        ];
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        let changes = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = changes.clone();
+        emu.on_sound_change(move |on| sink.lock().unwrap().push(on));
+
+        exec_cycles(&mut emu, 3);
+        assert!(emu.buzzer());
+        emu.decrease_timers();
+        assert!(!emu.buzzer());
+        assert_eq!(*changes.lock().unwrap(), [true, false]);
+
+        // ST = 1 only sounds with the quirk
+        emu.execute().unwrap();
+        assert!(!emu.buzzer());
+        emu.quirks.short_beeps = true;
+        assert!(emu.buzzer());
+        emu.decrease_timers();
+        assert_eq!(*changes.lock().unwrap(), [true, false]);
+    }
+
     #[test]
     fn test_bad_return() {
         let rom = [0x00u8, 0xEE];
@@ -1443,4 +3164,115 @@ mod tests {
             Err(EmulatorError::InvalidReturn(0x200))
         ));
     }
+
+    #[test]
+    fn test_snapshot() {
+        let rom = [
+            0x60, 0x05, // 0x200: V0 = 5
+            0xA2, 0x00, // 0x202: I = 0x200
+            0xD0, 0x05, // 0x204: draw 5 rows
+            0x12, 0x06, // 0x206: jump to 0x206
+        ];
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        emu.quirks.wrap_sprites = true;
+        exec_cycles(&mut emu, 3);
+        assert!(emu.screen_changed());
+
+        let snapshot = emu.snapshot();
+        emu.reset();
+        emu.quirks = Quirks::default();
+        emu.restore(&snapshot).unwrap();
+
+        assert_eq!(emu.V[0], 5);
+        assert_eq!(emu.PC, 0x206);
+        assert!(emu.quirks.wrap_sprites);
+        assert!(emu.screen_changed());
+        assert_eq!(emu.snapshot(), snapshot);
+
+        let copy = Emulator::from_snapshot(&snapshot).unwrap();
+        assert_eq!(copy.state_hash(), emu.state_hash());
+
+        // a broken snapshot changes nothing
+        let mut broken = snapshot.clone();
+        broken.memory.pop();
+        assert!(emu.restore(&broken).is_err());
+        let old = Snapshot {
+            version: 0,
+            ..snapshot
+        };
+        assert_eq!(emu.restore(&old), Err(SnapshotError::Version(0)));
+        assert_eq!(emu.V[0], 5);
+    }
+
+    #[test]
+    fn test_copy_rng() {
+        let rom = [0xC0, 0xFF, 0x12, 0x00]; // 0x200: v0 := random 0xFF, jump 0x200
+        let mut emu = Emulator::builder().seed(7).load_rom(&rom[..]).unwrap();
+        exec_cycles(&mut emu, 4);
+
+        // going back to a snapshot and the rng with it gives the same bytes
+        let (snapshot, rng) = (emu.snapshot(), emu.copy_rng().unwrap());
+        let mut first = Vec::new();
+        for _ in 0..8 {
+            exec_cycles(&mut emu, 2);
+            first.push(emu.V[0]);
+        }
+
+        emu.restore(&snapshot).unwrap();
+        emu.set_rng(rng);
+        let mut again = Vec::new();
+        for _ in 0..8 {
+            exec_cycles(&mut emu, 2);
+            again.push(emu.V[0]);
+        }
+        assert_eq!(first, again);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        let rom = [0x60, 0x05, 0x12, 0x02];
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        emu.execute().unwrap();
+
+        let json = serde_json::to_string(&emu).unwrap();
+        let copy: Emulator = serde_json::from_str(&json).unwrap();
+        assert_eq!(copy.snapshot(), emu.snapshot());
+
+        let version = format!("\"version\":{}", SNAPSHOT_VERSION);
+        let broken = json.replace(&version, "\"version\":99");
+        assert!(serde_json::from_str::<Emulator>(&broken).is_err());
+    }
+
+    #[test]
+    fn test_machine_code() {
+        let rom = [
+            0x63, 0x05, // 0x200: V3 = 5
+            0x03, 0x00, // 0x202: call machine code at 0x300, with R6 on V3
+            0x12, 0x04, // 0x204: jump to 0x204
+        ];
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        emu.memory[0x300..0x306].copy_from_slice(&[
+            0x06, //       0x300: LDN R6
+            0xFE, //       0x301: SHL
+            0x56, //       0x302: STR R6
+            0xF8, 0xFF, // 0x303: LDI 0xFF
+            0x5B, //       0x305: STR RB, top left of the screen
+        ]);
+        emu.memory[0x306] = 0xD4; // 0x306: SEP R4
+
+        exec_cycles(&mut emu, 1);
+        assert!(matches!(
+            emu.execute(),
+            Err(EmulatorError::MachineSubroutine(0x204))
+        ));
+
+        emu.quirks.vip_machine_code = true;
+        emu.PC = 0x202;
+        emu.execute().unwrap();
+        assert_eq!(emu.V[3], 10);
+        assert_eq!(emu.PC, 0x204);
+        assert!(emu.get_pixel(7, 0));
+        assert!(!emu.get_pixel(8, 0));
+    }
 }
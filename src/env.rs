@@ -0,0 +1,228 @@
+//! A gym-style environment around the emulator, so a rom can be used as a
+//! small reinforcement learning benchmark: reset, then step with the keys
+//! to hold, getting back the screen, a reward and whether it is over.
+//!
+//! The reward and the end of an episode come from user callbacks, which
+//! usually read the score or the lives straight from memory:
+//!
+//! ```
+//! use rc8::env::{byte_delta, Chip8Env};
+//!
+//! let rom = [
+//!     0x70, 0x01, // 0x200: V0 += 1
+//!     0xA3, 0x00, // 0x202: I = 0x300
+//!     0xF0, 0x55, // 0x204: store V0 on 0x300
+//!     0x12, 0x00, // 0x206: jump to 0x200
+//! ];
+//!
+//! let mut env = Chip8Env::new(&rom[..]).unwrap();
+//! env.set_reward(byte_delta(0x300));
+//! env.set_done(|emu| emu.memory[0x300] >= 10);
+//!
+//! env.reset(1234);
+//! let mut total = 0.0;
+//! loop {
+//!     let step = env.step(0);
+//!     total += step.reward;
+//!     if step.done {
+//!         break;
+//!     }
+//! }
+//! assert!(total >= 10.0);
+//! ```
+use super::{
+    emulator::{Emulator, EmulatorError, DISPLAY_HEIGHT},
+    timing::run_frame,
+};
+
+type RewardFn = Box<dyn FnMut(&Emulator) -> f32 + Send>;
+type DoneFn = Box<dyn FnMut(&Emulator) -> bool + Send>;
+
+/// What happened on a [`Chip8Env::step`]
+#[derive(Debug)]
+pub struct Step {
+    /// The screen after the step, one row per item, leftmost pixel on
+    /// the highest bit
+    pub screen: [u64; DISPLAY_HEIGHT],
+
+    /// Sum of the rewards of every frame in the step
+    pub reward: f32,
+
+    /// The episode is over: the done callback said so, the frame limit
+    /// was reached or the rom crashed
+    pub done: bool,
+
+    /// Why the rom stopped, if it crashed
+    pub error: Option<EmulatorError>,
+}
+
+pub struct Chip8Env {
+    emu: Emulator,
+    reward: RewardFn,
+    done: DoneFn,
+    frames: u32,
+    over: bool,
+
+    /// Frames run on each step, all with the same keys held
+    pub frame_skip: u32,
+
+    /// Episodes end after this many frames, if set
+    pub max_frames: Option<u32>,
+}
+
+impl Chip8Env {
+    /// An environment for the rom, with no reward and episodes that only
+    /// end on a crash
+    pub fn new<T: AsRef<[u8]>>(rom: T) -> Result<Self, EmulatorError> {
+        Ok(Chip8Env::from_emulator(Emulator::load_rom(rom.as_ref())?))
+    }
+
+    /// Use an emulator that is already set up, e.g. with other quirks
+    pub fn from_emulator(emu: Emulator) -> Self {
+        Chip8Env {
+            emu,
+            reward: Box::new(|_| 0.0),
+            done: Box::new(|_| false),
+            frames: 0,
+            over: false,
+            frame_skip: 1,
+            max_frames: None,
+        }
+    }
+
+    /// The reward of each frame, computed after it runs. It is also
+    /// called on reset, ignoring the result, so rewards that keep state
+    /// (like [`byte_delta`]) start over from the initial machine state.
+    pub fn set_reward(&mut self, reward: impl FnMut(&Emulator) -> f32 + Send + 'static) {
+        self.reward = Box::new(reward);
+    }
+
+    /// When the episode is over, checked after each frame
+    pub fn set_done(&mut self, done: impl FnMut(&Emulator) -> bool + Send + 'static) {
+        self.done = Box::new(done);
+    }
+
+    /// Start a new episode; the seed makes the random numbers (and so the
+    /// whole episode, given the same keys) reproducible
+    pub fn reset(&mut self, seed: u64) -> [u64; DISPLAY_HEIGHT] {
+        self.emu.reset();
+        self.emu.seed_rng(seed);
+        self.frames = 0;
+        self.over = false;
+        (self.reward)(&self.emu);
+        self.screen()
+    }
+
+    /// Hold `keys` (a bit mask, bit N for key N) for the next
+    /// `frame_skip` frames. After the episode is over, nothing runs
+    /// until the next [`reset`](Chip8Env::reset).
+    pub fn step(&mut self, keys: u16) -> Step {
+        let mut step = Step {
+            screen: self.screen(),
+            reward: 0.0,
+            done: self.over,
+            error: None,
+        };
+
+        if self.over {
+            return step;
+        }
+
+        for key in 0..16 {
+            self.emu.set_key(key, keys & (1 << key) != 0);
+        }
+
+        for _ in 0..self.frame_skip.max(1) {
+            if let Err(err) = run_frame(&mut self.emu) {
+                step.error = Some(err);
+                step.done = true;
+                break;
+            }
+
+            self.frames += 1;
+            step.reward += (self.reward)(&self.emu);
+            step.done = (self.done)(&self.emu) || self.max_frames == Some(self.frames);
+            if step.done {
+                break;
+            }
+        }
+
+        self.over = step.done;
+        step.screen = self.screen();
+        step
+    }
+
+    /// The emulator, to look at the machine state
+    pub fn emulator(&self) -> &Emulator {
+        &self.emu
+    }
+
+    /// The emulator, to change quirks or poke memory between episodes
+    pub fn emulator_mut(&mut self) -> &mut Emulator {
+        &mut self.emu
+    }
+
+    // only the low resolution screen fits on an observation
+    fn screen(&self) -> [u64; DISPLAY_HEIGHT] {
+        let rows = self.emu.framebuffer_bits();
+        std::array::from_fn(|y| rows[y] as u64)
+    }
+}
+
+/// A reward of how much the byte on `addr` went up (or down) since the
+/// last frame, as most roms keep the score on a counter in memory
+pub fn byte_delta(addr: usize) -> impl FnMut(&Emulator) -> f32 + Send {
+    let mut last = None;
+    move |emu| {
+        let value = emu.memory[addr];
+        let delta = last.map_or(0.0, |last: u8| value as f32 - last as f32);
+        last = Some(value);
+        delta
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_episode() {
+        let rom = [
+            0xE0, 0x9E, // 0x200: skip if key V0 (0) is pressed
+            0x12, 0x00, // 0x202: jump to 0x200
+            0x00, 0xEE, // 0x204: return, with an empty stack
+        ];
+
+        let mut env = Chip8Env::new(&rom[..]).unwrap();
+        env.max_frames = Some(3);
+        env.frame_skip = 2;
+        env.set_reward(|_| 1.0);
+
+        // without the key, it only ends at the frame limit
+        env.reset(0);
+        let first = env.step(0);
+        assert_eq!((first.reward, first.done), (2.0, false));
+        let last = env.step(0);
+        assert_eq!((last.reward, last.done), (1.0, true));
+        assert!(env.step(0).done);
+
+        // the key makes it crash
+        env.reset(0);
+        let crash = env.step(1);
+        assert!(crash.done);
+        assert!(matches!(crash.error, Some(EmulatorError::InvalidReturn(_))));
+    }
+
+    #[test]
+    fn test_byte_delta() {
+        let mut emu = Emulator::load_rom(&[][..]).unwrap();
+        let mut reward = byte_delta(0x300);
+
+        emu.memory[0x300] = 5;
+        assert_eq!(reward(&emu), 0.0);
+        emu.memory[0x300] = 8;
+        assert_eq!(reward(&emu), 3.0);
+        emu.memory[0x300] = 2;
+        assert_eq!(reward(&emu), -6.0);
+    }
+}
@@ -0,0 +1,182 @@
+use clap::ValueEnum;
+
+use super::{
+    emulator::{Emulator, DISPLAY_HEIGHT, DISPLAY_WIDTH},
+    palette::Palette,
+};
+
+/// Post-processing applied to the emulator screen before drawing
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Filter {
+    /// Pixels are switched on and off instantly
+    None,
+    /// Passive matrix LCD, with slow pixel response and some bleeding
+    Lcd,
+    /// CRT phosphor glow, with pixels fading out over a few frames
+    Phosphor,
+}
+
+impl Filter {
+    /// Default palette for the filter, if any
+    pub fn palette(&self) -> Option<Palette> {
+        match self {
+            Filter::None | Filter::Phosphor => None,
+            Filter::Lcd => Some(Palette::new([
+                0x9bab7700, 0x20301c00, 0x5e6e4a00, 0x40503400,
+            ])),
+        }
+    }
+
+    /// Build the intensity buffer used to emulate the display, if any
+    pub fn persistence(&self) -> Option<Persistence> {
+        match self {
+            Filter::None => None,
+            Filter::Lcd => Some(Persistence::new(0.35, 0.2, 0.08)),
+            // lit pixels show up at once, but take 4 frames to go dark,
+            // which hides most of the XOR drawing flicker
+            Filter::Phosphor => Some(Persistence::new(1.0, 0.25, 0.0)),
+        }
+    }
+}
+
+/// Per-pixel intensity buffer, used to emulate displays that
+/// do not switch their pixels on and off instantly.
+#[derive(Clone)]
+pub struct Persistence {
+    intensity: Vec<f32>,
+    width: usize,
+    height: usize,
+
+    // how much intensity a pixel gains/loses in a single frame
+    rise: f32,
+    fall: f32,
+
+    // how much of the neighbour pixels leak into each other
+    bleed: f32,
+}
+
+impl Persistence {
+    pub fn new(rise: f32, fall: f32, bleed: f32) -> Self {
+        Persistence {
+            intensity: vec![0.0; DISPLAY_WIDTH * DISPLAY_HEIGHT],
+            width: DISPLAY_WIDTH,
+            height: DISPLAY_HEIGHT,
+            rise,
+            fall,
+            bleed,
+        }
+    }
+
+    /// Move every pixel one frame closer to the current emulator screen.
+    /// Returns true if any of the intensities changed.
+    pub fn update(&mut self, emu: &Emulator) -> bool {
+        let mut changed = false;
+
+        // a new resolution starts from a blank screen
+        let display = emu.display();
+        if (display.width(), display.height()) != (self.width, self.height) {
+            self.width = display.width();
+            self.height = display.height();
+            self.intensity = vec![0.0; self.width * self.height];
+            changed = true;
+        }
+
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let value = &mut self.intensity[y * self.width + x];
+                let previous = *value;
+
+                *value = if emu.get_pixel(x, y) {
+                    (*value + self.rise).min(1.0)
+                } else {
+                    (*value - self.fall).max(0.0)
+                };
+
+                changed |= *value != previous;
+            }
+        }
+
+        changed
+    }
+
+    /// Intensity of the pixel at (x, y), from 0.0 (off) to 1.0 (fully on)
+    pub fn level(&self, x: usize, y: usize) -> f32 {
+        let width = self.width;
+        let value = self.intensity[y * width + x];
+        if self.bleed == 0.0 {
+            return value;
+        }
+
+        let mut neighbours = 0.0;
+        if x > 0 {
+            neighbours += self.intensity[y * width + x - 1];
+        }
+        if x + 1 < width {
+            neighbours += self.intensity[y * width + x + 1];
+        }
+        if y > 0 {
+            neighbours += self.intensity[(y - 1) * width + x];
+        }
+        if y + 1 < self.height {
+            neighbours += self.intensity[(y + 1) * width + x];
+        }
+
+        (value + self.bleed * neighbours / 4.0).min(1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_phosphor_decay() {
+        let rom: [u8; 8] = [
+            0x60, 0x00, // 0x200: Set V0 = 0x00
+            0xF0, 0x29, // 0x202: Set I = font digit 0
+            0xD0, 0x01, // 0x204: Draw 1 row at (V0, V0)
+            0x00, 0xE0, // 0x206: Clear the screen
+        ];
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        let mut persistence = Filter::Phosphor.persistence().unwrap();
+
+        emu.vblank();
+        for _ in 0..3 {
+            emu.execute().unwrap();
+        }
+        assert!(persistence.update(&emu));
+        assert_eq!(persistence.level(0, 0), 1.0);
+        assert_eq!(persistence.level(4, 0), 0.0);
+
+        // switched off, it takes 4 frames to go dark, and then stays there
+        emu.execute().unwrap();
+        for expected in [0.75, 0.5, 0.25, 0.0] {
+            assert!(persistence.update(&emu));
+            assert_eq!(persistence.level(0, 0), expected);
+        }
+        assert!(!persistence.update(&emu));
+    }
+
+    #[test]
+    fn test_lcd_bleed() {
+        let rom: [u8; 6] = [
+            0x60, 0x00, // 0x200: Set V0 = 0x00
+            0xF0, 0x29, // 0x202: Set I = font digit 0
+            0xD0, 0x01, // 0x204: Draw 1 row at (V0, V0)
+        ];
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        let mut persistence = Filter::Lcd.persistence().unwrap();
+        emu.vblank();
+        for _ in 0..3 {
+            emu.execute().unwrap();
+        }
+
+        // slow to light up, and the next pixel gets a bit of its neighbour
+        persistence.update(&emu);
+        assert_eq!(persistence.level(3, 0), 0.35 + 0.08 * 0.35 / 4.0);
+        assert_eq!(persistence.level(4, 0), 0.08 * 0.35 / 4.0);
+        persistence.update(&emu);
+        persistence.update(&emu);
+        assert_eq!(persistence.intensity[0], 1.0);
+    }
+}
@@ -0,0 +1,32 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::PathBuf,
+};
+
+use crate::romdb::sha1_hex;
+
+/// Where the RPL user flags of a rom are stored, by its hash, so renaming
+/// the file does not lose them
+fn file_path(rom: &[u8]) -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("rc8").join("flags").join(sha1_hex(rom)))
+}
+
+/// The flags saved for the rom; missing or broken files are just no flags
+pub fn load(rom: &[u8]) -> Option<[u8; 16]> {
+    let bytes = fs::read(file_path(rom)?).ok()?;
+    bytes[..].try_into().ok()
+}
+
+/// Save the flags of the rom, creating the directory if needed
+pub fn save(rom: &[u8], flags: &[u8; 16]) -> io::Result<()> {
+    let path = file_path(rom).ok_or_else(|| {
+        io::Error::new(ErrorKind::NotFound, "could not find the config directory")
+    })?;
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    fs::write(path, flags)
+}
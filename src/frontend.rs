@@ -0,0 +1,367 @@
+//! What the emulator needs from a frontend: somewhere to show the screen,
+//! a keypad and a buzzer.
+//!
+//! The SDL app is one implementation; anything else (a terminal, a web page)
+//! only has to implement these traits and call [`step`] or [`run`].
+use std::time::Duration;
+
+use thiserror::Error;
+
+use super::{
+    clock::Clock,
+    emulator::{Emulator, EmulatorError},
+    opcode::Opcode,
+    timing::{vip_cycles, CycleModel, Ticks, Timing},
+};
+
+/// Where the emulator screen goes
+pub trait Frame {
+    type Error;
+
+    /// Called on every vblank (60 times per second), with the current screen
+    fn present(&mut self, emu: &Emulator) -> Result<(), Self::Error>;
+}
+
+/// Where the keypad state comes from
+pub trait InputSource {
+    /// Apply the keys pressed and released since the last call
+    fn poll(&mut self, emu: &mut Emulator);
+
+    /// If the user wants to stop; only used by [`run`]
+    fn quit_requested(&self) -> bool {
+        false
+    }
+}
+
+/// Plays the buzzer
+pub trait AudioSink {
+    fn set_buzzer(&mut self, on: bool);
+}
+
+#[derive(Error, Debug)]
+pub enum FrontendError<E> {
+    #[error(transparent)]
+    Emulator(#[from] EmulatorError),
+
+    #[error("frontend error: {0}")]
+    Frame(E),
+}
+
+/// Run everything that is due in `ticks`: input, vblank, cpu, timers and sound.
+///
+/// `stop` is checked before every instruction; when it returns true, no more
+/// instructions are run on this step and `Ok(true)` is returned. With the VIP
+/// cycle model, the cycles used past the budget are left in `ticks.overrun`,
+/// and the sprite rows drawn in `ticks.drawn`.
+pub fn step<F, I, A>(
+    emu: &mut Emulator,
+    ticks: &mut Ticks,
+    frame: &mut F,
+    input: &mut I,
+    audio: &mut A,
+    mut stop: impl FnMut(&Emulator) -> bool,
+) -> Result<bool, FrontendError<F::Error>>
+where
+    F: Frame,
+    I: InputSource,
+    A: AudioSink,
+{
+    input.poll(emu);
+
+    if ticks.vblank {
+        emu.vblank();
+        frame.present(emu).map_err(FrontendError::Frame)?;
+    }
+
+    let mut stopped = false;
+    let mut spent = 0;
+    while spent < ticks.cycles {
+        if stop(emu) {
+            stopped = true;
+            break;
+        }
+
+        // once the draw budget is used up, the cpu waits for the next frame
+        let op = emu.next_opcode();
+        let rows = match op {
+            Some(Opcode::Draw(_, _, n)) => n as u32,
+            _ => 0,
+        };
+        if rows > 0 && ticks.draw_rows.is_some_and(|left| ticks.drawn >= left) {
+            break;
+        }
+
+        spent += match ticks.model {
+            CycleModel::Flat => {
+                emu.execute()?;
+                1
+            }
+            CycleModel::Vip => {
+                let info = emu.step()?;
+                vip_cycles(Some(info.op), info.new_pc == info.old_pc + 4)
+            }
+        };
+        ticks.drawn += rows;
+    }
+    ticks.overrun = spent.saturating_sub(ticks.cycles);
+
+    for _ in 0..ticks.timers {
+        emu.decrease_timers();
+    }
+
+    audio.set_buzzer(emu.buzzer());
+
+    Ok(stopped)
+}
+
+/// Run the rom in real time (as told by `clock`) until the input asks to
+/// quit, for frontends that need nothing else
+pub fn run<F, I, A>(
+    emu: &mut Emulator,
+    mut timing: Timing,
+    clock: &mut impl Clock,
+    frame: &mut F,
+    input: &mut I,
+    audio: &mut A,
+) -> Result<(), FrontendError<F::Error>>
+where
+    F: Frame,
+    I: InputSource,
+    A: AudioSink,
+{
+    let mut previous = clock.now();
+
+    while !input.quit_requested() {
+        clock.sleep(Duration::from_millis(1));
+
+        let now = clock.now();
+        let mut ticks = timing.advance(now - previous);
+        previous = now;
+
+        step(emu, &mut ticks, frame, input, audio, |_| false)?;
+        timing.charge(ticks.overrun);
+        timing.drew(ticks.drawn);
+    }
+
+    audio.set_buzzer(false);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::{SystemClock, VirtualClock};
+
+    #[derive(Default)]
+    struct Screen {
+        frames: usize,
+    }
+
+    impl Frame for Screen {
+        type Error = ();
+
+        fn present(&mut self, _emu: &Emulator) -> Result<(), ()> {
+            self.frames += 1;
+            Ok(())
+        }
+    }
+
+    /// Holds key 5 down, and quits after a few polls
+    #[derive(Default)]
+    struct Keypad {
+        polls: usize,
+    }
+
+    impl InputSource for Keypad {
+        fn poll(&mut self, emu: &mut Emulator) {
+            self.polls += 1;
+            emu.set_key(0x5, true);
+        }
+
+        fn quit_requested(&self) -> bool {
+            self.polls >= 20
+        }
+    }
+
+    #[derive(Default)]
+    struct Buzzer {
+        on: bool,
+    }
+
+    impl AudioSink for Buzzer {
+        fn set_buzzer(&mut self, on: bool) {
+            self.on = on;
+        }
+    }
+
+    #[test]
+    fn test_step() {
+        let rom = [
+            0x60, 0x05, // 0x200: V0 = 5
+            0xF0, 0x18, // 0x202: ST = V0
+            0x12, 0x04, // 0x204: jump to 0x204
+        ];
+        let mut ticks = Ticks {
+            vblank: true,
+            cycles: 10,
+            timers: 1,
+            model: CycleModel::Flat,
+            overrun: 0,
+            draw_rows: None,
+            drawn: 0,
+        };
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        let mut screen = Screen::default();
+        let mut keypad = Keypad::default();
+        let mut buzzer = Buzzer::default();
+        let stopped = step(
+            &mut emu,
+            &mut ticks,
+            &mut screen,
+            &mut keypad,
+            &mut buzzer,
+            |_| false,
+        );
+
+        assert!(!stopped.unwrap());
+        assert_eq!(screen.frames, 1);
+        assert!(emu.is_key_pressed(0x5));
+        assert_eq!(emu.ST, 4);
+        assert!(buzzer.on);
+
+        // stopping right before the jump
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        let stop = |emu: &Emulator| emu.PC == 0x204;
+        let stopped = step(
+            &mut emu,
+            &mut ticks,
+            &mut screen,
+            &mut keypad,
+            &mut buzzer,
+            stop,
+        );
+
+        assert!(stopped.unwrap());
+        assert_eq!(emu.PC, 0x204);
+    }
+
+    #[test]
+    fn test_step_vip() {
+        let rom = [
+            0x60, 0x05, // 0x200: V0 = 5
+            0x80, 0x04, // 0x202: V0 += V0
+            0x12, 0x02, // 0x204: jump to 0x202
+        ];
+        let mut ticks = Ticks {
+            vblank: false,
+            cycles: 200,
+            timers: 0,
+            model: CycleModel::Vip,
+            overrun: 0,
+            draw_rows: None,
+            drawn: 0,
+        };
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        let mut buzzer = Buzzer::default();
+        step(
+            &mut emu,
+            &mut ticks,
+            &mut Screen::default(),
+            &mut Keypad::default(),
+            &mut buzzer,
+            |_| false,
+        )
+        .unwrap();
+
+        // 74 + 112 cycles; the jump still runs, going past the budget
+        assert_eq!(emu.V[0], 10);
+        assert_eq!(emu.PC, 0x202);
+        assert_eq!(ticks.overrun, 74 + 112 + 91 - 200);
+    }
+
+    #[test]
+    fn test_step_draw_budget() {
+        let rom = [
+            0xD0, 0x05, // 0x200: draw 5 rows
+            0x12, 0x00, // 0x202: jump to 0x200
+        ];
+        let mut ticks = Ticks {
+            vblank: false,
+            cycles: 3000,
+            timers: 0,
+            model: CycleModel::Vip,
+            overrun: 0,
+            draw_rows: Some(8),
+            drawn: 0,
+        };
+
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        step(
+            &mut emu,
+            &mut ticks,
+            &mut Screen::default(),
+            &mut Keypad::default(),
+            &mut Buzzer::default(),
+            |_| false,
+        )
+        .unwrap();
+
+        // the second sprite still fits, the third waits
+        assert_eq!(ticks.drawn, 10);
+        assert_eq!(emu.PC, 0x200);
+        assert_eq!(ticks.overrun, 0);
+    }
+
+    #[test]
+    fn test_run() {
+        let rom = [0x12, 0x00]; // 0x200: jump to 0x200
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        let mut keypad = Keypad::default();
+        let mut buzzer = Buzzer { on: true };
+
+        run(
+            &mut emu,
+            Timing::new(540),
+            &mut SystemClock::new(),
+            &mut Screen::default(),
+            &mut keypad,
+            &mut buzzer,
+        )
+        .unwrap();
+        assert_eq!(keypad.polls, 20);
+        assert!(!buzzer.on);
+    }
+
+    #[test]
+    fn test_run_pacing() {
+        let rom = [
+            0x60, 0x3C, // 0x200: V0 = 60
+            0xF0, 0x15, // 0x202: DT = V0
+            0x71, 0x01, // 0x204: V1 += 1
+            0x12, 0x04, // 0x206: jump to 0x204
+        ];
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        let mut clock = VirtualClock::new(60);
+        let mut screen = Screen::default();
+
+        // exactly one frame per poll
+        run(
+            &mut emu,
+            Timing::new(540),
+            &mut clock,
+            &mut screen,
+            &mut Keypad::default(),
+            &mut Buzzer::default(),
+        )
+        .unwrap();
+
+        assert_eq!(clock.ticks(), 20);
+        assert_eq!(screen.frames, 20);
+        assert_eq!(emu.DT, 60 - 20);
+
+        // 20 frames of 9 instructions: 2 setting DT, the rest a loop of 2
+        assert_eq!(emu.V[1] as u32, (20 * 9 - 2) / 2);
+    }
+}
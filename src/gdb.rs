@@ -0,0 +1,244 @@
+//! A stub for the GDB remote serial protocol, so gdb (or an IDE talking to
+//! it) can attach to the running emulator: read and write registers and
+//! memory, set breakpoints, step and continue.
+//!
+//! The connection is handled on its own thread, which answers the queries
+//! that do not need the machine. Everything else goes to the main loop as a
+//! [`Request`], as the app decides when the rom runs; the packets themselves
+//! are in [`rsp`](super::rsp).
+use std::{
+    io::{self, ErrorKind, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver, RecvTimeoutError, Sender, TryIter},
+    thread,
+    time::Duration,
+};
+
+use tracing::{debug, info, warn};
+
+use super::rsp::{answer, Answer, Command};
+
+// how often a running rom is checked for an interrupt from gdb
+const INTERRUPT_POLL: Duration = Duration::from_millis(20);
+
+// ctrl-c on gdb, sent outside of a packet
+const INTERRUPT: u8 = 0x03;
+
+/// A command, and where its reply goes
+pub struct Request {
+    pub command: Command,
+    reply: Sender<String>,
+}
+
+impl Request {
+    pub fn reply(self, packet: &str) {
+        // the client may be gone already
+        let _ = self.reply.send(packet.to_owned());
+    }
+}
+
+/// Listens for gdb on a local port, one client at a time
+pub struct GdbServer {
+    requests: Receiver<Request>,
+}
+
+impl GdbServer {
+    pub fn spawn(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))?;
+        info!("waiting for gdb on port {}", port);
+
+        let (sender, requests) = mpsc::channel();
+        thread::Builder::new()
+            .name("gdb".to_owned())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let result = stream.and_then(|stream| serve(stream, &sender));
+                    if let Err(err) = result {
+                        warn!("gdb connection error: {}", err);
+                    }
+                    if sender.send(request(Command::Detach).0).is_err() {
+                        return;
+                    }
+                }
+            })?;
+
+        Ok(GdbServer { requests })
+    }
+
+    /// Everything gdb asked for since the last call
+    pub fn requests(&self) -> TryIter<'_, Request> {
+        self.requests.try_iter()
+    }
+}
+
+fn request(command: Command) -> (Request, Receiver<String>) {
+    let (reply, receiver) = mpsc::channel();
+    (Request { command, reply }, receiver)
+}
+
+/// Talk to a client until it leaves
+fn serve(mut stream: TcpStream, requests: &Sender<Request>) -> io::Result<()> {
+    info!("gdb connected from {}", stream.peer_addr()?);
+    let _ = requests.send(request(Command::Attach).0);
+
+    while let Some(packet) = read_packet(&mut stream)? {
+        debug!("gdb: {}", packet);
+        let reply = match answer(&packet) {
+            Answer::Reply(reply) => reply,
+            Answer::Machine(command) => {
+                let running = command == Command::Continue;
+                let (request, receiver) = request(command);
+                if requests.send(request).is_err() {
+                    return Ok(());
+                }
+
+                match wait_reply(&mut stream, requests, &receiver, running)? {
+                    Some(reply) => reply,
+                    None => return Ok(()),
+                }
+            }
+            Answer::Detach => {
+                write_packet(&mut stream, "OK")?;
+                return Ok(());
+            }
+        };
+        write_packet(&mut stream, &reply)?;
+    }
+
+    info!("gdb disconnected");
+    Ok(())
+}
+
+/// Wait for the main loop; while the rom runs, gdb can still interrupt it
+fn wait_reply(
+    stream: &mut TcpStream,
+    requests: &Sender<Request>,
+    receiver: &Receiver<String>,
+    running: bool,
+) -> io::Result<Option<String>> {
+    if !running {
+        return Ok(receiver.recv().ok());
+    }
+
+    stream.set_read_timeout(Some(INTERRUPT_POLL))?;
+    let reply = loop {
+        match receiver.recv_timeout(INTERRUPT_POLL) {
+            Ok(reply) => break Some(reply),
+            Err(RecvTimeoutError::Disconnected) => break None,
+            Err(RecvTimeoutError::Timeout) => {}
+        }
+
+        let mut byte = [0];
+        match stream.read(&mut byte) {
+            Ok(0) => break None,
+            Ok(_) if byte[0] == INTERRUPT => {
+                let _ = requests.send(request(Command::Interrupt).0);
+            }
+            Ok(_) => {}
+            Err(err) if matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+            Err(err) => return Err(err),
+        }
+    };
+    stream.set_read_timeout(None)?;
+    Ok(reply)
+}
+
+fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0, |sum, byte| sum.wrapping_add(byte))
+}
+
+/// `$data#checksum`
+fn frame(data: &str) -> String {
+    format!("${}#{:02x}", data, checksum(data))
+}
+
+fn write_packet(stream: &mut impl Write, data: &str) -> io::Result<()> {
+    debug!("gdb reply: {}", data);
+    stream.write_all(frame(data).as_bytes())?;
+    stream.flush()
+}
+
+/// The next packet, acknowledging it; `None` when the client is gone.
+/// Acks and stray interrupts in between are skipped.
+fn read_packet(stream: &mut (impl Read + Write)) -> io::Result<Option<String>> {
+    let mut byte = [0];
+    loop {
+        // wait for the start of a packet
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'$' {
+                break;
+            }
+        }
+
+        let mut data = Vec::new();
+        loop {
+            if stream.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            if byte[0] == b'#' {
+                break;
+            }
+            data.push(byte[0]);
+        }
+
+        let mut sum = [0; 2];
+        stream.read_exact(&mut sum)?;
+
+        let data = String::from_utf8_lossy(&data).into_owned();
+        let expected = std::str::from_utf8(&sum)
+            .ok()
+            .and_then(|sum| u8::from_str_radix(sum, 16).ok());
+
+        // a corrupted packet is sent again
+        if expected == Some(checksum(&data)) {
+            stream.write_all(b"+")?;
+            return Ok(Some(data));
+        }
+        stream.write_all(b"-")?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    /// A stream with the given input, that keeps what is written apart
+    struct Fake {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Fake {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Fake {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_packets() {
+        assert_eq!(frame("OK"), "$OK#9a");
+
+        let mut stream = Fake {
+            input: Cursor::new(b"+$g#00$g#67".to_vec()),
+            output: Vec::new(),
+        };
+        assert_eq!(read_packet(&mut stream).unwrap().as_deref(), Some("g"));
+        assert_eq!(stream.output, b"-+");
+        assert_eq!(read_packet(&mut stream).unwrap(), None);
+    }
+}
@@ -0,0 +1,162 @@
+//! Rom information and a map of how it uses the address space, so authors
+//! can see how close they are to the memory limit.
+//!
+//! The map combines the static disassembly with a short headless run, which
+//! finds code reached through `jump0` and the sprites that are really drawn.
+use std::path::Path;
+
+use super::{
+    audit::scripted_run,
+    disasm::{disassemble, ENTRY_POINT},
+    emulator::MAX_ROM_SIZE,
+    metadata::Metadata,
+    opcode::Opcode,
+};
+
+/// Bytes on each line of the map
+const MAP_WIDTH: usize = 64;
+
+/// What a byte of the address space is used for
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Usage {
+    Code,
+    Sprite,
+    Data,
+    Unused,
+}
+
+impl Usage {
+    fn symbol(&self) -> char {
+        match self {
+            Usage::Code => '#',
+            Usage::Sprite => 's',
+            Usage::Data => '.',
+            Usage::Unused => ' ',
+        }
+    }
+}
+
+/// Classify every byte from the entry point up to the memory limit
+fn usage_map(rom: &[u8], frames: usize) -> Vec<Usage> {
+    let mut map = vec![Usage::Unused; MAX_ROM_SIZE];
+    map[..rom.len().min(MAX_ROM_SIZE)].fill(Usage::Data);
+
+    let mut mark = |addr: usize, len: usize, usage: Usage| {
+        let start = addr.saturating_sub(ENTRY_POINT as usize);
+        let end = (addr + len)
+            .saturating_sub(ENTRY_POINT as usize)
+            .min(map.len());
+        for byte in map.iter_mut().take(end).skip(start) {
+            // code wins over sprites, as it is what actually runs
+            if *byte != Usage::Code {
+                *byte = usage;
+            }
+        }
+    };
+
+    // static: everything reachable from the entry point
+    for block in disassemble(rom).blocks {
+        for (addr, _, _) in block.instructions {
+            mark(addr as usize, 2, Usage::Code);
+        }
+    }
+
+    // dynamic: whatever runs and gets drawn in a few seconds of scripted input
+    scripted_run(rom, frames, |emu| {
+        mark(emu.PC, 2, Usage::Code);
+        if let Some(Opcode::Draw(_, _, n)) = emu.next_opcode() {
+            mark(emu.I as usize, n as usize, Usage::Sprite);
+        }
+    });
+
+    map
+}
+
+/// Print the rom information and its memory map
+pub fn info(path: &Path, rom: &[u8], frames: usize) -> Result<(), anyhow::Error> {
+    let metadata = Metadata::load(path)?;
+    if let Some(title) = &metadata.title {
+        println!("title:   {}", title);
+    }
+    if let Some(author) = &metadata.author {
+        println!("author:  {}", author);
+    }
+
+    let map = usage_map(rom, frames);
+    let count = |usage| map.iter().filter(|&&byte| byte == usage).count();
+
+    println!(
+        "size:    {} bytes ({}% of the {} available)",
+        rom.len(),
+        rom.len() * 100 / MAX_ROM_SIZE,
+        MAX_ROM_SIZE
+    );
+    println!(
+        "usage:   {} code, {} sprites, {} other data, {} free",
+        count(Usage::Code),
+        count(Usage::Sprite),
+        count(Usage::Data),
+        count(Usage::Unused)
+    );
+    println!();
+
+    // the free space at the end is summarized in a single line
+    let used_rows = map
+        .iter()
+        .rposition(|&byte| byte != Usage::Unused)
+        .map(|last| last / MAP_WIDTH + 1)
+        .unwrap_or(0);
+
+    for (row, bytes) in map.chunks(MAP_WIDTH).take(used_rows).enumerate() {
+        let line: String = bytes.iter().map(Usage::symbol).collect();
+        println!(
+            "  {:03X} |{:<width$}|",
+            ENTRY_POINT as usize + row * MAP_WIDTH,
+            line,
+            width = MAP_WIDTH
+        );
+    }
+
+    let free_from = used_rows * MAP_WIDTH;
+    if free_from < map.len() {
+        println!(
+            "  {:03X} - {:03X} free",
+            ENTRY_POINT as usize + free_from,
+            ENTRY_POINT as usize + map.len() - 1
+        );
+    }
+
+    println!();
+    println!(
+        "  {} code   {} sprites   {} other data   '{}' free",
+        Usage::Code.symbol(),
+        Usage::Sprite.symbol(),
+        Usage::Data.symbol(),
+        Usage::Unused.symbol()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_usage_map() {
+        let rom = [
+            0xA2, 0x08, // 0x200: I = 0x208
+            0xD0, 0x02, // 0x202: draw 2 rows
+            0x12, 0x04, // 0x204: jump to 0x204
+            0x00, 0x00, // 0x206: padding
+            0xF0, 0x90, // 0x208: sprite
+        ];
+
+        let map = usage_map(&rom, 2);
+        assert_eq!(map.len(), MAX_ROM_SIZE);
+        assert_eq!(&map[..6], &[Usage::Code; 6]);
+        assert_eq!(&map[6..8], &[Usage::Data; 2]);
+        assert_eq!(&map[8..10], &[Usage::Sprite; 2]);
+        assert!(map[10..].iter().all(|&byte| byte == Usage::Unused));
+    }
+}
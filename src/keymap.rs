@@ -1,4 +1,9 @@
-use sdl2::{event::Event, keyboard::Keycode};
+use sdl2::{
+    event::Event,
+    keyboard::{Keycode, Mod},
+};
+
+use super::remap::{Button, Remap};
 
 /// Makes dealing with SDL keymapping less verbose
 macro_rules! map_keys {
@@ -37,6 +42,54 @@ macro_rules! map_keys {
         )
     };
 
+    // keydown with the ctrl modifier
+    (
+        @inner
+        $event:expr,
+        parsed = [$($parsed:tt)*],
+        rest = [
+            @ctrl $keycode:pat => $action:expr,
+            $($rest:tt)*
+        ]
+    ) => {
+        map_keys!(
+            @inner
+            $event,
+            parsed = [
+                $($parsed)*
+                Event::KeyDown {keycode: Some($keycode), keymod, .. }
+                    if keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD) => Some($action),
+            ],
+            rest = [
+                $($rest)*
+            ]
+        )
+    };
+
+    // held down, with the action taking whether the key is down
+    (
+        @inner
+        $event:expr,
+        parsed = [$($parsed:tt)*],
+        rest = [
+            @hold $keycode:pat => $action:expr,
+            $($rest:tt)*
+        ]
+    ) => {
+        map_keys!(
+            @inner
+            $event,
+            parsed = [
+                $($parsed)*
+                Event::KeyDown {keycode: Some($keycode), .. } => Some($action(true)),
+                Event::KeyUp {keycode: Some($keycode), .. } => Some($action(false)),
+            ],
+            rest = [
+                $($rest)*
+            ]
+        )
+    };
+
     // emulate keydown
     (
         @inner
@@ -62,15 +115,74 @@ macro_rules! map_keys {
     };
 }
 
-/// Different key bindings depending on the application state
+/// The CHIP-8 keypad, as laid out on the original hardware, along with
+/// the keyboard key bound to each position
+pub const KEYPAD: [[(usize, &str); 4]; 4] = [
+    [(0x1, "1"), (0x2, "2"), (0x3, "3"), (0xC, "4")],
+    [(0x4, "Q"), (0x5, "W"), (0x6, "E"), (0xD, "R")],
+    [(0x7, "A"), (0x8, "S"), (0x9, "D"), (0xE, "F")],
+    [(0xA, "Z"), (0x0, "X"), (0xB, "C"), (0xF, "V")],
+];
+
+/// Different key bindings depending on the application state; only the
+/// active one sees the events
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Keymap {
+    /// The rom is running: the keypad, plus the app controls
     Chip8,
+    /// The rom is stopped (paused or crashed): just the app controls, so
+    /// the game cannot see keys pressed meanwhile
+    Paused,
+    /// The rom switcher
+    Menu,
+    /// The disassembly view navigation, on top of the running or paused
+    /// bindings
+    Debugger { paused: bool },
+    /// Typing a netplay chat message: the keys go to the message, and the
+    /// game only sees keys being let go
+    Chat,
 }
 
 /// Actions to be executed by the application
 pub enum Action {
     EmulateKeyState(usize, bool),
+    EmulateKey2State(usize, bool),
     TogglePause,
+    FrameAdvance,
+    FastForward(bool),
+    Reset,
+    RetryQuirks,
+    ToggleRemap,
+    ToggleKeypad,
+    SwapPlayers,
+    NetplayDelayUp,
+    NetplayDelayDown,
+    OpenChat,
+    ChatText(String),
+    ChatErase,
+    ChatSend,
+    ChatClose,
+    ScaleUp,
+    ScaleDown,
+    CycleWaveform,
+    PitchDown,
+    PitchUp,
+    VolumeDown,
+    VolumeUp,
+    ToggleMute,
+    ExportTrace,
+    TogglePerf,
+    ToggleCrt,
+    ToggleCapture,
+    ToggleDebugger,
+    DebugUp,
+    DebugDown,
+    ToggleBreakpoint,
+    OpenSwitcher,
+    MenuUp,
+    MenuDown,
+    MenuSelect,
+    MenuClose,
     Quit,
 }
 
@@ -78,26 +190,328 @@ impl Keymap {
     /// Translate and SDL2 event into an action to be executed by the app
     pub fn translate_action(&self, event: &Event) -> Option<Action> {
         match self {
-            Keymap::Chip8 => map_keys!(event,
-                @emulate Keycode::Num1 => 0x01,
-                @emulate Keycode::Num2 => 0x02,
-                @emulate Keycode::Num3 => 0x03,
-                @emulate Keycode::Num4 => 0x0C,
-                @emulate Keycode::Q => 0x04,
-                @emulate Keycode::W => 0x05,
-                @emulate Keycode::E => 0x06,
-                @emulate Keycode::R => 0x0D,
-                @emulate Keycode::A => 0x07,
-                @emulate Keycode::S => 0x08,
-                @emulate Keycode::D => 0x09,
-                @emulate Keycode::F => 0x0E,
-                @emulate Keycode::Z => 0x0A,
-                @emulate Keycode::X => 0x00,
-                @emulate Keycode::C => 0x0B,
-                @emulate Keycode::V => 0x0F,
-                Keycode::Space => Action::TogglePause,
-                Keycode::Escape => Action::Quit,
+            Keymap::Chip8 => translate_keypad(event).or_else(|| translate_controls(event)),
+            Keymap::Paused => translate_keypad(event)
+                // a key held when the rom stopped must still be let go
+                .filter(|action| matches!(action, Action::EmulateKeyState(_, false)))
+                .or_else(|| translate_controls(event)),
+            Keymap::Menu => map_keys!(event,
+                Keycode::Up => Action::MenuUp,
+                Keycode::Down => Action::MenuDown,
+                Keycode::Return | Keycode::KpEnter => Action::MenuSelect,
+                Keycode::Escape => Action::MenuClose,
             ),
+            Keymap::Debugger { paused } => map_keys!(event,
+                Keycode::Up => Action::DebugUp,
+                Keycode::Down => Action::DebugDown,
+                Keycode::Return | Keycode::KpEnter => Action::ToggleBreakpoint,
+            )
+            .or_else(|| {
+                let below = if *paused {
+                    Keymap::Paused
+                } else {
+                    Keymap::Chip8
+                };
+                below.translate_action(event)
+            }),
+            Keymap::Chat => match event {
+                Event::TextInput { text, .. } => Some(Action::ChatText(text.clone())),
+                _ => map_keys!(event,
+                    Keycode::Return | Keycode::KpEnter => Action::ChatSend,
+                    Keycode::Backspace => Action::ChatErase,
+                    Keycode::Escape => Action::ChatClose,
+                ),
+            }
+            .or_else(|| {
+                translate_keypad(event)
+                    .filter(|action| matches!(action, Action::EmulateKeyState(_, false)))
+            }),
         }
     }
+
+    /// The second CHIP-8X keypad, on the numeric keypad; only asked for
+    /// when the rom uses it, as it takes over some of the app controls
+    pub fn translate_keypad2(&self, event: &Event) -> Option<Action> {
+        let action = map_keys!(event,
+            @hold Keycode::Kp0 => |pressed| Action::EmulateKey2State(0x00, pressed),
+            @hold Keycode::Kp1 => |pressed| Action::EmulateKey2State(0x01, pressed),
+            @hold Keycode::Kp2 => |pressed| Action::EmulateKey2State(0x02, pressed),
+            @hold Keycode::Kp3 => |pressed| Action::EmulateKey2State(0x03, pressed),
+            @hold Keycode::Kp4 => |pressed| Action::EmulateKey2State(0x04, pressed),
+            @hold Keycode::Kp5 => |pressed| Action::EmulateKey2State(0x05, pressed),
+            @hold Keycode::Kp6 => |pressed| Action::EmulateKey2State(0x06, pressed),
+            @hold Keycode::Kp7 => |pressed| Action::EmulateKey2State(0x07, pressed),
+            @hold Keycode::Kp8 => |pressed| Action::EmulateKey2State(0x08, pressed),
+            @hold Keycode::Kp9 => |pressed| Action::EmulateKey2State(0x09, pressed),
+            @hold Keycode::KpDivide => |pressed| Action::EmulateKey2State(0x0A, pressed),
+            @hold Keycode::KpMultiply => |pressed| Action::EmulateKey2State(0x0B, pressed),
+            @hold Keycode::KpMinus => |pressed| Action::EmulateKey2State(0x0C, pressed),
+            @hold Keycode::KpPlus => |pressed| Action::EmulateKey2State(0x0D, pressed),
+            @hold Keycode::KpEnter => |pressed| Action::EmulateKey2State(0x0E, pressed),
+            @hold Keycode::KpPeriod => |pressed| Action::EmulateKey2State(0x0F, pressed),
+        );
+
+        match self {
+            Keymap::Chip8 | Keymap::Debugger { paused: false } => action,
+            Keymap::Paused | Keymap::Debugger { paused: true } | Keymap::Chat => {
+                action.filter(|action| matches!(action, Action::EmulateKey2State(_, false)))
+            }
+            Keymap::Menu => None,
+        }
+    }
+}
+
+/// The CHIP-8 keypad
+fn translate_keypad(event: &Event) -> Option<Action> {
+    map_keys!(event,
+        @emulate Keycode::Num1 => 0x01,
+        @emulate Keycode::Num2 => 0x02,
+        @emulate Keycode::Num3 => 0x03,
+        @emulate Keycode::Num4 => 0x0C,
+        @emulate Keycode::Q => 0x04,
+        @emulate Keycode::W => 0x05,
+        @emulate Keycode::E => 0x06,
+        @emulate Keycode::R => 0x0D,
+        @emulate Keycode::A => 0x07,
+        @emulate Keycode::S => 0x08,
+        @emulate Keycode::D => 0x09,
+        @emulate Keycode::F => 0x0E,
+        @emulate Keycode::Z => 0x0A,
+        @emulate Keycode::X => 0x00,
+        @emulate Keycode::C => 0x0B,
+        @emulate Keycode::V => 0x0F,
+    )
+}
+
+/// Everything that controls the app itself, running or not
+fn translate_controls(event: &Event) -> Option<Action> {
+    map_keys!(event,
+        Keycode::Space | Keycode::P => Action::TogglePause,
+        Keycode::Period => Action::FrameAdvance,
+        @hold Keycode::Backquote => Action::FastForward,
+        Keycode::Backspace => Action::Reset,
+        Keycode::Tab => Action::ToggleKeypad,
+        Keycode::Backslash => Action::SwapPlayers,
+        Keycode::PageUp => Action::NetplayDelayUp,
+        Keycode::PageDown => Action::NetplayDelayDown,
+        Keycode::Return => Action::OpenChat,
+        @ctrl Keycode::O => Action::OpenSwitcher,
+        Keycode::Equals | Keycode::KpPlus => Action::ScaleUp,
+        Keycode::Minus | Keycode::KpMinus => Action::ScaleDown,
+        Keycode::F5 => Action::CycleWaveform,
+        Keycode::F6 => Action::PitchDown,
+        Keycode::F7 => Action::PitchUp,
+        Keycode::F8 => Action::VolumeDown,
+        Keycode::F9 => Action::VolumeUp,
+        Keycode::M => Action::ToggleMute,
+        Keycode::F1 => Action::ToggleDebugger,
+        Keycode::F2 => Action::RetryQuirks,
+        Keycode::F3 => Action::TogglePerf,
+        Keycode::F4 => Action::ToggleRemap,
+        Keycode::F10 => Action::ToggleCrt,
+        Keycode::F11 => Action::ToggleCapture,
+        Keycode::F12 => Action::ExportTrace,
+        Keycode::Escape => Action::Quit,
+    )
+}
+
+/// The arrows and space, pressing the keys chosen by `remap`; anything
+/// the remap does not use keeps its usual action
+pub fn translate_remap(remap: &Remap, event: &Event) -> Option<Action> {
+    let (keycode, pressed) = match *event {
+        Event::KeyDown {
+            keycode: Some(keycode),
+            ..
+        } => (keycode, true),
+        Event::KeyUp {
+            keycode: Some(keycode),
+            ..
+        } => (keycode, false),
+        _ => return None,
+    };
+
+    let button = match keycode {
+        Keycode::Up => Button::Up,
+        Keycode::Down => Button::Down,
+        Keycode::Left => Button::Left,
+        Keycode::Right => Button::Right,
+        Keycode::Space => Button::Fire,
+        _ => return None,
+    };
+
+    remap
+        .key(button)
+        .map(|key| Action::EmulateKeyState(key, pressed))
+}
+
+/// If `keycode` controls the app itself (pause, reset, quit and so on)
+pub fn is_control(keycode: Keycode) -> bool {
+    let event = Event::KeyDown {
+        timestamp: 0,
+        window_id: 0,
+        keycode: Some(keycode),
+        scancode: None,
+        keymod: Mod::NOMOD,
+        repeat: false,
+    };
+    translate_controls(&event).is_some()
+}
+
+/// Keyboard keys bound to CHIP-8 keys by the rom, which win over the
+/// usual ones, but never over the app controls
+pub fn translate_bindings(bindings: &[(Keycode, usize)], event: &Event) -> Option<Action> {
+    if translate_controls(event).is_some() {
+        return None;
+    }
+
+    let (keycode, pressed) = match *event {
+        Event::KeyDown {
+            keycode: Some(keycode),
+            ..
+        } => (keycode, true),
+        Event::KeyUp {
+            keycode: Some(keycode),
+            ..
+        } => (keycode, false),
+        _ => return None,
+    };
+
+    bindings
+        .iter()
+        .find(|&&(bound, _)| bound == keycode)
+        .map(|&(_, key)| Action::EmulateKeyState(key, pressed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(keycode: Keycode, down: bool) -> Event {
+        if down {
+            Event::KeyDown {
+                timestamp: 0,
+                window_id: 0,
+                keycode: Some(keycode),
+                scancode: None,
+                keymod: Mod::NOMOD,
+                repeat: false,
+            }
+        } else {
+            Event::KeyUp {
+                timestamp: 0,
+                window_id: 0,
+                keycode: Some(keycode),
+                scancode: None,
+                keymod: Mod::NOMOD,
+                repeat: false,
+            }
+        }
+    }
+
+    #[test]
+    fn test_paused_keymap() {
+        let pressed = |keymap: Keymap| keymap.translate_action(&key(Keycode::W, true));
+        let released = |keymap: Keymap| keymap.translate_action(&key(Keycode::W, false));
+
+        assert!(matches!(
+            pressed(Keymap::Chip8),
+            Some(Action::EmulateKeyState(5, true))
+        ));
+        assert!(pressed(Keymap::Paused).is_none());
+        assert!(pressed(Keymap::Menu).is_none());
+        assert!(pressed(Keymap::Debugger { paused: true }).is_none());
+
+        // letting go always gets to the game
+        assert!(matches!(
+            released(Keymap::Paused),
+            Some(Action::EmulateKeyState(5, false))
+        ));
+
+        // the controls work either way
+        let pause = key(Keycode::P, true);
+        assert!(matches!(
+            Keymap::Paused.translate_action(&pause),
+            Some(Action::TogglePause)
+        ));
+        assert!(matches!(
+            Keymap::Debugger { paused: false }.translate_action(&key(Keycode::Up, true)),
+            Some(Action::DebugUp)
+        ));
+    }
+
+    #[test]
+    fn test_chat_keymap() {
+        let chat = |event: &Event| Keymap::Chat.translate_action(event);
+
+        // typing does not play, nor mute the sound
+        assert!(chat(&key(Keycode::W, true)).is_none());
+        assert!(chat(&key(Keycode::M, true)).is_none());
+        assert!(matches!(
+            chat(&key(Keycode::W, false)),
+            Some(Action::EmulateKeyState(5, false))
+        ));
+
+        let text = Event::TextInput {
+            timestamp: 0,
+            window_id: 0,
+            text: "w".to_owned(),
+        };
+        assert!(matches!(chat(&text), Some(Action::ChatText(text)) if text == "w"));
+        assert!(matches!(
+            chat(&key(Keycode::Return, true)),
+            Some(Action::ChatSend)
+        ));
+        assert!(matches!(
+            Keymap::Chip8.translate_action(&key(Keycode::Return, true)),
+            Some(Action::OpenChat)
+        ));
+    }
+
+    #[test]
+    fn test_keypad2() {
+        assert!(matches!(
+            Keymap::Chip8.translate_keypad2(&key(Keycode::KpPlus, true)),
+            Some(Action::EmulateKey2State(0xD, true))
+        ));
+        assert!(Keymap::Paused
+            .translate_keypad2(&key(Keycode::Kp5, true))
+            .is_none());
+        assert!(matches!(
+            Keymap::Paused.translate_keypad2(&key(Keycode::Kp5, false)),
+            Some(Action::EmulateKey2State(5, false))
+        ));
+        assert!(Keymap::Menu
+            .translate_keypad2(&key(Keycode::KpEnter, true))
+            .is_none());
+    }
+
+    #[test]
+    fn test_bindings() {
+        let bindings = [(Keycode::Up, 0x2), (Keycode::W, 0x8)];
+        assert!(matches!(
+            translate_bindings(&bindings, &key(Keycode::W, true)),
+            Some(Action::EmulateKeyState(0x8, true))
+        ));
+        assert!(translate_bindings(&bindings, &key(Keycode::S, true)).is_none());
+
+        // a rom can not take the controls away
+        assert!(is_control(Keycode::Escape));
+        assert!(is_control(Keycode::F1));
+        assert!(!is_control(Keycode::W));
+        let bindings = [(Keycode::Escape, 0x1), (Keycode::O, 0x2)];
+        assert!(translate_bindings(&bindings, &key(Keycode::Escape, true)).is_none());
+        let switcher = Event::KeyDown {
+            timestamp: 0,
+            window_id: 0,
+            keycode: Some(Keycode::O),
+            scancode: None,
+            keymod: Mod::LCTRLMOD,
+            repeat: false,
+        };
+        assert!(translate_bindings(&bindings, &switcher).is_none());
+        assert!(matches!(
+            translate_bindings(&bindings, &key(Keycode::O, true)),
+            Some(Action::EmulateKeyState(0x2, true))
+        ));
+    }
 }
@@ -0,0 +1,33 @@
+//! The CHIP-8 core used by rc8, with no dependency on SDL or any other
+//! frontend. New frontends implement the traits in [`frontend`].
+//!
+//! ```
+//! use rc8::{emulator::Emulator, timing::run_frame};
+//!
+//! let rom = [
+//!     0x60, 0x05, // 0x200: V0 = 5
+//!     0x12, 0x02, // 0x202: jump to 0x202
+//! ];
+//!
+//! let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+//! run_frame(&mut emu).unwrap();
+//! assert_eq!(emu.V[0], 5);
+//! ```
+pub mod asm;
+pub mod batch;
+pub mod cdp1802;
+pub mod chip8x;
+pub mod clock;
+pub mod disasm;
+pub mod display;
+pub mod emulator;
+pub mod env;
+pub mod frontend;
+pub mod memory;
+pub mod opcode;
+pub mod rng;
+pub mod rsp;
+pub mod snapshot;
+pub mod timing;
+#[cfg(feature = "wasm")]
+pub mod wasm;
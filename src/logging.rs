@@ -0,0 +1,49 @@
+//! Diagnostic logging, through `tracing`. Warnings and errors are always
+//! shown; each `-v` adds a more detailed level.
+use std::{fs::File, path::Path, sync::Mutex};
+
+use anyhow::Context;
+use tracing::Level;
+
+/// The most detailed level shown for the number of `-v` given
+fn level(verbose: u8) -> Level {
+    match verbose {
+        0 => Level::WARN,
+        1 => Level::INFO,
+        2 => Level::DEBUG,
+        _ => Level::TRACE,
+    }
+}
+
+/// Send the log to stderr, or to `file` (replacing it) if given
+pub fn init(verbose: u8, file: Option<&Path>) -> Result<(), anyhow::Error> {
+    let builder = tracing_subscriber::fmt()
+        .with_max_level(level(verbose))
+        .with_target(false);
+
+    match file {
+        Some(path) => {
+            let file = File::create(path)
+                .with_context(|| format!("error creating log file: {}", path.display()))?;
+            builder
+                .with_ansi(false)
+                .with_writer(Mutex::new(file))
+                .init();
+        }
+        None => builder.with_writer(std::io::stderr).without_time().init(),
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_level() {
+        assert_eq!(level(0), Level::WARN);
+        assert_eq!(level(2), Level::DEBUG);
+        assert_eq!(level(5), Level::TRACE);
+    }
+}
@@ -1,43 +1,456 @@
 use anyhow::Context;
-use clap::{ArgGroup, CommandFactory, ErrorKind, Parser};
+use clap::{ArgGroup, CommandFactory, ErrorKind, Parser, Subcommand, ValueEnum};
 
 mod app;
+mod audit;
+mod batchrun;
 mod beep;
-mod emulator;
+mod bench;
+mod capture;
+mod chat;
+mod check;
+mod condition;
+mod crt;
+mod debugger;
+mod diagnose;
+mod filter;
+mod flags;
+mod gdb;
+mod info;
 mod keymap;
+mod logging;
+mod metadata;
+#[cfg(feature = "dev")]
+mod minimize;
+mod mixer;
+mod mousekeys;
+mod netplay;
+mod osd;
+mod palette;
+mod perf;
+mod players;
+mod playground;
+mod portability;
+mod profile;
+mod recent;
+mod record;
+mod remap;
+mod romdb;
+mod selftest;
+mod shape;
+mod source;
+mod stream;
+#[cfg(feature = "dev")]
+mod testroms;
+mod trace;
+mod tui;
+mod turbo;
+#[cfg(feature = "net")]
+mod update;
+mod wav;
+mod worker;
 
-use app::{Options, PIXEL_SIZE};
-use emulator::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use rc8::{
+    asm, batch, chip8x, clock, disasm, display, emulator, frontend, opcode, rng, rsp, snapshot,
+    timing,
+};
+
+use app::{Options, Scaling, MAX_SCALE, MIN_SCALE, PIXEL_SIZE};
+use beep::{Tone, Waveform, MAX_PITCH, MIN_PITCH};
+use capture::CaptureFormat;
+use emulator::{IndexMode, StackMode, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use filter::Filter;
+use netplay::NetplayMode;
+use opcode::Variant;
+use palette::Palette;
+use rng::RngKind;
+use shape::PixelShape;
 
 const MIN_SCREEN_WIDTH: u32 = (DISPLAY_WIDTH * PIXEL_SIZE) as u32;
 const MIN_SCREEN_HEIGHT: u32 = (DISPLAY_HEIGHT * PIXEL_SIZE) as u32;
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
+#[clap(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
 #[clap(group(
     ArgGroup::new("ssize")
-        .args(&["window-size", "fullscreen"])
+        .args(&["window-size", "scale", "fullscreen"])
 ))]
 struct Cli {
-    /// ROM file to load
-    #[clap(value_parser)]
-    filename: String,
+    #[clap(subcommand)]
+    command: Option<Command>,
+
+    /// ROM file to load; `-` reads it from stdin, and http(s) URLs are
+    /// downloaded (with the net feature)
+    #[clap(value_parser, required = true)]
+    filename: Option<String>,
 
     /// Size of the window (WxH)
     #[clap(short, long)]
     window_size: Option<String>,
 
+    /// Size of the window, as a multiple of 64x32
+    #[clap(short, long)]
+    scale: Option<u32>,
+
     /// Enable fullscreen
     #[clap(short, long)]
     fullscreen: bool,
 
-    /// Set the background color
+    /// How the screen is fit on the window
+    #[clap(long, value_enum, default_value_t = Scaling::Integer)]
+    scaling: Scaling,
+
+    /// Color of the bars around the screen, when it does not fill the window
+    #[clap(long, default_value = "#000000")]
+    letterbox: String,
+
+    /// Color palette (mono, octo, gameboy, c64, amber or custom:BG,FG[,PLANE2,BOTH])
+    #[clap(long, value_parser)]
+    palette: Option<Palette>,
+
+    /// Set the background color, overriding the palette
     #[clap(long)]
     bg: Option<String>,
 
-    /// Set the foreground color
+    /// Set the foreground color, overriding the palette
     #[clap(long)]
     fg: Option<String>,
+
+    /// Set the colors of the XO-CHIP plane combinations (none, first,
+    /// second and both planes), overriding the palette
+    #[clap(long, value_name = "C0,C1,C2,C3")]
+    plane_colors: Option<String>,
+
+    /// Wrap sprites around the screen edges, instead of clipping
+    #[clap(long)]
+    wrap: bool,
+
+    /// Run 0NNN machine code subroutines on an emulated COSMAC VIP cpu
+    #[clap(long)]
+    vip_machine_code: bool,
+
+    /// Make FX0A take a key as soon as it is pressed, instead of released
+    #[clap(long)]
+    wait_key_press: bool,
+
+    /// Scroll half as far in low resolution, like SCHIP 1.1
+    #[clap(long)]
+    half_scroll: bool,
+
+    /// Let SCHIP and XO-CHIP roms use the memory up to 0xFFF
+    #[clap(long)]
+    full_memory: bool,
+
+    /// Run roms that don't fit in memory anyway, leaving their end out
+    #[clap(long)]
+    force: bool,
+
+    /// Sound the buzzer when ST is set to 1, which the COSMAC VIP skips
+    #[clap(long)]
+    short_beeps: bool,
+
+    /// Use the font and quirks of the Dream 6800 CHIPOS interpreter
+    #[clap(long)]
+    dream6800: bool,
+
+    /// Do not look up the rom on the database of known roms for its settings
+    #[clap(long)]
+    no_db: bool,
+
+    /// Do not keep the RPL user flags (FX75) of each rom between runs
+    #[clap(long)]
+    no_persist_flags: bool,
+
+    /// Look for a newer rc8 release on startup
+    #[cfg(feature = "net")]
+    #[clap(long)]
+    check_updates: bool,
+
+    /// Random number generator used by CXNN (wyrand or lfsr) [default: wyrand]
+    #[clap(long, value_parser)]
+    rng: Option<RngKind>,
+
+    /// How I wraps past the end of memory (wrap16, wrap12 or checked) [default: wrap16]
+    #[clap(long, value_parser)]
+    index_mode: Option<IndexMode>,
+
+    /// How deep subroutine calls go (unbounded, 12 or 16) [default: unbounded]
+    #[clap(long, value_parser)]
+    stack_depth: Option<StackMode>,
+
+    /// Instruction set of the rom (chip8, chip8x, schip or xochip) [default: chip8]
+    #[clap(long, value_parser)]
+    variant: Option<Variant>,
+
+    /// Instructions per second [default: 540, or the one in the rom metadata]
+    #[clap(long, value_parser = clap::value_parser!(u32).range(1..))]
+    speed: Option<u32>,
+
+    /// Give each instruction as much time as on the COSMAC VIP, instead of a fixed speed
+    #[clap(long, conflicts_with = "speed")]
+    vip_timing: bool,
+
+    /// Draw at most about this many sprite rows per frame, waiting for the next one after that
+    #[clap(long, value_name = "ROWS", requires = "vip-timing")]
+    draw_budget: Option<u32>,
+
+    /// Filter used to emulate different displays
+    #[clap(long, value_enum, default_value_t = Filter::None)]
+    filter: Filter,
+
+    /// How each pixel is drawn
+    #[clap(long, value_enum, default_value_t = PixelShape::Square)]
+    pixel_shape: PixelShape,
+
+    /// Draw the screen like an old CRT, with scanlines and a curved picture (F10 toggles)
+    #[clap(long)]
+    crt: bool,
+
+    /// Shape of the buzzer sound
+    #[clap(long, value_enum, default_value_t = Waveform::Square)]
+    waveform: Waveform,
+
+    /// Frequency of the buzzer, in Hz
+    #[clap(long, default_value_t = 120.0)]
+    pitch: f32,
+
+    /// Volume of the buzzer, from 0 to 100
+    #[clap(long, default_value_t = 40, value_parser = clap::value_parser!(u8).range(0..=100))]
+    volume: u8,
+
+    /// Volume of all sounds together, from 0 to 100
+    #[clap(long, default_value_t = 100, value_parser = clap::value_parser!(u8).range(0..=100))]
+    master_volume: u8,
+
+    /// Play the click of the COSMAC VIP keypad when a key is pressed
+    #[clap(long)]
+    keypad_click: bool,
+
+    /// CHIP-8 keys that are pressed over and over while held, as hex digits (like 5,6)
+    #[clap(long, value_name = "KEYS", value_delimiter = ',', value_parser = turbo::parse_key)]
+    turbo: Vec<usize>,
+
+    /// Mouse buttons and wheel that press CHIP-8 keys (like left=5,right=6,wheel-up=2); shows the keypad overlay, which is clicked with any unbound left button
+    #[clap(long, value_name = "BINDINGS", value_delimiter = ',', value_parser = mousekeys::parse_binding)]
+    mouse_keys: Vec<(mousekeys::MouseInput, usize)>,
+
+    /// Keys of player 1 and player 2, in the same order, swapped with the backslash key
+    #[clap(long, value_name = "KEYS", default_value = "1,4:C,D", value_parser)]
+    players: players::Players,
+
+    /// How many times per second the turbo keys are pressed
+    #[clap(long, value_name = "HZ", default_value_t = turbo::DEFAULT_RATE, value_parser = clap::value_parser!(u32).range(1..=30))]
+    turbo_rate: u32,
+
+    /// Size of the audio buffer, in samples (a power of 2); smaller ones play the buzzer sooner, but may crackle [default: picked by SDL]
+    #[clap(long, value_name = "SAMPLES", value_parser = mixer::parse_buffer_size)]
+    audio_buffer: Option<u16>,
+
+    /// Audio output device, as listed by `rc8 audio-devices` [default: the system one]
+    #[clap(long, value_name = "NAME")]
+    audio_device: Option<String>,
+
+    /// Save the screen and the held keys of every frame into a NumPy .npz file
+    #[clap(long, value_name = "FILE")]
+    record: Option<String>,
+
+    /// Save everything the speaker plays into a WAV file
+    #[clap(long, value_name = "FILE")]
+    record_audio: Option<String>,
+
+    /// Format of the videos recorded with F11 (needs ffmpeg)
+    #[clap(long, value_enum, default_value_t = CaptureFormat::Mp4)]
+    capture_format: CaptureFormat,
+
+    /// Let gdb (or an IDE) attach to the emulator on this local port
+    #[clap(long, value_name = "PORT")]
+    gdb: Option<u16>,
+
+    /// Run a debugger on the terminal, next to the window
+    #[clap(long)]
+    debug_tui: bool,
+
+    /// Count how often each instruction runs, and save a report to FILE on exit
+    #[clap(long, value_name = "FILE")]
+    profile: Option<String>,
+
+    /// Play with someone else over the network: host:PORT waits for them,
+    /// ADDRESS:PORT joins their game, watch:ADDRESS:PORT only watches it
+    #[clap(
+        long,
+        value_name = "host:PORT|ADDRESS:PORT|watch:ADDRESS:PORT",
+        value_parser
+    )]
+    netplay: Option<NetplayMode>,
+
+    /// On netplay, guess the keys of the other player instead of waiting
+    /// for them, and go back a few frames when the guess was wrong
+    #[clap(long, requires = "netplay", conflicts_with = "record")]
+    rollback: bool,
+
+    /// Serve the screen to web browsers on this port, which can also play
+    #[clap(long, value_name = "PORT")]
+    stream: Option<u16>,
+
+    /// Log what the emulator is doing; repeat for more detail (-vv)
+    #[clap(short, long, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Write the log to a file instead of the terminal
+    #[clap(long, global = true, value_name = "FILE")]
+    log_file: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run the bundled test roms and check the emulator timing
+    Selftest,
+
+    /// List the audio output devices, for --audio-device
+    AudioDevices,
+
+    /// Run a rom several times with the same inputs, looking for nondeterminism
+    Audit {
+        /// ROM file to audit
+        #[clap(value_parser)]
+        filename: String,
+
+        /// How many frames to run
+        #[clap(long, default_value_t = 600)]
+        frames: usize,
+
+        /// Seed for the random number generator and the scripted inputs
+        #[clap(long, default_value_t = 0)]
+        seed: u64,
+    },
+
+    /// Run many roms, or many seeds of each, in parallel and headless
+    BatchRun {
+        /// ROM files to run
+        #[clap(value_parser, required = true)]
+        filenames: Vec<String>,
+
+        /// How many seeds to run for each rom, starting from 0
+        #[clap(long, default_value_t = 1)]
+        seeds: u64,
+
+        /// How many frames to run
+        #[clap(long, default_value_t = 600)]
+        frames: usize,
+
+        /// Worker threads, or 0 for one per cpu
+        #[clap(short, long, default_value_t = 0)]
+        jobs: usize,
+    },
+
+    /// Look for problems on a rom, without running it
+    Check {
+        /// ROM file to check
+        #[clap(value_parser)]
+        filename: String,
+
+        /// Also count the instructions that behave differently between interpreters
+        #[clap(long)]
+        portability: bool,
+
+        /// How many frames to run, when counting executed instructions
+        #[clap(long, default_value_t = 600)]
+        frames: usize,
+    },
+
+    /// Run roms headless, measuring how fast the interpreter is
+    Bench {
+        /// ROM file to run, or a directory with the roms to run
+        #[clap(value_parser)]
+        filename: String,
+
+        /// How many instructions to run (`_` can separate the digits)
+        #[clap(long, default_value = "10_000_000", value_parser = bench::parse_count)]
+        cycles: u64,
+
+        /// Quirks profiles to run each rom with, by instruction set
+        #[clap(long, value_parser, value_delimiter = ',', default_value = "chip8")]
+        variants: Vec<Variant>,
+
+        /// How to print the results
+        #[clap(long, value_enum, default_value_t = bench::Format::Text)]
+        format: bench::Format,
+    },
+
+    /// Show the rom metadata and a map of its memory usage
+    Info {
+        /// ROM file to inspect
+        #[clap(value_parser)]
+        filename: String,
+
+        /// How many frames to run, looking for code and sprites
+        #[clap(long, default_value_t = 600)]
+        frames: usize,
+    },
+
+    /// Disassemble a rom, following its control flow
+    Disasm {
+        /// ROM file to disassemble
+        #[clap(value_parser)]
+        filename: String,
+
+        /// Output format
+        #[clap(long, value_enum, default_value_t = DisasmFormat::Text)]
+        format: DisasmFormat,
+    },
+
+    /// Serve a page to step a rom in the browser and watch its registers
+    Playground {
+        /// ROM file to open on the page; others can be picked there
+        #[clap(value_parser)]
+        filename: Option<String>,
+
+        /// Local port to serve the page on
+        #[clap(long, default_value_t = 8000)]
+        port: u16,
+
+        /// The core built for the browser [default: rc8.wasm next to the executable]
+        #[clap(long, value_name = "FILE")]
+        wasm: Option<String>,
+    },
+
+    /// Assemble Octo source code into a rom
+    Asm {
+        /// Source file
+        #[clap(value_parser)]
+        input: String,
+
+        /// Where to save the rom [default: INPUT with a .ch8 extension]
+        #[clap(short, long)]
+        output: Option<String>,
+    },
+
+    /// Shrink a crashing rom to the shortest sequence that crashes the same way
+    #[cfg(feature = "dev")]
+    Minimize {
+        /// Crashing ROM file
+        #[clap(value_parser)]
+        filename: String,
+
+        /// Where to save the minimized rom [default: FILENAME.min]
+        #[clap(short, long)]
+        output: Option<String>,
+
+        /// How many frames to run, looking for the crash
+        #[clap(long, default_value_t = 600)]
+        frames: usize,
+    },
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum DisasmFormat {
+    /// Octo-like listing
+    Text,
+    /// Basic blocks and edges
+    Json,
+    /// Control-flow graph for Graphviz
+    Dot,
 }
 
 impl TryFrom<&Cli> for Options {
@@ -45,8 +458,8 @@ impl TryFrom<&Cli> for Options {
 
     fn try_from(cli: &Cli) -> Result<Self, Self::Error> {
         // screen size
-        let (width, height) = match &cli.window_size {
-            Some(spec) => {
+        let (width, height) = match (&cli.window_size, cli.scale) {
+            (Some(spec), _) => {
                 let mut splitted: Vec<&str> = spec.split('x').collect();
                 if splitted.len() != 2 {
                     return Err((
@@ -60,33 +473,93 @@ impl TryFrom<&Cli> for Options {
 
                 (width, height)
             }
-            None => (MIN_SCREEN_WIDTH, MIN_SCREEN_HEIGHT),
+            (None, Some(scale)) => validate_scale(scale)?,
+            (None, None) => (MIN_SCREEN_WIDTH, MIN_SCREEN_HEIGHT),
         };
 
-        // colors
-        let (bgcolor, fgcolor) = match (&cli.bg, &cli.fg) {
-            (Some(bgcolor), Some(fgcolor)) => {
-                let bgcolor = validate_rgb(bgcolor)?;
-                let fgcolor = validate_rgb(fgcolor)?;
-                (bgcolor, fgcolor)
-            }
-            (Some(bgcolor), None) => {
-                let bgcolor = validate_rgb(bgcolor)?;
-                (bgcolor, 0xffffff00 - bgcolor)
-            }
-            (None, Some(fgcolor)) => {
-                let fgcolor = validate_rgb(fgcolor)?;
-                (0xffffff00 - fgcolor, fgcolor)
-            }
-            (None, None) => (0x00000000, 0xffffff00),
+        // colors: an explicit palette wins over the filter one,
+        // and single colors can still be replaced
+        let mut palette = cli
+            .palette
+            .or_else(|| cli.filter.palette())
+            .unwrap_or_default();
+
+        if let Some(colors) = &cli.plane_colors {
+            palette.colors = validate_plane_colors(colors)?;
+        }
+        if let Some(bgcolor) = &cli.bg {
+            palette.colors[0] = validate_rgb(bgcolor)?;
+        }
+        if let Some(fgcolor) = &cli.fg {
+            palette.colors[1] = validate_rgb(fgcolor)?;
+        }
+
+        // sound
+        if !(MIN_PITCH..=MAX_PITCH).contains(&cli.pitch) {
+            return Err((
+                ErrorKind::ValueValidation,
+                format!("PITCH must be between {} and {}", MIN_PITCH, MAX_PITCH),
+            ));
+        }
+
+        let tone = Tone {
+            waveform: cli.waveform,
+            pitch: cli.pitch,
         };
 
         Ok(Options {
             width,
             height,
             fullscreen: cli.fullscreen,
-            bgcolor,
-            fgcolor,
+            scaling: cli.scaling,
+            letterbox: validate_rgb(&cli.letterbox)?,
+            palette,
+            custom_palette: cli.palette.is_some()
+                || cli.filter.palette().is_some()
+                || cli.bg.is_some()
+                || cli.fg.is_some()
+                || cli.plane_colors.is_some(),
+            tone,
+            volume: cli.volume,
+            master_volume: cli.master_volume,
+            audio_buffer: cli.audio_buffer,
+            audio_device: cli.audio_device.clone(),
+            filter: cli.filter,
+            crt: cli.crt,
+            pixel_shape: cli.pixel_shape,
+            wrap: cli.wrap,
+            vip_machine_code: cli.vip_machine_code,
+            wait_key_press: cli.wait_key_press,
+            dream6800: cli.dream6800,
+            half_scroll: cli.half_scroll,
+            full_memory: cli.full_memory,
+            force: cli.force,
+            short_beeps: cli.short_beeps,
+            no_db: cli.no_db,
+            persist_flags: !cli.no_persist_flags,
+            gdb: cli.gdb,
+            debug_tui: cli.debug_tui,
+            profile: cli.profile.as_ref().map(std::path::PathBuf::from),
+            netplay: cli.netplay.clone(),
+            rollback: cli.rollback,
+            stream: cli.stream,
+            #[cfg(feature = "net")]
+            check_updates: cli.check_updates,
+            vip_timing: cli.vip_timing,
+            draw_budget: cli.draw_budget,
+            keypad_click: cli.keypad_click,
+            record: cli.record.as_ref().map(std::path::PathBuf::from),
+            record_audio: cli.record_audio.as_ref().map(std::path::PathBuf::from),
+            capture_format: cli.capture_format,
+            turbo: cli.turbo.clone(),
+            turbo_rate: cli.turbo_rate,
+            mouse_keys: cli.mouse_keys.clone(),
+            players: cli.players.clone(),
+            rng: cli.rng,
+            variant: cli.variant,
+            index_mode: cli.index_mode,
+            stack_depth: cli.stack_depth,
+            speed: cli.speed,
         })
     }
 }
@@ -94,6 +567,112 @@ impl TryFrom<&Cli> for Options {
 fn main() -> Result<(), anyhow::Error> {
     // parse command-line arguments
     let cli = Cli::parse();
+    logging::init(cli.verbose, cli.log_file.as_ref().map(std::path::Path::new))?;
+
+    // subcommands do not start the emulator window
+    if let Some(command) = &cli.command {
+        return match command {
+            Command::Selftest => selftest::run(),
+            Command::AudioDevices => app::list_audio_devices(),
+            Command::Audit {
+                filename,
+                frames,
+                seed,
+            } => {
+                let rom = std::fs::read(filename)
+                    .with_context(|| format!("error opening rom file: {}", filename))?;
+                audit::audit(&rom, *frames, *seed)
+            }
+            Command::BatchRun {
+                filenames,
+                seeds,
+                frames,
+                jobs,
+            } => batchrun::batch_run(filenames, *seeds, *frames, *jobs),
+            Command::Check {
+                filename,
+                portability,
+                frames,
+            } => {
+                let rom = std::fs::read(filename)
+                    .with_context(|| format!("error opening rom file: {}", filename))?;
+
+                let result = check::check(&rom);
+                if *portability {
+                    portability::report(&rom, *frames);
+                }
+                result
+            }
+            Command::Bench {
+                filename,
+                cycles,
+                variants,
+                format,
+            } => bench::bench(std::path::Path::new(filename), variants, *cycles, *format),
+            Command::Info { filename, frames } => {
+                let rom = std::fs::read(filename)
+                    .with_context(|| format!("error opening rom file: {}", filename))?;
+                info::info(std::path::Path::new(filename), &rom, *frames)
+            }
+            Command::Disasm { filename, format } => {
+                let rom = std::fs::read(filename)
+                    .with_context(|| format!("error opening rom file: {}", filename))?;
+                let disassembly = disasm::disassemble(&rom);
+
+                let output = match format {
+                    DisasmFormat::Text => disassembly.to_text(),
+                    DisasmFormat::Json => disassembly.to_json(),
+                    DisasmFormat::Dot => disassembly.to_dot(),
+                };
+                print!("{}", output);
+                Ok(())
+            }
+            Command::Playground {
+                filename,
+                port,
+                wasm,
+            } => playground::serve(
+                *port,
+                wasm.as_deref().map(std::path::Path::new),
+                filename.as_deref().map(std::path::Path::new),
+            ),
+            Command::Asm { input, output } => {
+                let source = std::fs::read_to_string(input)
+                    .with_context(|| format!("error opening source file: {}", input))?;
+                let rom = asm::assemble(&source)
+                    .with_context(|| format!("error assembling {}", input))?;
+
+                let output = output.clone().unwrap_or_else(|| {
+                    std::path::Path::new(input)
+                        .with_extension("ch8")
+                        .to_string_lossy()
+                        .into_owned()
+                });
+                std::fs::write(&output, &rom)
+                    .with_context(|| format!("error saving rom file: {}", output))?;
+                println!("assembled {} bytes to {}", rom.len(), output);
+                Ok(())
+            }
+            #[cfg(feature = "dev")]
+            Command::Minimize {
+                filename,
+                output,
+                frames,
+            } => {
+                let rom = std::fs::read(filename)
+                    .with_context(|| format!("error opening rom file: {}", filename))?;
+                let minimized = minimize::minimize(&rom, *frames)?;
+
+                let output = output
+                    .clone()
+                    .unwrap_or_else(|| format!("{}.min", filename));
+                std::fs::write(&output, minimized)
+                    .with_context(|| format!("error saving rom file: {}", output))?;
+                println!("saved to {}", output);
+                Ok(())
+            }
+        };
+    }
 
     // convert to app options
     let options = match Options::try_from(&cli) {
@@ -103,16 +682,29 @@ fn main() -> Result<(), anyhow::Error> {
         }
     };
 
-    // load the rom and build the emulator
-    let rom = std::fs::File::open(&cli.filename)
-        .with_context(|| format!("error opening rom file: {}", &cli.filename))?;
+    // load the rom and its metadata
+    let filename = cli.filename.as_deref().expect("filename is required");
+    let source = source::RomSource::parse(filename);
+    let (emu, metadata) = app::load_rom(&source, &options)?;
 
-    // load the rom
-    let emu = emulator::Emulator::load_rom(rom).context("error loading rom")?;
+    // remember the rom for the quick switcher
+    let mut recent = recent::RecentRoms::load();
+    if let Some(path) = source.file() {
+        recent.push(path);
+        if let Err(err) = recent.save() {
+            eprintln!("warning: could not save the recent roms list: {}", err);
+        }
+    }
 
-    // run
-    app::run(emu, options)?;
-    Ok(())
+    // run, keeping the last spans around in case something goes wrong
+    let tracer = trace::Tracer::new();
+    let path = source.name();
+    let result = app::run(emu, &path, metadata, options, recent, tracer.clone());
+    if result.is_err() && !tracer.is_empty() {
+        app::export_trace(&tracer);
+    }
+
+    result
 }
 
 fn validate_resolution(input: &str, field: &str, min: u32) -> Result<u32, (ErrorKind, String)> {
@@ -131,26 +723,88 @@ fn validate_resolution(input: &str, field: &str, min: u32) -> Result<u32, (Error
     Ok(value)
 }
 
-fn validate_rgb(input: &str) -> Result<u32, (ErrorKind, String)> {
-    let stripped = input.strip_prefix('#').unwrap_or(input);
-
-    if stripped.len() != 6 {
+fn validate_scale(scale: u32) -> Result<(u32, u32), (ErrorKind, String)> {
+    if scale < MIN_SCALE {
         return Err((
             ErrorKind::Format,
-            format!("wrong color size (expected: 6, got {})", stripped.len()),
+            format!("SCALE must be greater than or equal {}", MIN_SCALE),
+        ));
+    }
+    if scale > MAX_SCALE {
+        return Err((
+            ErrorKind::ValueValidation,
+            format!("SCALE must be less than or equal {}", MAX_SCALE),
         ));
     }
 
-    let value = match u32::from_str_radix(stripped, 16) {
-        Ok(value) => value,
-        Err(err) => {
-            return Err((
-                ErrorKind::Format,
-                format!("error parsing color value: {:?}", err),
-            ))
-        }
-    };
+    Ok((scale * DISPLAY_WIDTH as u32, scale * DISPLAY_HEIGHT as u32))
+}
 
-    let value = value << 8;
-    Ok(value)
+fn validate_rgb(input: &str) -> Result<u32, (ErrorKind, String)> {
+    palette::parse_rgb(input).map_err(|msg| (ErrorKind::Format, msg))
+}
+
+fn validate_plane_colors(input: &str) -> Result<[u32; 4], (ErrorKind, String)> {
+    let colors = palette::parse_colors(input).map_err(|msg| (ErrorKind::Format, msg))?;
+    colors.try_into().map_err(|colors: Vec<_>| {
+        (
+            ErrorKind::ValueValidation,
+            format!("PLANE_COLORS needs 4 colors (got {})", colors.len()),
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cli() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn test_verbose() {
+        let cli = Cli::try_parse_from(["rc8", "-vv", "game.ch8"]).unwrap();
+        assert_eq!(cli.verbose, 2);
+
+        // also after a subcommand
+        let cli = Cli::try_parse_from(["rc8", "check", "game.ch8", "-v"]).unwrap();
+        assert_eq!(cli.verbose, 1);
+    }
+
+    #[test]
+    fn test_scale() {
+        let options = |scale: &str| {
+            let cli = Cli::try_parse_from(["rc8", "--scale", scale, "game.ch8"]).unwrap();
+            Options::try_from(&cli).map(|options| (options.width, options.height))
+        };
+
+        assert_eq!(options("15").unwrap(), (960, 480));
+        assert!(options("9").is_err());
+        assert!(options("100000000").is_err());
+    }
+
+    #[test]
+    fn test_plane_colors() {
+        let cli = Cli::try_parse_from([
+            "rc8",
+            "--plane-colors",
+            "#000000,#ffffff,#ff0000,#0000ff",
+            "--fg",
+            "#00ff00",
+            "game.ch8",
+        ])
+        .unwrap();
+        let options = Options::try_from(&cli).unwrap();
+        assert_eq!(
+            options.palette.colors,
+            [0x00000000, 0x00ff0000, 0xff000000, 0x0000ff00]
+        );
+        assert!(options.custom_palette);
+
+        let cli =
+            Cli::try_parse_from(["rc8", "--plane-colors", "#000000,#ffffff", "game.ch8"]).unwrap();
+        assert!(Options::try_from(&cli).is_err());
+    }
 }
@@ -0,0 +1,97 @@
+//! The 4K of memory of the machine.
+//!
+//! [`Memory`] derefs to a byte slice, so the frontends and the tools can
+//! index and slice it as usual; the interpreter itself goes through the
+//! checked methods, so a malformed rom ends in an error instead of a panic.
+use std::ops::{Deref, DerefMut};
+
+use super::emulator::EmulatorError;
+
+/// How many bytes of memory there are
+pub const MEM_SIZE: usize = 4096;
+
+/// The machine memory
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Memory([u8; MEM_SIZE]);
+
+impl Memory {
+    /// The byte at `addr`
+    pub fn read(&self, addr: usize) -> Result<u8, EmulatorError> {
+        self.0
+            .get(addr)
+            .copied()
+            .ok_or(EmulatorError::OutOfBounds(addr))
+    }
+
+    /// Change the byte at `addr`
+    pub fn write(&mut self, addr: usize, value: u8) -> Result<(), EmulatorError> {
+        let byte = self
+            .0
+            .get_mut(addr)
+            .ok_or(EmulatorError::OutOfBounds(addr))?;
+        *byte = value;
+        Ok(())
+    }
+
+    /// The big-endian word at `addr`; both bytes must be in memory
+    pub fn word(&self, addr: usize) -> Result<u16, EmulatorError> {
+        Ok(u16::from_be_bytes([self.read(addr)?, self.read(addr + 1)?]))
+    }
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Memory([0; MEM_SIZE])
+    }
+}
+
+impl Deref for Memory {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl DerefMut for Memory {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.0
+    }
+}
+
+impl TryFrom<&[u8]> for Memory {
+    type Error = usize;
+
+    /// Copy the whole memory from `bytes`; fails with the length of
+    /// `bytes` if it is not exactly the memory size
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        bytes.try_into().map(Memory).map_err(|_| bytes.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checked() {
+        let mut memory = Memory::default();
+        memory.write(0xFFE, 0x12).unwrap();
+        memory.write(0xFFF, 0x34).unwrap();
+        assert_eq!(memory.read(0xFFE).unwrap(), 0x12);
+        assert_eq!(memory.word(0xFFE).unwrap(), 0x1234);
+
+        assert!(matches!(
+            memory.word(0xFFF),
+            Err(EmulatorError::OutOfBounds(0x1000))
+        ));
+        assert!(matches!(
+            memory.write(0x1000, 0),
+            Err(EmulatorError::OutOfBounds(0x1000))
+        ));
+
+        // everything else still works as a slice
+        assert_eq!(memory[0xFFE..], [0x12, 0x34]);
+        assert!(Memory::try_from(&memory[1..]).is_err());
+    }
+}
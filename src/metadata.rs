@@ -0,0 +1,261 @@
+//! Information about a rom, given by its author on a sidecar `.json` file
+//! (e.g. `game.json`, next to `game.ch8`).
+//!
+//! The keys follow the options exported by Octo, so the settings of a
+//! cartridge can be copied as they are. Keys rc8 does not understand
+//! are ignored. Besides these, `controls` and `keys` tell how the game is
+//! played, which Octo has no options for.
+use std::{collections::BTreeMap, io::ErrorKind, path::Path};
+
+use anyhow::Context;
+use sdl2::keyboard::Keycode;
+use serde::Deserialize;
+
+use super::{
+    emulator::{Font, Quirks},
+    keymap,
+    opcode::Variant,
+    palette::{self, Palette},
+    timing::DEFAULT_SPEED,
+    turbo::parse_key,
+};
+
+/// How many times per second the cpu is run, in Octo's `tickrate`
+const FRAMES_PER_SECOND: u32 = 60;
+
+#[derive(Deserialize, Clone, Default, Debug, PartialEq, Eq)]
+pub struct Metadata {
+    pub title: Option<String>,
+    pub author: Option<String>,
+
+    /// Instructions per frame
+    pub tickrate: Option<u32>,
+
+    /// Clip sprites at the edges of the screen, instead of wrapping them
+    #[serde(rename = "clipQuirks")]
+    pub clip_quirks: Option<bool>,
+
+    /// Octo's name for the hex digits font
+    #[serde(rename = "fontStyle")]
+    pub font_style: Option<String>,
+
+    /// Colors, as `#RRGGBB`
+    #[serde(rename = "backgroundColor")]
+    pub background_color: Option<String>,
+    #[serde(rename = "fillColor")]
+    pub fill_color: Option<String>,
+    #[serde(rename = "fillColor2")]
+    pub fill_color2: Option<String>,
+    #[serde(rename = "blendColor")]
+    pub blend_color: Option<String>,
+
+    /// What the CHIP-8 keys do, like `{"2": "up", "8": "down"}`
+    pub controls: Option<BTreeMap<String, String>>,
+
+    /// Keyboard keys that press CHIP-8 keys instead of their usual one,
+    /// like `{"Up": "2", "Down": "8"}`
+    pub keys: Option<BTreeMap<String, String>>,
+}
+
+impl Metadata {
+    /// Read the sidecar file of the rom at `path`, if there is one
+    pub fn load(path: &Path) -> Result<Self, anyhow::Error> {
+        let sidecar = path.with_extension("json");
+
+        let json = match std::fs::read_to_string(&sidecar) {
+            Ok(json) => json,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(Self::default()),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("error opening metadata: {}", sidecar.display()))
+            }
+        };
+
+        serde_json::from_str(&json)
+            .with_context(|| format!("invalid metadata: {}", sidecar.display()))
+    }
+
+    /// Fill in whatever is missing with the settings in `other`
+    pub fn or(self, other: &Metadata) -> Self {
+        let other = other.clone();
+        Metadata {
+            title: self.title.or(other.title),
+            author: self.author.or(other.author),
+            tickrate: self.tickrate.or(other.tickrate),
+            clip_quirks: self.clip_quirks.or(other.clip_quirks),
+            font_style: self.font_style.or(other.font_style),
+            background_color: self.background_color.or(other.background_color),
+            fill_color: self.fill_color.or(other.fill_color),
+            fill_color2: self.fill_color2.or(other.fill_color2),
+            blend_color: self.blend_color.or(other.blend_color),
+            controls: self.controls.or(other.controls),
+            keys: self.keys.or(other.keys),
+        }
+    }
+
+    /// What each CHIP-8 key does, when the rom tells; anything that is not
+    /// a key is ignored
+    pub fn controls(&self) -> [Option<String>; 16] {
+        let mut controls: [Option<String>; 16] = Default::default();
+        for (key, control) in self.controls.iter().flatten() {
+            if let Ok(key) = parse_key(key) {
+                controls[key] = Some(control.clone());
+            }
+        }
+        controls
+    }
+
+    /// The keyboard keys the rom binds to CHIP-8 keys; unknown ones, and
+    /// the ones that control the app (like Escape), are ignored
+    pub fn bindings(&self) -> Vec<(Keycode, usize)> {
+        self.keys
+            .iter()
+            .flatten()
+            .filter_map(|(name, key)| Some((Keycode::from_name(name)?, parse_key(key).ok()?)))
+            .filter(|&(keycode, _)| !keymap::is_control(keycode))
+            .collect()
+    }
+
+    /// Change the colors the rom asks for; invalid ones are ignored
+    pub fn apply_palette(&self, palette: &mut Palette) {
+        let colors = [
+            &self.background_color,
+            &self.fill_color,
+            &self.fill_color2,
+            &self.blend_color,
+        ];
+        for (color, value) in palette.colors.iter_mut().zip(colors) {
+            if let Some(rgb) = value
+                .as_deref()
+                .and_then(|value| palette::parse_rgb(value).ok())
+            {
+                *color = rgb;
+            }
+        }
+    }
+
+    /// Change the quirks the rom asks for; anything else stays as it is
+    pub fn apply(&self, quirks: &mut Quirks) {
+        if let Some(clip) = self.clip_quirks {
+            quirks.wrap_sprites = !clip;
+        }
+
+        // rc8 only has the VIP digits besides these
+        if self.font_style.as_deref() == Some("dream_6800") {
+            quirks.font = Font::Dream6800;
+        }
+    }
+
+    /// Instructions per second
+    pub fn speed(&self) -> u32 {
+        self.tickrate
+            .map(|tickrate| tickrate * FRAMES_PER_SECOND)
+            .unwrap_or(DEFAULT_SPEED)
+    }
+
+    /// The title of the rom, or its file name when it has none
+    pub fn window_title(&self, rom: &Path, variant: Variant) -> String {
+        let name = match &self.title {
+            Some(title) => title.clone(),
+            None => rom
+                .file_stem()
+                .map_or_else(String::new, |stem| stem.to_string_lossy().into_owned()),
+        };
+        format!("RC8 \u{2014} {} ({})", name, variant)
+    }
+
+    /// Who made what, when known
+    pub fn credits(&self) -> Option<String> {
+        match (&self.title, &self.author) {
+            (Some(title), Some(author)) => Some(format!("{} by {}", title, author)),
+            (Some(title), None) => Some(title.clone()),
+            (None, Some(author)) => Some(format!("By {}", author)),
+            (None, None) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_octo_options() {
+        let json = r##"{
+            "title": "Cave Explorer",
+            "author": "John Earnest",
+            "tickrate": 20,
+            "clipQuirks": false,
+            "shiftQuirks": true,
+            "fontStyle": "dream_6800",
+            "fillColor": "#FFCC00",
+            "fillColor2": "#FF6600",
+            "blendColor": "bad"
+        }"##;
+
+        let metadata: Metadata = serde_json::from_str(json).unwrap();
+        assert_eq!(metadata.speed(), 1200);
+        assert_eq!(
+            metadata.window_title(Path::new("cave.ch8"), Variant::Chip8),
+            "RC8 \u{2014} Cave Explorer (CHIP-8)"
+        );
+        assert_eq!(
+            metadata.credits().as_deref(),
+            Some("Cave Explorer by John Earnest")
+        );
+
+        let mut quirks = Quirks::default();
+        metadata.apply(&mut quirks);
+        assert!(quirks.wrap_sprites);
+        assert_eq!(quirks.font, Font::Dream6800);
+
+        let mut palette = Palette::default();
+        metadata.apply_palette(&mut palette);
+        assert_eq!(
+            palette.colors,
+            [0x00000000, 0xffcc0000, 0xff660000, 0x55555500]
+        );
+    }
+
+    #[test]
+    fn test_controls() {
+        let json = r##"{
+            "controls": { "2": "up", "8": "down", "G": "nothing" }
+        }"##;
+
+        let metadata: Metadata = serde_json::from_str(json).unwrap();
+        let controls = metadata.controls();
+        assert_eq!(controls[0x2].as_deref(), Some("up"));
+        assert_eq!(controls[0x8].as_deref(), Some("down"));
+        assert_eq!(controls.iter().flatten().count(), 2);
+    }
+
+    #[test]
+    fn test_or() {
+        let sidecar = Metadata {
+            title: Some("Mine".to_owned()),
+            ..Metadata::default()
+        };
+        let known = Metadata {
+            title: Some("Known".to_owned()),
+            tickrate: Some(20),
+            ..Metadata::default()
+        };
+
+        let metadata = sidecar.or(&known);
+        assert_eq!(metadata.title.as_deref(), Some("Mine"));
+        assert_eq!(metadata.speed(), 1200);
+    }
+
+    #[test]
+    fn test_missing() {
+        let metadata = Metadata::load(Path::new("roms/ibm-logo.ch8")).unwrap();
+        assert_eq!(metadata, Metadata::default());
+        assert_eq!(metadata.speed(), DEFAULT_SPEED);
+        assert_eq!(
+            metadata.window_title(Path::new("roms/ibm-logo.ch8"), Variant::Chip8X),
+            "RC8 \u{2014} ibm-logo (CHIP-8X)"
+        );
+        assert!(metadata.credits().is_none());
+    }
+}
@@ -0,0 +1,148 @@
+//! Shrinks a crashing ROM down to the shortest instruction sequence
+//! that still crashes the same way, to make fuzzer findings easier to triage.
+use std::{
+    mem::Discriminant,
+    panic::{self, AssertUnwindSafe},
+};
+
+use anyhow::bail;
+
+use super::{
+    emulator::{Emulator, EmulatorError},
+    timing::run_frame,
+};
+
+/// How a rom run ended
+#[derive(Debug, PartialEq)]
+enum Crash {
+    Error(Discriminant<EmulatorError>),
+    Panic,
+}
+
+/// Run the rom headlessly, returning how it crashed (if it did) and a message
+fn crash(rom: &[u8], frames: usize) -> Option<(Crash, String)> {
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let mut emu = Emulator::load_rom(rom).ok()?;
+        emu.seed_rng(0);
+
+        for _ in 0..frames {
+            if let Err(err) = run_frame(&mut emu) {
+                return Some((Crash::Error(std::mem::discriminant(&err)), err.to_string()));
+            }
+        }
+
+        None
+    }));
+
+    match result {
+        Ok(outcome) => outcome,
+        Err(payload) => {
+            let msg = payload
+                .downcast_ref::<&str>()
+                .map(|s| s.to_string())
+                .or_else(|| payload.downcast_ref::<String>().cloned())
+                .unwrap_or_default();
+            Some((Crash::Panic, format!("panic: {}", msg)))
+        }
+    }
+}
+
+/// Delta-debug the rom, removing whole instructions while the crash still happens
+fn reduce(rom: &[u8], frames: usize, target: &Crash) -> Vec<u8> {
+    let reproduces =
+        |units: &[[u8; 2]]| matches!(crash(&units.concat(), frames), Some((c, _)) if c == *target);
+
+    let mut units: Vec<[u8; 2]> = rom
+        .chunks(2)
+        .map(|chunk| [chunk[0], chunk.get(1).copied().unwrap_or(0)])
+        .collect();
+
+    let mut parts = 2;
+    while units.len() >= 2 {
+        let size = units.len().div_ceil(parts);
+        let mut reduced = false;
+
+        for start in (0..units.len()).step_by(size) {
+            let end = (start + size).min(units.len());
+            let candidate: Vec<_> = units[..start]
+                .iter()
+                .chain(units[end..].iter())
+                .copied()
+                .collect();
+
+            if reproduces(&candidate) {
+                units = candidate;
+                parts = (parts - 1).max(2);
+                reduced = true;
+                break;
+            }
+        }
+
+        if !reduced {
+            if parts >= units.len() {
+                break;
+            }
+            parts = (parts * 2).min(units.len());
+        }
+    }
+
+    // a single instruction might still be removable
+    if units.len() == 1 && reproduces(&[]) {
+        units.clear();
+    }
+
+    units.concat()
+}
+
+/// Minimize a crashing rom, printing a short report
+pub fn minimize(rom: &[u8], frames: usize) -> Result<Vec<u8>, anyhow::Error> {
+    // the panics are expected, and would just flood the terminal
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(|_| {}));
+
+    let result = crash(rom, frames).map(|(target, msg)| {
+        let minimized = reduce(rom, frames, &target);
+        (msg, minimized)
+    });
+
+    panic::set_hook(hook);
+
+    let (msg, minimized) = match result {
+        Some(result) => result,
+        None => bail!("the rom does not crash in {} frames", frames),
+    };
+
+    println!("crash: {}", msg);
+    println!("minimized from {} to {} bytes:", rom.len(), minimized.len());
+    for (i, unit) in minimized.chunks(2).enumerate() {
+        let bytes: Vec<_> = unit.iter().map(|b| format!("{:02X}", b)).collect();
+        println!("  {:#05X}: {}", 0x200 + i * 2, bytes.join(""));
+    }
+
+    Ok(minimized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reduce_invalid_return() {
+        let rom = [
+            0x60, 0x01, // 0x200: V0 = 1
+            0x61, 0x02, // 0x202: V1 = 2
+            0x80, 0x14, // 0x204: V0 += V1
+            0xA3, 0x00, // 0x206: I = 0x300
+            0x00, 0xEE, // 0x208: return, with an empty stack
+        ];
+
+        let (target, _) = crash(&rom, 1).unwrap();
+        assert_eq!(reduce(&rom, 1, &target), vec![0x00, 0xEE]);
+    }
+
+    #[test]
+    fn test_no_crash() {
+        let rom = [0x12, 0x00]; // 0x200: jump to 0x200
+        assert!(crash(&rom, 10).is_none());
+    }
+}
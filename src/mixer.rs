@@ -0,0 +1,182 @@
+//! Sums every sound source into the SDL audio callback, each one with
+//! its own volume, under a master volume.
+use std::sync::mpsc::Sender;
+
+use sdl2::audio::AudioCallback;
+use tracing::warn;
+
+use super::{
+    beep::{Beep, Click, Tone},
+    trace::{Tracer, AUDIO_THREAD},
+};
+
+// loudest a single source can get, at volume 100; the sum is clipped,
+// so this leaves room for a few sources playing at once
+const MAX_AMPLITUDE: f32 = 0.25;
+
+// volume of the keypad clicks
+const CLICK_VOLUME: u8 = 25;
+
+/// Parse the size of the audio buffer, which SDL wants as a power of 2
+pub fn parse_buffer_size(s: &str) -> Result<u16, String> {
+    match s.parse::<u16>() {
+        Ok(samples) if samples >= 16 && samples.is_power_of_two() => Ok(samples),
+        _ => Err(format!(
+            "invalid buffer size: {} (use a power of 2, like 512)",
+            s
+        )),
+    }
+}
+
+/// Something that makes sound
+pub trait Source: Send {
+    /// The next sample, from -1.0 to 1.0
+    fn next_sample(&mut self) -> f32;
+}
+
+/// A source, with its volume
+#[derive(Clone)]
+pub struct Channel<S> {
+    pub source: S,
+
+    /// Volume, from 0 to 100
+    pub volume: u8,
+}
+
+impl<S: Source> Channel<S> {
+    pub fn new(source: S, volume: u8) -> Self {
+        Channel {
+            source,
+            volume: volume.min(100),
+        }
+    }
+
+    /// Move the volume up or down by 10
+    pub fn shift_volume(&mut self, up: bool) {
+        self.volume = if up {
+            (self.volume + 10).min(100)
+        } else {
+            self.volume.saturating_sub(10)
+        };
+    }
+
+    fn next_sample(&mut self) -> f32 {
+        self.source.next_sample() * self.volume as f32 / 100.0
+    }
+}
+
+/// Every sound rc8 makes; cloned, it keeps all settings, to move them to
+/// another audio device
+#[derive(Clone)]
+pub struct Mixer {
+    /// Volume of everything, from 0 to 100
+    pub master: u8,
+    pub muted: bool,
+    pub buzzer: Channel<Beep>,
+    pub keypad: Channel<Click>,
+    /// Get a copy of everything played, for recording, until they hang up
+    pub taps: Vec<Sender<Vec<f32>>>,
+    sample_rate: f32,
+    tracer: Tracer,
+}
+
+impl Mixer {
+    pub fn new(sample_rate: f32, tone: Tone, volume: u8, master: u8, tracer: Tracer) -> Self {
+        Mixer {
+            master,
+            muted: false,
+            buzzer: Channel::new(Beep::new(sample_rate, tone), volume),
+            keypad: Channel::new(Click::new(sample_rate), CLICK_VOLUME),
+            taps: Vec::new(),
+            sample_rate,
+            tracer,
+        }
+    }
+
+    pub fn sample_rate(&self) -> f32 {
+        self.sample_rate
+    }
+
+    /// Play on a device with another sample rate
+    pub fn set_sample_rate(&mut self, sample_rate: f32) {
+        if sample_rate != self.sample_rate && !self.taps.is_empty() {
+            self.taps.clear();
+            warn!("the audio recording stopped, as the new device plays at another sample rate");
+        }
+        self.sample_rate = sample_rate;
+        self.buzzer.source.set_sample_rate(sample_rate);
+        self.keypad.source.set_sample_rate(sample_rate);
+    }
+
+    /// The next sample of all sources together
+    fn next_sample(&mut self) -> f32 {
+        // the sources keep running while muted, so they do not
+        // start from the middle when unmuted
+        let sum = self.buzzer.next_sample() + self.keypad.next_sample();
+        if self.muted {
+            return 0.0;
+        }
+
+        let master = self.master.min(100) as f32 / 100.0;
+        (sum * MAX_AMPLITUDE * master).clamp(-1.0, 1.0)
+    }
+}
+
+impl AudioCallback for Mixer {
+    type Channel = f32;
+
+    fn callback(&mut self, out: &mut [f32]) {
+        let tracer = self.tracer.clone();
+        let _span = tracer.span("audio", AUDIO_THREAD);
+
+        for x in out.iter_mut() {
+            *x = self.next_sample();
+        }
+        self.taps.retain(|tap| tap.send(out.to_vec()).is_ok());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::beep::RAMP_LENGTH;
+
+    #[test]
+    fn test_mixer() {
+        let mut mixer = Mixer::new(44100.0, Tone::default(), 100, 100, Tracer::new());
+        assert_eq!(mixer.next_sample(), 0.0);
+
+        // the square wave starts high, fading in
+        mixer.buzzer.source.playing = true;
+        let first = mixer.next_sample();
+        assert!(first > 0.0 && first < MAX_AMPLITUDE);
+        for _ in 0..(RAMP_LENGTH * 44100.0) as usize {
+            mixer.next_sample();
+        }
+        assert_eq!(mixer.next_sample().abs(), MAX_AMPLITUDE);
+
+        mixer.master = 50;
+        mixer.buzzer.shift_volume(false);
+        assert_eq!(mixer.next_sample().abs(), MAX_AMPLITUDE * 0.9 * 0.5);
+
+        // and fades out, instead of stopping at once
+        mixer.buzzer.source.playing = false;
+        assert_ne!(mixer.next_sample(), 0.0);
+        for _ in 0..(RAMP_LENGTH * 44100.0) as usize {
+            mixer.next_sample();
+        }
+        assert_eq!(mixer.next_sample(), 0.0);
+
+        mixer.muted = true;
+        mixer.keypad.source.trigger();
+        assert_eq!(mixer.next_sample(), 0.0);
+    }
+
+    #[test]
+    fn test_parse_buffer_size() {
+        assert_eq!(parse_buffer_size("512"), Ok(512));
+        assert!(parse_buffer_size("500").is_err());
+        assert!(parse_buffer_size("0").is_err());
+        assert!(parse_buffer_size("65536").is_err());
+    }
+}
@@ -0,0 +1,146 @@
+//! Mouse keys: the mouse buttons and the wheel press CHIP-8 keys, for
+//! anyone who finds the mouse easier than a cluster of keyboard keys.
+use sdl2::{
+    event::Event,
+    mouse::{MouseButton, MouseWheelDirection},
+};
+
+use super::keymap::Action;
+
+// microseconds a wheel notch holds its key down, so the game can see it
+const TAP_LENGTH: u128 = 50_000;
+
+/// What can be bound to a key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MouseInput {
+    Button(MouseButton),
+    WheelUp,
+    WheelDown,
+}
+
+/// The mouse bindings, and the keys held by wheel notches
+pub struct MouseKeys {
+    bindings: Vec<(MouseInput, usize)>,
+    // keys pressed by the wheel, and when
+    taps: Vec<(usize, u128)>,
+}
+
+impl MouseKeys {
+    pub fn new(bindings: &[(MouseInput, usize)]) -> Self {
+        MouseKeys {
+            bindings: bindings.to_vec(),
+            taps: Vec::new(),
+        }
+    }
+
+    fn key(&self, input: MouseInput) -> Option<usize> {
+        self.bindings
+            .iter()
+            .find(|(bound, _)| *bound == input)
+            .map(|&(_, key)| key)
+    }
+
+    /// The key pressed or released by a mouse event, if it is bound
+    pub fn translate(&mut self, event: &Event, now: u128) -> Option<Action> {
+        match *event {
+            Event::MouseButtonDown { mouse_btn, .. } => self
+                .key(MouseInput::Button(mouse_btn))
+                .map(|key| Action::EmulateKeyState(key, true)),
+            Event::MouseButtonUp { mouse_btn, .. } => self
+                .key(MouseInput::Button(mouse_btn))
+                .map(|key| Action::EmulateKeyState(key, false)),
+            Event::MouseWheel { y, direction, .. } if y != 0 => {
+                let up = (y > 0) != (direction == MouseWheelDirection::Flipped);
+                let input = if up {
+                    MouseInput::WheelUp
+                } else {
+                    MouseInput::WheelDown
+                };
+                let key = self.key(input)?;
+                self.taps.retain(|&(tapped, _)| tapped != key);
+                self.taps.push((key, now));
+                Some(Action::EmulateKeyState(key, true))
+            }
+            _ => None,
+        }
+    }
+
+    /// The keys pressed by the wheel that must be released now
+    pub fn update(&mut self, now: u128) -> Vec<usize> {
+        let (done, held): (Vec<_>, Vec<_>) = std::mem::take(&mut self.taps)
+            .into_iter()
+            .partition(|&(_, since)| now - since >= TAP_LENGTH);
+        self.taps = held;
+        done.into_iter().map(|(key, _)| key).collect()
+    }
+}
+
+/// Parse a binding, like `left=5` or `wheel-up=2`
+pub fn parse_binding(s: &str) -> Result<(MouseInput, usize), String> {
+    let (input, key) = s
+        .split_once('=')
+        .ok_or_else(|| format!("invalid mouse binding: {} (use like left=5)", s))?;
+
+    let input = match input.to_ascii_lowercase().as_str() {
+        "left" => MouseInput::Button(MouseButton::Left),
+        "middle" => MouseInput::Button(MouseButton::Middle),
+        "right" => MouseInput::Button(MouseButton::Right),
+        "x1" => MouseInput::Button(MouseButton::X1),
+        "x2" => MouseInput::Button(MouseButton::X2),
+        "wheel-up" => MouseInput::WheelUp,
+        "wheel-down" => MouseInput::WheelDown,
+        _ => {
+            return Err(format!(
+                "invalid mouse input: {} (use left, middle, right, x1, x2, wheel-up or wheel-down)",
+                input
+            ))
+        }
+    };
+
+    Ok((input, super::turbo::parse_key(key)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wheel(y: i32) -> Event {
+        Event::MouseWheel {
+            timestamp: 0,
+            window_id: 0,
+            which: 0,
+            x: 0,
+            y,
+            direction: MouseWheelDirection::Normal,
+        }
+    }
+
+    #[test]
+    fn test_wheel() {
+        let mut mouse = MouseKeys::new(&[(MouseInput::WheelUp, 0x2)]);
+        assert!(matches!(
+            mouse.translate(&wheel(1), 1_000),
+            Some(Action::EmulateKeyState(0x2, true))
+        ));
+        assert!(mouse.translate(&wheel(-1), 1_000).is_none());
+
+        assert!(mouse.update(20_000).is_empty());
+        assert_eq!(mouse.update(60_000), [0x2]);
+        assert!(mouse.update(120_000).is_empty());
+    }
+
+    #[test]
+    fn test_parse_binding() {
+        assert_eq!(
+            parse_binding("left=5"),
+            Ok((MouseInput::Button(MouseButton::Left), 0x5))
+        );
+        assert_eq!(
+            parse_binding("Wheel-Down=8"),
+            Ok((MouseInput::WheelDown, 0x8))
+        );
+        assert!(parse_binding("left").is_err());
+        assert!(parse_binding("thumb=5").is_err());
+        assert!(parse_binding("left=10").is_err());
+    }
+}
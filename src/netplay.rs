@@ -0,0 +1,1433 @@
+//! Lockstep netplay: two rc8 instances run the same rom, frame by frame,
+//! with the keys of both players pressed on the same keypad. The core is
+//! deterministic, so only the keys go through the network.
+//!
+//! Each side sends its keys a few frames ahead, and a frame only runs once
+//! the keys of the other player for it have arrived. Every second, both
+//! sides compare a hash of the machine state, to catch games going out of
+//! sync (e.g. when each player picked different options).
+//!
+//! How far ahead the keys go can change while playing: each side only
+//! decides when its own keys are played, so the other side needs not know.
+//! If the connection drops, both sides try to connect again for a while,
+//! sending again the keys the other side missed.
+//!
+//! Once the game started, spectators can connect to the host too: they get
+//! the keys of every frame played so far, and then each frame as it is
+//! played, to run the same game on their side.
+//!
+//! The players can also chat; the host passes the messages of both to the
+//! spectators.
+//!
+//! With rollback, a player does not wait for the keys of the other: frames
+//! run on a guess (the last keys that came), a few frames ahead at most,
+//! and when the keys come and the guess was wrong, the game goes back to
+//! the first wrong frame and runs it again. Frames only count as played
+//! once the keys of both are known, which is when their hashes are compared
+//! and the spectators get them.
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{self, Read, Write},
+    net::{Ipv4Addr, Shutdown, TcpListener, TcpStream, ToSocketAddrs},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, TryRecvError},
+        Arc, Mutex,
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::{bail, Context};
+use tracing::{info, warn};
+
+use rc8::emulator::Emulator;
+
+// frames between a key press and the frame it is played on, which hides
+// the network delay; 3 frames is 50 ms
+const DELAY: u32 = 3;
+
+/// Most frames the keys can be delayed
+pub const MAX_DELAY: u32 = 15;
+
+// how often the machine state is compared, in frames
+const HASH_INTERVAL: u32 = 60;
+
+// how often the round trip is measured, in frames, and how many of the
+// last measures the ping and the jitter come from
+const PING_INTERVAL: u32 = 30;
+const PING_SAMPLES: usize = 8;
+
+// how long a dropped connection is tried again before giving up, and how
+// many frames of keys are kept to send again once it is back
+const RECONNECT_TIME: Duration = Duration::from_secs(10);
+const HISTORY: usize = 4 * MAX_DELAY as usize;
+
+// frames a player with rollback can run ahead of the keys of the other
+pub const MAX_ROLLBACK: u32 = 8;
+
+// how long someone connecting to the host has to say who they are
+const GREETING_TIME: Duration = Duration::from_secs(2);
+
+// message tags
+const HELLO: u8 = b'R';
+const KEYS: u8 = b'K';
+const HASH: u8 = b'H';
+const PING: u8 = b'P';
+const PONG: u8 = b'O';
+const RESUME: u8 = b'S';
+const BYE: u8 = b'Q';
+const WATCH: u8 = b'W';
+const CHAT: u8 = b'C';
+
+// who said something on the chat
+const HOST: u8 = 0;
+const GUEST: u8 = 1;
+
+/// Who waits for whom
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum NetplayMode {
+    /// Wait for the other player on this port
+    Host(u16),
+    /// Join a game on `address:port`
+    Join(String),
+    /// Watch a game hosted on `address:port`, without playing
+    Watch(String),
+}
+
+impl FromStr for NetplayMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (address, port) = s.rsplit_once(':').ok_or_else(|| {
+            format!(
+                "expected host:PORT, ADDRESS:PORT or watch:ADDRESS:PORT, found '{}'",
+                s
+            )
+        })?;
+        let port: u16 = port
+            .parse()
+            .map_err(|_| format!("invalid port: '{}'", port))?;
+
+        Ok(match address.split_once(':') {
+            _ if address == "host" => NetplayMode::Host(port),
+            Some(("watch", address)) => NetplayMode::Watch(format!("{}:{}", address, port)),
+            _ => NetplayMode::Join(s.to_owned()),
+        })
+    }
+}
+
+enum Message {
+    Keys(u32, u16),
+    Hash(u32, u64),
+    Ping(u32, u64),
+    Pong(u64),
+    Chat(u8, String),
+    Bye,
+}
+
+/// The other player went away
+#[derive(Debug)]
+pub struct Disconnected;
+
+/// How the connection is doing, for the HUD
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Quality {
+    /// Average round trip of the last measures
+    pub ping: Option<Duration>,
+    /// How much the round trip changes from one measure to the next
+    pub jitter: Option<Duration>,
+    /// Frames between pressing a key and playing it
+    pub delay: u32,
+    pub reconnecting: bool,
+    /// Only watching the game, so there is nothing else to show
+    pub watching: bool,
+    /// With rollback, frames run on a guess of the keys of the other player
+    pub guessed: Option<u32>,
+}
+
+impl Quality {
+    /// The lines of the HUD
+    pub fn lines(&self) -> Vec<String> {
+        if self.watching {
+            return vec!["watching".to_owned()];
+        }
+
+        let ms = |time: Option<Duration>| match time {
+            Some(time) => format!("{} ms", time.as_millis()),
+            None => "-".to_owned(),
+        };
+
+        let mut lines = vec![
+            format!("ping   {}", ms(self.ping)),
+            format!("jitter {}", ms(self.jitter)),
+            format!("delay  {} frames", self.delay),
+        ];
+        if let Some(guessed) = self.guessed {
+            lines.push(format!("ahead  {} frames", guessed));
+        }
+        if self.reconnecting {
+            lines.push("reconnecting...".to_owned());
+        }
+        lines
+    }
+}
+
+/// A connection to the other player: messages are read on their own
+/// thread, which also answers the pings right away
+struct Link {
+    writer: Arc<Mutex<TcpStream>>,
+    incoming: Receiver<Message>,
+}
+
+impl Link {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        let mut reader = stream.try_clone()?;
+        let writer = Arc::new(Mutex::new(stream));
+        let (sender, incoming) = mpsc::channel();
+
+        let pong = Arc::clone(&writer);
+        thread::Builder::new()
+            .name("netplay".to_owned())
+            .spawn(move || {
+                while let Ok(message) = read_message(&mut reader) {
+                    if let Message::Ping(frame, stamp) = message {
+                        let mut stream = pong.lock().unwrap_or_else(|err| err.into_inner());
+                        if write_message(&mut *stream, PONG, frame, &stamp.to_be_bytes()).is_err() {
+                            return;
+                        }
+                    } else if sender.send(message).is_err() {
+                        return;
+                    }
+                }
+            })?;
+
+        Ok(Link { writer, incoming })
+    }
+
+    fn send(&self, tag: u8, frame: u32, payload: &[u8]) -> io::Result<()> {
+        let mut stream = self.writer.lock().unwrap_or_else(|err| err.into_inner());
+        write_message(&mut *stream, tag, frame, payload)
+    }
+
+    /// Close the connection, so the other side notices too
+    fn close(&self) {
+        let stream = self.writer.lock().unwrap_or_else(|err| err.into_inner());
+        let _ = stream.shutdown(Shutdown::Both);
+    }
+}
+
+/// A connection made again, with the first frame the other side needs
+type Resumed = Option<(TcpStream, u32)>;
+
+/// Someone watching the game; the messages are written on their own thread,
+/// so a slow spectator never holds up the game
+struct Spectator {
+    outgoing: Sender<Vec<u8>>,
+}
+
+impl Spectator {
+    fn new(mut stream: TcpStream) -> io::Result<Self> {
+        let (outgoing, messages) = mpsc::channel::<Vec<u8>>();
+        thread::Builder::new()
+            .name("netplay-spectator".to_owned())
+            .spawn(move || {
+                for message in messages {
+                    if stream.write_all(&message).is_err() {
+                        break;
+                    }
+                }
+                let _ = stream.shutdown(Shutdown::Both);
+            })?;
+        Ok(Spectator { outgoing })
+    }
+}
+
+/// Whoever connects to the host once the game started: the other player
+/// coming back after a drop, or spectators
+struct Arrivals {
+    resumes: Arc<Mutex<Receiver<(TcpStream, u32)>>>,
+    spectators: Receiver<Spectator>,
+    closed: Arc<AtomicBool>,
+}
+
+impl Arrivals {
+    /// Keep accepting on `listener`, for the game `session` that started
+    /// with the machine on `state`
+    fn new(listener: TcpListener, session: u64, state: u64) -> io::Result<Self> {
+        let (resumed, resumes) = mpsc::channel();
+        let (watching, spectators) = mpsc::channel();
+        let closed = Arc::new(AtomicBool::new(false));
+
+        let done = Arc::clone(&closed);
+        listener.set_nonblocking(true)?;
+        thread::Builder::new()
+            .name("netplay-accept".to_owned())
+            .spawn(move || {
+                while !done.load(Ordering::Relaxed) {
+                    let stream = match listener.accept() {
+                        Ok((stream, _)) => stream,
+                        Err(err) if err.kind() == io::ErrorKind::WouldBlock => {
+                            thread::sleep(Duration::from_millis(50));
+                            continue;
+                        }
+                        Err(_) => return,
+                    };
+
+                    // anyone else knocking is turned away
+                    match greet(stream, session, state) {
+                        Some(Arrival::Resume(stream, needed)) => {
+                            let _ = resumed.send((stream, needed));
+                        }
+                        Some(Arrival::Watch(spectator)) => {
+                            let _ = watching.send(spectator);
+                        }
+                        None => {}
+                    }
+                }
+            })?;
+
+        Ok(Arrivals {
+            resumes: Arc::new(Mutex::new(resumes)),
+            spectators,
+            closed,
+        })
+    }
+}
+
+impl Drop for Arrivals {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
+
+enum Arrival {
+    Resume(TcpStream, u32),
+    Watch(Spectator),
+}
+
+/// A game with another player, on its way
+pub struct Netplay {
+    mode: NetplayMode,
+    link: Link,
+    reconnect: Option<Receiver<Resumed>>,
+
+    // picked by the host, it tells this game apart when reconnecting
+    session: u64,
+
+    // next frame to run, next frame the keys of both players are not known
+    // for (the same, unless guessing), and next frame to send the keys for
+    frame: u32,
+    confirmed: u32,
+    sent: u32,
+    delay: u32,
+
+    // with rollback, the keys guessed for the other player on the frames
+    // run but not confirmed, and the last ones that came
+    rollback: bool,
+    guesses: VecDeque<u16>,
+    last_remote: u16,
+
+    // keys of this player, as being pressed now and as sent for the last
+    // frames (the ones not run yet, and a few more to send again), and
+    // the keys of the other player for the frames not run yet
+    local: u16,
+    history: VecDeque<u16>,
+    remote: VecDeque<u16>,
+
+    // hashes of the machine after the frames played, and of the frames run
+    // on a guess, to send once they are confirmed
+    hashes: HashMap<u32, u64>,
+    remote_hashes: HashMap<u32, u64>,
+    unconfirmed_hashes: HashMap<u32, u64>,
+    desynced: bool,
+
+    // when the game started, to time the pings, and the last round trips
+    started: Instant,
+    round_trips: VecDeque<Duration>,
+
+    // chat messages to show, and if the other side said bye
+    heard: Vec<String>,
+    left: bool,
+
+    // on the host, the keys of every frame played, for the spectators
+    arrivals: Option<Arrivals>,
+    played: Vec<u16>,
+    spectators: Vec<Spectator>,
+}
+
+impl Netplay {
+    /// Wait for (or join) the other player, and agree on the rom and the
+    /// random seed, which is set on `emu`
+    pub fn connect(mode: &NetplayMode, emu: &mut Emulator) -> Result<Self, anyhow::Error> {
+        // the host picks the seed; both check they run the same machine
+        let seed = match mode {
+            NetplayMode::Host(_) => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_nanos() as u64),
+            _ => 0,
+        };
+        let state = emu.state_hash();
+        let mut hello = vec![HELLO];
+        hello.extend(state.to_be_bytes());
+        hello.extend(seed.to_be_bytes());
+
+        let (stream, other, listener) = match mode {
+            NetplayMode::Host(port) => {
+                let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, *port))
+                    .with_context(|| format!("error listening on port {}", port))?;
+                info!("waiting for the other player on port {}", port);
+                loop {
+                    let (mut stream, address) =
+                        listener.accept().context("error accepting a player")?;
+                    // spectators can only come once the game started
+                    match introduce(&mut stream, &hello) {
+                        Ok(other) if other[0] != WATCH => {
+                            info!("{} joined", address);
+                            break (stream, other, Some(listener));
+                        }
+                        _ => info!("turned {} away before the game started", address),
+                    }
+                }
+            }
+            NetplayMode::Join(address) => {
+                info!("joining {}", address);
+                let mut stream = TcpStream::connect(address)
+                    .with_context(|| format!("error connecting to {}", address))?;
+                let other = introduce(&mut stream, &hello).context("the other player left")?;
+                (stream, other, None)
+            }
+            NetplayMode::Watch(address) => return Netplay::watch(address, state, emu),
+        };
+
+        if other[0] != HELLO {
+            bail!("the other side is not rc8");
+        }
+        if other[1..9] != state.to_be_bytes() {
+            bail!("the other player is running a different rom, or with different options");
+        }
+        let session = match mode {
+            NetplayMode::Host(_) => seed,
+            _ => u64::from_be_bytes(other[9..].try_into()?),
+        };
+        emu.seed_rng(session);
+
+        let arrivals = listener
+            .map(|listener| Arrivals::new(listener, session, state))
+            .transpose()?;
+
+        // nobody pressed anything on the frames before the first keys arrive
+        let mut netplay = Netplay::new(mode, Link::new(stream)?, session);
+        netplay.arrivals = arrivals;
+        netplay.sent = DELAY;
+        netplay.history = VecDeque::from(vec![0; DELAY as usize]);
+        netplay.remote = VecDeque::from(vec![0; DELAY as usize]);
+        Ok(netplay)
+    }
+
+    /// Ask the host to watch its game, which must have started, with the
+    /// machine on `state`
+    fn watch(address: &str, state: u64, emu: &mut Emulator) -> Result<Self, anyhow::Error> {
+        info!("watching {}", address);
+        let mut stream = TcpStream::connect(address)
+            .with_context(|| format!("error connecting to {}", address))?;
+        stream.set_nodelay(true)?;
+
+        let mut hello = vec![WATCH];
+        hello.extend(state.to_be_bytes());
+        hello.extend(0u64.to_be_bytes());
+        stream.write_all(&hello)?;
+
+        let mut reply = [0; 13];
+        stream
+            .read_exact(&mut reply)
+            .context("the host turned this spectator away")?;
+        match reply[0] {
+            WATCH => {}
+            HELLO => bail!("the game has not started yet"),
+            BYE => bail!("the players are running a different rom, or with different options"),
+            _ => bail!("the other side is not rc8"),
+        }
+
+        // the keys of every frame come from the host, from the first one
+        let session = u64::from_be_bytes(reply[5..].try_into()?);
+        emu.seed_rng(session);
+        Ok(Netplay::new(
+            &NetplayMode::Watch(address.to_owned()),
+            Link::new(stream)?,
+            session,
+        ))
+    }
+
+    fn new(mode: &NetplayMode, link: Link, session: u64) -> Self {
+        Netplay {
+            mode: mode.clone(),
+            link,
+            reconnect: None,
+            session,
+            frame: 0,
+            confirmed: 0,
+            sent: 0,
+            delay: DELAY,
+            rollback: false,
+            guesses: VecDeque::new(),
+            last_remote: 0,
+            local: 0,
+            history: VecDeque::new(),
+            remote: VecDeque::new(),
+            hashes: HashMap::new(),
+            remote_hashes: HashMap::new(),
+            unconfirmed_hashes: HashMap::new(),
+            desynced: false,
+            started: Instant::now(),
+            round_trips: VecDeque::new(),
+            arrivals: None,
+            played: Vec::new(),
+            spectators: Vec::new(),
+            heard: Vec::new(),
+            left: false,
+        }
+    }
+
+    /// If this side is only watching the game
+    pub fn watching(&self) -> bool {
+        matches!(self.mode, NetplayMode::Watch(_))
+    }
+
+    /// Frames a spectator got the keys for, but did not run yet; always 0
+    /// for the players
+    pub fn behind(&self) -> usize {
+        match self.watching() {
+            true => self.remote.len(),
+            false => 0,
+        }
+    }
+
+    /// Frames between pressing a key and playing it
+    pub fn delay(&self) -> u32 {
+        self.delay
+    }
+
+    /// Play the keys of this player `delay` frames after they are pressed,
+    /// from now on; returns the delay set, which has to be 1 to MAX_DELAY
+    pub fn set_delay(&mut self, delay: u32) -> u32 {
+        if !self.watching() {
+            self.delay = delay.clamp(1, MAX_DELAY);
+        }
+        self.delay
+    }
+
+    /// How the connection is doing
+    pub fn quality(&self) -> Quality {
+        let samples = self.round_trips.len() as u32;
+        let ping = (samples > 0).then(|| self.round_trips.iter().sum::<Duration>() / samples);
+        let jitter = (samples > 1).then(|| {
+            let changes = self.round_trips.iter().zip(self.round_trips.iter().skip(1));
+            changes.map(|(a, b)| a.abs_diff(*b)).sum::<Duration>() / (samples - 1)
+        });
+
+        Quality {
+            ping,
+            jitter,
+            delay: self.delay,
+            reconnecting: self.reconnect.is_some(),
+            watching: self.watching(),
+            guessed: self.rollback.then(|| self.frame - self.confirmed),
+        }
+    }
+
+    /// Guess the keys of the other player instead of waiting for them, and
+    /// go back when wrong; this hides the network delay, so the keys of
+    /// this player need only wait one frame
+    pub fn set_rollback(&mut self, rollback: bool) {
+        if self.watching() {
+            return;
+        }
+        self.rollback = rollback;
+        if rollback {
+            self.delay = 1;
+        }
+    }
+
+    /// If the keys of the other player are guessed
+    pub fn rollback(&self) -> bool {
+        self.rollback
+    }
+
+    /// The next frame to run
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    /// The first frame not played yet: earlier frames can never run again
+    pub fn confirmed(&self) -> u32 {
+        self.confirmed
+    }
+
+    /// Press or release a key of this player
+    pub fn set_key(&mut self, key: usize, pressed: bool) {
+        if self.watching() {
+            return;
+        }
+        let bit = 1 << (key & 0xF);
+        if pressed {
+            self.local |= bit;
+        } else {
+            self.local &= !bit;
+        }
+    }
+
+    /// The keys of both players for the next frame, if they are known
+    /// already; when they are, the frame is taken as run
+    pub fn next_keys(&mut self) -> Result<Option<u16>, Disconnected> {
+        if self.left {
+            return Err(Disconnected);
+        }
+        if self.reconnect.is_some() && !self.resume()? {
+            return Ok(None);
+        }
+
+        while self.sent < self.frame + self.delay && !self.watching() {
+            self.history.push_back(self.local);
+            if self.history.len() > HISTORY {
+                self.history.pop_front();
+            }
+            let sent = self.sent;
+            self.sent += 1;
+            self.send(KEYS, sent, &self.local.to_be_bytes())?;
+        }
+        if self.reconnect.is_some() {
+            return Ok(None);
+        }
+        if !self.poll()? {
+            return Ok(None);
+        }
+
+        // a guess, as long as it is not too far ahead
+        if self.rollback {
+            if self.frame - self.confirmed >= MAX_ROLLBACK {
+                return Ok(None);
+            }
+            let guess = self.remote.get(self.guesses.len()).copied();
+            let guess = guess.unwrap_or_else(|| self.guess());
+            self.guesses.push_back(guess);
+            self.frame += 1;
+            return Ok(Some(self.local_at(self.frame - 1) | guess));
+        }
+
+        let Some(remote) = self.remote.pop_front() else {
+            return Ok(None);
+        };
+        let keys = self.local_at(self.frame) | remote;
+        self.play(keys);
+        self.frame += 1;
+        Ok(Some(keys))
+    }
+
+    /// With rollback, check the guesses against the keys that came: the
+    /// frames guessed right are played, and if one was wrong, returns it
+    /// along with the keys to run it and the frames after it again
+    pub fn reconcile(&mut self) -> Result<Option<(u32, Vec<u16>)>, Disconnected> {
+        if !self.rollback || self.left || self.reconnect.is_some() || !self.poll()? {
+            return Ok(None);
+        }
+
+        let known = self.remote.len().min(self.guesses.len());
+        let wrong = (0..known).find(|&i| self.remote[i] != self.guesses[i]);
+        let Some(wrong) = wrong else {
+            self.confirm_guesses(known)?;
+            return Ok(None);
+        };
+        self.confirm_guesses(wrong)?;
+
+        // the frames from the wrong one on run again, with the keys that
+        // came, and new guesses after them
+        let guess = self.guess();
+        for (i, old) in self.guesses.iter_mut().enumerate() {
+            *old = self.remote.get(i).copied().unwrap_or(guess);
+        }
+        let from = self.confirmed;
+        let keys = (from..)
+            .zip(&self.guesses)
+            .map(|(frame, &remote)| self.local_at(frame) | remote)
+            .collect();
+        self.unconfirmed_hashes.clear();
+
+        let known = self.remote.len().min(self.guesses.len());
+        self.confirm_guesses(known)?;
+        Ok(Some((from, keys)))
+    }
+
+    /// Read whatever came; false while reconnecting
+    fn poll(&mut self) -> Result<bool, Disconnected> {
+        self.welcome();
+        loop {
+            match self.link.incoming.try_recv() {
+                Ok(message) => self.receive(message)?,
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.dropped()?;
+                    return Ok(false);
+                }
+            }
+        }
+        self.compare_hashes();
+        Ok(true)
+    }
+
+    /// The keys of the other player on the next frames: most of the time,
+    /// the same as the last ones
+    fn guess(&self) -> u16 {
+        self.remote.back().copied().unwrap_or(self.last_remote)
+    }
+
+    /// The first `count` guesses were right: those frames are played
+    fn confirm_guesses(&mut self, count: usize) -> Result<(), Disconnected> {
+        for _ in 0..count {
+            let remote = self.remote.pop_front().unwrap_or_default();
+            self.guesses.pop_front();
+            self.last_remote = remote;
+
+            let frame = self.confirmed;
+            if let Some(hash) = self.unconfirmed_hashes.remove(&frame) {
+                self.hashes.insert(frame, hash);
+                self.send(HASH, frame, &hash.to_be_bytes())?;
+            }
+            self.play(self.local_at(frame) | remote);
+        }
+        Ok(())
+    }
+
+    /// The keys this player sent for `frame`
+    fn local_at(&self, frame: u32) -> u16 {
+        match self.watching() {
+            true => 0,
+            false => self.history[self.history.len() - (self.sent - frame) as usize],
+        }
+    }
+
+    /// Send the spectators who just came all the keys played so far
+    fn welcome(&mut self) {
+        let Some(arrivals) = &self.arrivals else {
+            return;
+        };
+        for spectator in arrivals.spectators.try_iter() {
+            let mut messages = Vec::new();
+            for (frame, keys) in (0..).zip(&self.played) {
+                messages.extend(message(KEYS, frame, &keys.to_be_bytes()));
+            }
+            if spectator.outgoing.send(messages).is_ok() {
+                info!("netplay: a spectator joined");
+                self.spectators.push(spectator);
+            }
+        }
+    }
+
+    /// The keys of both players for the next frame are known: send them to
+    /// the spectators
+    fn play(&mut self, keys: u16) {
+        if self.arrivals.is_some() {
+            self.played.push(keys);
+            self.tell_spectators(&message(KEYS, self.confirmed, &keys.to_be_bytes()));
+        }
+        self.confirmed += 1;
+    }
+
+    /// Call after running each frame, to catch the games going out of sync
+    /// and to measure the round trip now and then
+    pub fn ran(&mut self, emu: &Emulator) -> Result<(), Disconnected> {
+        if self.watching() {
+            return Ok(());
+        }
+        let frame = self.frame - 1;
+        self.hash(frame, emu)?;
+        if frame.is_multiple_of(PING_INTERVAL) {
+            let stamp = self.started.elapsed().as_micros() as u64;
+            self.send(PING, frame, &stamp.to_be_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Call after running `frame` again, after going back for a wrong guess
+    pub fn ran_again(&mut self, frame: u32, emu: &Emulator) -> Result<(), Disconnected> {
+        self.hash(frame, emu)
+    }
+
+    /// Compare the machine after `frame` now and then; if the frame ran on
+    /// a guess, only once the guess is confirmed
+    fn hash(&mut self, frame: u32, emu: &Emulator) -> Result<(), Disconnected> {
+        if !frame.is_multiple_of(HASH_INTERVAL) {
+            return Ok(());
+        }
+        let hash = emu.state_hash();
+        if frame >= self.confirmed {
+            self.unconfirmed_hashes.insert(frame, hash);
+            return Ok(());
+        }
+        self.hashes.insert(frame, hash);
+        self.send(HASH, frame, &hash.to_be_bytes())
+    }
+
+    fn receive(&mut self, message: Message) -> Result<(), Disconnected> {
+        match message {
+            // keys come in order, so a gap means a broken connection
+            Message::Keys(frame, keys) => {
+                if frame != self.confirmed + self.remote.len() as u32 {
+                    return Err(Disconnected);
+                }
+                self.remote.push_back(keys);
+            }
+            Message::Hash(frame, hash) => {
+                self.remote_hashes.insert(frame, hash);
+            }
+            Message::Pong(stamp) => {
+                // a stamp from the future is not ours, so it is no sample
+                let now = self.started.elapsed().as_micros() as u64;
+                if let Some(round_trip) = now.checked_sub(stamp) {
+                    self.round_trips
+                        .push_back(Duration::from_micros(round_trip));
+                    if self.round_trips.len() > PING_SAMPLES {
+                        self.round_trips.pop_front();
+                    }
+                }
+            }
+            Message::Ping(..) => {}
+            Message::Chat(from, text) => self.hear(from, text),
+            // the other player quit, so there is nobody to wait for
+            Message::Bye => {
+                self.left = true;
+                return Err(Disconnected);
+            }
+        }
+        Ok(())
+    }
+
+    /// The connection dropped: try to make it again, on another thread
+    fn dropped(&mut self) -> Result<(), Disconnected> {
+        if self.reconnect.is_some() {
+            return Ok(());
+        }
+        self.link.close();
+
+        // the players only wait for each other
+        if self.watching() {
+            return Err(Disconnected);
+        }
+
+        // whatever arrived before, which may be the other player quitting
+        while let Ok(message) = self.link.incoming.recv() {
+            self.receive(message)?;
+        }
+        warn!("netplay: the connection dropped, trying again");
+
+        let resumes = self
+            .arrivals
+            .as_ref()
+            .map(|arrivals| Arc::clone(&arrivals.resumes));
+        let (mode, session) = (self.mode.clone(), self.session);
+        let needed = self.confirmed + self.remote.len() as u32;
+        let (sender, receiver) = mpsc::channel();
+        let spawned = thread::Builder::new()
+            .name("netplay-reconnect".to_owned())
+            .spawn(move || {
+                let deadline = Instant::now() + RECONNECT_TIME;
+                let resumed = match (mode, resumes) {
+                    (NetplayMode::Host(_), Some(resumes)) => {
+                        wait_again(&resumes, session, needed, deadline)
+                    }
+                    (NetplayMode::Join(address), _) => {
+                        connect_again(&address, session, needed, deadline)
+                    }
+                    _ => None,
+                };
+                let _ = sender.send(resumed);
+            });
+
+        // without the thread, the receiver says it gave up right away
+        if spawned.is_err() {
+            warn!("netplay: could not try to reconnect");
+        }
+        self.reconnect = Some(receiver);
+        Ok(())
+    }
+
+    /// If the connection is back; the keys the other side missed are sent
+    /// again
+    fn resume(&mut self) -> Result<bool, Disconnected> {
+        let Some(reconnect) = &self.reconnect else {
+            return Ok(true);
+        };
+        let (stream, needed) = match reconnect.try_recv() {
+            Ok(Some(resumed)) => resumed,
+            Err(TryRecvError::Empty) => return Ok(false),
+            Ok(None) | Err(TryRecvError::Disconnected) => return Err(Disconnected),
+        };
+        self.reconnect = None;
+        self.link = Link::new(stream).map_err(|_| Disconnected)?;
+        info!("netplay: connected again");
+
+        // the keys of the frames the other side is still waiting for
+        let first = self.sent - self.history.len() as u32;
+        if needed < first || needed > self.sent {
+            return Err(Disconnected);
+        }
+        let missed: Vec<_> = self
+            .history
+            .range((needed - first) as usize..)
+            .copied()
+            .collect();
+        for (frame, keys) in (needed..).zip(missed) {
+            self.send(KEYS, frame, &keys.to_be_bytes())?;
+        }
+        Ok(self.reconnect.is_none())
+    }
+
+    /// Send a chat message to the other player, and any spectators;
+    /// spectators cannot chat
+    pub fn say(&mut self, text: &str) {
+        let from = match self.mode {
+            NetplayMode::Host(_) => HOST,
+            NetplayMode::Join(_) => GUEST,
+            NetplayMode::Watch(_) => return,
+        };
+        let payload = chat_payload(from, text);
+        let _ = self.send(CHAT, self.frame, &payload);
+        self.tell_spectators(&message(CHAT, self.frame, &payload));
+    }
+
+    /// The chat messages that came since the last call, with who said them
+    pub fn heard(&mut self) -> Vec<String> {
+        std::mem::take(&mut self.heard)
+    }
+
+    fn hear(&mut self, from: u8, text: String) {
+        self.tell_spectators(&message(CHAT, self.frame, &chat_payload(from, &text)));
+        let from = if from == HOST { "host" } else { "guest" };
+        self.heard.push(format!("{}: {}", from, text));
+    }
+
+    fn tell_spectators(&mut self, message: &[u8]) {
+        self.spectators
+            .retain(|spectator| spectator.outgoing.send(message.to_vec()).is_ok());
+    }
+
+    /// The keys held by this player
+    pub fn local_keys(&self) -> u16 {
+        self.local
+    }
+
+    fn compare_hashes(&mut self) {
+        let frames: Vec<_> = self
+            .remote_hashes
+            .keys()
+            .filter(|frame| self.hashes.contains_key(frame))
+            .copied()
+            .collect();
+
+        for frame in frames {
+            let remote = self.remote_hashes.remove(&frame);
+            let local = self.hashes.remove(&frame);
+            if remote != local && !self.desynced {
+                warn!("netplay: the games went out of sync on frame {}", frame);
+                self.desynced = true;
+            }
+        }
+    }
+
+    /// Send a message; if the connection dropped, it is tried again, and
+    /// the keys are sent again when it is back
+    fn send(&mut self, tag: u8, frame: u32, payload: &[u8]) -> Result<(), Disconnected> {
+        if self.reconnect.is_none() && self.link.send(tag, frame, payload).is_err() {
+            self.dropped()?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Netplay {
+    /// Tell the other player this one quit, so they do not wait for a
+    /// reconnection
+    fn drop(&mut self) {
+        if self.reconnect.is_none() && !self.watching() {
+            let _ = self.link.send(BYE, self.frame, &[]);
+        }
+        self.link.close();
+
+        for spectator in &self.spectators {
+            let _ = spectator.outgoing.send(message(BYE, self.frame, &[]));
+        }
+    }
+}
+
+/// Say hello to the other side, and hear theirs
+fn introduce(stream: &mut TcpStream, hello: &[u8]) -> io::Result<[u8; 17]> {
+    stream.set_nodelay(true)?;
+    stream.write_all(hello)?;
+
+    let mut other = [0; 17];
+    stream.read_exact(&mut other)?;
+    Ok(other)
+}
+
+/// Find out who connected to the host during the game: the other player
+/// coming back to `session`, or a spectator of the game that started on
+/// `state`
+fn greet(mut stream: TcpStream, session: u64, state: u64) -> Option<Arrival> {
+    stream.set_nonblocking(false).ok()?;
+    stream.set_nodelay(true).ok()?;
+    stream.set_read_timeout(Some(GREETING_TIME)).ok()?;
+
+    let mut tag = [0];
+    stream.read_exact(&mut tag).ok()?;
+    match tag[0] {
+        RESUME => {
+            let mut other = [0; 12];
+            stream.read_exact(&mut other).ok()?;
+            if other[4..] != session.to_be_bytes() {
+                return None;
+            }
+            let needed = u32::from_be_bytes(other[..4].try_into().ok()?);
+            Some(Arrival::Resume(stream, needed))
+        }
+        WATCH => {
+            let mut other = [0; 16];
+            stream.read_exact(&mut other).ok()?;
+            if other[..8] != state.to_be_bytes() {
+                let _ = write_message(&mut stream, BYE, 0, &[0; 8]);
+                return None;
+            }
+            write_message(&mut stream, WATCH, 0, &session.to_be_bytes()).ok()?;
+            stream.set_read_timeout(None).ok()?;
+            Spectator::new(stream).ok().map(Arrival::Watch)
+        }
+        _ => None,
+    }
+}
+
+/// Wait for the other player to come back, until `deadline`
+fn wait_again(
+    resumes: &Mutex<Receiver<(TcpStream, u32)>>,
+    session: u64,
+    needed: u32,
+    deadline: Instant,
+) -> Resumed {
+    let resumes = resumes.lock().unwrap_or_else(|err| err.into_inner());
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        let (mut stream, other) = match resumes.recv_timeout(timeout) {
+            Ok(resumed) => resumed,
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => return None,
+        };
+        if write_message(&mut stream, RESUME, needed, &session.to_be_bytes()).is_ok() {
+            stream.set_read_timeout(None).ok()?;
+            return Some((stream, other));
+        }
+    }
+}
+
+/// Connect to the other player again, until `deadline`
+fn connect_again(address: &str, session: u64, needed: u32, deadline: Instant) -> Resumed {
+    while Instant::now() < deadline {
+        let stream = address
+            .to_socket_addrs()
+            .ok()
+            .and_then(|mut addresses| addresses.next())
+            .and_then(|address| TcpStream::connect_timeout(&address, Duration::from_secs(1)).ok());
+        if let Some(resumed) = stream.and_then(|stream| handshake(stream, session, needed)) {
+            return Some(resumed);
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+    None
+}
+
+/// Tell each other which game this is and the first frame of keys needed
+fn handshake(mut stream: TcpStream, session: u64, needed: u32) -> Resumed {
+    stream.set_nonblocking(false).ok()?;
+    stream.set_nodelay(true).ok()?;
+    stream.set_read_timeout(Some(Duration::from_secs(2))).ok()?;
+    write_message(&mut stream, RESUME, needed, &session.to_be_bytes()).ok()?;
+
+    let mut other = [0; 13];
+    stream.read_exact(&mut other).ok()?;
+    if other[0] != RESUME || other[5..] != session.to_be_bytes() {
+        return None;
+    }
+    stream.set_read_timeout(None).ok()?;
+    Some((stream, u32::from_be_bytes(other[1..5].try_into().ok()?)))
+}
+
+/// Who said it, and up to 255 bytes of what they said
+fn chat_payload(from: u8, text: &str) -> Vec<u8> {
+    let mut end = text.len().min(u8::MAX as usize);
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    let mut payload = vec![from, end as u8];
+    payload.extend(&text.as_bytes()[..end]);
+    payload
+}
+
+fn message(tag: u8, frame: u32, payload: &[u8]) -> Vec<u8> {
+    let mut message = vec![tag];
+    message.extend(frame.to_be_bytes());
+    message.extend(payload);
+    message
+}
+
+fn write_message(writer: &mut impl Write, tag: u8, frame: u32, payload: &[u8]) -> io::Result<()> {
+    writer.write_all(&message(tag, frame, payload))
+}
+
+fn read_message(reader: &mut impl Read) -> io::Result<Message> {
+    let mut header = [0; 5];
+    reader.read_exact(&mut header)?;
+    let frame = u32::from_be_bytes(header[1..].try_into().unwrap());
+
+    match header[0] {
+        KEYS => {
+            let mut keys = [0; 2];
+            reader.read_exact(&mut keys)?;
+            Ok(Message::Keys(frame, u16::from_be_bytes(keys)))
+        }
+        HASH | PING | PONG => {
+            let mut value = [0; 8];
+            reader.read_exact(&mut value)?;
+            let value = u64::from_be_bytes(value);
+            Ok(match header[0] {
+                HASH => Message::Hash(frame, value),
+                PING => Message::Ping(frame, value),
+                _ => Message::Pong(value),
+            })
+        }
+        CHAT => {
+            let mut header = [0; 2];
+            reader.read_exact(&mut header)?;
+            let mut text = vec![0; header[1] as usize];
+            reader.read_exact(&mut text)?;
+            let text = String::from_utf8_lossy(&text).into_owned();
+            Ok(Message::Chat(header[0], text))
+        }
+        BYE => Ok(Message::Bye),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "unknown message",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mode() {
+        assert_eq!("host:7000".parse(), Ok(NetplayMode::Host(7000)));
+        assert_eq!(
+            "192.168.0.2:7000".parse(),
+            Ok(NetplayMode::Join("192.168.0.2:7000".to_owned()))
+        );
+        assert_eq!(
+            "watch:192.168.0.2:7000".parse(),
+            Ok(NetplayMode::Watch("192.168.0.2:7000".to_owned()))
+        );
+        assert!("7000".parse::<NetplayMode>().is_err());
+        assert!("watch:192.168.0.2:port".parse::<NetplayMode>().is_err());
+        assert!("host:port".parse::<NetplayMode>().is_err());
+    }
+
+    // 0x200: v0 := random 0xFF, jump 0x200
+    const ROM: [u8; 4] = [0xC0, 0xFF, 0x12, 0x00];
+
+    /// Both sides of a game on loopback, with their emulators
+    fn connect_pair() -> (Netplay, Emulator, Netplay, Emulator) {
+        let (_, pair) = connect_pair_on_port();
+        pair
+    }
+
+    fn connect_pair_on_port() -> (u16, (Netplay, Emulator, Netplay, Emulator)) {
+        let mut host_emu = Emulator::load_rom(&ROM[..]).unwrap();
+        let mut guest_emu = Emulator::load_rom(&ROM[..]).unwrap();
+
+        // find a free port first
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let host = thread::spawn(move || {
+            let netplay = Netplay::connect(&NetplayMode::Host(port), &mut host_emu).unwrap();
+            (netplay, host_emu)
+        });
+
+        let guest = loop {
+            match Netplay::connect(
+                &NetplayMode::Join(format!("127.0.0.1:{}", port)),
+                &mut guest_emu,
+            ) {
+                Ok(netplay) => break netplay,
+                Err(_) => thread::sleep(std::time::Duration::from_millis(10)),
+            }
+        };
+        let (host, host_emu) = host.join().unwrap();
+        (port, (host, host_emu, guest, guest_emu))
+    }
+
+    /// Run `frames` frames on both sides, returning the keys each played
+    fn play(
+        (host, host_emu): (&mut Netplay, &Emulator),
+        (guest, guest_emu): (&mut Netplay, &Emulator),
+        frames: usize,
+    ) -> (Vec<u16>, Vec<u16>) {
+        let (mut host_played, mut guest_played) = (Vec::new(), Vec::new());
+        while host_played.len() < frames || guest_played.len() < frames {
+            if host_played.len() < frames {
+                if let Some(keys) = host.next_keys().unwrap() {
+                    host_played.push(keys);
+                    host.ran(host_emu).unwrap();
+                }
+            }
+            if guest_played.len() < frames {
+                if let Some(keys) = guest.next_keys().unwrap() {
+                    guest_played.push(keys);
+                    guest.ran(guest_emu).unwrap();
+                }
+            }
+            thread::sleep(Duration::from_micros(100));
+        }
+        (host_played, guest_played)
+    }
+
+    #[test]
+    fn test_lockstep() {
+        let (mut host, mut host_emu, mut guest, mut guest_emu) = connect_pair();
+
+        // both got the same seed
+        host_emu.execute().unwrap();
+        guest_emu.execute().unwrap();
+        assert_eq!(host_emu.V[0], guest_emu.V[0]);
+
+        // a key pressed now is played a few frames later, on both sides
+        host.set_key(5, true);
+        guest.set_key(0xA, true);
+        let mut played = Vec::new();
+        while played.len() < DELAY as usize + 2 {
+            if let Some(keys) = guest.next_keys().unwrap() {
+                played.push(keys);
+            }
+            let _ = host.next_keys().unwrap();
+        }
+        assert_eq!(played[0], 0);
+        assert_eq!(played[DELAY as usize], 1 << 5 | 1 << 0xA);
+    }
+
+    #[test]
+    fn test_quality() {
+        let (mut host, host_emu, mut guest, guest_emu) = connect_pair();
+        assert_eq!(guest.quality().ping, None);
+
+        play(
+            (&mut host, &host_emu),
+            (&mut guest, &guest_emu),
+            PING_INTERVAL as usize + 1,
+        );
+        // the last pong may still be on its way
+        thread::sleep(Duration::from_millis(50));
+        play((&mut host, &host_emu), (&mut guest, &guest_emu), 1);
+
+        let quality = guest.quality();
+        assert!(quality.ping.is_some());
+        assert_eq!(quality.delay, DELAY);
+        assert!(!quality.reconnecting);
+    }
+
+    #[test]
+    fn test_bogus_pong() {
+        let (_host, _host_emu, mut guest, _guest_emu) = connect_pair();
+
+        // a pong stamped after now is dropped, instead of overflowing
+        guest.receive(Message::Pong(u64::MAX)).unwrap();
+        assert_eq!(guest.quality().ping, None);
+
+        guest.receive(Message::Pong(0)).unwrap();
+        guest.receive(Message::Pong(u64::MAX)).unwrap();
+        assert!(guest.quality().ping.is_some());
+    }
+
+    #[test]
+    fn test_delay() {
+        let (mut host, host_emu, mut guest, guest_emu) = connect_pair();
+        assert_eq!(guest.set_delay(0), 1);
+        assert_eq!(guest.set_delay(100), MAX_DELAY);
+
+        // each side picks its own delay, and both still play the same keys
+        host.set_key(5, true);
+        play((&mut host, &host_emu), (&mut guest, &guest_emu), 1);
+        guest.set_key(0xA, true);
+        let (host_played, guest_played) =
+            play((&mut host, &host_emu), (&mut guest, &guest_emu), 30);
+        assert_eq!(host_played, guest_played);
+        assert_eq!(guest_played[DELAY as usize - 1], 1 << 5);
+        // these frames start on frame 1
+        let pressed = guest_played.iter().position(|keys| keys & 1 << 0xA != 0);
+        assert_eq!(pressed, Some(MAX_DELAY as usize - 1));
+
+        guest.set_delay(2);
+        guest.set_key(0xA, false);
+        let (host_played, guest_played) =
+            play((&mut host, &host_emu), (&mut guest, &guest_emu), 30);
+        assert_eq!(host_played, guest_played);
+        assert_eq!(guest_played.last(), Some(&(1 << 5)));
+    }
+
+    #[test]
+    fn test_reconnect() {
+        let (mut host, host_emu, mut guest, guest_emu) = connect_pair();
+        host.set_key(5, true);
+        play((&mut host, &host_emu), (&mut guest, &guest_emu), 10);
+
+        // the connection drops, and comes back with the keys sent meanwhile
+        guest.link.close();
+        guest.set_key(0xA, true);
+        let (host_played, guest_played) =
+            play((&mut host, &host_emu), (&mut guest, &guest_emu), 30);
+        assert_eq!(host_played, guest_played);
+        assert_eq!(guest_played.last(), Some(&(1 << 5 | 1 << 0xA)));
+        assert!(!guest.quality().reconnecting);
+
+        // quitting is not a drop, so the other side does not wait for it
+        drop(host);
+        let started = Instant::now();
+        while guest.next_keys().is_ok() {
+            assert!(started.elapsed() < RECONNECT_TIME);
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn test_watch() {
+        let (port, (mut host, host_emu, mut guest, guest_emu)) = connect_pair_on_port();
+        let address = format!("127.0.0.1:{}", port);
+
+        // spectators of another rom are turned away
+        let mut other = Emulator::load_rom(&[0x12, 0x00][..]).unwrap();
+        assert!(Netplay::connect(&NetplayMode::Watch(address.clone()), &mut other).is_err());
+
+        host.set_key(5, true);
+        let (played, _) = play((&mut host, &host_emu), (&mut guest, &guest_emu), 20);
+
+        // a spectator coming late gets every frame, and plays no keys
+        let mut emu = Emulator::load_rom(&ROM[..]).unwrap();
+        let mut spectator = Netplay::connect(&NetplayMode::Watch(address), &mut emu).unwrap();
+        assert_eq!(spectator.session, host.session);
+        spectator.set_key(0xA, true);
+        let (more, _) = play((&mut host, &host_emu), (&mut guest, &guest_emu), 10);
+
+        let mut watched = Vec::new();
+        let started = Instant::now();
+        while watched.len() < played.len() + more.len() {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            if let Some(keys) = spectator.next_keys().unwrap() {
+                watched.push(keys);
+            }
+        }
+        assert_eq!(watched, [played, more].concat());
+        assert_eq!(spectator.quality().lines(), ["watching"]);
+
+        // once the host quits, the game is over
+        drop(host);
+        while spectator.next_keys().is_ok() {
+            assert!(started.elapsed() < Duration::from_secs(5));
+        }
+    }
+
+    #[test]
+    fn test_chat() {
+        let (port, (mut host, host_emu, mut guest, guest_emu)) = connect_pair_on_port();
+        let mut emu = Emulator::load_rom(&ROM[..]).unwrap();
+        let address = format!("127.0.0.1:{}", port);
+        let mut spectator = Netplay::connect(&NetplayMode::Watch(address), &mut emu).unwrap();
+        play((&mut host, &host_emu), (&mut guest, &guest_emu), 1);
+
+        // spectators hear both players, but cannot talk
+        host.say("ready?");
+        guest.say(&"é".repeat(200));
+        spectator.say("hello");
+
+        let (mut host_heard, mut guest_heard, mut spectator_heard) = (vec![], vec![], vec![]);
+        let started = Instant::now();
+        while spectator_heard.len() < 2 {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            play((&mut host, &host_emu), (&mut guest, &guest_emu), 1);
+            let _ = spectator.next_keys().unwrap();
+            host_heard.extend(host.heard());
+            guest_heard.extend(guest.heard());
+            spectator_heard.extend(spectator.heard());
+        }
+        // the text is cut to 255 bytes, on a character boundary
+        let long = format!("guest: {}", "é".repeat(127));
+        assert_eq!(guest_heard, ["host: ready?"]);
+        assert_eq!(host_heard, std::slice::from_ref(&long));
+        assert_eq!(spectator_heard, ["host: ready?".to_owned(), long]);
+    }
+
+    /// With rollback, run the next frame, and the frames to run again
+    /// first; `timeline` has the keys each frame ran with last
+    fn run_ahead(netplay: &mut Netplay, emu: &Emulator, timeline: &mut Vec<u16>) -> bool {
+        let rolled_back = match netplay.reconcile().unwrap() {
+            Some((from, keys)) => {
+                timeline.truncate(from as usize);
+                for (frame, keys) in (from..).zip(keys) {
+                    timeline.push(keys);
+                    netplay.ran_again(frame, emu).unwrap();
+                }
+                true
+            }
+            None => false,
+        };
+        if let Some(keys) = netplay.next_keys().unwrap() {
+            timeline.push(keys);
+            netplay.ran(emu).unwrap();
+        }
+        rolled_back
+    }
+
+    #[test]
+    fn test_rollback() {
+        let (mut host, host_emu, mut guest, guest_emu) = connect_pair();
+        host.set_rollback(true);
+        guest.set_rollback(true);
+        assert_eq!(guest.delay(), 1);
+
+        // the guest runs ahead on its own, but only so far past the first
+        // frames, which nobody pressed anything on
+        let mut guest_timeline = Vec::new();
+        for _ in 0..DELAY + MAX_ROLLBACK + 1 {
+            run_ahead(&mut guest, &guest_emu, &mut guest_timeline);
+        }
+        assert_eq!(guest_timeline, [0; (DELAY + MAX_ROLLBACK) as usize]);
+        assert_eq!(guest.quality().guessed, Some(MAX_ROLLBACK));
+
+        // the guesses of both are wrong once the other presses a key, and
+        // in the end both played the same keys
+        let mut host_timeline = Vec::new();
+        let mut rolled_back = false;
+        host.set_key(5, true);
+        let started = Instant::now();
+        while host.confirmed() < 40 || guest.confirmed() < 40 {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            if host_timeline.len() == 20 {
+                guest.set_key(0xA, true);
+            }
+            run_ahead(&mut host, &host_emu, &mut host_timeline);
+            rolled_back |= run_ahead(&mut guest, &guest_emu, &mut guest_timeline);
+            thread::sleep(Duration::from_micros(100));
+        }
+        assert!(rolled_back);
+        assert_eq!(host_timeline[..40], guest_timeline[..40]);
+        assert_eq!(guest_timeline[DELAY as usize], 1 << 5);
+        assert_eq!(guest_timeline[39], 1 << 5 | 1 << 0xA);
+    }
+}
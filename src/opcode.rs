@@ -0,0 +1,569 @@
+use std::{fmt, str::FromStr};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Which instruction set the rom is written for
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum Variant {
+    #[default]
+    Chip8,
+    /// CHIP-8X, with colors and a second keypad (see [`crate::chip8x`])
+    Chip8X,
+    /// SUPER-CHIP, with a high resolution mode and scrolling
+    SChip,
+    /// XO-CHIP, which extends SUPER-CHIP
+    XoChip,
+}
+
+impl FromStr for Variant {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "chip8" => Ok(Variant::Chip8),
+            "chip8x" => Ok(Variant::Chip8X),
+            "schip" => Ok(Variant::SChip),
+            "xochip" => Ok(Variant::XoChip),
+            _ => Err(format!("unknown variant: {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for Variant {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Variant::Chip8 => write!(f, "CHIP-8"),
+            Variant::Chip8X => write!(f, "CHIP-8X"),
+            Variant::SChip => write!(f, "SCHIP"),
+            Variant::XoChip => write!(f, "XO-CHIP"),
+        }
+    }
+}
+
+/// A decoded CHIP-8 instruction.
+///
+/// Register operands are register numbers (0x0 - 0xF); `Display`
+/// prints the instruction in Octo syntax.
+///
+/// ```
+/// use rc8::opcode::Opcode;
+///
+/// let op = Opcode::decode(0x8124).unwrap();
+/// assert_eq!(op, Opcode::AddReg(1, 2));
+/// assert_eq!(op.to_string(), "v1 += v2");
+/// assert_eq!(op.encode(), 0x8124);
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Opcode {
+    /// 0NNN - call machine code (unsupported)
+    Sys(u16),
+    /// 00E0
+    Clear,
+    /// 00EE
+    Return,
+    /// 1NNN
+    Jump(u16),
+    /// 2NNN
+    Call(u16),
+    /// 3XNN - skip if VX == NN
+    SkipEqByte(u8, u8),
+    /// 4XNN - skip if VX != NN
+    SkipNeqByte(u8, u8),
+    /// 5XY0 - skip if VX == VY
+    SkipEqReg(u8, u8),
+    /// 6XNN
+    LoadByte(u8, u8),
+    /// 7XNN
+    AddByte(u8, u8),
+    /// 8XY0
+    LoadReg(u8, u8),
+    /// 8XY1
+    Or(u8, u8),
+    /// 8XY2
+    And(u8, u8),
+    /// 8XY3
+    Xor(u8, u8),
+    /// 8XY4
+    AddReg(u8, u8),
+    /// 8XY5 - VX = VX - VY
+    Sub(u8, u8),
+    /// 8XY6
+    ShiftRight(u8, u8),
+    /// 8XY7 - VX = VY - VX
+    SubN(u8, u8),
+    /// 8XYE
+    ShiftLeft(u8, u8),
+    /// 9XY0 - skip if VX != VY
+    SkipNeqReg(u8, u8),
+    /// ANNN
+    LoadI(u16),
+    /// BNNN
+    JumpV0(u16),
+    /// CXNN
+    Random(u8, u8),
+    /// DXYN
+    Draw(u8, u8, u8),
+    /// EX9E - skip if the key in VX is pressed
+    SkipKey(u8),
+    /// EXA1 - skip if the key in VX is not pressed
+    SkipNotKey(u8),
+    /// FX07
+    GetDelay(u8),
+    /// FX0A
+    WaitKey(u8),
+    /// FX15
+    SetDelay(u8),
+    /// FX18
+    SetSound(u8),
+    /// FX1E
+    AddI(u8),
+    /// FX29
+    LoadFont(u8),
+    /// FX33
+    Bcd(u8),
+    /// FX55
+    Save(u8),
+    /// FX65
+    Load(u8),
+    /// 02A0 - cycle the background color (CHIP-8X)
+    CycleBackground,
+    /// 5XY1 - VX += VY, each nibble on its own, modulo 8 (CHIP-8X)
+    AddNibbles(u8, u8),
+    /// BXY0 - color the zones given by VX and VX+1 with VY (CHIP-8X)
+    ColorZones(u8, u8),
+    /// BXYN - color N rows on VX, VX+1 with VY (CHIP-8X)
+    ColorRows(u8, u8, u8),
+    /// EXF2 - skip if the key in VX is pressed on the second keypad (CHIP-8X)
+    SkipKey2(u8),
+    /// EXF5 - skip if the key in VX is not pressed on the second keypad (CHIP-8X)
+    SkipNotKey2(u8),
+    /// FXF8 - send VX to the tone generator (CHIP-8X)
+    SetTone(u8),
+    /// 00CN - scroll the screen down N pixels (SCHIP)
+    ScrollDown(u8),
+    /// 00DN - scroll the screen up N pixels (XO-CHIP)
+    ScrollUp(u8),
+    /// 00FB - scroll the screen 4 pixels to the right (SCHIP)
+    ScrollRight,
+    /// 00FC - scroll the screen 4 pixels to the left (SCHIP)
+    ScrollLeft,
+    /// 00FE - low resolution, 64x32 (SCHIP)
+    Lores,
+    /// 00FF - high resolution, 128x64 (SCHIP)
+    Hires,
+    /// FX30 - point I to the big (8x10) sprite of the digit in VX (SCHIP)
+    LoadBigFont(u8),
+    /// FX75 - save V0 to VX on the RPL user flags (SCHIP)
+    SaveFlags(u8),
+    /// FX85 - load V0 to VX from the RPL user flags (SCHIP)
+    LoadFlags(u8),
+    /// FN01 - select the planes (a bit each) the sprites are drawn on (XO-CHIP)
+    SelectPlanes(u8),
+}
+
+impl Opcode {
+    /// Decode a big-endian instruction word; unknown instructions return `None`
+    pub fn decode(word: u16) -> Option<Self> {
+        let x = ((word >> 8) & 0xF) as u8;
+        let y = ((word >> 4) & 0xF) as u8;
+        let n = (word & 0xF) as u8;
+        let nn = (word & 0xFF) as u8;
+        let nnn = word & 0xFFF;
+
+        let op = match (word >> 12, n) {
+            _ if word == 0x00E0 => Opcode::Clear,
+            _ if word == 0x00EE => Opcode::Return,
+            (0x0, _) => Opcode::Sys(nnn),
+            (0x1, _) => Opcode::Jump(nnn),
+            (0x2, _) => Opcode::Call(nnn),
+            (0x3, _) => Opcode::SkipEqByte(x, nn),
+            (0x4, _) => Opcode::SkipNeqByte(x, nn),
+            (0x5, 0x0) => Opcode::SkipEqReg(x, y),
+            (0x6, _) => Opcode::LoadByte(x, nn),
+            (0x7, _) => Opcode::AddByte(x, nn),
+            (0x8, 0x0) => Opcode::LoadReg(x, y),
+            (0x8, 0x1) => Opcode::Or(x, y),
+            (0x8, 0x2) => Opcode::And(x, y),
+            (0x8, 0x3) => Opcode::Xor(x, y),
+            (0x8, 0x4) => Opcode::AddReg(x, y),
+            (0x8, 0x5) => Opcode::Sub(x, y),
+            (0x8, 0x6) => Opcode::ShiftRight(x, y),
+            (0x8, 0x7) => Opcode::SubN(x, y),
+            (0x8, 0xE) => Opcode::ShiftLeft(x, y),
+            (0x9, 0x0) => Opcode::SkipNeqReg(x, y),
+            (0xA, _) => Opcode::LoadI(nnn),
+            (0xB, _) => Opcode::JumpV0(nnn),
+            (0xC, _) => Opcode::Random(x, nn),
+            (0xD, _) => Opcode::Draw(x, y, n),
+            (0xE, _) if nn == 0x9E => Opcode::SkipKey(x),
+            (0xE, _) if nn == 0xA1 => Opcode::SkipNotKey(x),
+            (0xF, _) => match nn {
+                0x07 => Opcode::GetDelay(x),
+                0x0A => Opcode::WaitKey(x),
+                0x15 => Opcode::SetDelay(x),
+                0x18 => Opcode::SetSound(x),
+                0x1E => Opcode::AddI(x),
+                0x29 => Opcode::LoadFont(x),
+                0x33 => Opcode::Bcd(x),
+                0x55 => Opcode::Save(x),
+                0x65 => Opcode::Load(x),
+                _ => return None,
+            },
+            _ => return None,
+        };
+
+        Some(op)
+    }
+
+    /// Decode an instruction of the given variant; the others add a few
+    /// instructions (CHIP-8X also replaces BNNN), keeping the rest
+    pub fn decode_variant(word: u16, variant: Variant) -> Option<Self> {
+        match variant {
+            Variant::Chip8 => Opcode::decode(word),
+            Variant::Chip8X => Opcode::decode_chip8x(word),
+            Variant::SChip | Variant::XoChip => {
+                let n = (word & 0xF) as u8;
+                let op = match word & 0xFFF0 {
+                    0x00C0 => Opcode::ScrollDown(n),
+                    0x00D0 if variant == Variant::XoChip => Opcode::ScrollUp(n),
+                    _ => match word {
+                        0x00FB => Opcode::ScrollRight,
+                        0x00FC => Opcode::ScrollLeft,
+                        0x00FE => Opcode::Lores,
+                        0x00FF => Opcode::Hires,
+                        _ if word & 0xF0FF == 0xF030 => {
+                            Opcode::LoadBigFont((word >> 8) as u8 & 0xF)
+                        }
+                        _ if word & 0xF0FF == 0xF075 => Opcode::SaveFlags((word >> 8) as u8 & 0xF),
+                        _ if word & 0xF0FF == 0xF085 => Opcode::LoadFlags((word >> 8) as u8 & 0xF),
+                        _ if word & 0xF0FF == 0xF001 && variant == Variant::XoChip => {
+                            Opcode::SelectPlanes((word >> 8) as u8 & 0xF)
+                        }
+                        _ => return Opcode::decode(word),
+                    },
+                };
+                Some(op)
+            }
+        }
+    }
+
+    fn decode_chip8x(word: u16) -> Option<Self> {
+        let x = ((word >> 8) & 0xF) as u8;
+        let y = ((word >> 4) & 0xF) as u8;
+        let n = (word & 0xF) as u8;
+
+        let op = match (word >> 12, n, word & 0xFF) {
+            _ if word == 0x02A0 => Opcode::CycleBackground,
+            (0x5, 0x1, _) => Opcode::AddNibbles(x, y),
+            (0xB, 0x0, _) => Opcode::ColorZones(x, y),
+            (0xB, _, _) => Opcode::ColorRows(x, y, n),
+            (0xE, _, 0xF2) => Opcode::SkipKey2(x),
+            (0xE, _, 0xF5) => Opcode::SkipNotKey2(x),
+            (0xF, _, 0xF8) => Opcode::SetTone(x),
+            _ => return Opcode::decode(word),
+        };
+
+        Some(op)
+    }
+
+    /// Encode the instruction back into a big-endian word
+    pub fn encode(&self) -> u16 {
+        let xy = |base: u16, x: u8, y: u8, n: u16| base | (x as u16) << 8 | (y as u16) << 4 | n;
+        let xnn = |base: u16, x: u8, nn: u8| base | (x as u16) << 8 | nn as u16;
+
+        match *self {
+            Opcode::Sys(nnn) => nnn & 0xFFF,
+            Opcode::Clear => 0x00E0,
+            Opcode::Return => 0x00EE,
+            Opcode::Jump(nnn) => 0x1000 | (nnn & 0xFFF),
+            Opcode::Call(nnn) => 0x2000 | (nnn & 0xFFF),
+            Opcode::SkipEqByte(x, nn) => xnn(0x3000, x, nn),
+            Opcode::SkipNeqByte(x, nn) => xnn(0x4000, x, nn),
+            Opcode::SkipEqReg(x, y) => xy(0x5000, x, y, 0x0),
+            Opcode::LoadByte(x, nn) => xnn(0x6000, x, nn),
+            Opcode::AddByte(x, nn) => xnn(0x7000, x, nn),
+            Opcode::LoadReg(x, y) => xy(0x8000, x, y, 0x0),
+            Opcode::Or(x, y) => xy(0x8000, x, y, 0x1),
+            Opcode::And(x, y) => xy(0x8000, x, y, 0x2),
+            Opcode::Xor(x, y) => xy(0x8000, x, y, 0x3),
+            Opcode::AddReg(x, y) => xy(0x8000, x, y, 0x4),
+            Opcode::Sub(x, y) => xy(0x8000, x, y, 0x5),
+            Opcode::ShiftRight(x, y) => xy(0x8000, x, y, 0x6),
+            Opcode::SubN(x, y) => xy(0x8000, x, y, 0x7),
+            Opcode::ShiftLeft(x, y) => xy(0x8000, x, y, 0xE),
+            Opcode::SkipNeqReg(x, y) => xy(0x9000, x, y, 0x0),
+            Opcode::LoadI(nnn) => 0xA000 | (nnn & 0xFFF),
+            Opcode::JumpV0(nnn) => 0xB000 | (nnn & 0xFFF),
+            Opcode::Random(x, nn) => xnn(0xC000, x, nn),
+            Opcode::Draw(x, y, n) => xy(0xD000, x, y, n as u16),
+            Opcode::SkipKey(x) => xnn(0xE000, x, 0x9E),
+            Opcode::SkipNotKey(x) => xnn(0xE000, x, 0xA1),
+            Opcode::GetDelay(x) => xnn(0xF000, x, 0x07),
+            Opcode::WaitKey(x) => xnn(0xF000, x, 0x0A),
+            Opcode::SetDelay(x) => xnn(0xF000, x, 0x15),
+            Opcode::SetSound(x) => xnn(0xF000, x, 0x18),
+            Opcode::AddI(x) => xnn(0xF000, x, 0x1E),
+            Opcode::LoadFont(x) => xnn(0xF000, x, 0x29),
+            Opcode::Bcd(x) => xnn(0xF000, x, 0x33),
+            Opcode::Save(x) => xnn(0xF000, x, 0x55),
+            Opcode::Load(x) => xnn(0xF000, x, 0x65),
+            Opcode::CycleBackground => 0x02A0,
+            Opcode::AddNibbles(x, y) => xy(0x5000, x, y, 0x1),
+            Opcode::ColorZones(x, y) => xy(0xB000, x, y, 0x0),
+            Opcode::ColorRows(x, y, n) => xy(0xB000, x, y, n as u16),
+            Opcode::SkipKey2(x) => xnn(0xE000, x, 0xF2),
+            Opcode::SkipNotKey2(x) => xnn(0xE000, x, 0xF5),
+            Opcode::SetTone(x) => xnn(0xF000, x, 0xF8),
+            Opcode::ScrollDown(n) => 0x00C0 | n as u16,
+            Opcode::ScrollUp(n) => 0x00D0 | n as u16,
+            Opcode::ScrollRight => 0x00FB,
+            Opcode::ScrollLeft => 0x00FC,
+            Opcode::Lores => 0x00FE,
+            Opcode::Hires => 0x00FF,
+            Opcode::LoadBigFont(x) => xnn(0xF000, x, 0x30),
+            Opcode::SaveFlags(x) => xnn(0xF000, x, 0x75),
+            Opcode::LoadFlags(x) => xnn(0xF000, x, 0x85),
+            Opcode::SelectPlanes(n) => xnn(0xF000, n, 0x01),
+        }
+    }
+
+    /// The instruction pattern, like `8XY4`
+    pub fn pattern(&self) -> &'static str {
+        match self {
+            Opcode::Sys(_) => "0NNN",
+            Opcode::Clear => "00E0",
+            Opcode::Return => "00EE",
+            Opcode::Jump(_) => "1NNN",
+            Opcode::Call(_) => "2NNN",
+            Opcode::SkipEqByte(_, _) => "3XNN",
+            Opcode::SkipNeqByte(_, _) => "4XNN",
+            Opcode::SkipEqReg(_, _) => "5XY0",
+            Opcode::LoadByte(_, _) => "6XNN",
+            Opcode::AddByte(_, _) => "7XNN",
+            Opcode::LoadReg(_, _) => "8XY0",
+            Opcode::Or(_, _) => "8XY1",
+            Opcode::And(_, _) => "8XY2",
+            Opcode::Xor(_, _) => "8XY3",
+            Opcode::AddReg(_, _) => "8XY4",
+            Opcode::Sub(_, _) => "8XY5",
+            Opcode::ShiftRight(_, _) => "8XY6",
+            Opcode::SubN(_, _) => "8XY7",
+            Opcode::ShiftLeft(_, _) => "8XYE",
+            Opcode::SkipNeqReg(_, _) => "9XY0",
+            Opcode::LoadI(_) => "ANNN",
+            Opcode::JumpV0(_) => "BNNN",
+            Opcode::Random(_, _) => "CXNN",
+            Opcode::Draw(_, _, _) => "DXYN",
+            Opcode::SkipKey(_) => "EX9E",
+            Opcode::SkipNotKey(_) => "EXA1",
+            Opcode::GetDelay(_) => "FX07",
+            Opcode::WaitKey(_) => "FX0A",
+            Opcode::SetDelay(_) => "FX15",
+            Opcode::SetSound(_) => "FX18",
+            Opcode::AddI(_) => "FX1E",
+            Opcode::LoadFont(_) => "FX29",
+            Opcode::Bcd(_) => "FX33",
+            Opcode::Save(_) => "FX55",
+            Opcode::Load(_) => "FX65",
+            Opcode::CycleBackground => "02A0",
+            Opcode::AddNibbles(_, _) => "5XY1",
+            Opcode::ColorZones(_, _) => "BXY0",
+            Opcode::ColorRows(_, _, _) => "BXYN",
+            Opcode::SkipKey2(_) => "EXF2",
+            Opcode::SkipNotKey2(_) => "EXF5",
+            Opcode::SetTone(_) => "FXF8",
+            Opcode::ScrollDown(_) => "00CN",
+            Opcode::ScrollUp(_) => "00DN",
+            Opcode::ScrollRight => "00FB",
+            Opcode::ScrollLeft => "00FC",
+            Opcode::Lores => "00FE",
+            Opcode::Hires => "00FF",
+            Opcode::LoadBigFont(_) => "FX30",
+            Opcode::SaveFlags(_) => "FX75",
+            Opcode::LoadFlags(_) => "FX85",
+            Opcode::SelectPlanes(_) => "FN01",
+        }
+    }
+
+    /// Whether the instruction may skip the next one
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Opcode::SkipEqByte(..)
+                | Opcode::SkipNeqByte(..)
+                | Opcode::SkipEqReg(..)
+                | Opcode::SkipNeqReg(..)
+                | Opcode::SkipKey(_)
+                | Opcode::SkipNotKey(_)
+                | Opcode::SkipKey2(_)
+                | Opcode::SkipNotKey2(_)
+        )
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        // Octo writes skips as the condition for running the next instruction,
+        // so the comparisons are the opposite of the opcode names
+        match *self {
+            Opcode::Sys(nnn) => write!(f, "0x{:02X} 0x{:02X}", nnn >> 8, nnn & 0xFF),
+            Opcode::Clear => write!(f, "clear"),
+            Opcode::Return => write!(f, "return"),
+            Opcode::Jump(nnn) => write!(f, "jump 0x{:03X}", nnn),
+            Opcode::Call(nnn) => write!(f, ":call 0x{:03X}", nnn),
+            Opcode::SkipEqByte(x, nn) => write!(f, "if v{:x} != 0x{:02X} then", x, nn),
+            Opcode::SkipNeqByte(x, nn) => write!(f, "if v{:x} == 0x{:02X} then", x, nn),
+            Opcode::SkipEqReg(x, y) => write!(f, "if v{:x} != v{:x} then", x, y),
+            Opcode::LoadByte(x, nn) => write!(f, "v{:x} := 0x{:02X}", x, nn),
+            Opcode::AddByte(x, nn) => write!(f, "v{:x} += 0x{:02X}", x, nn),
+            Opcode::LoadReg(x, y) => write!(f, "v{:x} := v{:x}", x, y),
+            Opcode::Or(x, y) => write!(f, "v{:x} |= v{:x}", x, y),
+            Opcode::And(x, y) => write!(f, "v{:x} &= v{:x}", x, y),
+            Opcode::Xor(x, y) => write!(f, "v{:x} ^= v{:x}", x, y),
+            Opcode::AddReg(x, y) => write!(f, "v{:x} += v{:x}", x, y),
+            Opcode::Sub(x, y) => write!(f, "v{:x} -= v{:x}", x, y),
+            Opcode::ShiftRight(x, y) => write!(f, "v{:x} >>= v{:x}", x, y),
+            Opcode::SubN(x, y) => write!(f, "v{:x} =- v{:x}", x, y),
+            Opcode::ShiftLeft(x, y) => write!(f, "v{:x} <<= v{:x}", x, y),
+            Opcode::SkipNeqReg(x, y) => write!(f, "if v{:x} == v{:x} then", x, y),
+            Opcode::LoadI(nnn) => write!(f, "i := 0x{:03X}", nnn),
+            Opcode::JumpV0(nnn) => write!(f, "jump0 0x{:03X}", nnn),
+            Opcode::Random(x, nn) => write!(f, "v{:x} := random 0x{:02X}", x, nn),
+            Opcode::Draw(x, y, n) => write!(f, "sprite v{:x} v{:x} 0x{:X}", x, y, n),
+            Opcode::SkipKey(x) => write!(f, "if v{:x} -key then", x),
+            Opcode::SkipNotKey(x) => write!(f, "if v{:x} key then", x),
+            Opcode::GetDelay(x) => write!(f, "v{:x} := delay", x),
+            Opcode::WaitKey(x) => write!(f, "v{:x} := key", x),
+            Opcode::SetDelay(x) => write!(f, "delay := v{:x}", x),
+            Opcode::SetSound(x) => write!(f, "buzzer := v{:x}", x),
+            Opcode::AddI(x) => write!(f, "i += v{:x}", x),
+            Opcode::LoadFont(x) => write!(f, "i := hex v{:x}", x),
+            Opcode::Bcd(x) => write!(f, "bcd v{:x}", x),
+            Opcode::Save(x) => write!(f, "save v{:x}", x),
+            Opcode::Load(x) => write!(f, "load v{:x}", x),
+            Opcode::ScrollDown(n) => write!(f, "scroll-down 0x{:X}", n),
+            Opcode::ScrollUp(n) => write!(f, "scroll-up 0x{:X}", n),
+            Opcode::ScrollRight => write!(f, "scroll-right"),
+            Opcode::ScrollLeft => write!(f, "scroll-left"),
+            Opcode::Lores => write!(f, "lores"),
+            Opcode::Hires => write!(f, "hires"),
+            Opcode::LoadBigFont(x) => write!(f, "i := bighex v{:x}", x),
+            Opcode::SaveFlags(x) => write!(f, "saveflags v{:x}", x),
+            Opcode::LoadFlags(x) => write!(f, "loadflags v{:x}", x),
+            Opcode::SelectPlanes(n) => write!(f, "plane {}", n),
+
+            // Octo knows nothing about CHIP-8X, so these stay as raw bytes
+            Opcode::CycleBackground
+            | Opcode::AddNibbles(..)
+            | Opcode::ColorZones(..)
+            | Opcode::ColorRows(..)
+            | Opcode::SkipKey2(_)
+            | Opcode::SkipNotKey2(_)
+            | Opcode::SetTone(_) => {
+                let word = self.encode();
+                write!(f, "0x{:02X} 0x{:02X}", word >> 8, word & 0xFF)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() {
+        for word in 0..=u16::MAX {
+            for variant in [
+                Variant::Chip8,
+                Variant::Chip8X,
+                Variant::SChip,
+                Variant::XoChip,
+            ] {
+                if let Some(op) = Opcode::decode_variant(word, variant) {
+                    assert_eq!(op.encode(), word, "{:04X} decoded as {:?}", word, op);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_pattern() {
+        for word in 0..=u16::MAX {
+            if let Some(op) = Opcode::decode_variant(word, Variant::Chip8X) {
+                let pattern = op.pattern();
+                for (digit, nibble) in pattern.chars().zip(format!("{:04X}", word).chars()) {
+                    assert!(digit == nibble || "NXY".contains(digit), "{:04X}", word);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_unknown() {
+        for word in [0x5121, 0x800F, 0x9128, 0xE1FF, 0xF1FF] {
+            assert_eq!(Opcode::decode(word), None, "{:04X}", word);
+        }
+    }
+
+    #[test]
+    fn test_chip8x() {
+        let decode = |word| Opcode::decode_variant(word, Variant::Chip8X);
+        assert_eq!(decode(0x02A0), Some(Opcode::CycleBackground));
+        assert_eq!(decode(0xB120), Some(Opcode::ColorZones(1, 2)));
+        assert_eq!(decode(0xB123), Some(Opcode::ColorRows(1, 2, 3)));
+        assert_eq!(decode(0x8124), Some(Opcode::AddReg(1, 2)));
+        assert_eq!(decode(0xE1F2).unwrap().to_string(), "0xE1 0xF2");
+
+        // plain CHIP-8 knows none of them
+        assert_eq!(Opcode::decode(0x02A0), Some(Opcode::Sys(0x2A0)));
+        assert_eq!(Opcode::decode(0xB123), Some(Opcode::JumpV0(0x123)));
+        assert_eq!(Opcode::decode(0x5121), None);
+    }
+
+    #[test]
+    fn test_schip() {
+        let decode = |word| Opcode::decode_variant(word, Variant::SChip);
+        assert_eq!(decode(0x00C4), Some(Opcode::ScrollDown(4)));
+        assert_eq!(decode(0x00FF), Some(Opcode::Hires));
+        assert_eq!(decode(0x00D4), Some(Opcode::Sys(0xD4)));
+        assert_eq!(decode(0x00E0), Some(Opcode::Clear));
+        assert_eq!(decode(0x00FB).unwrap().to_string(), "scroll-right");
+        assert_eq!(decode(0xF330), Some(Opcode::LoadBigFont(3)));
+        assert_eq!(Opcode::decode(0xF330), None);
+        assert_eq!(decode(0xF775).unwrap().to_string(), "saveflags v7");
+        assert_eq!(decode(0xF285), Some(Opcode::LoadFlags(2)));
+
+        // only XO-CHIP scrolls up
+        let decode = |word| Opcode::decode_variant(word, Variant::XoChip);
+        assert_eq!(decode(0x00D4), Some(Opcode::ScrollUp(4)));
+        assert_eq!(decode(0x00D4).unwrap().to_string(), "scroll-up 0x4");
+        assert_eq!(decode(0xF201), Some(Opcode::SelectPlanes(2)));
+        assert_eq!(decode(0xF301).unwrap().to_string(), "plane 3");
+        assert_eq!(Opcode::decode_variant(0xF201, Variant::SChip), None);
+    }
+
+    #[test]
+    fn test_display() {
+        let cases = [
+            (0x00E0, "clear"),
+            (0x0123, "0x01 0x23"),
+            (0x1234, "jump 0x234"),
+            (0x2ABC, ":call 0xABC"),
+            (0x3A12, "if va != 0x12 then"),
+            (0x4A12, "if va == 0x12 then"),
+            (0x6F01, "vf := 0x01"),
+            (0x8AB7, "va =- vb"),
+            (0xC10F, "v1 := random 0x0F"),
+            (0xD125, "sprite v1 v2 0x5"),
+            (0xE39E, "if v3 -key then"),
+            (0xF329, "i := hex v3"),
+        ];
+
+        for (word, text) in cases {
+            assert_eq!(Opcode::decode(word).unwrap().to_string(), text);
+        }
+    }
+}
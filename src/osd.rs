@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+// how long a message stays up, and how long it takes to fade out after that
+const SHOW_DURATION: Duration = Duration::from_millis(1500);
+const FADE_DURATION: Duration = Duration::from_millis(400);
+
+/// A short message, shown over the screen
+pub struct Toast {
+    pub text: String,
+    /// Changes with every new message, so the app knows when to draw it again
+    pub id: u64,
+    shown: u128,
+}
+
+/// Short messages on the corner of the screen, that fade out by themselves;
+/// a new message replaces the one showing
+#[derive(Default)]
+pub struct Osd {
+    current: Option<Toast>,
+    next_id: u64,
+}
+
+impl Osd {
+    /// Show `text` from `now` (in microseconds) on
+    pub fn show(&mut self, text: impl Into<String>, now: u128) {
+        self.next_id += 1;
+        self.current = Some(Toast {
+            text: text.into(),
+            id: self.next_id,
+            shown: now,
+        });
+    }
+
+    /// If a message is up, which will need redrawing every frame
+    pub fn is_active(&self) -> bool {
+        self.current.is_some()
+    }
+
+    /// The message to show at `now`, and how opaque it is (0.0 to 1.0)
+    pub fn visible(&mut self, now: u128) -> Option<(&Toast, f32)> {
+        let shown = self.current.as_ref()?.shown;
+        let elapsed = now.saturating_sub(shown);
+
+        let fading = elapsed.saturating_sub(SHOW_DURATION.as_micros());
+        if fading >= FADE_DURATION.as_micros() {
+            self.current = None;
+            return None;
+        }
+
+        let alpha = 1.0 - fading as f32 / FADE_DURATION.as_micros() as f32;
+        self.current.as_ref().map(|toast| (toast, alpha))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fade() {
+        let mut osd = Osd::default();
+        assert!(osd.visible(0).is_none());
+
+        osd.show("Muted", 1_000_000);
+        let (toast, alpha) = osd.visible(2_000_000).unwrap();
+        assert_eq!((toast.text.as_str(), alpha), ("Muted", 1.0));
+
+        // halfway through the fade
+        let (_, alpha) = osd.visible(2_700_000).unwrap();
+        assert_eq!(alpha, 0.5);
+
+        assert!(osd.visible(2_900_000).is_none());
+        assert!(!osd.is_active());
+    }
+
+    #[test]
+    fn test_replace() {
+        let mut osd = Osd::default();
+        osd.show("Volume 50%", 0);
+        let first = osd.visible(0).unwrap().0.id;
+
+        // the new message starts over
+        osd.show("Volume 60%", 1_800_000);
+        let (toast, alpha) = osd.visible(1_900_000).unwrap();
+        assert_eq!(toast.text, "Volume 60%");
+        assert_ne!(toast.id, first);
+        assert_eq!(alpha, 1.0);
+    }
+}
@@ -0,0 +1,144 @@
+use std::str::FromStr;
+
+/// Colors used to draw the screen, as 0xRRGGBB00 values.
+///
+/// The first two are the background and foreground; the other two are
+/// only used by XO-CHIP roms, for pixels set on the second plane and on
+/// both planes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub colors: [u32; 4],
+}
+
+/// Named palettes, as accepted by `--palette`
+const PRESETS: [(&str, Palette); 5] = [
+    (
+        "mono",
+        Palette::new([0x00000000, 0xffffff00, 0xaaaaaa00, 0x55555500]),
+    ),
+    (
+        "octo",
+        Palette::new([0x99660000, 0xffcc0000, 0xff660000, 0x66220000]),
+    ),
+    (
+        "gameboy",
+        Palette::new([0x9bbc0f00, 0x0f380f00, 0x8bac0f00, 0x30623000]),
+    ),
+    (
+        "c64",
+        Palette::new([0x40318d00, 0x7869c400, 0xffffff00, 0x9ae29b00]),
+    ),
+    (
+        "amber",
+        Palette::new([0x1a100000, 0xffb00000, 0x80580000, 0xffd08000]),
+    ),
+];
+
+impl Palette {
+    pub const fn new(colors: [u32; 4]) -> Self {
+        Palette { colors }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        PRESETS[0].1
+    }
+}
+
+impl FromStr for Palette {
+    type Err = String;
+
+    /// Either the name of a preset, or `custom:` followed by 2 or 4
+    /// comma-separated colors
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        if let Some(spec) = input.strip_prefix("custom:") {
+            let colors = parse_colors(spec)?;
+
+            // without the plane colors, XO-CHIP roms just use the foreground
+            return match colors[..] {
+                [bg, fg] => Ok(Palette::new([bg, fg, fg, fg])),
+                [bg, fg, plane2, both] => Ok(Palette::new([bg, fg, plane2, both])),
+                _ => Err(format!(
+                    "a custom palette needs 2 or 4 colors (got {})",
+                    colors.len()
+                )),
+            };
+        }
+
+        PRESETS
+            .iter()
+            .find_map(|&(name, palette)| (name == input).then_some(palette))
+            .ok_or_else(|| {
+                let names: Vec<_> = PRESETS.iter().map(|&(name, _)| name).collect();
+                format!(
+                    "unknown palette '{}' (expected one of: {}, custom:...)",
+                    input,
+                    names.join(", ")
+                )
+            })
+    }
+}
+
+/// Parse comma-separated `#RRGGBB` colors
+pub fn parse_colors(input: &str) -> Result<Vec<u32>, String> {
+    input.split(',').map(parse_rgb).collect()
+}
+
+/// Parse a `#RRGGBB` (or `RRGGBB`) color into a 0xRRGGBB00 value
+pub fn parse_rgb(input: &str) -> Result<u32, String> {
+    let stripped = input.strip_prefix('#').unwrap_or(input);
+
+    if stripped.len() != 6 {
+        return Err(format!(
+            "wrong color size (expected: 6, got {})",
+            stripped.len()
+        ));
+    }
+
+    match u32::from_str_radix(stripped, 16) {
+        Ok(value) => Ok(value << 8),
+        Err(err) => Err(format!("error parsing color value: {:?}", err)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rgb() {
+        assert_eq!(parse_rgb("#00dead"), Ok(0x00dead00));
+        assert_eq!(parse_rgb("cabeca"), Ok(0xcabeca00));
+        assert!(parse_rgb("#fff").is_err());
+        assert!(parse_rgb("#gggggg").is_err());
+    }
+
+    #[test]
+    fn test_preset() {
+        let palette: Palette = "gameboy".parse().unwrap();
+        assert_eq!(palette.colors[..2], [0x9bbc0f00, 0x0f380f00]);
+
+        assert_eq!("mono".parse(), Ok(Palette::default()));
+        assert!("vga".parse::<Palette>().is_err());
+    }
+
+    #[test]
+    fn test_custom() {
+        let palette: Palette = "custom:#000000,#00ff00".parse().unwrap();
+        assert_eq!(
+            palette.colors,
+            [0x00000000, 0x00ff0000, 0x00ff0000, 0x00ff0000]
+        );
+
+        let palette: Palette = "custom:#000000,#00ff00,#ff0000,#0000ff".parse().unwrap();
+        assert_eq!(
+            palette.colors,
+            [0x00000000, 0x00ff0000, 0xff000000, 0x0000ff00]
+        );
+
+        assert!("custom:#000000".parse::<Palette>().is_err());
+        assert!("custom:#000000,#00ff00,#ff0000".parse::<Palette>().is_err());
+        assert!("custom:#000000,green".parse::<Palette>().is_err());
+    }
+}
@@ -0,0 +1,114 @@
+//! Frame pacing numbers for the performance overlay: frames and
+//! instructions per second, and how long the frames took.
+use std::collections::VecDeque;
+
+/// How many frame times are kept for the percentiles
+const HISTORY: usize = 240;
+
+/// How often the rates are updated, in microseconds; faster is just hard
+/// to read
+const REFRESH: u128 = 500_000;
+
+pub struct Stats {
+    // most recent frame times, in microseconds
+    frame_times: VecDeque<u128>,
+
+    // counted since the last refresh
+    frames: u32,
+    instructions: u64,
+    since: u128,
+
+    fps: f64,
+    ips: f64,
+}
+
+impl Stats {
+    /// Start counting at `now`, in microseconds of the app clock
+    pub fn new(now: u128) -> Self {
+        Stats {
+            frame_times: VecDeque::with_capacity(HISTORY),
+            frames: 0,
+            instructions: 0,
+            since: now,
+            fps: 0.0,
+            ips: 0.0,
+        }
+    }
+
+    /// Account for a frame that took `elapsed` microseconds and ran
+    /// `instructions`; returns true when the numbers change
+    pub fn frame(&mut self, elapsed: u128, instructions: u64, now: u128) -> bool {
+        if self.frame_times.len() == HISTORY {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(elapsed);
+        self.frames += 1;
+        self.instructions += instructions;
+
+        let period = now - self.since;
+        if period < REFRESH {
+            return false;
+        }
+
+        let seconds = period as f64 / 1_000_000.0;
+        self.fps = self.frames as f64 / seconds;
+        self.ips = self.instructions as f64 / seconds;
+        self.frames = 0;
+        self.instructions = 0;
+        self.since = now;
+        true
+    }
+
+    /// The overlay text
+    pub fn lines(&self) -> Vec<String> {
+        let mut sorted: Vec<_> = self.frame_times.iter().copied().collect();
+        sorted.sort_unstable();
+
+        vec![
+            format!("FPS: {:.1}", self.fps),
+            format!("IPS: {:.0}", self.ips),
+            format!(
+                "Frame ms: {:.1} / {:.1} / {:.1}",
+                percentile(&sorted, 50),
+                percentile(&sorted, 95),
+                percentile(&sorted, 99)
+            ),
+            "(median / p95 / p99)".to_owned(),
+        ]
+    }
+}
+
+/// Frame time percentile, in milliseconds
+fn percentile(sorted: &[u128], p: usize) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+
+    let index = (sorted.len() * p / 100).min(sorted.len() - 1);
+    sorted[index] as f64 / 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stats() {
+        let mut stats = Stats::new(0);
+
+        // one slow frame among 59 good ones, each running 9 instructions
+        let mut now = 0;
+        let mut refreshed = false;
+        for frame in 0..60 {
+            let elapsed = if frame == 30 { 50_000 } else { 16_000 };
+            now += elapsed;
+            refreshed |= stats.frame(elapsed, 9, now);
+        }
+
+        assert!(refreshed);
+        let lines = stats.lines();
+        assert_eq!(lines[2], "Frame ms: 16.0 / 16.0 / 50.0");
+        assert!(lines[0].starts_with("FPS: 5"));
+        assert!(lines[1].starts_with("IPS: 5"));
+    }
+}
@@ -0,0 +1,99 @@
+//! Swapping sides on two-player games: the keys of player 1 press the keys
+//! of player 2, and the other way around, so one person can try both.
+use std::str::FromStr;
+
+use super::turbo::parse_key;
+
+/// The keys of each player, paired up in order (the first key of one
+/// player swaps with the first of the other, and so on)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Players {
+    one: Vec<usize>,
+    two: Vec<usize>,
+    swapped: bool,
+}
+
+impl Players {
+    /// Swap the sides, or put them back; returns if they are swapped now
+    pub fn swap(&mut self) -> bool {
+        self.swapped = !self.swapped;
+        self.swapped
+    }
+
+    /// The key actually pressed for `key`
+    pub fn key(&self, key: usize) -> usize {
+        if !self.swapped {
+            return key;
+        }
+
+        let one = self.one.iter().position(|&k| k == key);
+        let two = self.two.iter().position(|&k| k == key);
+        match (one, two) {
+            (Some(i), _) => self.two[i],
+            (_, Some(i)) => self.one[i],
+            _ => key,
+        }
+    }
+
+    /// Every key of both players
+    pub fn keys(&self) -> impl Iterator<Item = usize> + '_ {
+        self.one.iter().chain(&self.two).copied()
+    }
+}
+
+/// Like `1,4:C,D`: the keys of player 1, then the ones of player 2
+impl FromStr for Players {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |keys: &str| -> Result<Vec<usize>, String> {
+            keys.split(',').map(|key| parse_key(key.trim())).collect()
+        };
+
+        let (one, two) = s
+            .split_once(':')
+            .ok_or_else(|| format!("invalid players: {} (use like 1,4:C,D)", s))?;
+        let (one, two) = (parse(one)?, parse(two)?);
+
+        if one.len() != two.len() {
+            return Err("both players need the same number of keys".to_owned());
+        }
+        if one.iter().any(|key| two.contains(key)) {
+            return Err("the players cannot share keys".to_owned());
+        }
+
+        Ok(Players {
+            one,
+            two,
+            swapped: false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_swap() {
+        let mut players: Players = "1,4:C,D".parse().unwrap();
+        assert_eq!(players.key(0x1), 0x1);
+
+        assert!(players.swap());
+        assert_eq!(players.key(0x1), 0xC);
+        assert_eq!(players.key(0x4), 0xD);
+        assert_eq!(players.key(0xD), 0x4);
+        assert_eq!(players.key(0x5), 0x5);
+
+        assert!(!players.swap());
+        assert_eq!(players.key(0xC), 0xC);
+    }
+
+    #[test]
+    fn test_parse() {
+        assert!("1,4".parse::<Players>().is_err());
+        assert!("1,4:C".parse::<Players>().is_err());
+        assert!("1,4:4,C".parse::<Players>().is_err());
+        assert!("1,G:C,D".parse::<Players>().is_err());
+    }
+}
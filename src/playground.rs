@@ -0,0 +1,126 @@
+//! The web playground: a page where a rom runs on the core built for the
+//! browser (see `rc8::wasm`), to step it and watch its registers.
+//!
+//! `GET /` is the page, `GET /rc8.wasm` the core and `GET /rom` the rom
+//! given on the command line, if any.
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use tracing::debug;
+
+const PAGE: &str = include_str!("playground.html");
+
+// how to build the core, when it is not found
+const BUILD: &str =
+    "cargo rustc --lib --release --target wasm32-unknown-unknown --features wasm --crate-type cdylib";
+
+/// Serve the playground on a local `port` until rc8 is stopped; the core is
+/// read from `wasm`, or `rc8.wasm` next to the executable
+pub fn serve(port: u16, wasm: Option<&Path>, rom: Option<&Path>) -> Result<(), anyhow::Error> {
+    let wasm = match wasm {
+        Some(wasm) => wasm.to_path_buf(),
+        None => default_wasm()?,
+    };
+    let core = std::fs::read(&wasm).with_context(|| {
+        format!(
+            "error reading the playground core: {} (build it with `{}`)",
+            wasm.display(),
+            BUILD
+        )
+    })?;
+    let rom = rom
+        .map(|path| {
+            std::fs::read(path)
+                .with_context(|| format!("error opening rom file: {}", path.display()))
+        })
+        .transpose()?;
+
+    let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, port))
+        .with_context(|| format!("error listening on port {}", port))?;
+    println!("playground on http://localhost:{}/", port);
+
+    for stream in listener.incoming().flatten() {
+        if let Err(err) = answer(stream, &core, rom.as_deref()) {
+            debug!("playground client error: {}", err);
+        }
+    }
+    Ok(())
+}
+
+fn default_wasm() -> Result<PathBuf, anyhow::Error> {
+    let exe = std::env::current_exe().context("error finding the rc8 executable")?;
+    Ok(exe.with_file_name("rc8.wasm"))
+}
+
+/// Answer a single HTTP request
+fn answer(mut stream: TcpStream, core: &[u8], rom: Option<&[u8]>) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+
+    // nothing in the headers matters
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+    }
+
+    let Some((kind, body)) = route(path, core, rom) else {
+        return write!(
+            stream,
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+    };
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n",
+        kind,
+        body.len()
+    )?;
+    stream.write_all(body)
+}
+
+/// The content type and body of `path`
+fn route<'a>(
+    path: &str,
+    core: &'a [u8],
+    rom: Option<&'a [u8]>,
+) -> Option<(&'static str, &'a [u8])> {
+    match (path, rom) {
+        ("/", _) => Some(("text/html; charset=utf-8", PAGE.as_bytes())),
+        ("/rc8.wasm", _) => Some(("application/wasm", core)),
+        ("/rom", Some(rom)) => Some(("application/octet-stream", rom)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_route() {
+        let (core, rom) = ([0x00, 0x61, 0x73, 0x6D], [0x12, 0x00]);
+        assert_eq!(
+            route("/", &core, None).map(|(kind, _)| kind),
+            Some("text/html; charset=utf-8")
+        );
+        assert_eq!(
+            route("/rc8.wasm", &core, None),
+            Some(("application/wasm", &core[..]))
+        );
+        assert_eq!(route("/rom", &core, None), None);
+        assert_eq!(
+            route("/rom", &core, Some(&rom)).map(|(_, body)| body),
+            Some(&rom[..])
+        );
+        assert_eq!(route("/other", &core, Some(&rom)), None);
+    }
+}
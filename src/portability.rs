@@ -0,0 +1,157 @@
+//! Instructions that behave differently between CHIP-8 interpreters, or
+//! that only work on some of them; a rom that avoids them runs anywhere.
+use std::collections::BTreeMap;
+
+use super::{audit::scripted_run, disasm::disassemble, emulator::Emulator, opcode::Opcode};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Risk {
+    MachineCode,
+    Shift,
+    SaveLoad,
+    JumpV0,
+    SelfModifying,
+}
+
+impl Risk {
+    fn of(op: Opcode) -> Option<Risk> {
+        let risk = match op {
+            Opcode::Sys(_) => Risk::MachineCode,
+            Opcode::ShiftRight(_, _) | Opcode::ShiftLeft(_, _) => Risk::Shift,
+            Opcode::Save(_) | Opcode::Load(_) => Risk::SaveLoad,
+            Opcode::JumpV0(_) => Risk::JumpV0,
+            _ => return None,
+        };
+
+        Some(risk)
+    }
+
+    fn description(&self) -> &'static str {
+        match self {
+            Risk::MachineCode => "0NNN machine code call, only runs on the COSMAC VIP",
+            Risk::Shift => "8XY6/8XYE shift VY on the VIP, but VX on SCHIP",
+            Risk::SaveLoad => "FX55/FX65 change I on the VIP, but not on SCHIP",
+            Risk::JumpV0 => "BNNN adds V0 on the VIP, but VX on SCHIP",
+            Risk::SelfModifying => "FX55/FX33 writing over code (self-modifying)",
+        }
+    }
+}
+
+/// How often a risky instruction shows up
+#[derive(Default, Debug, PartialEq, Eq)]
+struct Count {
+    /// In the disassembly
+    found: usize,
+    /// While running the rom
+    executed: usize,
+    /// Where it was first seen
+    first: Option<usize>,
+}
+
+/// Memory written by the instruction, if any
+fn writes(emu: &Emulator, op: Opcode) -> Option<(usize, usize)> {
+    let start = emu.I as usize;
+    match op {
+        Opcode::Save(x) => Some((start, start + x as usize + 1)),
+        Opcode::Bcd(_) => Some((start, start + 3)),
+        _ => None,
+    }
+}
+
+fn counts(rom: &[u8], frames: usize) -> BTreeMap<Risk, Count> {
+    let mut counts: BTreeMap<Risk, Count> = BTreeMap::new();
+    let mut code = vec![false; 0x1000];
+
+    for block in disassemble(rom).blocks {
+        for (addr, _, op) in block.instructions {
+            code[addr as usize] = true;
+            if let Some(risk) = op.and_then(Risk::of) {
+                let count = counts.entry(risk).or_default();
+                count.found += 1;
+                count.first.get_or_insert(addr as usize);
+            }
+        }
+    }
+
+    scripted_run(rom, frames, |emu| {
+        code[emu.PC] = true;
+
+        let op = match emu.next_opcode() {
+            Some(op) => op,
+            None => return,
+        };
+
+        let mut seen = |risk| {
+            let count = counts.entry(risk).or_default();
+            count.executed += 1;
+            count.first.get_or_insert(emu.PC);
+        };
+
+        if let Some(risk) = Risk::of(op) {
+            seen(risk);
+        }
+
+        if let Some((start, end)) = writes(emu, op) {
+            if code[start.min(code.len())..end.min(code.len())].contains(&true) {
+                seen(Risk::SelfModifying);
+            }
+        }
+    });
+
+    counts
+}
+
+/// Print how often each risky instruction is used
+pub fn report(rom: &[u8], frames: usize) {
+    let counts = counts(rom, frames);
+
+    println!("portability ({} frames):", frames);
+    if counts.is_empty() {
+        println!("  no quirk-sensitive instructions found");
+        return;
+    }
+
+    println!("  {:>5} {:>8}  {:>5}", "found", "executed", "first");
+    for (risk, count) in &counts {
+        println!(
+            "  {:>5} {:>8}  {:03X}   {}",
+            count.found,
+            count.executed,
+            count.first.unwrap_or_default(),
+            risk.description()
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counts() {
+        let rom = [
+            0x80, 0x16, // 0x200: v0 >>= v1
+            0xA2, 0x00, // 0x202: i := 0x200
+            0xF0, 0x33, // 0x204: bcd v0, over the code
+            0x12, 0x00, // 0x206: jump 0x200, which is now 0000
+        ];
+
+        let counts = counts(&rom, 1);
+        assert!(!counts.contains_key(&Risk::SaveLoad));
+
+        let shift = &counts[&Risk::Shift];
+        assert_eq!(shift.found, 1);
+        assert_eq!(shift.executed, 1);
+        assert_eq!(shift.first, Some(0x200));
+
+        let writes = &counts[&Risk::SelfModifying];
+        assert_eq!(writes.found, 0);
+        assert_eq!(writes.first, Some(0x204));
+    }
+
+    #[test]
+    fn test_portable() {
+        let rom = [0x60, 0x01, 0x12, 0x02];
+        assert!(counts(&rom, 1).is_empty());
+    }
+}
@@ -0,0 +1,191 @@
+//! Counts how many times each address runs, and how many times each byte
+//! of memory is read and written, for `--profile`. It helps finding the
+//! hot loops and the dead code of a rom, or where it hangs.
+use std::{fmt::Write as _, ops::Range};
+
+use super::{emulator::Emulator, opcode::Opcode};
+
+// how many of the hottest instructions go on the report
+const HOTTEST: usize = 20;
+
+/// Execution and memory access counts, per address
+pub struct Profile {
+    executed: Vec<u64>,
+    reads: Vec<u64>,
+    writes: Vec<u64>,
+}
+
+impl Profile {
+    pub fn new(emu: &Emulator) -> Self {
+        let size = emu.memory.len();
+        Profile {
+            executed: vec![0; size],
+            reads: vec![0; size],
+            writes: vec![0; size],
+        }
+    }
+
+    /// Count the instruction about to run, and the memory it touches
+    pub fn record(&mut self, emu: &Emulator) {
+        let Some(count) = self.executed.get_mut(emu.PC) else {
+            return;
+        };
+        *count += 1;
+
+        let Some(op) = emu.next_opcode() else {
+            return;
+        };
+        let (counts, len) = match op {
+            Opcode::Draw(_, _, n) => (&mut self.reads, n as usize),
+            Opcode::Load(x) => (&mut self.reads, x as usize + 1),
+            Opcode::Save(x) => (&mut self.writes, x as usize + 1),
+            Opcode::Bcd(_) => (&mut self.writes, 3),
+            _ => return,
+        };
+
+        let size = counts.len();
+        for offset in 0..len {
+            counts[(emu.I as usize + offset) % size] += 1;
+        }
+    }
+
+    /// How often `address` ran, compared to the hottest one, from 0 to 255;
+    /// on a log scale, as a few loops usually take most of the time
+    pub fn heat(&self, address: usize) -> u8 {
+        let max = self.executed.iter().max().copied().unwrap_or_default();
+        match self.executed.get(address) {
+            Some(&count) if count > 0 => {
+                let heat = ((count + 1) as f64).ln() / ((max + 1) as f64).ln();
+                (heat * 255.0).round() as u8
+            }
+            _ => 0,
+        }
+    }
+
+    /// A text report: the hottest instructions, the parts of the rom that
+    /// never ran and the memory that was read and written
+    pub fn report(&self, emu: &Emulator) -> String {
+        let total: u64 = self.executed.iter().sum();
+        let mut report = format!("{} instructions run\n\nhottest instructions:\n", total);
+
+        let mut hottest: Vec<_> = (0..self.executed.len())
+            .filter(|&address| self.executed[address] > 0)
+            .collect();
+        hottest.sort_by_key(|&address| std::cmp::Reverse(self.executed[address]));
+        for &address in hottest.iter().take(HOTTEST) {
+            let count = self.executed[address];
+            let text = Opcode::decode_variant(emu.word_at(address), emu.quirks.variant)
+                .map_or_else(|| "?".to_owned(), |op| op.to_string());
+            let _ = writeln!(
+                report,
+                "{:>12}  {:5.1}%  {:03X}  {}",
+                count,
+                count as f64 * 100.0 / total as f64,
+                address,
+                text
+            );
+        }
+
+        // every instruction of the rom, by its address
+        let start = emu.start_address();
+        let end = (start + emu.rom().len()).min(emu.memory.len());
+        let instructions = (start..end).step_by(2);
+        let ran = instructions
+            .clone()
+            .filter(|&address| self.executed[address] > 0)
+            .count();
+        let never: Vec<_> = instructions
+            .filter(|&address| self.executed[address] == 0)
+            .collect();
+        let _ = writeln!(
+            report,
+            "\ncoverage: {} of {} rom words ran ({:.0}%)",
+            ran,
+            ran + never.len(),
+            ran as f64 * 100.0 / (ran + never.len()).max(1) as f64
+        );
+        let _ = writeln!(
+            report,
+            "never ran (dead code, or data): {}",
+            format_ranges(&ranges(&never, 2))
+        );
+
+        for (name, counts) in [("read", &self.reads), ("written", &self.writes)] {
+            let touched: Vec<_> = (0..counts.len()).filter(|&a| counts[a] > 0).collect();
+            let _ = writeln!(
+                report,
+                "memory {}: {}",
+                name,
+                format_ranges(&ranges(&touched, 1))
+            );
+        }
+        report
+    }
+}
+
+/// Join sorted addresses `step` apart into ranges
+fn ranges(addresses: &[usize], step: usize) -> Vec<Range<usize>> {
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    for &address in addresses {
+        match ranges.last_mut() {
+            Some(range) if range.end == address => range.end = address + step,
+            _ => ranges.push(address..address + step),
+        }
+    }
+    ranges
+}
+
+fn format_ranges(ranges: &[Range<usize>]) -> String {
+    if ranges.is_empty() {
+        return "none".to_owned();
+    }
+
+    ranges
+        .iter()
+        .map(|range| match range.len() {
+            1 => format!("{:03X}", range.start),
+            _ => format!("{:03X}-{:03X}", range.start, range.end - 1),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile() {
+        let rom = [
+            0xA3, 0x00, // 0x200: i := 0x300
+            0xF1, 0x55, // 0x202: save v1
+            0x12, 0x02, // 0x204: jump 0x202
+            0x00, 0xE0, // 0x206: clear (never runs)
+        ];
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        let mut profile = Profile::new(&emu);
+        for _ in 0..5 {
+            profile.record(&emu);
+            emu.execute().unwrap();
+        }
+
+        assert_eq!(profile.executed[0x202], 2);
+        assert_eq!(profile.heat(0x202), 255);
+        assert!(profile.heat(0x200) < profile.heat(0x202));
+        assert_eq!(profile.heat(0x206), 0);
+
+        let report = profile.report(&emu);
+        assert!(report.starts_with("5 instructions run"));
+        assert!(report.contains("coverage: 3 of 4 rom words ran (75%)"));
+        assert!(report.contains("never ran (dead code, or data): 206-207"));
+        // the second save goes on after the first, as I moves
+        assert!(report.contains("memory written: 300-303"));
+        assert!(report.contains("memory read: none"));
+    }
+
+    #[test]
+    fn test_ranges() {
+        assert_eq!(ranges(&[1, 2, 3, 7], 1), vec![1..4, 7..8]);
+        assert_eq!(format_ranges(&[1..4, 7..8]), "001-003, 007");
+    }
+}
@@ -0,0 +1,69 @@
+use std::{
+    fs,
+    io::{self, ErrorKind},
+    path::{Path, PathBuf},
+};
+
+// how many roms are remembered
+const MAX_ENTRIES: usize = 10;
+
+/// Most-recently-used list of roms, persisted on the config directory
+pub struct RecentRoms {
+    entries: Vec<PathBuf>,
+}
+
+/// Where the list is stored
+fn file_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("rc8").join("recent.txt"))
+}
+
+impl RecentRoms {
+    /// Load the list from disk; a missing or unreadable file is just an empty list
+    pub fn load() -> Self {
+        let entries = file_path()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|contents| {
+                contents
+                    .lines()
+                    .filter(|line| !line.is_empty())
+                    .map(PathBuf::from)
+                    .take(MAX_ENTRIES)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        RecentRoms { entries }
+    }
+
+    /// Save the list to disk, creating the config directory if needed
+    pub fn save(&self) -> io::Result<()> {
+        let path = file_path().ok_or_else(|| {
+            io::Error::new(ErrorKind::NotFound, "could not find the config directory")
+        })?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let contents: Vec<_> = self
+            .entries
+            .iter()
+            .map(|entry| entry.to_string_lossy())
+            .collect();
+
+        fs::write(path, contents.join("\n"))
+    }
+
+    /// Move (or add) a rom to the top of the list
+    pub fn push(&mut self, path: &Path) {
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+
+        self.entries.retain(|entry| *entry != path);
+        self.entries.insert(0, path);
+        self.entries.truncate(MAX_ENTRIES);
+    }
+
+    pub fn entries(&self) -> &[PathBuf] {
+        &self.entries
+    }
+}
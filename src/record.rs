@@ -0,0 +1,206 @@
+//! Records what is on the screen and which keys are held on every frame,
+//! as training data for game playing agents.
+//!
+//! The recording is saved as a NumPy `.npz` file (a zip with one `.npy` per
+//! array, uncompressed), so it opens with a plain `numpy.load`:
+//!
+//! - `screens`: `uint8`, shape `(frames, 32, 8)`; each row is packed with 8
+//!   pixels per byte, leftmost pixel first, so `numpy.unpackbits(screens,
+//!   axis=-1)` gives the `(frames, 32, 64)` pixels;
+//! - `keys`: `bool`, shape `(frames, 16)`, indexed by the CHIP-8 key.
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+use super::emulator::{Emulator, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+const ROW_BYTES: usize = DISPLAY_WIDTH / 8;
+
+#[derive(Default)]
+pub struct Recorder {
+    screens: Vec<u8>,
+    keys: Vec<u8>,
+    frames: usize,
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        Recorder::default()
+    }
+
+    /// Add the current screen, along with the keys held during the frame
+    pub fn record(&mut self, emu: &Emulator) {
+        // recordings keep the low resolution shape
+        for &row in emu.display().rows(0).iter().take(DISPLAY_HEIGHT) {
+            self.screens.extend_from_slice(&(row as u64).to_be_bytes());
+        }
+        self.keys
+            .extend((0..16).map(|key| emu.is_key_pressed(key) as u8));
+        self.frames += 1;
+    }
+
+    pub fn frames(&self) -> usize {
+        self.frames
+    }
+
+    /// Save everything recorded so far
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_npz(&mut out)?;
+        out.flush()
+    }
+
+    fn write_npz<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let screens = npy(
+            "|u1",
+            &[self.frames, DISPLAY_HEIGHT, ROW_BYTES],
+            &self.screens,
+        );
+        let keys = npy("|b1", &[self.frames, 16], &self.keys);
+
+        write_zip(out, &[("screens.npy", &screens), ("keys.npy", &keys)])
+    }
+}
+
+/// A `.npy` (format 1.0) holding `data`, of the given type and shape
+fn npy(dtype: &str, shape: &[usize], data: &[u8]) -> Vec<u8> {
+    let shape: Vec<_> = shape.iter().map(|n| n.to_string()).collect();
+    let mut header = format!(
+        "{{'descr': '{}', 'fortran_order': False, 'shape': ({}), }}",
+        dtype,
+        shape.join(", ")
+    );
+
+    // magic, version and header length take 10 bytes; the data starts
+    // aligned to 64 bytes, after a newline
+    let padding = (64 - (10 + header.len() + 1) % 64) % 64;
+    header.push_str(&" ".repeat(padding));
+    header.push('\n');
+
+    let mut bytes = Vec::with_capacity(10 + header.len() + data.len());
+    bytes.extend_from_slice(b"\x93NUMPY\x01\x00");
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    bytes.extend_from_slice(data);
+    bytes
+}
+
+/// A zip file with the given entries, stored without compression
+fn write_zip<W: Write>(out: &mut W, entries: &[(&str, &[u8])]) -> io::Result<()> {
+    // 1980-01-01, the earliest date zip knows about
+    const DATE: u16 = 0x21;
+    let mut central = Vec::new();
+    let mut offset = 0u32;
+
+    for &(name, data) in entries {
+        let crc = crc32(data);
+        let size = data.len() as u32;
+
+        // fields shared by the local and the central headers: version
+        // needed, flags, method, time, date, crc, sizes and name length
+        let mut common = Vec::new();
+        common.extend_from_slice(&20u16.to_le_bytes());
+        common.extend_from_slice(&[0; 6]);
+        common.extend_from_slice(&DATE.to_le_bytes());
+        common.extend_from_slice(&crc.to_le_bytes());
+        common.extend_from_slice(&size.to_le_bytes());
+        common.extend_from_slice(&size.to_le_bytes());
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+
+        out.write_all(&0x04034b50u32.to_le_bytes())?;
+        out.write_all(&common)?;
+        out.write_all(&[0; 2])?; // extra length
+        out.write_all(name.as_bytes())?;
+        out.write_all(data)?;
+
+        central.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        central.extend_from_slice(&20u16.to_le_bytes()); // version made by
+        central.extend_from_slice(&common);
+        central.extend_from_slice(&[0; 12]); // extra, comment, disk, attributes
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+
+        offset += 30 + name.len() as u32 + size;
+    }
+
+    let count = entries.len() as u16;
+    out.write_all(&central)?;
+    out.write_all(&0x06054b50u32.to_le_bytes())?;
+    out.write_all(&[0; 4])?; // disk numbers
+    out.write_all(&count.to_le_bytes())?;
+    out.write_all(&count.to_le_bytes())?;
+    out.write_all(&(central.len() as u32).to_le_bytes())?;
+    out.write_all(&offset.to_le_bytes())?;
+    out.write_all(&[0; 2]) // comment length
+}
+
+/// CRC-32, as used by zip
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB88320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_npy() {
+        let bytes = npy("|b1", &[2, 16], &[0; 32]);
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+
+        assert_eq!(&bytes[..6], b"\x93NUMPY");
+        assert_eq!((10 + header_len) % 64, 0);
+        assert_eq!(bytes.len(), 10 + header_len + 32);
+
+        let header = std::str::from_utf8(&bytes[10..10 + header_len]).unwrap();
+        assert!(header.starts_with("{'descr': '|b1', 'fortran_order': False, 'shape': (2, 16), }"));
+        assert!(header.ends_with(" \n"));
+    }
+
+    #[test]
+    fn test_npz() {
+        assert_eq!(crc32(b"123456789"), 0xCBF43926);
+
+        let rom = [
+            0xA0, 0x00, // 0x200: I = sprite of "0"
+            0xD0, 0x01, // 0x202: draw its top row on 0,0
+        ];
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        emu.vblank();
+        emu.execute().unwrap();
+        emu.execute().unwrap();
+        emu.set_key(0xA, true);
+
+        let mut recorder = Recorder::new();
+        recorder.record(&emu);
+        recorder.record(&emu);
+        assert_eq!(recorder.frames(), 2);
+        assert_eq!(recorder.screens[0], 0xF0);
+        assert_eq!(recorder.keys[0xA], 1);
+
+        let mut zip = Vec::new();
+        recorder.write_npz(&mut zip).unwrap();
+        assert_eq!(&zip[..4], &0x04034b50u32.to_le_bytes());
+        assert_eq!(&zip[30..41], b"screens.npy");
+
+        // the end of central directory points back at the entries
+        let end = &zip[zip.len() - 22..];
+        assert_eq!(&end[..4], &0x06054b50u32.to_le_bytes());
+        assert_eq!(u16::from_le_bytes([end[10], end[11]]), 2);
+        let central = u32::from_le_bytes(end[16..20].try_into().unwrap()) as usize;
+        assert_eq!(&zip[central..central + 4], &0x02014b50u32.to_le_bytes());
+    }
+}
@@ -0,0 +1,194 @@
+//! Guesses which CHIP-8 keys a rom uses, to offer playing it with the
+//! arrows and space instead of hunting for the right keys on the keypad.
+//!
+//! Keys are found statically (`vX := NN` right before a key check) and by
+//! watching a short headless run with scripted input.
+use std::fmt;
+
+use super::{audit::scripted_run, disasm::disassemble, keymap::KEYPAD, opcode::Opcode};
+
+/// More keys than this are probably a keyboard-heavy game, like a puzzle,
+/// where arrows would not help
+const MAX_KEYS: usize = 5;
+
+/// Keys whose offset from the center is smaller than this are the fire key
+const CENTER: f32 = 0.1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Button {
+    Up,
+    Down,
+    Left,
+    Right,
+    Fire,
+}
+
+impl fmt::Display for Button {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Button::Up => "up",
+            Button::Down => "down",
+            Button::Left => "left",
+            Button::Right => "right",
+            Button::Fire => "space",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Which CHIP-8 key each button presses
+#[derive(Debug, PartialEq, Eq)]
+pub struct Remap {
+    pub bindings: Vec<(Button, usize)>,
+}
+
+impl Remap {
+    /// Suggest a mapping for the rom, if it uses just a few keys
+    pub fn suggest(rom: &[u8], frames: usize) -> Option<Remap> {
+        Remap::for_keys(used_keys(rom, frames))
+    }
+
+    /// Give each key the button closest to where it sits on the keypad,
+    /// relative to the other keys
+    fn for_keys(keys: u16) -> Option<Remap> {
+        let positions: Vec<(usize, f32, f32)> = KEYPAD
+            .iter()
+            .enumerate()
+            .flat_map(|(row, keys)| {
+                keys.iter()
+                    .enumerate()
+                    .map(move |(col, &(key, _))| (key, row as f32, col as f32))
+            })
+            .filter(|&(key, _, _)| keys & (1 << key) != 0)
+            .collect();
+
+        if positions.is_empty() || positions.len() > MAX_KEYS {
+            return None;
+        }
+
+        let count = positions.len() as f32;
+        let center_row = positions.iter().map(|&(_, row, _)| row).sum::<f32>() / count;
+        let center_col = positions.iter().map(|&(_, _, col)| col).sum::<f32>() / count;
+
+        // (button, key, distance from the center)
+        let mut bindings: Vec<(Button, usize, f32)> = Vec::new();
+        for (key, row, col) in positions {
+            let (dy, dx) = (row - center_row, col - center_col);
+            let button = if dx.abs() < CENTER && dy.abs() < CENTER {
+                Button::Fire
+            } else if dy.abs() >= dx.abs() {
+                if dy < 0.0 {
+                    Button::Up
+                } else {
+                    Button::Down
+                }
+            } else if dx < 0.0 {
+                Button::Left
+            } else {
+                Button::Right
+            };
+
+            // when two keys want the same button, the farthest one wins
+            let distance = dx.abs() + dy.abs();
+            match bindings.iter_mut().find(|(b, _, _)| *b == button) {
+                Some(binding) if binding.2 < distance => *binding = (button, key, distance),
+                Some(_) => {}
+                None => bindings.push((button, key, distance)),
+            }
+        }
+
+        Some(Remap {
+            bindings: bindings
+                .into_iter()
+                .map(|(button, key, _)| (button, key))
+                .collect(),
+        })
+    }
+
+    /// The key pressed by `button`, if any
+    pub fn key(&self, button: Button) -> Option<usize> {
+        self.bindings
+            .iter()
+            .find(|&&(b, _)| b == button)
+            .map(|&(_, key)| key)
+    }
+}
+
+impl fmt::Display for Remap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (button, key)) in self.bindings.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{}={:X}", button, key)?;
+        }
+        Ok(())
+    }
+}
+
+/// Keys the rom checks with EX9E/EXA1, as a bit mask
+fn used_keys(rom: &[u8], frames: usize) -> u16 {
+    let mut keys = 0u16;
+
+    // static: a register loaded with a constant, then checked right away
+    for block in disassemble(rom).blocks {
+        let mut constants = [None; 16];
+        for (_, _, op) in block.instructions {
+            match op {
+                Some(Opcode::LoadByte(x, nn)) => constants[x as usize] = Some(nn),
+                Some(Opcode::SkipKey(x) | Opcode::SkipNotKey(x)) => {
+                    if let Some(nn) = constants[x as usize] {
+                        keys |= 1 << (nn & 0xF);
+                    }
+                }
+                // anything else might change the registers
+                _ => constants = [None; 16],
+            }
+        }
+    }
+
+    // dynamic: whatever the register holds when the key is checked
+    scripted_run(rom, frames, |emu| {
+        if let Some(Opcode::SkipKey(x) | Opcode::SkipNotKey(x)) = emu.next_opcode() {
+            keys |= 1 << (emu.V[x as usize] & 0xF);
+        }
+    });
+
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_for_keys() {
+        let cross = Remap::for_keys(1 << 2 | 1 << 4 | 1 << 5 | 1 << 6 | 1 << 8).unwrap();
+        assert_eq!(cross.to_string(), "up=2 left=4 space=5 right=6 down=8");
+
+        // a single paddle, moving up and down
+        let paddles = Remap::for_keys(1 << 0x1 | 1 << 0x4).unwrap();
+        assert_eq!(paddles.key(Button::Up), Some(0x1));
+        assert_eq!(paddles.key(Button::Down), Some(0x4));
+        assert_eq!(paddles.key(Button::Fire), None);
+
+        assert_eq!(Remap::for_keys(0), None);
+        assert_eq!(Remap::for_keys(0xFFFF), None);
+    }
+
+    #[test]
+    fn test_used_keys() {
+        let rom = [
+            0x60, 0x05, // 0x200: v0 := 5
+            0xE0, 0x9E, // 0x202: skip if key v0 is pressed
+            0x61, 0x07, // 0x204: v1 := 7
+            0x71, 0x02, // 0x206: v1 += 2
+            0xE1, 0xA1, // 0x208: skip if key v1 is not pressed
+            0x12, 0x00, // 0x20A: jump 0x200
+        ];
+
+        // 5 is found statically, 9 only while running
+        assert_eq!(used_keys(&rom, 0), 1 << 5);
+        assert_eq!(used_keys(&rom, 1), 1 << 5 | 1 << 9);
+    }
+}
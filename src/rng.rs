@@ -0,0 +1,180 @@
+//! Random number generators for CXNN.
+//!
+//! ```
+//! use rc8::rng::{Chip8Rng, Sequence};
+//!
+//! let mut rng = Sequence::new(vec![1, 2]);
+//! assert_eq!([rng.next_byte(), rng.next_byte(), rng.next_byte()], [1, 2, 1]);
+//! ```
+use std::str::FromStr;
+
+use nanorand::{BufferedRng, Rng, WyRand};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// A source of random bytes for the emulator
+pub trait Chip8Rng: Send {
+    fn next_byte(&mut self) -> u8;
+
+    /// A copy in the same state, that goes on with the same bytes, for
+    /// going back to an earlier machine state; not every generator can
+    fn try_clone(&self) -> Option<Box<dyn Chip8Rng>> {
+        None
+    }
+}
+
+/// Which generator the emulator uses, unless one is set by hand
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum RngKind {
+    /// Fast, good quality generator
+    #[default]
+    WyRand,
+    /// 16-bit LFSR, with the short and patterned output of 8-bit machines
+    Lfsr,
+}
+
+impl RngKind {
+    /// Build the generator, with a fixed seed or from system entropy
+    pub fn build(self, seed: Option<u64>) -> Box<dyn Chip8Rng> {
+        let seed = seed.unwrap_or_else(|| WyRand::new().generate());
+
+        match self {
+            RngKind::WyRand => Box::new(WyRandRng::new(seed)),
+            RngKind::Lfsr => Box::new(Lfsr::new(seed as u16)),
+        }
+    }
+}
+
+impl FromStr for RngKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wyrand" => Ok(RngKind::WyRand),
+            "lfsr" => Ok(RngKind::Lfsr),
+            _ => Err(format!("unknown random number generator: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct WyRandRng(BufferedRng<WyRand, 8>);
+
+impl WyRandRng {
+    pub fn new(seed: u64) -> Self {
+        WyRandRng(BufferedRng::new(WyRand::new_seed(seed)))
+    }
+}
+
+impl Chip8Rng for WyRandRng {
+    fn next_byte(&mut self) -> u8 {
+        let mut n = [0u8; 1];
+        self.0.fill(&mut n);
+        n[0]
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn Chip8Rng>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+/// Galois LFSR, with a period of 65535
+#[derive(Clone)]
+pub struct Lfsr {
+    state: u16,
+}
+
+impl Lfsr {
+    pub fn new(seed: u16) -> Self {
+        // an all-zero state never changes
+        Lfsr {
+            state: if seed == 0 { 0xACE1 } else { seed },
+        }
+    }
+}
+
+impl Chip8Rng for Lfsr {
+    fn next_byte(&mut self) -> u8 {
+        for _ in 0..8 {
+            let bit = self.state & 1;
+            self.state >>= 1;
+            if bit == 1 {
+                self.state ^= 0xB400;
+            }
+        }
+        self.state as u8
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn Chip8Rng>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+/// Repeats a fixed list of values, for tests
+#[derive(Clone)]
+pub struct Sequence {
+    values: Vec<u8>,
+    position: usize,
+}
+
+impl Sequence {
+    pub fn new(values: Vec<u8>) -> Self {
+        assert!(!values.is_empty(), "the sequence needs at least one value");
+        Sequence {
+            values,
+            position: 0,
+        }
+    }
+}
+
+impl Chip8Rng for Sequence {
+    fn next_byte(&mut self) -> u8 {
+        let value = self.values[self.position];
+        self.position = (self.position + 1) % self.values.len();
+        value
+    }
+
+    fn try_clone(&self) -> Option<Box<dyn Chip8Rng>> {
+        Some(Box::new(self.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded() {
+        for kind in [RngKind::WyRand, RngKind::Lfsr] {
+            let mut a = kind.build(Some(42));
+            let mut b = kind.build(Some(42));
+            let a: Vec<_> = (0..16).map(|_| a.next_byte()).collect();
+            let b: Vec<_> = (0..16).map(|_| b.next_byte()).collect();
+            assert_eq!(a, b);
+        }
+    }
+
+    #[test]
+    fn test_lfsr() {
+        // a zero seed would get stuck
+        let mut lfsr = Lfsr::new(0);
+        let bytes: Vec<_> = (0..8).map(|_| lfsr.next_byte()).collect();
+        assert!(bytes.iter().any(|&b| b != bytes[0]));
+
+        assert_eq!("lfsr".parse(), Ok(RngKind::Lfsr));
+        assert!("vip".parse::<RngKind>().is_err());
+    }
+
+    #[test]
+    fn test_try_clone() {
+        for kind in [RngKind::WyRand, RngKind::Lfsr] {
+            let mut rng = kind.build(Some(42));
+            rng.next_byte();
+            let mut copy = rng.try_clone().unwrap();
+            let a: Vec<_> = (0..16).map(|_| rng.next_byte()).collect();
+            let b: Vec<_> = (0..16).map(|_| copy.next_byte()).collect();
+            assert_eq!(a, b);
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! Settings for known roms, looked up by the SHA-1 of the rom, so games
+//! run right without their players knowing which quirks they need.
+//!
+//! Each entry uses the same keys as a sidecar file (see [`Metadata`]). A
+//! small database is built in, and entries on `rc8/romdb.json`, inside the
+//! config directory, are added to it (replacing the built-in ones):
+//!
+//! ```json
+//! {
+//!     "1ba58656810b67fd131eb9af3e3987863bf26c90": {
+//!         "title": "IBM Logo",
+//!         "tickrate": 15,
+//!         "fillColor": "#4080FF"
+//!     }
+//! }
+//! ```
+use std::{collections::HashMap, fs, io::ErrorKind, path::PathBuf};
+
+use anyhow::Context;
+
+use super::metadata::Metadata;
+
+const BUILT_IN: &str = include_str!("romdb.json");
+
+/// Where the user entries are stored
+fn file_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("rc8").join("romdb.json"))
+}
+
+pub struct RomDb {
+    entries: HashMap<String, Metadata>,
+}
+
+impl RomDb {
+    /// The built-in database, along with the user entries
+    pub fn load() -> Result<Self, anyhow::Error> {
+        let mut db = RomDb::from_json(BUILT_IN).context("invalid built-in rom database")?;
+
+        let path = match file_path() {
+            Some(path) => path,
+            None => return Ok(db),
+        };
+
+        let json = match fs::read_to_string(&path) {
+            Ok(json) => json,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(db),
+            Err(err) => {
+                return Err(err)
+                    .with_context(|| format!("error opening rom database: {}", path.display()))
+            }
+        };
+
+        let user = RomDb::from_json(&json)
+            .with_context(|| format!("invalid rom database: {}", path.display()))?;
+        db.entries.extend(user.entries);
+        Ok(db)
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let entries: HashMap<String, Metadata> = serde_json::from_str(json)?;
+
+        // hashes are compared in lowercase
+        let entries = entries
+            .into_iter()
+            .map(|(hash, metadata)| (hash.to_ascii_lowercase(), metadata))
+            .collect();
+        Ok(RomDb { entries })
+    }
+
+    /// The settings for `rom`, if it is a known one
+    pub fn lookup(&self, rom: &[u8]) -> Option<&Metadata> {
+        self.entries.get(&sha1_hex(rom))
+    }
+}
+
+/// SHA-1 of `data`, as lowercase hex
+pub fn sha1_hex(data: &[u8]) -> String {
+    sha1(data)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// SHA-1, as used by the CHIP-8 rom archives to identify roms
+pub fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+
+    // the message is padded with a single bit, zeroes and its length in
+    // bits, up to a multiple of 64 bytes
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for chunk in message.chunks(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in chunk.chunks(4).enumerate() {
+            w[i] = u32::from_be_bytes(word.try_into().unwrap());
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = h;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5A827999),
+                20..=39 => (b ^ c ^ d, 0x6ED9EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1BBCDC),
+                _ => (b ^ c ^ d, 0xCA62C1D6),
+            };
+
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        for (value, add) in h.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, value) in digest.chunks_mut(4).zip(h) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sha1() {
+        assert_eq!(sha1_hex(b""), "da39a3ee5e6b4b0d3255bfef95601890afd80709");
+        assert_eq!(sha1_hex(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+
+        // more than one block
+        let long = [b'a'; 1000];
+        assert_eq!(sha1_hex(&long), "291e9a6c66994949b57ba5e650361e98fc36b1ba");
+    }
+
+    #[test]
+    fn test_lookup() {
+        let db = RomDb::from_json(BUILT_IN).unwrap();
+        let rom = std::fs::read("roms/ibm-logo.ch8").unwrap();
+        let metadata = db.lookup(&rom).unwrap();
+        assert_eq!(metadata.title.as_deref(), Some("IBM Logo"));
+        assert!(db.lookup(&rom[1..]).is_none());
+
+        // user entries may use uppercase hashes
+        let json = r#"{"DA39A3EE5E6B4B0D3255BFEF95601890AFD80709": {"tickrate": 20}}"#;
+        let db = RomDb::from_json(json).unwrap();
+        assert_eq!(db.lookup(&[]).unwrap().speed(), 1200);
+    }
+}
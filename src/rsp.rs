@@ -0,0 +1,332 @@
+//! The packets of the GDB remote serial protocol that the CHIP-8 can
+//! answer, without the connection: what a packet asks for, and the reply
+//! once it ran on the machine.
+//!
+//! The gdb stub of the app sends the replies over TCP; the web playground
+//! answers the same packets in the browser.
+use std::{collections::BTreeSet, fmt::Write as _};
+
+use super::emulator::Emulator;
+
+/// Stop reply for a breakpoint, a step or an interrupt (SIGTRAP)
+pub const STOPPED: &str = "S05";
+
+/// Stop reply when the instruction could not run (SIGILL)
+pub const CRASHED: &str = "S04";
+
+// V0-VF, then I and PC (little-endian), then the stack depth and the timers
+const REGISTERS: [(&str, u32, &str); 21] = [
+    ("v0", 8, "uint8"),
+    ("v1", 8, "uint8"),
+    ("v2", 8, "uint8"),
+    ("v3", 8, "uint8"),
+    ("v4", 8, "uint8"),
+    ("v5", 8, "uint8"),
+    ("v6", 8, "uint8"),
+    ("v7", 8, "uint8"),
+    ("v8", 8, "uint8"),
+    ("v9", 8, "uint8"),
+    ("va", 8, "uint8"),
+    ("vb", 8, "uint8"),
+    ("vc", 8, "uint8"),
+    ("vd", 8, "uint8"),
+    ("ve", 8, "uint8"),
+    ("vf", 8, "uint8"),
+    ("i", 16, "data_ptr"),
+    ("pc", 16, "code_ptr"),
+    ("sp", 8, "uint8"),
+    ("dt", 8, "uint8"),
+    ("st", 8, "uint8"),
+];
+
+/// What gdb asked for, that needs the machine
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    /// A client connected, and expects the rom to be stopped
+    Attach,
+    /// Ctrl-C while the rom runs; the stop reply goes to the [`Command::Continue`]
+    Interrupt,
+    Continue,
+    Step,
+    ReadRegisters,
+    WriteRegisters(Vec<u8>),
+    ReadMemory(usize, usize),
+    WriteMemory(usize, Vec<u8>),
+    Breakpoint(usize, bool),
+    /// The client left; the rom runs on its own again
+    Detach,
+}
+
+/// What to do with a packet
+pub enum Answer {
+    /// Reply right away
+    Reply(String),
+    /// Run a command on the machine, which has the reply
+    Machine(Command),
+    /// The client is leaving
+    Detach,
+}
+
+/// Where the breakpoints set by gdb go
+pub trait Breakpoints {
+    fn set_breakpoint(&mut self, addr: usize, set: bool);
+}
+
+impl Breakpoints for BTreeSet<usize> {
+    fn set_breakpoint(&mut self, addr: usize, set: bool) {
+        if set {
+            self.insert(addr);
+        } else {
+            self.remove(&addr);
+        }
+    }
+}
+
+/// What to do with a packet; anything not understood gets an empty reply,
+/// which tells gdb it is not supported
+pub fn answer(packet: &str) -> Answer {
+    let reply = |text: &str| Answer::Reply(text.to_owned());
+    let (kind, args) = packet.split_at(packet.len().min(1));
+
+    match kind {
+        "?" => reply(STOPPED),
+        "g" => Answer::Machine(Command::ReadRegisters),
+        "G" => match from_hex(args) {
+            Some(bytes) => Answer::Machine(Command::WriteRegisters(bytes)),
+            None => reply("E01"),
+        },
+        "m" => match parse_range(args) {
+            Some((addr, len)) => Answer::Machine(Command::ReadMemory(addr, len)),
+            None => reply("E01"),
+        },
+        "M" => {
+            let write = args.split_once(':').and_then(|(range, data)| {
+                let (addr, len) = parse_range(range)?;
+                let bytes = from_hex(data).filter(|bytes| bytes.len() == len)?;
+                Some(Command::WriteMemory(addr, bytes))
+            });
+            match write {
+                Some(command) => Answer::Machine(command),
+                None => reply("E01"),
+            }
+        }
+        "Z" | "z" => {
+            // software or hardware breakpoints only, not watchpoints
+            let mut parts = args.split(',');
+            let addr = match (parts.next(), parts.next()) {
+                (Some("0" | "1"), Some(addr)) => usize::from_str_radix(addr, 16).ok(),
+                _ => return reply(""),
+            };
+            match addr {
+                Some(addr) => Answer::Machine(Command::Breakpoint(addr, kind == "Z")),
+                None => reply("E01"),
+            }
+        }
+        "s" if args.is_empty() => Answer::Machine(Command::Step),
+        "c" if args.is_empty() => Answer::Machine(Command::Continue),
+        "D" | "k" => Answer::Detach,
+        "H" | "T" => reply("OK"),
+        _ if packet.starts_with("qSupported") => reply("PacketSize=1000;qXfer:features:read+"),
+        _ if packet == "qAttached" => reply("1"),
+        _ => match packet.strip_prefix("qXfer:features:read:target.xml:") {
+            Some(range) => match parse_range(range) {
+                Some((offset, len)) => Answer::Reply(chunk(&target_xml(), offset, len)),
+                None => reply("E01"),
+            },
+            None => reply(""),
+        },
+    }
+}
+
+/// Run `command` on a stopped machine; the run control commands are left to
+/// the caller
+pub fn access(
+    command: &Command,
+    emu: &mut Emulator,
+    breakpoints: &mut impl Breakpoints,
+) -> Option<String> {
+    let reply = match command {
+        Command::ReadRegisters => to_hex(&registers(emu)),
+        Command::WriteRegisters(bytes) => match set_registers(emu, bytes) {
+            Some(()) => "OK".to_owned(),
+            None => "E01".to_owned(),
+        },
+        Command::ReadMemory(addr, len) if *addr < emu.memory.len() => {
+            match addr.checked_add(*len) {
+                Some(end) => to_hex(&emu.memory[*addr..end.min(emu.memory.len())]),
+                None => "E01".to_owned(),
+            }
+        }
+        Command::WriteMemory(addr, bytes)
+            if addr
+                .checked_add(bytes.len())
+                .is_some_and(|end| end <= emu.memory.len()) =>
+        {
+            emu.memory[*addr..addr + bytes.len()].copy_from_slice(bytes);
+            "OK".to_owned()
+        }
+        Command::ReadMemory(..) | Command::WriteMemory(..) => "E01".to_owned(),
+        Command::Breakpoint(addr, set) => {
+            breakpoints.set_breakpoint(*addr, *set);
+            "OK".to_owned()
+        }
+        Command::Step => match emu.execute() {
+            Ok(()) => STOPPED.to_owned(),
+            Err(_) => CRASHED.to_owned(),
+        },
+        Command::Attach | Command::Interrupt | Command::Continue | Command::Detach => return None,
+    };
+    Some(reply)
+}
+
+/// The registers, as gdb expects them on a `g` packet
+fn registers(emu: &Emulator) -> Vec<u8> {
+    let mut bytes = emu.V.to_vec();
+    bytes.extend_from_slice(&emu.I.to_le_bytes());
+    bytes.extend_from_slice(&(emu.PC as u16).to_le_bytes());
+    bytes.extend([emu.sub_stack.len() as u8, emu.DT, emu.ST]);
+    bytes
+}
+
+/// A `G` packet; the stack depth can not be changed
+fn set_registers(emu: &mut Emulator, bytes: &[u8]) -> Option<()> {
+    let bytes: &[u8; 23] = bytes.try_into().ok()?;
+    let pc = u16::from_le_bytes([bytes[18], bytes[19]]) as usize;
+    if pc >= emu.memory.len() - 1 {
+        return None;
+    }
+
+    emu.V.copy_from_slice(&bytes[..16]);
+    emu.I = u16::from_le_bytes([bytes[16], bytes[17]]);
+    emu.PC = pc;
+    emu.DT = bytes[21];
+    emu.ST = bytes[22];
+    Some(())
+}
+
+/// Describes the registers, as gdb knows nothing about the CHIP-8
+fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+         <target version=\"1.0\"><feature name=\"org.rc8.chip8\">",
+    );
+    for (name, bits, kind) in REGISTERS {
+        let _ = write!(
+            xml,
+            "<reg name=\"{name}\" bitsize=\"{bits}\" type=\"{kind}\"/>"
+        );
+    }
+    xml.push_str("</feature></target>");
+    xml
+}
+
+/// Part of a qXfer document: `m` if there is more, `l` on the last part
+fn chunk(document: &str, offset: usize, len: usize) -> String {
+    let rest = document.get(offset..).unwrap_or("");
+    if rest.len() > len {
+        format!("m{}", &rest[..len])
+    } else {
+        format!("l{}", rest)
+    }
+}
+
+/// `addr,len`, both in hex
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    let (addr, len) = range.split_once(',')?;
+    Some((
+        usize::from_str_radix(addr, 16).ok()?,
+        usize::from_str_radix(len, 16).ok()?,
+    ))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_answer() {
+        assert!(matches!(
+            answer("m200,4"),
+            Answer::Machine(Command::ReadMemory(0x200, 4))
+        ));
+        assert!(matches!(
+            answer("M200,2:a0b1"),
+            Answer::Machine(Command::WriteMemory(0x200, bytes)) if bytes == [0xA0, 0xB1]
+        ));
+        assert!(matches!(
+            answer("Z0,202,2"),
+            Answer::Machine(Command::Breakpoint(0x202, true))
+        ));
+        assert!(matches!(answer("Z2,202,2"), Answer::Reply(reply) if reply.is_empty()));
+        assert!(matches!(answer("vMustReplyEmpty"), Answer::Reply(reply) if reply.is_empty()));
+
+        let xml = target_xml();
+        assert!(
+            matches!(answer("qXfer:features:read:target.xml:0,10"), Answer::Reply(reply) if reply == format!("m{}", &xml[..16]))
+        );
+        assert_eq!(chunk(&xml, 16, 10000), format!("l{}", &xml[16..]));
+    }
+
+    #[test]
+    fn test_access() {
+        let rom = [0x60, 0x2A]; // 0x200: v0 := 0x2A
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        let mut breakpoints = BTreeSet::new();
+
+        let step = access(&Command::Step, &mut emu, &mut breakpoints);
+        assert_eq!(step.as_deref(), Some(STOPPED));
+
+        let registers = access(&Command::ReadRegisters, &mut emu, &mut breakpoints).unwrap();
+        assert_eq!(registers.len(), 23 * 2);
+        assert!(registers.starts_with("2a00"));
+        assert_eq!(&registers[36..40], "0202");
+
+        let mut bytes = from_hex(&registers).unwrap();
+        bytes[18] = 0x00;
+        let write = access(&Command::WriteRegisters(bytes), &mut emu, &mut breakpoints);
+        assert_eq!(write.as_deref(), Some("OK"));
+        assert_eq!(emu.PC, 0x200);
+
+        let read = access(&Command::ReadMemory(0x200, 4), &mut emu, &mut breakpoints);
+        assert_eq!(read.as_deref(), Some("602a0000"));
+        let read = access(&Command::ReadMemory(0x1000, 1), &mut emu, &mut breakpoints);
+        assert_eq!(read.as_deref(), Some("E01"));
+        assert_eq!(access(&Command::Continue, &mut emu, &mut breakpoints), None);
+
+        let set = access(
+            &Command::Breakpoint(0x202, true),
+            &mut emu,
+            &mut breakpoints,
+        );
+        assert_eq!(set.as_deref(), Some("OK"));
+        assert!(breakpoints.contains(&0x202));
+    }
+
+    #[test]
+    fn test_access_overflow() {
+        let mut emu = Emulator::load_rom(&[][..]).unwrap();
+        let mut breakpoints = BTreeSet::new();
+
+        for packet in ["m200,ffffffffffffffff", "Mffffffffffffffff,1:00"] {
+            let Answer::Machine(command) = answer(packet) else {
+                panic!("{} is not a machine command", packet);
+            };
+            let reply = access(&command, &mut emu, &mut breakpoints);
+            assert_eq!(reply.as_deref(), Some("E01"), "{}", packet);
+        }
+    }
+}
@@ -0,0 +1,314 @@
+use std::{
+    convert::Infallible,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::bail;
+
+use super::{
+    batch::{run_many, Outcome, Run},
+    clock::{SystemClock, VirtualClock},
+    emulator::{Emulator, Quirks},
+    filter::Filter,
+    frontend::{self, AudioSink, Frame, InputSource},
+    timing::{Timing, DEFAULT_SPEED, FRAME_CYCLES, TIMER_DELAY, TIMER_RATE},
+    trace::Tracer,
+    worker::{Event, Worker},
+};
+
+// how long the timing checks run, and how far off they can be
+const PACING_DURATION: Duration = Duration::from_millis(500);
+const PACING_TOLERANCE: f64 = 0.05;
+
+/// A bundled rom, with the expected screen after running for a while
+struct Fingerprint {
+    name: &'static str,
+    rom: &'static [u8],
+    quirks: Quirks,
+    frames: usize,
+    hash: u64,
+}
+
+fn fingerprints() -> Vec<Fingerprint> {
+    let clip = Quirks::default();
+    let wrap = Quirks {
+        wrap_sprites: true,
+        ..clip
+    };
+
+    vec![
+        Fingerprint {
+            name: "ibm-logo (clip)",
+            rom: include_bytes!("../roms/ibm-logo.ch8"),
+            quirks: clip,
+            frames: 60,
+            hash: 0xc094f65422bd4e58,
+        },
+        Fingerprint {
+            name: "ibm-logo (wrap)",
+            rom: include_bytes!("../roms/ibm-logo.ch8"),
+            quirks: wrap,
+            frames: 60,
+            hash: 0xc094f65422bd4e58,
+        },
+        Fingerprint {
+            name: "chip8-emulator-logo (clip)",
+            rom: include_bytes!("../roms/chip8-emulator-logo.ch8"),
+            quirks: clip,
+            frames: 120,
+            hash: 0x948b6049743bdac9,
+        },
+        Fingerprint {
+            name: "chip8-emulator-logo (wrap)",
+            rom: include_bytes!("../roms/chip8-emulator-logo.ch8"),
+            quirks: wrap,
+            frames: 120,
+            hash: 0x948b6049743bdac9,
+        },
+        Fingerprint {
+            name: "chip8-test-suite (clip)",
+            rom: include_bytes!("../roms/chip8-test-suite.ch8"),
+            quirks: clip,
+            frames: 120,
+            hash: 0x2ec18019f9f0eb5b,
+        },
+        Fingerprint {
+            name: "chip8-test-suite (wrap)",
+            rom: include_bytes!("../roms/chip8-test-suite.ch8"),
+            quirks: wrap,
+            frames: 120,
+            hash: 0x2ec18019f9f0eb5b,
+        },
+    ]
+}
+
+/// Run the bundled roms headlessly and check the emulator pacing,
+/// printing a short report.
+pub fn run() -> Result<(), anyhow::Error> {
+    let mut failures = 0;
+    let mut report = |name: &str, result: Result<(), String>| match result {
+        Ok(()) => println!("  ok    {}", name),
+        Err(msg) => {
+            println!("  FAIL  {}: {}", name, msg);
+            failures += 1;
+        }
+    };
+
+    println!("framebuffer fingerprints:");
+    let fingerprints = fingerprints();
+    let runs: Vec<Run> = fingerprints
+        .iter()
+        .map(|fingerprint| Run {
+            rom: fingerprint.rom,
+            quirks: fingerprint.quirks,
+            seed: 0,
+            frames: fingerprint.frames,
+        })
+        .collect();
+    for (fingerprint, outcome) in fingerprints.iter().zip(run_many(&runs, 0)?) {
+        report(fingerprint.name, check_fingerprint(fingerprint, outcome));
+    }
+
+    #[cfg(feature = "dev")]
+    {
+        println!("generated test roms:");
+        for test in super::testroms::corpus() {
+            report(test.name, super::testroms::run(&test));
+        }
+    }
+
+    println!("timing:");
+    report("cpu, timer and buzzer pacing", check_virtual_pacing());
+    report("frames delivered on time", check_frames());
+    report("buzzer duration", check_buzzer());
+
+    if failures > 0 {
+        bail!("{} check(s) failed", failures);
+    }
+
+    println!("all checks passed");
+    Ok(())
+}
+
+fn check_fingerprint(fingerprint: &Fingerprint, outcome: Outcome) -> Result<(), String> {
+    if let Some(err) = outcome.error {
+        return Err(err.to_string());
+    }
+
+    let hash = outcome.screen_hash;
+    if hash != fingerprint.hash {
+        return Err(format!(
+            "expected hash {:016x}, got {:016x}",
+            fingerprint.hash, hash
+        ));
+    }
+
+    Ok(())
+}
+
+/// Checks if the value is within the tolerance of the expected one
+fn within_tolerance(name: &str, value: u128, expected: u128) -> Result<(), String> {
+    let delta = (value as f64 - expected as f64).abs() / expected as f64;
+    if delta > PACING_TOLERANCE {
+        return Err(format!("expected ~{} {}, got {}", expected, name, value));
+    }
+
+    Ok(())
+}
+
+/// Checks if the value is exactly the expected one
+fn exactly(name: &str, value: u128, expected: u128) -> Result<(), String> {
+    if value != expected {
+        return Err(format!("expected {} {}, got {}", expected, name, value));
+    }
+
+    Ok(())
+}
+
+// counts the frames shown
+struct Frames(u128);
+
+impl Frame for Frames {
+    type Error = Infallible;
+
+    fn present(&mut self, _emu: &Emulator) -> Result<(), Infallible> {
+        self.0 += 1;
+        Ok(())
+    }
+}
+
+// asks to quit after a number of steps
+struct Steps(u32);
+
+impl InputSource for Steps {
+    fn poll(&mut self, _emu: &mut Emulator) {
+        self.0 = self.0.saturating_sub(1);
+    }
+
+    fn quit_requested(&self) -> bool {
+        self.0 == 0
+    }
+}
+
+// counts the steps that end with the buzzer on
+struct Beeps(u128);
+
+impl AudioSink for Beeps {
+    fn set_buzzer(&mut self, on: bool) {
+        self.0 += on as u128;
+    }
+}
+
+/// Run half a second of a virtual clock, one frame per step, through the
+/// same loop the frontends use; every frame, instruction, timer tick and
+/// beep must be there, exactly
+fn check_virtual_pacing() -> Result<(), String> {
+    let rom = [
+        0x60, 0xFF, // 0x200: Set V0 = 255
+        0xF0, 0x15, // 0x202: Set DT = V0
+        0x61, 0x1E, // 0x204: Set V1 = 30
+        0xF1, 0x18, // 0x206: Set ST = V1
+        0x72, 0x01, // 0x208: Add 1 to V2
+        0x12, 0x08, // 0x20A: Jump to 0x208
+    ];
+    let steps = TIMER_RATE / 2;
+
+    let mut emu = Emulator::load_rom(&rom[..]).map_err(|e| e.to_string())?;
+    let mut clock = VirtualClock::new(TIMER_RATE);
+    let (mut frames, mut beeps) = (Frames(0), Beeps(0));
+    frontend::run(
+        &mut emu,
+        Timing::default(),
+        &mut clock,
+        &mut frames,
+        &mut Steps(steps),
+        &mut beeps,
+    )
+    .map_err(|e| e.to_string())?;
+
+    let steps = steps as u128;
+    exactly("frames", frames.0, steps)?;
+    exactly("timer ticks", 255 - emu.DT as u128, steps)?;
+
+    // 4 instructions set the timers, the rest is a loop of 2
+    let cycles = steps * FRAME_CYCLES;
+    exactly("loop iterations", emu.V[2] as u128, (cycles - 4) / 2)?;
+
+    // the buzzer goes off once the sound timer is down to 1
+    exactly("frames of sound", beeps.0, 28)
+}
+
+/// Frames the emulation thread delivers over a known period of wall clock
+/// time; a host too slow to keep up shows up as missing ones
+fn check_frames() -> Result<(), String> {
+    let rom = [
+        0x12, 0x00, // 0x200: Jump to 0x200
+    ];
+
+    let emu = Emulator::load_rom(&rom[..]).map_err(|e| e.to_string())?;
+    let start = Instant::now();
+    let worker = spawn(emu);
+    thread::sleep(PACING_DURATION);
+    let (frames, instructions) = {
+        let machine = worker.lock();
+        (machine.screen.frames as u128, machine.instructions as u128)
+    };
+    let elapsed = start.elapsed().as_micros();
+    worker.stop();
+
+    within_tolerance("frames", frames, elapsed * TIMER_RATE as u128 / 1_000_000)?;
+    within_tolerance(
+        "instructions",
+        instructions,
+        elapsed * DEFAULT_SPEED as u128 / 1_000_000,
+    )
+}
+
+/// How long the emulation thread keeps the buzzer on, for half a second
+/// of sound, as seen by the UI
+fn check_buzzer() -> Result<(), String> {
+    let rom = [
+        0x60, 0x1E, // 0x200: Set V0 = 30
+        0xF0, 0x18, // 0x202: Set ST = V0
+        0x12, 0x04, // 0x204: Jump to 0x204
+    ];
+
+    let emu = Emulator::load_rom(&rom[..]).map_err(|e| e.to_string())?;
+    let worker = spawn(emu);
+
+    let timeout = Instant::now() + PACING_DURATION * 4;
+    let mut started = None;
+    let mut duration = None;
+    while duration.is_none() && Instant::now() < timeout {
+        for event in worker.events() {
+            match (event, started) {
+                (Event::Buzzer(true), None) => started = Some(Instant::now()),
+                (Event::Buzzer(false), Some(start)) => duration = Some(start.elapsed()),
+                _ => {}
+            }
+        }
+        thread::sleep(Duration::from_millis(1));
+    }
+    worker.stop();
+
+    let duration = duration.ok_or("the buzzer never went off")?;
+    within_tolerance(
+        "microseconds of sound",
+        duration.as_micros(),
+        29 * TIMER_DELAY,
+    )
+}
+
+/// Run `emu` on its own thread, on the wall clock, like the app does
+fn spawn(emu: Emulator) -> Worker {
+    Worker::spawn(
+        emu,
+        Timing::default(),
+        SystemClock::new(),
+        Filter::None,
+        None,
+        None,
+        Tracer::new(),
+    )
+}
@@ -0,0 +1,82 @@
+use clap::ValueEnum;
+
+/// How each CHIP-8 pixel is drawn on the window
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum PixelShape {
+    /// Solid squares, touching each other
+    Square,
+    /// Squares with a 1 pixel gap between them, like graph paper
+    Grid,
+    /// Squares with rounded corners
+    Dot,
+    /// Round LEDs; the ones that are off still show, faintly
+    Led,
+}
+
+impl PixelShape {
+    /// The part of each row of a `size` wide pixel covered by the shape,
+    /// as the offset and the length of the row
+    pub fn spans(&self, size: usize) -> Vec<(usize, usize)> {
+        // everything but the plain squares leaves a gap, when there is room
+        let inner = match self {
+            PixelShape::Square => return vec![(0, size); size],
+            _ if size > 2 => size - 1,
+            _ => size,
+        };
+
+        let radius = match self {
+            PixelShape::Square | PixelShape::Grid => 0.0,
+            PixelShape::Dot => inner as f32 / 3.0,
+            PixelShape::Led => inner as f32 / 2.0,
+        };
+
+        let mut spans: Vec<_> = (0..inner)
+            .map(|row| {
+                // how far into a rounded corner this row is
+                let center = row as f32 + 0.5;
+                let depth = (radius - center)
+                    .max(center - (inner as f32 - radius))
+                    .max(0.0);
+                let inset = (radius - (radius * radius - depth * depth).max(0.0).sqrt()).round();
+                let inset = (inset as usize).min(inner / 2);
+                (inset, inner - 2 * inset)
+            })
+            .collect();
+        spans.resize(size, (0, 0));
+        spans
+    }
+
+    /// If pixels that are off are drawn too
+    pub fn shows_unlit(&self) -> bool {
+        *self == PixelShape::Led
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spans() {
+        assert_eq!(PixelShape::Square.spans(2), vec![(0, 2), (0, 2)]);
+        assert_eq!(
+            PixelShape::Grid.spans(4),
+            vec![(0, 3), (0, 3), (0, 3), (0, 0)]
+        );
+
+        // round things are narrow at the top and bottom, and symmetric
+        let spans = PixelShape::Led.spans(10);
+        assert_eq!(spans.len(), 10);
+        assert_eq!(spans[4], (0, 9));
+        assert!(spans[0].1 < spans[2].1);
+        assert_eq!(spans[0], spans[8]);
+        assert_eq!(spans[9], (0, 0));
+
+        let dot = PixelShape::Dot.spans(10);
+        assert_eq!(dot[4], (0, 9));
+        assert!(dot[0].1 > spans[0].1);
+
+        // too small to be anything but squares
+        assert_eq!(PixelShape::Led.spans(1), vec![(0, 1)]);
+    }
+}
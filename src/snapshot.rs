@@ -0,0 +1,67 @@
+//! A copy of the whole machine state, for save states, rewind and netplay.
+//!
+//! With the `serde` feature, snapshots (and the [`Emulator`] itself) can be
+//! serialized with any serde format, like JSON or bincode. The random number
+//! generator state is not saved: a restored machine gets a new generator
+//! of the same kind.
+//!
+//! ```
+//! use rc8::emulator::Emulator;
+//!
+//! let rom = [0x60, 0x05]; // 0x200: V0 = 5
+//! let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+//! let snapshot = emu.snapshot();
+//!
+//! emu.execute().unwrap();
+//! emu.restore(&snapshot).unwrap();
+//! assert_eq!(emu.V[0], 0);
+//! ```
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[cfg(doc)]
+use super::emulator::Emulator;
+use super::{chip8x::ColorBoard, display::Display, emulator::Quirks};
+
+/// Bumped every time the snapshot fields change
+pub const SNAPSHOT_VERSION: u32 = 14;
+
+#[derive(Error, Debug, PartialEq, Eq)]
+pub enum SnapshotError {
+    #[error("snapshot version {0} is not supported (expected {SNAPSHOT_VERSION})")]
+    Version(u32),
+
+    #[error("invalid snapshot: {0}")]
+    Invalid(&'static str),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[allow(non_snake_case)]
+pub struct Snapshot {
+    pub version: u32,
+    pub PC: usize,
+    pub memory: Vec<u8>,
+    pub V: [u8; 16],
+    pub I: u16,
+    pub sub_stack: Vec<usize>,
+    pub DT: u8,
+    pub ST: u8,
+    pub keys: [bool; 16],
+    pub screen: Display,
+    pub planes: u8,
+    pub vblank_interrupt: bool,
+    pub key_clock: u64,
+    pub key_down_at: [u64; 16],
+    pub key_up_at: [u64; 16],
+    pub key_mark: u64,
+    pub waiting_key: bool,
+    pub quirks: Quirks,
+    pub clipped_sprites: u32,
+    pub rom: Vec<u8>,
+    pub keys2: [bool; 16],
+    pub color_board: ColorBoard,
+    pub tone: u8,
+    pub flags: [u8; 16],
+}
@@ -0,0 +1,121 @@
+//! Where the rom given on the command line comes from: a file, stdin (as
+//! `-`) or, when built with the `net` feature, an http(s) URL. Roms are
+//! small, so they are read whole into memory before loading.
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+
+#[cfg(feature = "net")]
+use std::time::Duration;
+
+// more than any rom needs, so a wrong URL does not download forever
+#[cfg(feature = "net")]
+const MAX_DOWNLOAD: u64 = 1024 * 1024;
+
+#[cfg(feature = "net")]
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RomSource {
+    File(PathBuf),
+    Stdin,
+    Url(String),
+}
+
+impl RomSource {
+    pub fn parse(arg: &str) -> Self {
+        if arg == "-" {
+            RomSource::Stdin
+        } else if arg.starts_with("http://") || arg.starts_with("https://") {
+            RomSource::Url(arg.to_owned())
+        } else {
+            RomSource::File(PathBuf::from(arg))
+        }
+    }
+
+    /// The file the rom is on, if any; only those have a sidecar, and go
+    /// on the recent roms list
+    pub fn file(&self) -> Option<&Path> {
+        match self {
+            RomSource::File(path) => Some(path),
+            RomSource::Stdin | RomSource::Url(_) => None,
+        }
+    }
+
+    /// A name for the window title and the logs, like a file would have
+    pub fn name(&self) -> PathBuf {
+        match self {
+            RomSource::File(path) => path.clone(),
+            RomSource::Stdin => PathBuf::from("stdin"),
+            RomSource::Url(url) => {
+                let path = url.split(['?', '#']).next().unwrap_or_default();
+                let name = path.trim_end_matches('/').rsplit('/').next();
+                PathBuf::from(name.unwrap_or(url))
+            }
+        }
+    }
+
+    /// The whole rom
+    pub fn read(&self) -> Result<Vec<u8>, anyhow::Error> {
+        match self {
+            RomSource::File(path) => std::fs::read(path)
+                .with_context(|| format!("error opening rom file: {}", path.display())),
+            RomSource::Stdin => {
+                let mut rom = Vec::new();
+                std::io::stdin()
+                    .lock()
+                    .read_to_end(&mut rom)
+                    .context("error reading the rom from stdin")?;
+                Ok(rom)
+            }
+            RomSource::Url(url) => download(url),
+        }
+    }
+}
+
+#[cfg(feature = "net")]
+fn download(url: &str) -> Result<Vec<u8>, anyhow::Error> {
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    let response = agent
+        .get(url)
+        .set("User-Agent", concat!("rc8/", env!("CARGO_PKG_VERSION")))
+        .call()
+        .with_context(|| format!("error downloading rom: {}", url))?;
+
+    let mut rom = Vec::new();
+    response
+        .into_reader()
+        .take(MAX_DOWNLOAD)
+        .read_to_end(&mut rom)
+        .with_context(|| format!("error downloading rom: {}", url))?;
+    Ok(rom)
+}
+
+#[cfg(not(feature = "net"))]
+fn download(url: &str) -> Result<Vec<u8>, anyhow::Error> {
+    anyhow::bail!(
+        "cannot download {}: rc8 was built without the net feature",
+        url
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(RomSource::parse("-"), RomSource::Stdin);
+        assert_eq!(
+            RomSource::parse("roms/pong.ch8"),
+            RomSource::File(PathBuf::from("roms/pong.ch8"))
+        );
+
+        let url = RomSource::parse("https://example.com/roms/pong.ch8?raw=true");
+        assert_eq!(url.file(), None);
+        assert_eq!(url.name(), PathBuf::from("pong.ch8"));
+    }
+}
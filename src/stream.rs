@@ -0,0 +1,355 @@
+//! Serves the screen to web browsers, over a WebSocket, and takes key
+//! presses back, so the game can be watched and played from another
+//! machine.
+//!
+//! `GET /` is a page that does everything; `GET /ws` is the WebSocket. Each
+//! screen change goes out as a binary message: the width and height, the
+//! number of planes, the four palette colors (RGB: the background, then
+//! the first, second and both planes) and then every row of every plane,
+//! 8 pixels per byte. Keys come back as text
+//! messages: `+` or `-` followed by the CHIP-8 key, in hex.
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{Ipv4Addr, TcpListener, TcpStream},
+    sync::{
+        mpsc::{self, Receiver, Sender, TryIter},
+        Arc, Mutex,
+    },
+    thread,
+};
+
+use tracing::{debug, info};
+
+use super::romdb::sha1;
+use rc8::display::Display;
+
+const PAGE: &str = include_str!("stream.html");
+
+// added to the client key for the handshake, as told by RFC 6455
+const WEBSOCKET_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// frame opcodes
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+// the only messages clients send are keys, so anything big is an error
+const MAX_MESSAGE: u64 = 1024;
+
+// what goes to each client: an opcode and its payload
+type Clients = Arc<Mutex<Vec<Sender<(u8, Arc<Vec<u8>>)>>>>;
+
+/// Sends the screen to every browser watching
+pub struct StreamServer {
+    clients: Clients,
+    last: Arc<Mutex<Option<Arc<Vec<u8>>>>>,
+    keys: Receiver<(usize, bool)>,
+}
+
+impl StreamServer {
+    pub fn spawn(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, port))?;
+        info!("streaming the screen on http://localhost:{}/", port);
+
+        let clients = Clients::default();
+        let last = Arc::new(Mutex::new(None));
+        let (sender, keys) = mpsc::channel();
+
+        let shared = (Arc::clone(&clients), Arc::clone(&last));
+        thread::Builder::new()
+            .name("stream".to_owned())
+            .spawn(move || {
+                for stream in listener.incoming().flatten() {
+                    let (clients, last) = (Arc::clone(&shared.0), Arc::clone(&shared.1));
+                    let keys = sender.clone();
+                    thread::spawn(move || {
+                        if let Err(err) = serve(stream, &clients, &last, keys) {
+                            debug!("stream client error: {}", err);
+                        }
+                    });
+                }
+            })?;
+
+        Ok(StreamServer {
+            clients,
+            last,
+            keys,
+        })
+    }
+
+    /// Send the screen to everyone; new clients get it as soon as they connect
+    pub fn publish(&self, display: &Display, colors: [[u8; 3]; 4]) {
+        let message = Arc::new(encode_screen(display, colors));
+        *self.last.lock().unwrap() = Some(Arc::clone(&message));
+
+        // clients that went away are dropped
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|client| client.send((BINARY, Arc::clone(&message))).is_ok());
+    }
+
+    /// Keys pressed and released on the browsers since the last call
+    pub fn keys(&self) -> TryIter<'_, (usize, bool)> {
+        self.keys.try_iter()
+    }
+}
+
+fn encode_screen(display: &Display, colors: [[u8; 3]; 4]) -> Vec<u8> {
+    let mut message = vec![
+        display.width() as u8,
+        display.height() as u8,
+        display.planes() as u8,
+    ];
+    message.extend(colors.as_flattened());
+    message.extend(display.bytes());
+    message
+}
+
+/// Answer a single HTTP request: the page, or the WebSocket
+fn serve(
+    stream: TcpStream,
+    clients: &Clients,
+    last: &Mutex<Option<Arc<Vec<u8>>>>,
+    keys: Sender<(usize, bool)>,
+) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    let path = request.split_whitespace().nth(1).unwrap_or("/").to_owned();
+
+    let mut key = None;
+    loop {
+        let mut line = String::new();
+        if reader.read_line(&mut line)? == 0 || line.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.eq_ignore_ascii_case("sec-websocket-key") {
+                key = Some(value.trim().to_owned());
+            }
+        }
+    }
+
+    match (path.as_str(), key) {
+        ("/ws", Some(key)) => {
+            write!(
+                writer,
+                "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                 Connection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+                accept_key(&key)
+            )?;
+            info!("stream client connected from {}", writer.peer_addr()?);
+
+            let (sender, outgoing) = mpsc::channel();
+            if let Some(screen) = last.lock().unwrap().clone() {
+                let _ = sender.send((BINARY, screen));
+            }
+            clients.lock().unwrap().push(sender.clone());
+
+            // the screens (and the pongs) are written on another thread,
+            // so a slow client does not hold the others back
+            thread::spawn(move || {
+                for (opcode, payload) in outgoing {
+                    if write_frame(&mut writer, opcode, &payload).is_err() {
+                        return;
+                    }
+                }
+            });
+
+            let mut held = [false; 16];
+            while let Some((opcode, payload)) = read_frame(&mut reader)? {
+                match opcode {
+                    TEXT => {
+                        if let Some((key, pressed)) = parse_key(&payload) {
+                            held[key] = pressed;
+                            let _ = keys.send((key, pressed));
+                        }
+                    }
+                    PING => {
+                        let _ = sender.send((PONG, Arc::new(payload)));
+                    }
+                    CLOSE => break,
+                    _ => {}
+                }
+            }
+            info!("stream client disconnected");
+
+            // nothing stays pressed after the browser is gone
+            for key in (0..16).filter(|&key| held[key]) {
+                let _ = keys.send((key, false));
+            }
+            Ok(())
+        }
+        ("/", _) => {
+            write!(
+                writer,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\n\
+                 Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                PAGE.len(),
+                PAGE
+            )
+        }
+        _ => write!(
+            writer,
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        ),
+    }
+}
+
+/// `+5` presses key 5, `-5` releases it
+fn parse_key(payload: &[u8]) -> Option<(usize, bool)> {
+    let text = std::str::from_utf8(payload).ok()?;
+    let (pressed, key) = match text.split_at(text.len().min(1)) {
+        ("+", key) => (true, key),
+        ("-", key) => (false, key),
+        _ => return None,
+    };
+    let key = usize::from_str_radix(key, 16)
+        .ok()
+        .filter(|&key| key < 16)?;
+    Some((key, pressed))
+}
+
+/// The `Sec-WebSocket-Accept` answer for a client key
+fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key, WEBSOCKET_GUID).as_bytes()))
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+    let mut encoded = String::new();
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(bits >> (18 - i * 6)) as usize & 0x3F] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+/// A server frame, which is never masked or split
+fn write_frame(writer: &mut impl Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut header = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => header.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            header.push(126);
+            header.extend((len as u16).to_be_bytes());
+        }
+        len => {
+            header.push(127);
+            header.extend((len as u64).to_be_bytes());
+        }
+    }
+    writer.write_all(&header)?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+/// The opcode and the unmasked payload of the next client frame; `None`
+/// when the client is gone
+fn read_frame(reader: &mut impl Read) -> io::Result<Option<(u8, Vec<u8>)>> {
+    let mut header = [0; 2];
+    if let Err(err) = reader.read_exact(&mut header) {
+        return match err.kind() {
+            io::ErrorKind::UnexpectedEof => Ok(None),
+            _ => Err(err),
+        };
+    }
+
+    let opcode = header[0] & 0x0F;
+    let len = match header[1] & 0x7F {
+        126 => {
+            let mut len = [0; 2];
+            reader.read_exact(&mut len)?;
+            u16::from_be_bytes(len) as u64
+        }
+        127 => {
+            let mut len = [0; 8];
+            reader.read_exact(&mut len)?;
+            u64::from_be_bytes(len)
+        }
+        len => len as u64,
+    };
+    if len > MAX_MESSAGE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message too big",
+        ));
+    }
+
+    let mut mask = [0; 4];
+    if header[1] & 0x80 != 0 {
+        reader.read_exact(&mut mask)?;
+    }
+
+    let mut payload = vec![0; len as usize];
+    reader.read_exact(&mut payload)?;
+    for (i, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[i % 4];
+    }
+    Ok(Some((opcode, payload)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_handshake() {
+        // the example from RFC 6455
+        assert_eq!(
+            accept_key("dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"a"), "YQ==");
+    }
+
+    #[test]
+    fn test_frames() {
+        // a masked "+a", as a browser sends it
+        let mask = [1, 2, 3, 4];
+        let mut frame = vec![0x81, 0x82];
+        frame.extend(mask);
+        frame.extend([b'+' ^ 1, b'a' ^ 2]);
+
+        let (opcode, payload) = read_frame(&mut &frame[..]).unwrap().unwrap();
+        assert_eq!(opcode, TEXT);
+        assert_eq!(parse_key(&payload), Some((0xA, true)));
+        assert_eq!(parse_key(b"-f"), Some((0xF, false)));
+        assert_eq!(parse_key(b"+10"), None);
+        assert!(read_frame(&mut &[][..]).unwrap().is_none());
+
+        let mut written = Vec::new();
+        write_frame(&mut written, BINARY, &[0; 300]).unwrap();
+        assert_eq!(written[..4], [0x82, 126, 1, 44]);
+        assert_eq!(written.len(), 4 + 300);
+    }
+
+    #[test]
+    fn test_screen() {
+        let mut display = Display::default();
+        display.set_row(0, 0, 0x8000_0000_0000_0001);
+        let message = encode_screen(&display, [[0, 0, 0], [255, 255, 255], [1, 1, 1], [2, 2, 2]]);
+        assert_eq!(message[..9], [64, 32, 1, 0, 0, 0, 255, 255, 255]);
+        assert_eq!(message[9..15], [1, 1, 1, 2, 2, 2]);
+        assert_eq!(message[15..23], [0x80, 0, 0, 0, 0, 0, 0, 1]);
+        assert_eq!(message.len(), 15 + 32 * 8);
+    }
+}
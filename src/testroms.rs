@@ -0,0 +1,400 @@
+//! Small generated ROMs exercising display edge cases.
+//!
+//! Each ROM runs until it reaches a "halt" (a jump to itself) and the
+//! result is checked against the expected VF value and screen rows.
+use super::{
+    emulator::{Emulator, Quirks, DISPLAY_HEIGHT, DISPLAY_WIDTH},
+    opcode::Variant,
+};
+
+// where the generated programs start and keep their sprites
+const CODE_START: u16 = 0x200;
+const DATA_START: u16 = 0x300;
+
+// how long a test rom can run before being considered stuck
+const MAX_CYCLES: usize = 10_000;
+
+/// A generated test ROM, along with the expected results
+pub struct TestRom {
+    pub name: &'static str,
+    pub quirks: Quirks,
+    pub rom: Vec<u8>,
+
+    /// Expected value of VF when the program halts
+    pub flag: u8,
+
+    /// Expected (row, pixels) of the screen when the program halts;
+    /// rows not listed must be blank
+    pub rows: Vec<(usize, u64)>,
+
+    /// Expected (row, pixels) of the second plane (XO-CHIP), the same way
+    pub second_plane: Vec<(usize, u64)>,
+}
+
+/// Helper to write small programs by hand
+struct RomBuilder {
+    code: Vec<u8>,
+    data: Vec<u8>,
+}
+
+impl RomBuilder {
+    fn new() -> Self {
+        RomBuilder {
+            code: Vec::new(),
+            data: Vec::new(),
+        }
+    }
+
+    fn op(mut self, opcode: u16) -> Self {
+        self.code.extend_from_slice(&opcode.to_be_bytes());
+        self
+    }
+
+    /// VX = NN
+    fn ld(self, x: u16, nn: u16) -> Self {
+        self.op(0x6000 | (x << 8) | nn)
+    }
+
+    /// I = address of the sprite data at `offset`
+    fn sprite(self, offset: u16) -> Self {
+        self.op(0xA000 | (DATA_START + offset))
+    }
+
+    /// Draw N rows at VX, VY
+    fn draw(self, x: u16, y: u16, n: u16) -> Self {
+        self.op(0xD000 | (x << 8) | (y << 4) | n)
+    }
+
+    /// Select the planes to draw on (XO-CHIP)
+    fn planes(self, n: u16) -> Self {
+        self.op(0xF001 | (n << 8))
+    }
+
+    /// Sprite data, stored at DATA_START
+    fn data(mut self, bytes: &[u8]) -> Self {
+        self.data.extend_from_slice(bytes);
+        self
+    }
+
+    /// Finish the program with a jump to itself
+    fn halt(self) -> Vec<u8> {
+        let addr = CODE_START + self.code.len() as u16;
+        let mut this = self.op(0x1000 | addr);
+
+        let padding = (DATA_START - CODE_START) as usize - this.code.len();
+        this.code.extend(std::iter::repeat_n(0, padding));
+        this.code.extend_from_slice(&this.data);
+        this.code
+    }
+}
+
+/// All the generated test ROMs
+pub fn corpus() -> Vec<TestRom> {
+    let clip = Quirks::default();
+    let wrap = Quirks {
+        wrap_sprites: true,
+        ..clip
+    };
+    let schip = Quirks {
+        variant: Variant::SChip,
+        ..clip
+    };
+    let xochip = Quirks {
+        variant: Variant::XoChip,
+        ..clip
+    };
+
+    vec![
+        TestRom {
+            name: "collision-erase",
+            quirks: clip,
+            rom: RomBuilder::new()
+                .ld(0, 10)
+                .ld(1, 10)
+                .sprite(0)
+                .draw(0, 1, 2)
+                .draw(0, 1, 2)
+                .data(&[0xFF, 0x81])
+                .halt(),
+            flag: 1,
+            rows: vec![],
+            second_plane: vec![],
+        },
+        TestRom {
+            name: "collision-clears-flag",
+            quirks: clip,
+            rom: RomBuilder::new()
+                .ld(0, 0)
+                .ld(1, 0)
+                .sprite(0)
+                .draw(0, 1, 1)
+                .draw(0, 1, 1)
+                .ld(0, 8)
+                .draw(0, 1, 1)
+                .data(&[0xF0])
+                .halt(),
+            flag: 0,
+            rows: vec![(0, 0xF0 << 48)],
+            second_plane: vec![],
+        },
+        TestRom {
+            name: "collision-single-pixel",
+            quirks: clip,
+            rom: RomBuilder::new()
+                .ld(0, 0)
+                .ld(1, 0)
+                .sprite(0)
+                .draw(0, 1, 1)
+                .ld(0, 7)
+                .draw(0, 1, 1)
+                .data(&[0xFF])
+                .halt(),
+            flag: 1,
+            rows: vec![(0, 0xFEFE << 48)],
+            second_plane: vec![],
+        },
+        TestRom {
+            name: "clip-bottom-right",
+            quirks: clip,
+            rom: RomBuilder::new()
+                .ld(0, 60)
+                .ld(1, 30)
+                .sprite(0)
+                .draw(0, 1, 3)
+                .ld(0, 0)
+                .ld(1, 0)
+                .draw(0, 1, 3)
+                .data(&[0xFF, 0xFF, 0xFF])
+                .halt(),
+            flag: 0,
+            rows: vec![
+                (0, 0xFF << 56),
+                (1, 0xFF << 56),
+                (2, 0xFF << 56),
+                (30, 0xF),
+                (31, 0xF),
+            ],
+            second_plane: vec![],
+        },
+        TestRom {
+            name: "wrap-bottom-right",
+            quirks: wrap,
+            rom: RomBuilder::new()
+                .ld(0, 60)
+                .ld(1, 31)
+                .sprite(0)
+                .draw(0, 1, 2)
+                .ld(0, 0)
+                .ld(1, 0)
+                .draw(0, 1, 1)
+                .data(&[0xFF, 0xFF])
+                .halt(),
+            flag: 1,
+            rows: vec![(0, 0x0F0000000000000F), (31, 0xF00000000000000F)],
+            second_plane: vec![],
+        },
+        TestRom {
+            name: "start-position-modulo",
+            quirks: clip,
+            rom: RomBuilder::new()
+                .ld(0, 64 + 4)
+                .ld(1, 32 + 2)
+                .sprite(0)
+                .draw(0, 1, 1)
+                .data(&[0x80])
+                .halt(),
+            flag: 0,
+            rows: vec![(2, 1 << (63 - 4))],
+            second_plane: vec![],
+        },
+        TestRom {
+            name: "scroll-down",
+            quirks: schip,
+            rom: RomBuilder::new()
+                .sprite(0)
+                .draw(0, 0, 1)
+                .op(0x00C3) // scroll down 3
+                .data(&[0xFF])
+                .halt(),
+            flag: 0,
+            rows: vec![(3, 0xFF << 56)],
+            second_plane: vec![],
+        },
+        TestRom {
+            name: "scroll-down-clips",
+            quirks: schip,
+            rom: RomBuilder::new()
+                .ld(1, 30)
+                .sprite(0)
+                .draw(0, 1, 2)
+                .op(0x00C1) // scroll down 1
+                .data(&[0xFF, 0x81])
+                .halt(),
+            flag: 0,
+            rows: vec![(31, 0xFF << 56)],
+            second_plane: vec![],
+        },
+        TestRom {
+            name: "scroll-up",
+            quirks: xochip,
+            rom: RomBuilder::new()
+                .ld(1, 5)
+                .sprite(0)
+                .draw(0, 1, 1)
+                .op(0x00D2) // scroll up 2
+                .data(&[0xFF])
+                .halt(),
+            flag: 0,
+            rows: vec![(3, 0xFF << 56)],
+            second_plane: vec![],
+        },
+        TestRom {
+            name: "scroll-right",
+            quirks: schip,
+            rom: RomBuilder::new()
+                .sprite(0)
+                .draw(0, 0, 1)
+                .op(0x00FB) // scroll right 4
+                .data(&[0xFF])
+                .halt(),
+            flag: 0,
+            rows: vec![(0, 0xFF << 52)],
+            second_plane: vec![],
+        },
+        TestRom {
+            name: "scroll-left-clips",
+            quirks: schip,
+            rom: RomBuilder::new()
+                .sprite(0)
+                .draw(0, 0, 1)
+                .op(0x00FC) // scroll left 4
+                .data(&[0xFF])
+                .halt(),
+            flag: 0,
+            rows: vec![(0, 0xF0 << 56)],
+            second_plane: vec![],
+        },
+        TestRom {
+            name: "plane-second",
+            quirks: xochip,
+            rom: RomBuilder::new()
+                .planes(2)
+                .sprite(0)
+                .draw(0, 0, 1)
+                .data(&[0x80])
+                .halt(),
+            flag: 0,
+            rows: vec![(0, 1 << 63)],
+            second_plane: vec![(0, 1 << 63)],
+        },
+        TestRom {
+            name: "plane-both-collide",
+            quirks: xochip,
+            rom: RomBuilder::new()
+                .planes(3)
+                .sprite(0)
+                .draw(0, 0, 1)
+                .planes(1)
+                .draw(0, 0, 1)
+                .data(&[0xF0, 0x0F])
+                .halt(),
+            flag: 1,
+            rows: vec![(0, 0x0F << 56)],
+            second_plane: vec![(0, 0x0F << 56)],
+        },
+        TestRom {
+            name: "plane-none",
+            quirks: xochip,
+            rom: RomBuilder::new()
+                .planes(0)
+                .sprite(0)
+                .draw(0, 0, 1)
+                .data(&[0xFF])
+                .halt(),
+            flag: 0,
+            rows: vec![],
+            second_plane: vec![],
+        },
+    ]
+}
+
+/// Run a test ROM until it halts, checking the results
+pub fn run(test: &TestRom) -> Result<(), String> {
+    // the variant picks the planes on reset, so it goes in before loading
+    let mut emu = Emulator::builder()
+        .quirks(test.quirks)
+        .load_rom(&test.rom[..])
+        .map_err(|e| e.to_string())?;
+
+    // run until the program jumps to itself
+    let mut halted = false;
+    for _ in 0..MAX_CYCLES {
+        let pc = emu.PC;
+        emu.vblank();
+        emu.execute().map_err(|e| e.to_string())?;
+
+        if emu.PC == pc {
+            halted = true;
+            break;
+        }
+    }
+
+    if !halted {
+        return Err(format!("{}: did not halt", test.name));
+    }
+
+    if emu.V[0xF] != test.flag {
+        return Err(format!(
+            "{}: expected VF = {}, got {}",
+            test.name, test.flag, emu.V[0xF]
+        ));
+    }
+
+    let display = emu.display();
+    let second_plane = |x, y| display.planes() > 1 && display.plane_pixel(1, x, y);
+    check_rows(test.name, "row", &test.rows, |x, y| emu.get_pixel(x, y))?;
+    check_rows(
+        test.name,
+        "second plane row",
+        &test.second_plane,
+        second_plane,
+    )
+}
+
+/// Compare every row of the screen, as `pixel` sees it, to `expected`
+fn check_rows(
+    name: &str,
+    what: &str,
+    expected: &[(usize, u64)],
+    pixel: impl Fn(usize, usize) -> bool,
+) -> Result<(), String> {
+    for y in 0..DISPLAY_HEIGHT {
+        let expected = expected
+            .iter()
+            .find_map(|&(row, pixels)| (row == y).then_some(pixels))
+            .unwrap_or(0);
+
+        let actual = (0..DISPLAY_WIDTH).fold(0u64, |acc, x| (acc << 1) | pixel(x, y) as u64);
+
+        if actual != expected {
+            return Err(format!(
+                "{}: {} {} expected {:016X}, got {:016X}",
+                name, what, y, expected, actual
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_corpus() {
+        for test in corpus() {
+            run(&test).unwrap();
+        }
+    }
+}
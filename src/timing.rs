@@ -0,0 +1,422 @@
+//! When things happen in emulated time: instructions, timer ticks and
+//! vblank interrupts.
+//!
+//! Everything here (and in the emulator core) uses integer arithmetic only.
+//! Rates are kept as exact fractions of a second, accumulating
+//! `elapsed * rate` and counting whole millionths, so no rounding error
+//! builds up over a long session and the same elapsed times always give the
+//! same ticks, on any platform. Replays and netplay rely on this.
+use super::{
+    emulator::{Emulator, EmulatorError},
+    opcode::Opcode,
+};
+
+/// Instructions per second, unless the rom asks for something else
+pub const DEFAULT_SPEED: u32 = 540;
+
+/// Timer decrements and vblank interrupts per second
+pub const TIMER_RATE: u32 = 60;
+
+// microseconds in a second
+const MICROS: u128 = 1_000_000;
+
+// delays, in microseconds, rounded down; [`Timing`] counts the exact rates
+pub const CYCLE_DELAY: u128 = 1_000_000 / DEFAULT_SPEED as u128;
+pub const TIMER_DELAY: u128 = 1_000_000 / 60;
+pub const VBLANK_DELAY: u128 = 1_000_000 / 60;
+
+/// How many cpu cycles fit in a single frame
+pub const FRAME_CYCLES: u128 = (DEFAULT_SPEED / TIMER_RATE) as u128;
+
+/// COSMAC VIP machine cycles in a frame (1.76 MHz clock, 8 clocks per cycle)
+pub const VIP_FRAME_CYCLES: u128 = 3668;
+
+/// VIP machine cycles of each frame taken by the display interrupt and DMA,
+/// which the interpreter does not get to use
+pub const VIP_DISPLAY_CYCLES: u128 = 1832;
+
+// VIP machine cycles per second left for running instructions
+const VIP_CPU_CYCLES: u128 = (VIP_FRAME_CYCLES - VIP_DISPLAY_CYCLES) * TIMER_RATE as u128;
+
+// machine cycles the VIP interpreter spends fetching and decoding
+// every instruction, before running it
+const VIP_FETCH_CYCLES: u32 = 68;
+
+/// How long each instruction takes
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CycleModel {
+    /// All instructions take the same time, at a fixed speed
+    #[default]
+    Flat,
+    /// Each instruction takes as many machine cycles as on the COSMAC VIP
+    Vip,
+}
+
+/// What the emulator must do to catch up with the elapsed time
+pub struct Ticks {
+    pub vblank: bool,
+    /// Instructions to run; with [`CycleModel::Vip`], VIP machine cycles
+    pub cycles: u32,
+    pub timers: u32,
+    pub model: CycleModel,
+    /// Machine cycles used past `cycles` by the last instruction, with
+    /// [`CycleModel::Vip`]; give them back with [`Timing::charge`]
+    pub overrun: u32,
+    /// Sprite rows the frame still has room for, with a draw budget
+    pub draw_rows: Option<u32>,
+    /// Sprite rows drawn on this step; give them back with [`Timing::drew`]
+    pub drawn: u32,
+}
+
+/// Keeps track of how much time has passed since the last
+/// cpu cycle, timer decrease and vblank interrupt.
+///
+/// The deltas are in millionths of a tick (microseconds times the rate),
+/// so nothing is lost to rounding.
+pub struct Timing {
+    model: CycleModel,
+    speed: u128,
+    cpu_delta: u128,
+    timer_delta: u128,
+    vblank_delta: u128,
+    overrun: u128,
+    draw_budget: Option<u32>,
+    drawn: u32,
+}
+
+impl Default for Timing {
+    fn default() -> Self {
+        Self::new(DEFAULT_SPEED)
+    }
+}
+
+impl Timing {
+    /// Run the cpu at `speed` instructions per second
+    pub fn new(speed: u32) -> Self {
+        Self {
+            model: CycleModel::Flat,
+            speed: speed.max(1) as u128,
+            cpu_delta: 0,
+            timer_delta: 0,
+            vblank_delta: 0,
+            overrun: 0,
+            draw_budget: None,
+            drawn: 0,
+        }
+    }
+
+    /// Run the cpu as fast as a COSMAC VIP, with per-instruction costs
+    pub fn vip() -> Self {
+        Self {
+            model: CycleModel::Vip,
+            ..Self::default()
+        }
+    }
+
+    /// Draw at most about `rows` sprite rows per frame, like the limited
+    /// draw throughput of the VIP; once they are used up, the cpu waits
+    /// for the next frame. A sprite started within the budget is always
+    /// finished, so no rom gets stuck on a big one.
+    pub fn with_draw_budget(self, rows: u32) -> Self {
+        Self {
+            draw_budget: Some(rows),
+            ..self
+        }
+    }
+
+    /// Account for the sprite rows drawn on the last step
+    pub fn drew(&mut self, rows: u32) {
+        self.drawn = self.drawn.saturating_add(rows);
+    }
+
+    /// Account for machine cycles that were used past the last
+    /// [`Ticks::cycles`], taking them from the next ones
+    pub fn charge(&mut self, overrun: u32) {
+        self.overrun += overrun as u128;
+    }
+
+    /// Machine cycles owed to the next frames; like the machine state, it
+    /// decides what the next [`Timing::frame`] runs
+    pub fn overrun(&self) -> u128 {
+        self.overrun
+    }
+
+    /// Put back what [`Timing::overrun`] returned
+    pub fn set_overrun(&mut self, overrun: u128) {
+        self.overrun = overrun;
+    }
+
+    /// Exactly one frame worth of ticks, however long it took, for
+    /// frontends that must run the same instructions on every frame
+    pub fn frame(&mut self) -> Ticks {
+        let per_second = match self.model {
+            CycleModel::Flat => self.speed,
+            CycleModel::Vip => VIP_CPU_CYCLES,
+        };
+        let cycles = per_second / TIMER_RATE as u128;
+        let paid = cycles.min(self.overrun);
+        self.overrun -= paid;
+        self.drawn = 0;
+
+        Ticks {
+            vblank: true,
+            cycles: (cycles - paid) as u32,
+            timers: 1,
+            model: self.model,
+            overrun: 0,
+            draw_rows: self.draw_budget,
+            drawn: 0,
+        }
+    }
+
+    /// Account for `elapsed` microseconds, returning what is due
+    pub fn advance(&mut self, elapsed: u128) -> Ticks {
+        let rate = TIMER_RATE as u128;
+        self.timer_delta += elapsed * rate;
+        self.vblank_delta += elapsed * rate;
+
+        // vblank signal - just one trigger is enough
+        let vblank = self.vblank_delta >= MICROS;
+        if vblank {
+            self.vblank_delta -= MICROS;
+            self.drawn = 0;
+        }
+
+        let cycles = match self.model {
+            CycleModel::Flat => {
+                self.cpu_delta += elapsed * self.speed;
+                let cycles = self.cpu_delta / MICROS;
+                self.cpu_delta -= cycles * MICROS;
+                cycles
+            }
+            CycleModel::Vip => {
+                self.cpu_delta += elapsed * VIP_CPU_CYCLES;
+                let cycles = self.cpu_delta / MICROS;
+                self.cpu_delta -= cycles * MICROS;
+
+                let paid = cycles.min(self.overrun);
+                self.overrun -= paid;
+                cycles - paid
+            }
+        };
+
+        let timers = self.timer_delta / MICROS;
+        self.timer_delta -= timers * MICROS;
+
+        Ticks {
+            vblank,
+            cycles: cycles as u32,
+            timers: timers as u32,
+            model: self.model,
+            overrun: 0,
+            draw_rows: self
+                .draw_budget
+                .map(|budget| budget.saturating_sub(self.drawn)),
+            drawn: 0,
+        }
+    }
+}
+
+/// Approximate COSMAC VIP machine cycles used by an instruction, including
+/// the fetch and decode; `skipped` is if a skip instruction did skip.
+///
+/// These are based on timing analyses of the original interpreter. Drawing
+/// depends on where the sprite is, so it uses the average cost.
+pub fn vip_cycles(op: Option<Opcode>, skipped: bool) -> u32 {
+    let skip = if skipped { 2 } else { 0 };
+
+    let cycles = match op {
+        // invalid instructions stop the emulator anyway
+        None => 0,
+        Some(op) => match op {
+            Opcode::Sys(_)
+            | Opcode::Return
+            | Opcode::Jump(_)
+            | Opcode::Call(_)
+            | Opcode::JumpV0(_) => 23,
+            Opcode::Clear => 24,
+            Opcode::SkipEqByte(_, _) | Opcode::SkipNeqByte(_, _) => 12 + skip,
+            Opcode::SkipEqReg(_, _)
+            | Opcode::SkipNeqReg(_, _)
+            | Opcode::SkipKey(_)
+            | Opcode::SkipNotKey(_) => 16 + skip,
+            Opcode::LoadByte(_, _) => 6,
+            Opcode::AddByte(_, _)
+            | Opcode::GetDelay(_)
+            | Opcode::WaitKey(_)
+            | Opcode::SetDelay(_)
+            | Opcode::SetSound(_) => 10,
+            Opcode::LoadReg(_, _)
+            | Opcode::Or(_, _)
+            | Opcode::And(_, _)
+            | Opcode::Xor(_, _)
+            | Opcode::AddReg(_, _)
+            | Opcode::Sub(_, _)
+            | Opcode::ShiftRight(_, _)
+            | Opcode::SubN(_, _)
+            | Opcode::ShiftLeft(_, _) => 44,
+            Opcode::LoadI(_) => 12,
+            Opcode::Random(_, _) => 36,
+            Opcode::Draw(_, _, n) => 170 + 46 * n as u32,
+            Opcode::AddI(_) => 19,
+            Opcode::LoadFont(_) => 20,
+            Opcode::Bcd(_) => 204,
+            Opcode::Save(x) | Opcode::Load(x) => 14 + 14 * (x as u32 + 1),
+            // CHIP-8X; rough guesses, as there is no analysis of it
+            Opcode::CycleBackground | Opcode::SetTone(_) => 23,
+            Opcode::SkipKey2(_) | Opcode::SkipNotKey2(_) => 16 + skip,
+            Opcode::AddNibbles(_, _) => 44,
+            Opcode::ColorZones(_, _) => 170,
+            Opcode::ColorRows(_, _, n) => 60 + 16 * n as u32,
+            // SCHIP never ran on the VIP; about a display clear
+            Opcode::ScrollDown(_)
+            | Opcode::ScrollUp(_)
+            | Opcode::ScrollRight
+            | Opcode::ScrollLeft
+            | Opcode::Lores
+            | Opcode::Hires => 24,
+            Opcode::LoadBigFont(_) | Opcode::SelectPlanes(_) => 20,
+            Opcode::SaveFlags(x) | Opcode::LoadFlags(x) => 14 + 14 * (x as u32 + 1),
+        },
+    };
+
+    VIP_FETCH_CYCLES + cycles
+}
+
+/// Run exactly one frame worth of emulation at the default speed,
+/// regardless of the elapsed time; see [`Emulator::run_frame`]
+///
+/// ```
+/// use rc8::{emulator::Emulator, timing::run_frame};
+///
+/// let rom = [
+///     0x60, 0x3C, // 0x200: V0 = 60
+///     0xF0, 0x15, // 0x202: DT = V0
+///     0x12, 0x04, // 0x204: jump to 0x204
+/// ];
+///
+/// let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+/// run_frame(&mut emu).unwrap();
+///
+/// // the delay timer goes down once per frame
+/// assert_eq!(emu.DT, 59);
+/// ```
+pub fn run_frame(emu: &mut Emulator) -> Result<(), EmulatorError> {
+    emu.run_frame(FRAME_CYCLES as u32).map(|_| ())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame() {
+        let mut timing = Timing::new(600);
+        let ticks = timing.frame();
+        assert!(ticks.vblank);
+        assert_eq!((ticks.cycles, ticks.timers), (10, 1));
+
+        let mut timing = Timing::vip();
+        timing.charge(100);
+        assert_eq!(timing.frame().cycles + 100, timing.frame().cycles);
+    }
+
+    #[test]
+    fn test_vip_timing() {
+        // a frame is 16666.67us, so the rounded delay falls just short
+        let mut timing = Timing::vip();
+        let ticks = timing.advance(VBLANK_DELAY + 1);
+        assert!(ticks.vblank);
+        assert_eq!(ticks.model, CycleModel::Vip);
+        assert_eq!(ticks.cycles as u128, VIP_FRAME_CYCLES - VIP_DISPLAY_CYCLES);
+
+        // an instruction that did not fit is paid by the next frame
+        timing.charge(100);
+        let next = timing.advance(VBLANK_DELAY + 1);
+        assert_eq!(next.cycles + 100, ticks.cycles);
+
+        // skipping costs a little more
+        let skip = Opcode::SkipEqByte(0, 0);
+        assert!(vip_cycles(Some(skip), true) > vip_cycles(Some(skip), false));
+    }
+
+    #[test]
+    fn test_draw_budget() {
+        let mut timing = Timing::vip().with_draw_budget(32);
+        assert_eq!(Timing::vip().advance(1000).draw_rows, None);
+        assert_eq!(timing.advance(1000).draw_rows, Some(32));
+
+        timing.drew(20);
+        assert_eq!(timing.advance(1000).draw_rows, Some(12));
+        timing.drew(15);
+        assert_eq!(timing.advance(1000).draw_rows, Some(0));
+
+        // a new frame starts over
+        assert_eq!(timing.advance(VBLANK_DELAY).draw_rows, Some(32));
+    }
+
+    /// Advance `total` microseconds in steps of `step`, adding up the ticks
+    fn ticks_over(timing: &mut Timing, total: u128, step: u128) -> (u32, u32, u32) {
+        let (mut cycles, mut timers, mut vblanks) = (0, 0, 0);
+        let mut elapsed = 0;
+        while elapsed < total {
+            let ticks = timing.advance(step.min(total - elapsed));
+            cycles += ticks.cycles;
+            timers += ticks.timers;
+            vblanks += ticks.vblank as u32;
+            elapsed += step;
+        }
+        (cycles, timers, vblanks)
+    }
+
+    #[test]
+    fn test_exact_rates() {
+        // no drift, however the time is split
+        for step in [1, 997, 1000, 16_666, 16_667] {
+            let ticks = ticks_over(&mut Timing::default(), 10_000_000, step);
+            assert_eq!(ticks, (5400, 600, 600), "steps of {}us", step);
+        }
+
+        let ticks = ticks_over(&mut Timing::new(1000), 1_000_000, 333);
+        assert_eq!(ticks, (1000, 60, 60));
+    }
+
+    #[test]
+    fn test_deterministic() {
+        let rom = [
+            0xC0, 0x3F, // 0x200: V0 = random & 0x3F
+            0xC1, 0x1F, // 0x202: V1 = random & 0x1F
+            0xA0, 0x00, // 0x204: I = sprite of "0"
+            0xD0, 0x15, // 0x206: draw it on V0,V1
+            0x62, 0x03, // 0x208: V2 = 3
+            0xF2, 0x15, // 0x20A: DT = V2
+            0xE2, 0xA1, // 0x20C: skip if key 3 is not pressed
+            0x00, 0xE0, // 0x20E: clear the screen
+            0xF3, 0x07, // 0x210: V3 = DT
+            0x33, 0x00, // 0x212: skip if V3 == 0
+            0x12, 0x10, // 0x214: jump to 0x210
+            0x12, 0x00, // 0x216: jump to 0x200
+        ];
+
+        // same seed, keys and elapsed times: the same machine, bit by bit,
+        // pinned so any platform (or a float sneaking in) that differs fails
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        emu.seed_rng(1804);
+        let mut timing = Timing::default();
+        for step in 0..2000u128 {
+            emu.set_key(3, step % 150 < 20);
+            let ticks = timing.advance(1000 + step % 7 * 311);
+            if ticks.vblank {
+                emu.vblank();
+            }
+            for _ in 0..ticks.cycles {
+                emu.execute().unwrap();
+            }
+            for _ in 0..ticks.timers {
+                emu.decrease_timers();
+            }
+        }
+
+        assert_eq!(emu.state_hash(), 0xAFA3_C2B7_1C35_4AD8);
+    }
+}
@@ -0,0 +1,166 @@
+//! Lightweight span recorder, to investigate stutters after the fact.
+//!
+//! The most recent spans are kept on a ring buffer and can be exported
+//! as a Chrome tracing JSON, which opens on chrome://tracing or Perfetto.
+use std::{
+    collections::VecDeque,
+    fmt::Write as _,
+    fs, io,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+// how many spans are kept; older ones are dropped
+const CAPACITY: usize = 1 << 16;
+
+/// Thread ids, as shown on the trace viewer
+pub const MAIN_THREAD: u32 = 1;
+pub const AUDIO_THREAD: u32 = 2;
+pub const EMULATION_THREAD: u32 = 3;
+
+struct Span {
+    name: &'static str,
+    tid: u32,
+    start: u64,
+    duration: u64,
+}
+
+struct Ring {
+    epoch: Instant,
+    spans: VecDeque<Span>,
+}
+
+/// Shared handle to the span recorder; clones record to the same buffer
+#[derive(Clone)]
+pub struct Tracer {
+    ring: Arc<Mutex<Ring>>,
+}
+
+/// Records a span when dropped
+pub struct SpanGuard<'a> {
+    tracer: &'a Tracer,
+    name: &'static str,
+    tid: u32,
+    start: Instant,
+}
+
+impl Tracer {
+    pub fn new() -> Self {
+        Tracer {
+            ring: Arc::new(Mutex::new(Ring {
+                epoch: Instant::now(),
+                spans: VecDeque::with_capacity(CAPACITY),
+            })),
+        }
+    }
+
+    /// Start a span, which lasts until the returned guard is dropped
+    pub fn span(&self, name: &'static str, tid: u32) -> SpanGuard<'_> {
+        SpanGuard {
+            tracer: self,
+            name,
+            tid,
+            start: Instant::now(),
+        }
+    }
+
+    fn record(&self, name: &'static str, tid: u32, start: Instant) {
+        let duration = start.elapsed().as_micros() as u64;
+
+        // a poisoned lock just means we lose the trace, not a reason to crash
+        if let Ok(mut ring) = self.ring.lock() {
+            let start = start.saturating_duration_since(ring.epoch).as_micros() as u64;
+            if ring.spans.len() == CAPACITY {
+                ring.spans.pop_front();
+            }
+
+            ring.spans.push_back(Span {
+                name,
+                tid,
+                start,
+                duration,
+            });
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ring
+            .lock()
+            .map(|ring| ring.spans.is_empty())
+            .unwrap_or(true)
+    }
+
+    /// The recorded spans, in the Chrome tracing JSON format
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\"traceEvents\":[\n");
+        json.push_str(
+            "{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":1,\"args\":{\"name\":\"main\"}},\n",
+        );
+        json.push_str(
+            "{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":2,\"args\":{\"name\":\"audio\"}},\n",
+        );
+        json.push_str(
+            "{\"name\":\"thread_name\",\"ph\":\"M\",\"pid\":1,\"tid\":3,\"args\":{\"name\":\"emulation\"}}",
+        );
+
+        if let Ok(ring) = self.ring.lock() {
+            for span in &ring.spans {
+                let _ = write!(
+                    json,
+                    ",\n{{\"name\":\"{}\",\"ph\":\"X\",\"pid\":1,\"tid\":{},\"ts\":{},\"dur\":{}}}",
+                    span.name, span.tid, span.start, span.duration
+                );
+            }
+        }
+
+        json.push_str("\n]}\n");
+        json
+    }
+
+    /// Save the recorded spans to a file
+    pub fn export(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_json())
+    }
+}
+
+impl Default for Tracer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for SpanGuard<'_> {
+    fn drop(&mut self) {
+        self.tracer.record(self.name, self.tid, self.start);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        let tracer = Tracer::new();
+        {
+            let _frame = tracer.span("frame", MAIN_THREAD);
+            let _audio = tracer.span("audio", AUDIO_THREAD);
+        }
+
+        let json = tracer.to_json();
+        assert!(json.starts_with("{\"traceEvents\":["));
+        assert!(json.contains("\"name\":\"frame\",\"ph\":\"X\",\"pid\":1,\"tid\":1"));
+        assert!(json.contains("\"name\":\"audio\",\"ph\":\"X\",\"pid\":1,\"tid\":2"));
+    }
+
+    #[test]
+    fn test_ring_capacity() {
+        let tracer = Tracer::new();
+        for _ in 0..CAPACITY + 10 {
+            drop(tracer.span("cpu", MAIN_THREAD));
+        }
+
+        assert_eq!(tracer.ring.lock().unwrap().spans.len(), CAPACITY);
+    }
+}
@@ -0,0 +1,322 @@
+//! A debugger on the terminal, for `--debug-tui`: commands are read from
+//! stdin, one per line, and the answers are printed on stdout, while the
+//! game keeps running on its window.
+//!
+//! Like the gdb stub, lines are read on their own thread and the commands
+//! go to the main loop, as the app decides when the rom runs.
+use std::{
+    fmt::Write as _,
+    io::{self, BufRead, Write},
+    sync::mpsc::{self, Receiver, TryIter},
+    thread,
+};
+
+use super::{condition::Condition, debugger::Debugger, emulator::Emulator, opcode::Opcode};
+
+const HELP: &str = "\
+r               show the registers
+l [ADDR]        disassemble around ADDR (default: PC)
+m ADDR [LEN]    dump LEN bytes of memory (default: 64)
+b [ADDR]        set a breakpoint on ADDR, or list them
+b ADDR if COND  stop on ADDR only when COND holds (e.g. V3 == 0x1F && I > 0x300)
+d ADDR          delete the breakpoint on ADDR
+s [N]           run N instructions (default: 1)
+p               pause
+c               continue
+h               show this help
+numbers are decimal, unless they start with 0x; an empty line repeats the last command";
+
+// instructions shown by `l`, before and after the address
+const CONTEXT: usize = 5;
+
+/// What was asked on the terminal
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    Help,
+    Registers,
+    List(Option<usize>),
+    Memory(usize, usize),
+    Break(Option<usize>),
+    BreakIf(usize, Condition),
+    Delete(usize),
+    Step(u32),
+    Pause,
+    Continue,
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        // only breakpoints take a condition
+        if let Some((line, condition)) = line.split_once(" if ") {
+            return match Command::parse(line)? {
+                Command::Break(Some(address)) => Ok(Command::BreakIf(address, condition.parse()?)),
+                _ => Err("only breakpoints take a condition".to_owned()),
+            };
+        }
+
+        let mut words = line.split_whitespace();
+        let name = words.next().unwrap_or_default();
+        let args = words.map(parse_number).collect::<Result<Vec<_>, _>>()?;
+
+        let command = match (name, &args[..]) {
+            ("h" | "help", []) => Command::Help,
+            ("r" | "regs", []) => Command::Registers,
+            ("l" | "list", []) => Command::List(None),
+            ("l" | "list", &[address]) => Command::List(Some(address)),
+            ("m" | "mem", &[address]) => Command::Memory(address, 64),
+            ("m" | "mem", &[address, len]) => Command::Memory(address, len),
+            ("b" | "break", []) => Command::Break(None),
+            ("b" | "break", &[address]) => Command::Break(Some(address)),
+            ("d" | "delete", &[address]) => Command::Delete(address),
+            ("s" | "step", []) => Command::Step(1),
+            ("s" | "step", &[count]) => Command::Step(count as u32),
+            ("p" | "pause", []) => Command::Pause,
+            ("c" | "continue", []) => Command::Continue,
+            _ => return Err(format!("unknown command '{}' (h for help)", line.trim())),
+        };
+        Ok(command)
+    }
+}
+
+fn parse_number(word: &str) -> Result<usize, String> {
+    match word.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16),
+        None => word.parse(),
+    }
+    .map_err(|_| format!("invalid number: '{}'", word))
+}
+
+/// Reads the commands typed on the terminal
+pub struct DebugTui {
+    commands: Receiver<Command>,
+}
+
+impl DebugTui {
+    pub fn spawn() -> io::Result<Self> {
+        let (sender, commands) = mpsc::channel();
+        prompt();
+
+        thread::Builder::new()
+            .name("debug-tui".to_owned())
+            .spawn(move || {
+                let mut last = None;
+                for line in io::stdin().lock().lines() {
+                    let Ok(line) = line else { return };
+                    let command = match line.trim() {
+                        "" => last.clone().ok_or_else(|| "h for help".to_owned()),
+                        line => Command::parse(line),
+                    };
+
+                    match command {
+                        Ok(command) => {
+                            last = Some(command.clone());
+                            if sender.send(command).is_err() {
+                                return;
+                            }
+                        }
+                        Err(message) => {
+                            println!("{}", message);
+                            prompt();
+                        }
+                    }
+                }
+            })?;
+
+        Ok(DebugTui { commands })
+    }
+
+    /// The commands typed since the last call
+    pub fn commands(&self) -> TryIter<'_, Command> {
+        self.commands.try_iter()
+    }
+}
+
+/// Print an answer, and ask for the next command
+pub fn print(text: &str) {
+    println!("{}", text);
+    prompt();
+}
+
+fn prompt() {
+    print!("(rc8) ");
+    let _ = io::stdout().flush();
+}
+
+/// Answer the commands that only look at (or change) the machine; the ones
+/// that start and stop it are up to the app
+pub fn access(command: &Command, emu: &mut Emulator, debugger: &mut Debugger) -> Option<String> {
+    let answer = match command {
+        Command::Help => HELP.to_owned(),
+        Command::Registers => registers(emu),
+        Command::List(address) => list(emu, debugger, address.unwrap_or(emu.PC)),
+        Command::Memory(address, len) => memory(emu, *address, *len),
+        Command::Break(Some(address)) => {
+            debugger.set_breakpoint(*address, true);
+            format!("breakpoint at 0x{:03X}", address)
+        }
+        Command::BreakIf(address, condition) => {
+            debugger.set_conditional_breakpoint(*address, condition.clone());
+            format!("breakpoint at 0x{:03X} if {}", address, condition)
+        }
+        Command::Break(None) => {
+            let breakpoints: Vec<_> = debugger
+                .breakpoints()
+                .map(|(address, condition)| match condition {
+                    Some(condition) => format!("0x{:03X} if {}", address, condition),
+                    None => format!("0x{:03X}", address),
+                })
+                .collect();
+            match breakpoints.is_empty() {
+                true => "no breakpoints".to_owned(),
+                false => breakpoints.join("\n"),
+            }
+        }
+        Command::Delete(address) => {
+            debugger.set_breakpoint(*address, false);
+            format!("deleted the breakpoint at 0x{:03X}", address)
+        }
+        Command::Step(count) => {
+            for _ in 0..*count {
+                if let Err(err) = emu.execute() {
+                    return Some(format!("error: {}", err));
+                }
+            }
+            list(emu, debugger, emu.PC)
+        }
+        Command::Pause | Command::Continue => return None,
+    };
+    Some(answer)
+}
+
+/// All the registers, on two lines
+pub fn registers(emu: &Emulator) -> String {
+    let mut text = String::new();
+    for (i, value) in emu.V.iter().enumerate() {
+        let _ = write!(text, "V{:X}={:02X} ", i, value);
+    }
+    let _ = write!(
+        text,
+        "\nI={:03X} PC={:03X} SP={} DT={:02X} ST={:02X}",
+        emu.I,
+        emu.PC,
+        emu.sub_stack.len(),
+        emu.DT,
+        emu.ST
+    );
+
+    let keys: Vec<_> = (0..16)
+        .filter(|&key| emu.is_key_pressed(key))
+        .map(|key| format!("{:X}", key))
+        .collect();
+    let _ = write!(
+        text,
+        "\nkeys: {}{}",
+        if keys.is_empty() {
+            "none".to_owned()
+        } else {
+            keys.join(" ")
+        },
+        if emu.waiting_for_key() {
+            " (FX0A waiting)"
+        } else {
+            ""
+        }
+    );
+    text
+}
+
+/// The instructions around `address`; `>` marks the PC and `*` the breakpoints
+pub fn list(emu: &Emulator, debugger: &Debugger, address: usize) -> String {
+    let last = emu.memory.len() - 2;
+    let first = address.saturating_sub(CONTEXT * 2).min(last);
+    let breakpoints: Vec<_> = debugger.breakpoints().map(|(address, _)| address).collect();
+
+    (first..=(address + CONTEXT * 2).min(last))
+        .step_by(2)
+        .map(|address| {
+            let word = emu.word_at(address);
+            let text = match Opcode::decode_variant(word, emu.quirks.variant) {
+                Some(op) => op.to_string(),
+                None => format!("0x{:02X} 0x{:02X}", word >> 8, word & 0xFF),
+            };
+            format!(
+                "{}{} {:03X}  {:04X}  {}",
+                if address == emu.PC { '>' } else { ' ' },
+                if breakpoints.contains(&address) {
+                    '*'
+                } else {
+                    ' '
+                },
+                address,
+                word,
+                text
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// A hex dump, 16 bytes per line
+fn memory(emu: &Emulator, address: usize, len: usize) -> String {
+    let end = (address + len).min(emu.memory.len());
+    if address >= end {
+        return format!("address out of range: 0x{:03X}", address);
+    }
+
+    (address..end)
+        .step_by(16)
+        .map(|start| {
+            let bytes: Vec<_> = emu.memory[start..(start + 16).min(end)]
+                .iter()
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            format!("{:03X}  {}", start, bytes.join(" "))
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Command::parse("b 0x220"), Ok(Command::Break(Some(0x220))));
+        assert_eq!(Command::parse("s"), Ok(Command::Step(1)));
+        assert_eq!(Command::parse("s 10"), Ok(Command::Step(10)));
+        assert_eq!(Command::parse("m 0x300 16"), Ok(Command::Memory(0x300, 16)));
+        assert_eq!(
+            Command::parse("b 0x220 if V3 == 0x1F"),
+            Ok(Command::BreakIf(0x220, "V3 == 0x1F".parse().unwrap()))
+        );
+        assert!(Command::parse("s if V0 == 1").is_err());
+        assert!(Command::parse("b 0x220 if V3 =").is_err());
+        assert!(Command::parse("b 0xzz").is_err());
+        assert!(Command::parse("x").is_err());
+        assert!(Command::parse("c 1").is_err());
+    }
+
+    #[test]
+    fn test_access() {
+        let rom = [0x60, 0x2A, 0x12, 0x00]; // 0x200: v0 := 0x2A, jump 0x200
+        let mut emu = Emulator::load_rom(&rom[..]).unwrap();
+        let mut debugger = Debugger::default();
+
+        let set = access(&Command::Break(Some(0x202)), &mut emu, &mut debugger).unwrap();
+        assert_eq!(set, "breakpoint at 0x202");
+
+        let step = access(&Command::Step(1), &mut emu, &mut debugger).unwrap();
+        assert!(step.contains(">* 202  1200  jump 0x200"));
+        assert!(step.contains("   200  602A  v0 := 0x2A"));
+
+        let registers = access(&Command::Registers, &mut emu, &mut debugger).unwrap();
+        assert!(registers.starts_with("V0=2A V1=00"));
+        assert!(registers.contains("PC=202"));
+
+        let memory = access(&Command::Memory(0x200, 4), &mut emu, &mut debugger).unwrap();
+        assert_eq!(memory, "200  60 2A 12 00");
+
+        assert_eq!(access(&Command::Continue, &mut emu, &mut debugger), None);
+    }
+}
@@ -0,0 +1,100 @@
+//! Turbo keys: while held, they are pressed and released over and over, for
+//! games that need fast tapping that is hard to do on a keyboard.
+
+/// Presses per second, unless asked otherwise
+pub const DEFAULT_RATE: u32 = 10;
+
+/// The turbo keys, and which ones are held
+pub struct Turbo {
+    keys: [bool; 16],
+    // microseconds of a press and a release
+    period: u128,
+    // since when each key is held, and if it is down on the keypad now
+    held: [Option<u128>; 16],
+    down: [bool; 16],
+}
+
+impl Turbo {
+    /// Make `keys` repeat `rate` times per second
+    pub fn new(keys: &[usize], rate: u32) -> Self {
+        let mut turbo = [false; 16];
+        for &key in keys {
+            turbo[key & 0xF] = true;
+        }
+
+        Turbo {
+            keys: turbo,
+            period: 1_000_000 / rate.max(1) as u128,
+            held: [None; 16],
+            down: [false; 16],
+        }
+    }
+
+    pub fn is_turbo(&self, key: usize) -> bool {
+        self.keys[key & 0xF]
+    }
+
+    /// The key went down or up on the keyboard; it is pressed on the
+    /// keypad on the next update
+    pub fn set_held(&mut self, key: usize, held: bool, now: u128) {
+        let since = &mut self.held[key & 0xF];
+        match held {
+            // key repeats do not start over
+            true => *since = since.or(Some(now)),
+            false => *since = None,
+        }
+    }
+
+    /// The keys to press or release on the keypad now
+    pub fn update(&mut self, now: u128) -> Vec<(usize, bool)> {
+        let mut changes = Vec::new();
+        for key in 0..16 {
+            let down =
+                self.held[key].is_some_and(|since| (now - since) % self.period < self.period / 2);
+            if down != self.down[key] {
+                self.down[key] = down;
+                changes.push((key, down));
+            }
+        }
+        changes
+    }
+}
+
+/// Parse a CHIP-8 key, as a hex digit
+pub fn parse_key(s: &str) -> Result<usize, String> {
+    match usize::from_str_radix(s, 16) {
+        Ok(key) if s.len() == 1 => Ok(key),
+        _ => Err(format!("invalid key: {} (use a hex digit, 0 to F)", s)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_turbo() {
+        // 10 presses per second: 50ms down, 50ms up
+        let mut turbo = Turbo::new(&[0x5], 10);
+        assert!(turbo.is_turbo(0x5));
+        assert!(!turbo.is_turbo(0x6));
+
+        turbo.set_held(0x5, true, 1_000);
+        assert_eq!(turbo.update(1_000), [(0x5, true)]);
+        assert!(turbo.update(40_000).is_empty());
+        turbo.set_held(0x5, true, 45_000);
+        assert_eq!(turbo.update(60_000), [(0x5, false)]);
+        assert_eq!(turbo.update(110_000), [(0x5, true)]);
+
+        turbo.set_held(0x5, false, 120_000);
+        assert_eq!(turbo.update(120_000), [(0x5, false)]);
+        assert!(turbo.update(200_000).is_empty());
+    }
+
+    #[test]
+    fn test_parse_key() {
+        assert_eq!(parse_key("a"), Ok(0xA));
+        assert!(parse_key("10").is_err());
+        assert!(parse_key("g").is_err());
+    }
+}
@@ -0,0 +1,92 @@
+//! Looks for a newer rc8 on the GitHub releases, in the background, so
+//! startup does not wait for the network.
+use std::{
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::Duration,
+};
+
+use anyhow::Context;
+use serde::Deserialize;
+use tracing::warn;
+
+const LATEST_RELEASE: &str = "https://api.github.com/repos/ibraimgm/rc8/releases/latest";
+
+// a slow network should not keep the thread around for long
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Deserialize, Debug)]
+pub struct Release {
+    #[serde(rename = "tag_name")]
+    pub version: String,
+
+    /// The release page, with the changelog
+    #[serde(rename = "html_url")]
+    pub url: String,
+}
+
+/// Check for a newer release on another thread; the receiver gets it only
+/// if there is one, and failures are just logged
+pub fn spawn_check() -> Receiver<Release> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || match latest_release() {
+        Ok(release) if is_newer(&release.version, env!("CARGO_PKG_VERSION")) => {
+            let _ = sender.send(release);
+        }
+        Ok(_) => {}
+        Err(err) => warn!("{:#}", err),
+    });
+    receiver
+}
+
+fn latest_release() -> Result<Release, anyhow::Error> {
+    let agent = ureq::AgentBuilder::new().timeout(TIMEOUT).build();
+    let json = agent
+        .get(LATEST_RELEASE)
+        .set("User-Agent", concat!("rc8/", env!("CARGO_PKG_VERSION")))
+        .call()
+        .context("error checking for updates")?
+        .into_string()
+        .context("error checking for updates")?;
+    serde_json::from_str(&json).context("invalid release information")
+}
+
+/// `major.minor.patch`, with an optional `v` in front
+fn parse_version(version: &str) -> Option<(u32, u32, u32)> {
+    let version = version.trim().trim_start_matches('v');
+    let mut parts = version.split('.').map(|part| part.parse().ok());
+    let parsed = (parts.next()??, parts.next()??, parts.next()??);
+    parts.next().is_none().then_some(parsed)
+}
+
+/// If `latest` is a later version than `current`; anything that does not
+/// look like a version is not
+fn is_newer(latest: &str, current: &str) -> bool {
+    match (parse_version(latest), parse_version(current)) {
+        (Some(latest), Some(current)) => latest > current,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_newer() {
+        assert!(is_newer("v0.2.0", "0.1.0"));
+        assert!(is_newer("0.1.10", "0.1.9"));
+        assert!(!is_newer("v0.1.0", "0.1.0"));
+        assert!(!is_newer("0.0.9", "0.1.0"));
+        assert!(!is_newer("nightly", "0.1.0"));
+        assert!(!is_newer("1.0.0.1", "0.1.0"));
+    }
+
+    #[test]
+    fn test_release() {
+        let json = r#"{"tag_name": "v0.2.0", "html_url": "https://example.com", "draft": false}"#;
+        let release: Release = serde_json::from_str(json).unwrap();
+        assert_eq!(release.version, "v0.2.0");
+        assert_eq!(release.url, "https://example.com");
+    }
+}
@@ -0,0 +1,311 @@
+//! The core for the web playground (`rc8 playground`), built for the
+//! browser with:
+//!
+//! ```text
+//! cargo rustc --lib --release --target wasm32-unknown-unknown --features wasm --crate-type cdylib
+//! ```
+//!
+//! The page loads a rom and talks to it with the same gdb packets as the
+//! gdb stub of the app (see [`rsp`]), to step it and read its
+//! registers and memory, and runs it a frame at a time while continuing.
+//!
+//! Everything goes through a few plain functions: the page asks for room
+//! with [`rc8_buffer`] and writes a rom or a packet there, then reads the
+//! replies and the screen straight from the memory of the module. Replies
+//! are given by their length, or -1 when there is none (yet). There is no
+//! system entropy on the browser, so the page picks the seed.
+use std::{collections::BTreeSet, convert::Infallible};
+
+use super::{
+    display::{HIRES_HEIGHT, HIRES_WIDTH},
+    emulator::{Emulator, EmulatorError},
+    frontend::{self, AudioSink, Frame, InputSource},
+    rsp::{self, Answer, Command},
+    timing::Timing,
+};
+
+/// A rom on the page, stopped or running
+pub struct Playground {
+    emu: Emulator,
+    timing: Timing,
+    seed: u64,
+    breakpoints: BTreeSet<usize>,
+    running: bool,
+    // a breakpoint on PC does not stop the rom as it continues from there
+    resuming: bool,
+    buzzer: bool,
+
+    // what the page writes, the last reply, and the screen as drawn last
+    input: Vec<u8>,
+    reply: String,
+    rgba: Vec<u8>,
+    size: (usize, usize),
+}
+
+impl Playground {
+    /// An empty machine, seeding its random numbers with `seed`
+    pub fn new(seed: u64) -> Self {
+        Playground {
+            emu: Self::machine(seed, &[]).expect("an empty rom always loads"),
+            timing: Timing::default(),
+            seed,
+            breakpoints: BTreeSet::new(),
+            running: false,
+            resuming: false,
+            buzzer: false,
+            input: Vec::new(),
+            reply: String::new(),
+            rgba: vec![0; HIRES_WIDTH * HIRES_HEIGHT * 4],
+            size: (0, 0),
+        }
+    }
+
+    fn machine(seed: u64, rom: &[u8]) -> Result<Emulator, EmulatorError> {
+        Emulator::builder().seed(seed).load_rom(rom)
+    }
+
+    /// Start over with `rom`, stopped on its first instruction; the
+    /// breakpoints are kept
+    pub fn load(&mut self, rom: &[u8]) -> Result<(), EmulatorError> {
+        self.emu = Self::machine(self.seed, rom)?;
+        self.timing = Timing::default();
+        self.running = false;
+        Ok(())
+    }
+
+    /// Answer a gdb packet; a `c` is only answered by [`Playground::frame`],
+    /// once something stops the rom
+    pub fn packet(&mut self, packet: &str) -> Option<String> {
+        match rsp::answer(packet) {
+            Answer::Reply(reply) => Some(reply),
+            Answer::Detach => Some("OK".to_owned()),
+            Answer::Machine(Command::Continue) => {
+                self.running = true;
+                self.resuming = true;
+                None
+            }
+            Answer::Machine(command) => rsp::access(&command, &mut self.emu, &mut self.breakpoints),
+        }
+    }
+
+    /// Run the next frame, while continuing; the stop reply, when a
+    /// breakpoint is hit or the rom crashes
+    pub fn frame(&mut self) -> Option<String> {
+        if !self.running {
+            return None;
+        }
+
+        let mut ticks = self.timing.frame();
+        let mut buzzer = Buzzer(false);
+        let (breakpoints, resuming) = (&self.breakpoints, &mut self.resuming);
+        let result = frontend::step(
+            &mut self.emu,
+            &mut ticks,
+            &mut Page,
+            &mut Page,
+            &mut buzzer,
+            |emu| !std::mem::take(resuming) && breakpoints.contains(&emu.PC),
+        );
+        self.timing.charge(ticks.overrun);
+        self.timing.drew(ticks.drawn);
+        self.buzzer = buzzer.0;
+
+        let reply = match result {
+            Ok(false) => return None,
+            Ok(true) => rsp::STOPPED,
+            Err(_) => rsp::CRASHED,
+        };
+        self.running = false;
+        Some(reply.to_owned())
+    }
+
+    /// Stop a running rom, like ctrl-c on gdb; the stop reply, if it was
+    /// running
+    pub fn interrupt(&mut self) -> Option<String> {
+        std::mem::take(&mut self.running).then(|| rsp::STOPPED.to_owned())
+    }
+
+    pub fn set_key(&mut self, key: usize, pressed: bool) {
+        self.emu.set_key(key, pressed);
+    }
+
+    /// If the buzzer was on at the end of the last frame
+    pub fn buzzer(&self) -> bool {
+        self.buzzer
+    }
+
+    /// Draw the screen, with the colors given as `0xRRGGBB`
+    pub fn render(&mut self, fg: u32, bg: u32) -> &[u8] {
+        self.size = self.emu.render_rgba(&mut self.rgba, fg, bg);
+        &self.rgba[..self.size.0 * self.size.1 * 4]
+    }
+
+    /// Keep `reply` for the page to read, returning its length
+    fn keep(&mut self, reply: Option<String>) -> i32 {
+        match reply {
+            Some(reply) => {
+                self.reply = reply;
+                self.reply.len() as i32
+            }
+            None => -1,
+        }
+    }
+}
+
+/// The page shows the screen when it wants, and sets the keys directly
+struct Page;
+
+impl Frame for Page {
+    type Error = Infallible;
+
+    fn present(&mut self, _emu: &Emulator) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+impl InputSource for Page {
+    fn poll(&mut self, _emu: &mut Emulator) {}
+}
+
+struct Buzzer(bool);
+
+impl AudioSink for Buzzer {
+    fn set_buzzer(&mut self, on: bool) {
+        self.0 = on;
+    }
+}
+
+/// A new playground, which the page keeps for the other calls
+#[no_mangle]
+pub extern "C" fn rc8_new(seed: u32) -> &'static mut Playground {
+    Box::leak(Box::new(Playground::new(seed as u64)))
+}
+
+/// Room for `len` bytes, where the page writes a rom or a packet
+#[no_mangle]
+pub extern "C" fn rc8_buffer(playground: &mut Playground, len: usize) -> *mut u8 {
+    playground.input.resize(len, 0);
+    playground.input.as_mut_ptr()
+}
+
+/// Load the rom on the buffer; false if it does not fit
+#[no_mangle]
+pub extern "C" fn rc8_load(playground: &mut Playground) -> bool {
+    let rom = std::mem::take(&mut playground.input);
+    let loaded = playground.load(&rom).is_ok();
+    playground.input = rom;
+    loaded
+}
+
+/// Answer the packet on the buffer
+#[no_mangle]
+pub extern "C" fn rc8_packet(playground: &mut Playground) -> i32 {
+    let packet = String::from_utf8_lossy(&playground.input).into_owned();
+    let reply = playground.packet(&packet);
+    playground.keep(reply)
+}
+
+/// Run a frame, if continuing
+#[no_mangle]
+pub extern "C" fn rc8_frame(playground: &mut Playground) -> i32 {
+    let reply = playground.frame();
+    playground.keep(reply)
+}
+
+/// Stop the rom, if continuing
+#[no_mangle]
+pub extern "C" fn rc8_interrupt(playground: &mut Playground) -> i32 {
+    let reply = playground.interrupt();
+    playground.keep(reply)
+}
+
+/// The last reply, of the length the call that made it returned
+#[no_mangle]
+pub extern "C" fn rc8_reply(playground: &Playground) -> *const u8 {
+    playground.reply.as_ptr()
+}
+
+#[no_mangle]
+pub extern "C" fn rc8_set_key(playground: &mut Playground, key: u32, pressed: bool) {
+    playground.set_key(key as usize, pressed);
+}
+
+#[no_mangle]
+pub extern "C" fn rc8_buzzer(playground: &Playground) -> bool {
+    playground.buzzer()
+}
+
+/// Draw the screen as RGBA, [`rc8_width`] by [`rc8_height`] pixels
+#[no_mangle]
+pub extern "C" fn rc8_render(playground: &mut Playground, fg: u32, bg: u32) -> *const u8 {
+    playground.render(fg, bg).as_ptr()
+}
+
+#[no_mangle]
+pub extern "C" fn rc8_width(playground: &Playground) -> u32 {
+    playground.size.0 as u32
+}
+
+#[no_mangle]
+pub extern "C" fn rc8_height(playground: &Playground) -> u32 {
+    playground.size.1 as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step() {
+        let mut playground = Playground::new(0);
+        let rom = [
+            0x60, 0x2A, // 0x200: v0 := 0x2A
+            0x12, 0x02, // 0x202: jump 0x202
+        ];
+        playground.load(&rom).unwrap();
+
+        assert_eq!(playground.packet("s").as_deref(), Some(rsp::STOPPED));
+        let registers = playground.packet("g").unwrap();
+        assert!(registers.starts_with("2a00"));
+        assert_eq!(&registers[36..40], "0202");
+        assert_eq!(playground.frame(), None);
+
+        // continuing runs frame by frame, until interrupted
+        assert_eq!(playground.packet("c"), None);
+        assert_eq!(playground.frame(), None);
+        assert_eq!(playground.interrupt().as_deref(), Some(rsp::STOPPED));
+        assert_eq!(playground.interrupt(), None);
+
+        let lores = (HIRES_WIDTH / 2) * (HIRES_HEIGHT / 2);
+        assert_eq!(playground.render(0xFFFFFF, 0).len(), lores * 4);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut playground = Playground::new(0);
+        let rom = [
+            0x70, 0x01, // 0x200: v0 += 1
+            0x12, 0x00, // 0x202: jump 0x200
+        ];
+        playground.load(&rom).unwrap();
+        assert_eq!(playground.packet("Z0,202,2").as_deref(), Some("OK"));
+
+        // a breakpoint stops the rom, and does not stop it again as it
+        // continues from there
+        for v0 in 1..=3 {
+            assert_eq!(playground.packet("c"), None);
+            assert_eq!(playground.frame().as_deref(), Some(rsp::STOPPED));
+            assert_eq!(playground.emu.PC, 0x202);
+            assert_eq!(playground.emu.V[0], v0);
+        }
+
+        assert_eq!(playground.packet("z0,202,2").as_deref(), Some("OK"));
+        playground.packet("c");
+        assert_eq!(playground.frame(), None);
+
+        // a crash stops it too
+        playground.load(&[0x00, 0xEE]).unwrap();
+        playground.packet("c");
+        assert_eq!(playground.frame().as_deref(), Some(rsp::CRASHED));
+    }
+}
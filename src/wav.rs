@@ -0,0 +1,136 @@
+//! Saves everything the speaker plays as a WAV file, for `--record-audio`.
+//!
+//! The samples are written as 16-bit mono PCM, on a thread of their own, so
+//! the audio callback never waits on the disk. The sizes on the header are
+//! only known at the end, so they are filled in when the recording stops.
+use std::{
+    fs::File,
+    io::{self, BufWriter, Seek, SeekFrom, Write},
+    path::Path,
+    sync::mpsc::{self, Sender},
+    thread::JoinHandle,
+};
+
+// bytes of the header, before the samples
+const HEADER_SIZE: u32 = 44;
+
+/// Writes samples to a WAV file, as they come
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    samples: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<Self> {
+        // RIFF header and format chunk: PCM, mono, 16 bits per sample
+        out.write_all(b"RIFF")?;
+        out.write_all(&0u32.to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&1u16.to_le_bytes())?;
+        out.write_all(&sample_rate.to_le_bytes())?;
+        out.write_all(&(sample_rate * 2).to_le_bytes())?;
+        out.write_all(&2u16.to_le_bytes())?;
+        out.write_all(&16u16.to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&0u32.to_le_bytes())?;
+
+        Ok(WavWriter { out, samples: 0 })
+    }
+
+    /// Add samples, from -1.0 to 1.0
+    pub fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        for &sample in samples {
+            let value = (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16;
+            self.out.write_all(&value.to_le_bytes())?;
+        }
+        self.samples += samples.len() as u32;
+        Ok(())
+    }
+
+    /// Fill in the sizes on the header; returns how many samples were
+    /// written
+    pub fn finish(mut self) -> io::Result<u32> {
+        let data_size = self.samples * 2;
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out
+            .write_all(&(HEADER_SIZE - 8 + data_size).to_le_bytes())?;
+        self.out.seek(SeekFrom::Start(HEADER_SIZE as u64 - 4))?;
+        self.out.write_all(&data_size.to_le_bytes())?;
+        self.out.flush()?;
+        Ok(self.samples)
+    }
+}
+
+/// Records the samples sent through [`WavRecorder::sender`] into a file
+pub struct WavRecorder {
+    sender: Sender<Vec<f32>>,
+    thread: JoinHandle<io::Result<u32>>,
+    sample_rate: u32,
+}
+
+impl WavRecorder {
+    pub fn create(path: &Path, sample_rate: u32) -> io::Result<Self> {
+        let mut writer = WavWriter::new(BufWriter::new(File::create(path)?), sample_rate)?;
+        let (sender, receiver) = mpsc::channel::<Vec<f32>>();
+
+        // an empty buffer ends the recording, as the mixer may still hold
+        // senders of its own
+        let thread = std::thread::spawn(move || {
+            for samples in receiver.iter().take_while(|samples| !samples.is_empty()) {
+                writer.write(&samples)?;
+            }
+            writer.finish()
+        });
+
+        Ok(WavRecorder {
+            sender,
+            thread,
+            sample_rate,
+        })
+    }
+
+    /// Where the mixer sends what it plays
+    pub fn sender(&self) -> Sender<Vec<f32>> {
+        self.sender.clone()
+    }
+
+    /// Stop recording, and save the file; returns how many seconds were
+    /// recorded
+    pub fn finish(self) -> io::Result<f32> {
+        let _ = self.sender.send(Vec::new());
+        let samples = self
+            .thread
+            .join()
+            .map_err(|_| io::Error::other("the wav writer panicked"))??;
+        Ok(samples as f32 / self.sample_rate as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_wav() {
+        let mut out = Cursor::new(Vec::new());
+        let mut writer = WavWriter::new(&mut out, 44100).unwrap();
+        writer.write(&[0.0, 1.0, -1.0]).unwrap();
+        writer.write(&[2.0]).unwrap();
+        assert_eq!(writer.finish().unwrap(), 4);
+
+        let bytes = out.into_inner();
+        assert_eq!(bytes.len(), HEADER_SIZE as usize + 8);
+        assert_eq!(&bytes[..4], b"RIFF");
+        assert_eq!(bytes[4..8], 44u32.to_le_bytes());
+        assert_eq!(bytes[24..28], 44100u32.to_le_bytes());
+        assert_eq!(&bytes[36..40], b"data");
+        assert_eq!(bytes[40..44], 8u32.to_le_bytes());
+        assert_eq!(
+            bytes[HEADER_SIZE as usize..],
+            [0x00, 0x00, 0xFF, 0x7F, 0x01, 0x80, 0xFF, 0x7F]
+        );
+    }
+}
@@ -0,0 +1,589 @@
+//! Runs the emulation on its own thread, so rendering, vsync and window
+//! events on the SDL loop cannot stall it, and a slow frame on the cpu
+//! side does not hold the window back either.
+//!
+//! The machine is shared behind a mutex, which the UI only takes for a
+//! moment to handle input and build its textures. Key presses go to the
+//! emulation through a channel, and the buzzer, breakpoints and crashes
+//! come back through another one.
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    sync::{
+        mpsc::{self, Receiver, Sender, TryIter},
+        Arc, Mutex, MutexGuard,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use super::{
+    clock::Clock,
+    debugger::Debugger,
+    emulator::{Emulator, EmulatorError},
+    filter::{Filter, Persistence},
+    frontend::{self, AudioSink, Frame, FrontendError, InputSource},
+    netplay::Netplay,
+    profile::Profile,
+    record::Recorder,
+    rng::Chip8Rng,
+    snapshot::Snapshot,
+    timing::Timing,
+    trace::{Tracer, EMULATION_THREAD},
+};
+
+/// How much faster the rom runs while fast-forwarding
+const FAST_FORWARD: u128 = 8;
+
+// frames netplay can fall behind the clock, waiting for the other player,
+// and then run in a row to catch up
+const NETPLAY_CATCH_UP: u32 = 2;
+
+// frames a spectator who came late runs on each step, until they are up
+// to date with the players
+const WATCH_CATCH_UP: u32 = 600;
+
+// how long the thread sleeps between steps; longer while paused
+const TICK: Duration = Duration::from_millis(1);
+const IDLE_TICK: Duration = Duration::from_millis(10);
+
+/// What the emulation thread tells the UI
+pub enum Event {
+    Buzzer(bool),
+    Breakpoint,
+    Crashed(EmulatorError),
+    /// The other netplay player left (or, when watching, the host); the
+    /// game goes on, single player
+    PlayerLeft,
+}
+
+/// Everything the emulation touches
+pub struct Machine {
+    pub emu: Emulator,
+    pub timing: Timing,
+    pub debugger: Debugger,
+    pub screen: Screen,
+    pub keypad: Keypad,
+    pub recorder: Option<Recorder>,
+
+    /// The rom only runs while set; the UI clears it to pause
+    pub running: bool,
+    pub fast_forward: bool,
+
+    /// Instructions run since the UI last took the count
+    pub instructions: u64,
+
+    /// Set with `--profile`, counts every instruction run
+    pub profile: Option<Profile>,
+
+    /// The game with another player, while there is one
+    pub netplay: Option<Netplay>,
+    frames_due: u32,
+    player_left: bool,
+    // with rollback, the machine before each frame not confirmed yet
+    saves: VecDeque<Savestate>,
+    quit: bool,
+}
+
+impl Machine {
+    /// Run whatever is due after `elapsed` microseconds
+    fn step(&mut self, elapsed: u128) -> (bool, Result<bool, EmulatorError>) {
+        if self.netplay.is_some() {
+            return self.step_netplay(elapsed);
+        }
+
+        let elapsed = if self.fast_forward {
+            elapsed * FAST_FORWARD
+        } else {
+            elapsed
+        };
+
+        let mut ticks = self.timing.advance(elapsed);
+        let mut buzzer = Buzzer(false);
+        let Machine {
+            emu,
+            debugger,
+            screen,
+            keypad,
+            instructions,
+            profile,
+            ..
+        } = self;
+
+        let result = frontend::step(emu, &mut ticks, screen, keypad, &mut buzzer, |emu| {
+            *instructions += 1;
+            if let Some(profile) = profile {
+                profile.record(emu);
+            }
+            debugger.should_stop(emu)
+        });
+        self.timing.charge(ticks.overrun);
+        self.timing.drew(ticks.drawn);
+
+        // one entry per emulated frame, however many run per step
+        if let Some(recorder) = &mut self.recorder {
+            if ticks.vblank {
+                recorder.record(&self.emu);
+            }
+        }
+
+        (buzzer.0, result.map_err(emulator_error))
+    }
+
+    /// Whole frames with the keys of both players, paced by the clock but
+    /// only once the other player sent their keys; there are no breakpoints
+    /// or fast-forward, as they would leave the other player behind
+    fn step_netplay(&mut self, elapsed: u128) -> (bool, Result<bool, EmulatorError>) {
+        if self.timing.advance(elapsed).vblank {
+            self.frames_due = (self.frames_due + 1).min(NETPLAY_CATCH_UP);
+        }
+
+        let mut buzzer = Buzzer(self.emu.buzzer());
+        let Machine {
+            emu,
+            timing,
+            screen,
+            keypad,
+            recorder,
+            instructions,
+            profile,
+            netplay: Some(netplay),
+            frames_due,
+            saves,
+            ..
+        } = self
+        else {
+            return (buzzer.0, Ok(false));
+        };
+
+        let mut run_frame = |emu: &mut Emulator, timing: &mut Timing, keys: u16| {
+            let mut ticks = timing.frame();
+            let result = frontend::step(
+                emu,
+                &mut ticks,
+                screen,
+                &mut Lockstep(keys),
+                &mut buzzer,
+                |emu| {
+                    *instructions += 1;
+                    if let Some(profile) = profile {
+                        profile.record(emu);
+                    }
+                    false
+                },
+            );
+            timing.charge(ticks.overrun);
+            timing.drew(ticks.drawn);
+            if let Some(recorder) = recorder {
+                recorder.record(emu);
+            }
+            result.map_err(emulator_error)
+        };
+
+        // the second keypad is not shared
+        for (key, pressed) in keypad.pending.try_iter() {
+            if key & KEYPAD2 == 0 {
+                netplay.set_key(key, pressed);
+            }
+        }
+
+        let mut left = false;
+
+        // a wrong guess: back to the frame it was made on, and run the
+        // frames from there again with the keys that came
+        match netplay.reconcile() {
+            Ok(None) => (),
+            Ok(Some((from, replay))) => match saves.iter().position(|save| save.frame == from) {
+                Some(start) => {
+                    saves[start].load(emu, timing);
+                    saves.truncate(start);
+                    for (frame, keys) in (from..).zip(replay) {
+                        saves.push_back(Savestate::new(frame, emu, timing));
+                        if let Err(err) = run_frame(emu, timing, keys) {
+                            return (buzzer.0, Err(err));
+                        }
+                        if netplay.ran_again(frame, emu).is_err() {
+                            left = true;
+                            break;
+                        }
+                    }
+                }
+                None => left = true,
+            },
+            Err(_) => left = true,
+        }
+        saves.retain(|save| save.frame >= netplay.confirmed());
+
+        let mut caught_up = 0;
+        while !left
+            && (*frames_due > 0
+                || (netplay.behind() > NETPLAY_CATCH_UP as usize && caught_up < WATCH_CATCH_UP))
+        {
+            let keys = match netplay.next_keys() {
+                Ok(Some(keys)) => keys,
+                Ok(None) => break,
+                Err(_) => {
+                    left = true;
+                    break;
+                }
+            };
+            *frames_due = frames_due.saturating_sub(1);
+            caught_up += 1;
+
+            if netplay.rollback() {
+                saves.push_back(Savestate::new(netplay.frame() - 1, emu, timing));
+            }
+            if let Err(err) = run_frame(emu, timing, keys) {
+                return (buzzer.0, Err(err));
+            }
+            if netplay.ran(emu).is_err() {
+                left = true;
+                break;
+            }
+        }
+
+        // whatever this player holds stays pressed
+        if left {
+            let local = netplay.local_keys();
+            for key in 0..16 {
+                emu.set_key(key, local & 1 << key != 0);
+            }
+            self.netplay = None;
+            self.keypad.lockstep = false;
+            self.player_left = true;
+        }
+        (buzzer.0, Ok(false))
+    }
+}
+
+fn emulator_error(err: FrontendError<Infallible>) -> EmulatorError {
+    match err {
+        FrontendError::Emulator(err) => err,
+        FrontendError::Frame(never) => match never {},
+    }
+}
+
+/// The emulation thread, and the way to talk to it
+pub struct Worker {
+    machine: Arc<Mutex<Machine>>,
+    keys: Sender<(usize, bool)>,
+    events: Receiver<Event>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Worker {
+    /// Start running `emu` on a new thread, paced by `clock`
+    pub fn spawn(
+        emu: Emulator,
+        timing: Timing,
+        clock: impl Clock + Send + 'static,
+        filter: Filter,
+        recorder: Option<Recorder>,
+        netplay: Option<Netplay>,
+        tracer: Tracer,
+    ) -> Self {
+        let (keys, pending) = mpsc::channel();
+        let (sender, events) = mpsc::channel();
+        let machine = Arc::new(Mutex::new(Machine {
+            emu,
+            timing,
+            debugger: Debugger::default(),
+            screen: Screen::new(filter),
+            keypad: Keypad {
+                pending,
+                lockstep: netplay.is_some(),
+            },
+            recorder,
+            running: true,
+            fast_forward: false,
+            instructions: 0,
+            profile: None,
+            netplay,
+            frames_due: 0,
+            player_left: false,
+            saves: VecDeque::new(),
+            quit: false,
+        }));
+
+        let shared = Arc::clone(&machine);
+        let handle = thread::Builder::new()
+            .name("emulation".to_owned())
+            .spawn(move || run(shared, clock, sender, tracer))
+            .expect("failed to start the emulation thread");
+
+        Worker {
+            machine,
+            keys,
+            events,
+            handle: Some(handle),
+        }
+    }
+
+    /// The machine, for as long as the guard is held; the emulation
+    /// waits meanwhile, so keep it short
+    pub fn lock(&self) -> MutexGuard<'_, Machine> {
+        self.machine.lock().expect("the emulation thread panicked")
+    }
+
+    /// Press or release a key on the next step
+    pub fn set_key(&self, key: usize, pressed: bool) {
+        // the thread only goes away with the worker
+        let _ = self.keys.send((key, pressed));
+    }
+
+    /// Press or release a key of the second keypad (CHIP-8X) on the next step
+    pub fn set_key2(&self, key: usize, pressed: bool) {
+        let _ = self.keys.send((KEYPAD2 | key, pressed));
+    }
+
+    /// Everything that happened since the last call
+    pub fn events(&self) -> TryIter<'_, Event> {
+        self.events.try_iter()
+    }
+
+    /// Stop the thread, giving back the recording
+    pub fn stop(mut self) -> Option<Recorder> {
+        self.lock().quit = true;
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+        self.lock().recorder.take()
+    }
+}
+
+fn run(machine: Arc<Mutex<Machine>>, mut clock: impl Clock, events: Sender<Event>, tracer: Tracer) {
+    let mut previous = clock.now();
+    let mut buzzer = false;
+
+    loop {
+        let now = clock.now();
+        let elapsed = now - previous;
+        previous = now;
+
+        let idle = {
+            let mut machine = match machine.lock() {
+                Ok(machine) => machine,
+                Err(_) => return,
+            };
+
+            if machine.quit {
+                return;
+            }
+
+            if machine.running {
+                let _span = tracer.span("cpu", EMULATION_THREAD);
+                let _cpu = tracing::trace_span!("cpu", elapsed).entered();
+                let (on, result) = machine.step(elapsed);
+                if on != buzzer {
+                    buzzer = on;
+                    let _ = events.send(Event::Buzzer(on));
+                }
+
+                if std::mem::take(&mut machine.player_left) {
+                    let _ = events.send(Event::PlayerLeft);
+                }
+
+                match result {
+                    Ok(false) => {}
+                    Ok(true) => {
+                        machine.running = false;
+                        let _ = events.send(Event::Breakpoint);
+                    }
+                    Err(err) => {
+                        machine.running = false;
+                        let _ = events.send(Event::Crashed(err));
+                    }
+                }
+                false
+            } else {
+                // the UI silences the buzzer while stopped
+                buzzer = false;
+                true
+            }
+        };
+
+        clock.sleep(if idle { IDLE_TICK } else { TICK });
+    }
+}
+
+/// The emulator screen, as seen through the display filter. The texture
+/// itself is drawn by the main loop, along with the overlays.
+pub struct Screen {
+    pub persistence: Option<Persistence>,
+    pub changed: bool,
+    /// Vblanks shown so far
+    pub frames: u64,
+}
+
+impl Screen {
+    pub fn new(filter: Filter) -> Self {
+        Screen {
+            persistence: filter.persistence(),
+            changed: false,
+            frames: 0,
+        }
+    }
+
+    /// Move the display filter one frame forward
+    pub fn update(&mut self, emu: &Emulator) {
+        if let Some(persistence) = self.persistence.as_mut() {
+            self.changed |= persistence.update(emu);
+        }
+    }
+}
+
+impl Frame for Screen {
+    type Error = Infallible;
+
+    fn present(&mut self, emu: &Emulator) -> Result<(), Infallible> {
+        self.frames += 1;
+        self.update(emu);
+        Ok(())
+    }
+}
+
+// keys from the second keypad have this bit set on their way to the thread
+const KEYPAD2: usize = 0x10;
+
+/// Key changes from the keyboard and the on-screen keypad, waiting
+/// for the next step
+pub struct Keypad {
+    pending: Receiver<(usize, bool)>,
+
+    // with netplay, the keys only reach the emulator through the lockstep
+    lockstep: bool,
+}
+
+impl InputSource for Keypad {
+    fn poll(&mut self, emu: &mut Emulator) {
+        if self.lockstep {
+            return;
+        }
+
+        for (key, pressed) in self.pending.try_iter() {
+            if key & KEYPAD2 != 0 {
+                emu.set_key2(key, pressed);
+            } else {
+                emu.set_key(key, pressed);
+            }
+        }
+    }
+}
+
+/// The keys of both netplay players, for a frame
+struct Lockstep(u16);
+
+impl InputSource for Lockstep {
+    fn poll(&mut self, emu: &mut Emulator) {
+        for key in 0..16 {
+            emu.set_key(key, self.0 & 1 << key != 0);
+        }
+    }
+}
+
+/// The machine before a frame, to go back to when the keys guessed for it
+/// turn out wrong
+struct Savestate {
+    frame: u32,
+    snapshot: Snapshot,
+    rng: Option<Box<dyn Chip8Rng>>,
+    overrun: u128,
+}
+
+impl Savestate {
+    fn new(frame: u32, emu: &Emulator, timing: &Timing) -> Self {
+        Savestate {
+            frame,
+            snapshot: emu.snapshot(),
+            rng: emu.copy_rng(),
+            overrun: timing.overrun(),
+        }
+    }
+
+    fn load(&self, emu: &mut Emulator, timing: &mut Timing) {
+        emu.restore(&self.snapshot)
+            .expect("a snapshot of the machine is always valid");
+        if let Some(rng) = self.rng.as_ref().and_then(|rng| rng.try_clone()) {
+            emu.set_rng(rng);
+        }
+        timing.set_overrun(self.overrun);
+    }
+}
+
+/// The last buzzer state asked for by a step
+struct Buzzer(bool);
+
+impl AudioSink for Buzzer {
+    fn set_buzzer(&mut self, on: bool) {
+        self.0 = on;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::SystemClock;
+    use std::time::Instant;
+
+    #[test]
+    fn test_worker() {
+        let rom = [
+            0x60, 0x05, // 0x200: V0 = 5
+            0xE0, 0x9E, // 0x202: skip if key 5 is pressed
+            0x12, 0x02, // 0x204: jump to 0x202
+            0x00, 0xEE, // 0x206: return, with an empty stack
+        ];
+        let emu = Emulator::load_rom(&rom[..]).unwrap();
+        let worker = Worker::spawn(
+            emu,
+            Timing::new(1000),
+            SystemClock::new(),
+            Filter::None,
+            None,
+            None,
+            Tracer::new(),
+        );
+
+        // the key makes the rom crash, which stops the emulation
+        worker.set_key(5, true);
+        let start = Instant::now();
+        while worker.lock().running {
+            assert!(start.elapsed() < Duration::from_secs(5));
+            thread::sleep(TICK);
+        }
+
+        assert!(worker.lock().instructions > 0);
+        assert!(matches!(
+            worker.events().last(),
+            Some(Event::Crashed(EmulatorError::InvalidReturn(0x206)))
+        ));
+        assert!(worker.stop().is_none());
+    }
+
+    #[test]
+    fn test_savestate() {
+        // 0x200: v0 := random 0xFF, jump 0x200
+        let mut emu = Emulator::load_rom(&[0xC0, 0xFF, 0x12, 0x00][..]).unwrap();
+        let mut timing = Timing::new(1000);
+        timing.charge(3);
+        let save = Savestate::new(0, &emu, &timing);
+
+        // going back runs the same random numbers again
+        let mut first = Vec::new();
+        for _ in 0..4 {
+            emu.execute().unwrap();
+            first.push(emu.V[0]);
+            emu.execute().unwrap();
+        }
+        timing.charge(5);
+        save.load(&mut emu, &mut timing);
+        let mut again = Vec::new();
+        for _ in 0..4 {
+            emu.execute().unwrap();
+            again.push(emu.V[0]);
+            emu.execute().unwrap();
+        }
+        assert_eq!(first, again);
+        assert_eq!(timing.overrun(), 3);
+    }
+}
@@ -0,0 +1,122 @@
+//! Runs the bundled CHIP-8 test suite (by Timendus) headlessly, and checks
+//! the screen each test ends on.
+//!
+//! The suite picks the test to run from the byte at 0x1FF, and the platform
+//! for the quirks test from the byte at 0x1FE, skipping its menus. If one of
+//! these fails after a change to the core, run the rom with `rc8` and the
+//! same settings to see which check went wrong.
+use rc8::{emulator::Emulator, opcode::Variant, timing::run_frame};
+
+const SUITE: &[u8] = include_bytes!("../roms/chip8-test-suite.ch8");
+
+// every test is done well before this
+const FRAMES: usize = 300;
+
+const IBM_LOGO: u8 = 1;
+const CORAX: u8 = 2;
+const FLAGS: u8 = 3;
+const QUIRKS: u8 = 4;
+const KEYPAD: u8 = 5;
+
+/// Load the suite to run `test`, under the `variant` quirks
+fn suite(test: u8, platform: u8, variant: Variant) -> Emulator {
+    let mut emu = Emulator::builder()
+        .variant(variant)
+        .load_rom(SUITE)
+        .unwrap();
+    emu.memory[0x1FF] = test;
+    emu.memory[0x1FE] = platform;
+    emu
+}
+
+fn run(emu: &mut Emulator, frames: usize) {
+    for frame in 0..frames {
+        if let Err(err) = run_frame(emu) {
+            panic!("error on frame {}: {}", frame, err);
+        }
+    }
+}
+
+fn assert_screen(emu: &Emulator, hash: u64) {
+    assert_eq!(
+        emu.screen_hash(),
+        hash,
+        "unexpected screen: 0x{:016x}",
+        emu.screen_hash()
+    );
+}
+
+#[test]
+fn test_ibm_logo() {
+    let mut emu = suite(IBM_LOGO, 0, Variant::Chip8);
+    run(&mut emu, FRAMES);
+    assert_screen(&emu, 0xc094f65422bd4e58);
+}
+
+#[test]
+fn test_corax() {
+    for variant in [Variant::Chip8, Variant::SChip] {
+        let mut emu = suite(CORAX, 0, variant);
+        run(&mut emu, FRAMES);
+        assert_screen(&emu, 0x64da6ced0a45e175);
+    }
+}
+
+#[test]
+fn test_flags() {
+    for variant in [Variant::Chip8, Variant::SChip] {
+        let mut emu = suite(FLAGS, 0, variant);
+        run(&mut emu, FRAMES);
+        assert_screen(&emu, 0x178f86cd3c911e89);
+    }
+}
+
+#[test]
+fn test_quirks() {
+    // the platform asked on the menu: 1 for CHIP-8, 2 for SCHIP, 3 for XO-CHIP
+    let mut emu = suite(QUIRKS, 1, Variant::Chip8);
+    run(&mut emu, FRAMES);
+    assert_screen(&emu, 0xbf58fe49c0a153fb);
+
+    // the results stay up, nothing is left running
+    run(&mut emu, FRAMES);
+    assert_screen(&emu, 0xbf58fe49c0a153fb);
+}
+
+// these are the screens with the checks that still fail; when the quirks
+// are done, pin the screens they end on instead:
+// - SCHIP fails vF reset, memory, display wait, shifting and jumping
+// - XO-CHIP fails vF reset, display wait and clipping
+#[test]
+#[ignore = "the SCHIP and XO-CHIP quirks are not all done"]
+fn test_quirks_schip_xochip() {
+    let failing = [
+        (2, Variant::SChip, 0x6949c3027b032067),
+        (3, Variant::XoChip, 0xb7e5ee8c08d7e3ef),
+    ];
+    for (platform, variant, hash) in failing {
+        let mut emu = suite(QUIRKS, platform, variant);
+        run(&mut emu, FRAMES);
+        assert_ne!(emu.screen_hash(), hash, "{} quirks still fail", variant);
+    }
+}
+
+#[test]
+fn test_keypad() {
+    // the key down test draws the keypad, with nothing held
+    let mut emu = suite(KEYPAD, 1, Variant::Chip8);
+    run(&mut emu, FRAMES);
+    assert_screen(&emu, 0xa7e2a9cf379ef535);
+
+    // the get key test waits for a key to be pressed and released
+    let mut emu = suite(KEYPAD, 3, Variant::Chip8);
+    run(&mut emu, 60);
+    let waiting = emu.screen_hash();
+    emu.set_key(0xA, true);
+    run(&mut emu, 30);
+    assert_screen(&emu, waiting);
+
+    emu.set_key(0xA, false);
+    run(&mut emu, 30);
+    assert_screen(&emu, 0x3785c0b45dceace2);
+}